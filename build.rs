@@ -0,0 +1,17 @@
+// With the `capi` feature, regenerates the C header include/sim.h from src/capi.rs
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/capi.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    #[cfg(feature = "capi")]
+    {
+        let dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", dir)).expect("Failed to read cbindgen.toml");
+        cbindgen::Builder::new()
+            .with_src(format!("{}/src/capi.rs", dir))
+            .with_config(config)
+            .generate()
+            .expect("Failed to generate the C header")
+            .write_to_file(format!("{}/include/sim.h", dir));
+    }
+}
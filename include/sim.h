@@ -0,0 +1,170 @@
+/* Generated by cbindgen from src/capi.rs; do not edit. */
+
+#ifndef SIM_H
+#define SIM_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Version of this interface, raised whenever a function changes in an incompatible way
+#define SIM_ABI_VERSION 1
+
+// Success
+#define SIM_OK 0
+
+// A handle or array pointer was null
+#define SIM_NULL_POINTER -1
+
+// An argument was out of range, or an array had the wrong length
+#define SIM_INVALID_ARGUMENT -2
+
+// The engine panicked; the simulation should be destroyed
+#define SIM_PANIC -3
+
+// A state file could not be written
+#define SIM_IO_ERROR -4
+
+// Opaque simulation handle
+typedef struct SimHandle SimHandle;
+
+// Thermodynamic state of a simulation
+typedef struct SimThermo {
+  // Simulated time, engine time units
+  double time;
+  // K
+  double temperature;
+  double kinetic_energy;
+  // Inside the cutoff
+  double potential_energy;
+  // Inside the cutoff
+  double pressure;
+} SimThermo;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Version of the interface the library was built with, to compare with SIM_ABI_VERSION
+uint32_t sim_abi_version(void);
+
+// Creates a Lennard-Jones simulation of `num_atoms` argon atoms in a periodic cubic box, in
+// the units of the `sim` command (sigma = epsilon = 1, engine time units). `velocities` may be null for atoms
+// at rest. Returns null if an argument is invalid or the engine fails.
+//
+// # Safety
+//
+// `positions`, and `velocities` unless null, must point to `3 * num_atoms` doubles.
+struct SimHandle *sim_create(size_t num_atoms,
+                             const double *positions,
+                             const double *velocities,
+                             double box_length,
+                             double timestep,
+                             double cutoff);
+
+// Frees a simulation. Null is ignored.
+//
+// # Safety
+//
+// `handle` must come from `sim_create` and not be used again.
+void sim_destroy(struct SimHandle *handle);
+
+// Sets the timestep in engine time units.
+//
+// # Safety
+//
+// `handle` must come from `sim_create`.
+int32_t sim_set_timestep(struct SimHandle *handle, double timestep);
+
+// Sets the interaction cutoff, cut back to half the box as in the engine.
+//
+// # Safety
+//
+// `handle` must come from `sim_create`.
+int32_t sim_set_cutoff(struct SimHandle *handle, double cutoff);
+
+// Couples the temperature to `temperature` (K) with a Berendsen thermostat of time constant
+// `tau` (engine time units). A `tau` of zero removes the thermostat.
+//
+// # Safety
+//
+// `handle` must come from `sim_create`.
+int32_t sim_set_berendsen(struct SimHandle *handle, double temperature, double tau);
+
+// Adds a Langevin thermostat at `temperature` (K) with collision frequency `friction` (per engine time unit)
+// and noise from `seed`. A `friction` of zero removes it.
+//
+// # Safety
+//
+// `handle` must come from `sim_create`.
+int32_t sim_set_langevin(struct SimHandle *handle,
+                         double temperature,
+                         double friction,
+                         uint64_t seed);
+
+// Advances the simulation by `steps` velocity Verlet steps.
+//
+// # Safety
+//
+// `handle` must come from `sim_create`.
+int32_t sim_step(struct SimHandle *handle, uint64_t steps);
+
+// Number of atoms, or 0 for a null handle.
+//
+// # Safety
+//
+// `handle` must come from `sim_create`.
+size_t sim_num_atoms(const struct SimHandle *handle);
+
+// Copies the positions into `out`, `len` = 3 * sim_num_atoms doubles.
+//
+// # Safety
+//
+// `handle` must come from `sim_create` and `out` must point to `len` writable doubles.
+int32_t sim_get_positions(const struct SimHandle *handle, double *out, size_t len);
+
+// Copies the velocities into `out`, `len` = 3 * sim_num_atoms doubles.
+//
+// # Safety
+//
+// `handle` must come from `sim_create` and `out` must point to `len` writable doubles.
+int32_t sim_get_velocities(const struct SimHandle *handle, double *out, size_t len);
+
+// Copies the forces into `out`, `len` = 3 * sim_num_atoms doubles.
+//
+// # Safety
+//
+// `handle` must come from `sim_create` and `out` must point to `len` writable doubles.
+int32_t sim_get_forces(const struct SimHandle *handle, double *out, size_t len);
+
+// Fills `out` with the current time, temperature, energies and pressure.
+//
+// # Safety
+//
+// `handle` must come from `sim_create` and `out` must point to a writable SimThermo.
+int32_t sim_get_thermo(const struct SimHandle *handle, struct SimThermo *out);
+
+// Saves the full state of a simulation (configuration, step, couplings and their seeds) as JSON
+// to the UTF-8 path `path`, for `sim_load_state`.
+//
+// # Safety
+//
+// `handle` must come from `sim_create` or `sim_load_state` and `path` must be a NUL-terminated
+// string.
+int32_t sim_save_state(const struct SimHandle *handle, const char *path);
+
+// Creates a simulation from a state saved by `sim_save_state` or `SaveState::write_json`; it
+// continues exactly as the saved one would have. Returns null if the file cannot be read.
+//
+// # Safety
+//
+// `path` must be a NUL-terminated string.
+struct SimHandle *sim_load_state(const char *path);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* SIM_H */
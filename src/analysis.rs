@@ -0,0 +1,555 @@
+use rayon::prelude::*;
+use std::f64::consts::PI;
+
+use crate::forces::lj_potential;
+use crate::geometry::{invert3, minimum_image_vector};
+use crate::neighbors::CellList;
+use crate::simulation::{KB, MASS_ARGON};
+
+// Kept here for code written against the analysis module before `geometry` existed
+pub use crate::geometry::minimum_image_distance;
+
+// Radial distribution function averaged over `frames`, with `bins` shells up to `r_max`
+// (at most half the box). Returns the shell centres and g(r).
+pub fn rdf(frames: &[Vec<[f64; 3]>], box_length: f64, bins: usize, r_max: f64) -> (Vec<f64>, Vec<f64>) {
+    let n = frames.first().map_or(0, |frame| frame.len());
+    let (centres, mut partials) = partial_rdf(frames, &vec![0; n], box_length, bins, r_max);
+    (centres, partials.pop().map_or_else(|| vec![0.0; bins], |(_, g)| g))
+}
+
+// Species pair (a <= b) and its g(r) per shell
+pub type PartialRdf = ((usize, usize), Vec<f64>);
+
+// Partial radial distribution functions g_ab(r) between the species of every atom, given as
+// indices into a list of species. Returns the shell centres and one curve per pair a <= b of
+// species present. g_ab(r) is the density of b atoms at distance r from an a atom over the
+// mean density of b atoms, so every partial tends to 1 in a uniform mixture.
+pub fn partial_rdf(
+    frames: &[Vec<[f64; 3]>],
+    species: &[usize],
+    box_length: f64,
+    bins: usize,
+    r_max: f64,
+) -> (Vec<f64>, Vec<PartialRdf>) {
+    let r_max = r_max.min(0.5 * box_length);
+    let dr = r_max / bins as f64;
+    let num_species = species.iter().max().map_or(0, |&s| s + 1);
+    let pair_index = |a: usize, b: usize| {
+        let (a, b) = (a.min(b), a.max(b));
+        a * num_species + b
+    };
+
+    let histogram = frames.par_iter().map(|positions| {
+        let mut histogram = vec![0u64; num_species * num_species * bins];
+        for i in 0..positions.len() {
+            for j in (i + 1)..positions.len() {
+                let r = minimum_image_distance(&positions[i], &positions[j], box_length);
+                if r < r_max {
+                    let bin = ((r / dr) as usize).min(bins - 1);
+                    histogram[pair_index(species[i], species[j]) * bins + bin] += 1;
+                }
+            }
+        }
+        histogram
+    }).reduce(|| vec![0u64; num_species * num_species * bins], |mut a, b| {
+        for (x, y) in a.iter_mut().zip(b) {
+            *x += y;
+        }
+        a
+    });
+
+    let mut counts = vec![0usize; num_species];
+    for &s in species {
+        counts[s] += 1;
+    }
+    let volume = box_length.powi(3);
+    let centres = (0..bins).map(|b| (b as f64 + 0.5) * dr).collect();
+    let mut partials = Vec::new();
+    for a in 0..num_species {
+        for b in a..num_species {
+            if counts[a] == 0 || counts[b] == 0 {
+                continue;
+            }
+            let offset = pair_index(a, b) * bins;
+            // An a-b pair is seen once from each of its atoms when a = b, otherwise once from its a atom
+            let visits = if a == b { 2.0 } else { 1.0 };
+            let density_b = counts[b] as f64 / volume;
+            let g = (0..bins).map(|bin| {
+                let shell_volume = 4.0 / 3.0 * PI * (((bin + 1) as f64 * dr).powi(3) - (bin as f64 * dr).powi(3));
+                visits * histogram[offset + bin] as f64
+                    / (frames.len() as f64 * counts[a] as f64 * density_b * shell_volume)
+            }).collect();
+            partials.push(((a, b), g));
+        }
+    }
+    (centres, partials)
+}
+
+// Mean squared displacement against lag (in frames), averaged over all time origins.
+// Frames are unwrapped with the minimum image convention, which assumes no atom moves more
+// than half a box between two stored frames.
+pub fn msd(frames: &[Vec<[f64; 3]>], box_length: f64) -> Vec<f64> {
+    let unwrapped = unwrap(frames, box_length);
+    (0..unwrapped.len()).into_par_iter().map(|lag| {
+        let origins = unwrapped.len() - lag;
+        let total: f64 = (0..origins).map(|t0| {
+            unwrapped[t0].iter().zip(unwrapped[t0 + lag].iter()).map(|(a, b)| {
+                (b[0] - a[0]).powi(2) + (b[1] - a[1]).powi(2) + (b[2] - a[2]).powi(2)
+            }).sum::<f64>()
+        }).sum();
+        total / (origins * unwrapped[0].len()) as f64
+    }).collect()
+}
+
+fn unwrap(frames: &[Vec<[f64; 3]>], box_length: f64) -> Vec<Vec<[f64; 3]>> {
+    let mut unwrapped: Vec<Vec<[f64; 3]>> = Vec::with_capacity(frames.len());
+    for (f, frame) in frames.iter().enumerate() {
+        if f == 0 {
+            unwrapped.push(frame.clone());
+            continue;
+        }
+        let previous = &frames[f - 1];
+        let next = unwrapped[f - 1].iter().enumerate().map(|(i, prev_unwrapped)| {
+            let mut pos = [0.0; 3];
+            for k in 0..3 {
+                let mut delta = frame[i][k] - previous[i][k];
+                delta -= (delta / box_length).round() * box_length;
+                pos[k] = prev_unwrapped[k] + delta;
+            }
+            pos
+        }).collect();
+        unwrapped.push(next);
+    }
+    unwrapped
+}
+
+// Velocity autocorrelation <v(0) . v(t)> against lag (in frames), averaged over atoms and all
+// time origins. Each atom's velocity components are correlated by FFT, so this costs
+// O(F log F) per atom for F frames instead of O(F^2).
+pub fn vacf(frames: &[Vec<[f64; 3]>]) -> Vec<f64> {
+    let n = frames.first().map_or(0, |frame| frame.len());
+    let total = (0..n).into_par_iter().map(|i| {
+        let mut sum = vec![0.0; frames.len()];
+        for k in 0..3 {
+            let series: Vec<f64> = frames.iter().map(|frame| frame[i][k]).collect();
+            for (s, c) in sum.iter_mut().zip(autocorrelation(&series)) {
+                *s += c;
+            }
+        }
+        sum
+    }).reduce(|| vec![0.0; frames.len()], |a, b| a.iter().zip(&b).map(|(x, y)| x + y).collect());
+    total.into_iter().map(|c| c / n.max(1) as f64).collect()
+}
+
+// Residence correlation functions of the `neighbours` in the first shell (within `shell`) of
+// each of the `centres`, after Impey, Madden and McDonald (J. Phys. Chem. 87, 5071). The
+// continuous function counts pairs that stayed in the shell for the whole lag, forgiving
+// absences of up to `tolerance` frames; the intermittent one counts pairs that are in the
+// shell at both ends. Both are averaged over time origins, normalised to 1 at lag 0 and go up
+// to `max_lag` frames. Also returns the mean number of neighbours in the shell.
+pub fn residence_correlation(
+    frames: &[Vec<[f64; 3]>],
+    box_length: f64,
+    centres: &[usize],
+    neighbours: &[usize],
+    shell: f64,
+    max_lag: usize,
+    tolerance: usize,
+) -> (Vec<f64>, Vec<f64>, f64) {
+    let f = frames.len();
+    let (continuous, intermittent) = centres.par_iter().map(|&i| {
+        let mut continuous = vec![0.0; max_lag + 1];
+        let mut intermittent = vec![0.0; max_lag + 1];
+        for &j in neighbours.iter().filter(|&&j| j != i) {
+            let mut inside: Vec<bool> = frames.iter().map(|frame| minimum_image_distance(&frame[i], &frame[j], box_length) <= shell).collect();
+            if !inside.contains(&true) {
+                continue;
+            }
+            let values: Vec<f64> = inside.iter().map(|&x| if x { 1.0 } else { 0.0 }).collect();
+            for (c, a) in intermittent.iter_mut().zip(autocorrelation(&values)) {
+                *c += a;
+            }
+
+            // Bridge short absences between two stays, then count the origins inside each stay
+            let mut t = 0;
+            while t < f {
+                let gap_start = t;
+                while t < f && !inside[t] {
+                    t += 1;
+                }
+                if gap_start > 0 && t < f && t - gap_start <= tolerance {
+                    inside[gap_start..t].fill(true);
+                }
+                while t < f && inside[t] {
+                    t += 1;
+                }
+            }
+            let mut t = 0;
+            while t < f {
+                let start = t;
+                while t < f && inside[t] {
+                    t += 1;
+                }
+                let length = t - start;
+                for (lag, c) in continuous.iter_mut().enumerate().take(length) {
+                    *c += (length - lag) as f64 / (f - lag) as f64;
+                }
+                t += 1;
+            }
+        }
+        (continuous, intermittent)
+    }).reduce(
+        || (vec![0.0; max_lag + 1], vec![0.0; max_lag + 1]),
+        |a, b| {
+            let add = |x: &[f64], y: &[f64]| x.iter().zip(y).map(|(p, q)| p + q).collect::<Vec<f64>>();
+            (add(&a.0, &b.0), add(&a.1, &b.1))
+        },
+    );
+    let coordination = intermittent[0] / centres.len().max(1) as f64;
+    let normalise = |values: Vec<f64>| {
+        let first = values[0];
+        values.into_iter().map(|v| if first > 0.0 { v / first } else { f64::NAN }).collect()
+    };
+    (normalise(continuous), normalise(intermittent), coordination)
+}
+
+// Autocorrelation <x(t0) x(t0 + lag)> averaged over the time origins available at each lag,
+// for every lag up to the length of the series. Computed by FFT with zero padding, which
+// keeps the correlation from wrapping around.
+pub fn autocorrelation(series: &[f64]) -> Vec<f64> {
+    let n = series.len();
+    if n == 0 {
+        return Vec::new();
+    }
+    let size = (2 * n).next_power_of_two();
+    let mut re = series.to_vec();
+    re.resize(size, 0.0);
+    let mut im = vec![0.0; size];
+    fft(&mut re, &mut im, false);
+    for (r, i) in re.iter_mut().zip(im.iter_mut()) {
+        *r = *r * *r + *i * *i;
+        *i = 0.0;
+    }
+    fft(&mut re, &mut im, true);
+    (0..n).map(|lag| re[lag] / (size * (n - lag)) as f64).collect()
+}
+
+// In-place radix-2 FFT of the complex sequence (re, im), whose length must be a power of two.
+// The inverse transform is left unnormalized.
+fn fft(re: &mut [f64], im: &mut [f64], inverse: bool) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut len = 2;
+    while len <= n {
+        let angle = sign * 2.0 * PI / len as f64;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (w_im, w_re) = (angle * k as f64).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let t_re = re[b] * w_re - im[b] * w_im;
+                let t_im = re[b] * w_im + im[b] * w_re;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+// Running trapezoid integral of samples spaced `dt` apart, starting from 0
+pub fn cumulative_integral(values: &[f64], dt: f64) -> Vec<f64> {
+    let mut total = 0.0;
+    let mut integral = Vec::with_capacity(values.len());
+    for (i, &value) in values.iter().enumerate() {
+        if i > 0 {
+            total += 0.5 * dt * (values[i - 1] + value);
+        }
+        integral.push(total);
+    }
+    integral
+}
+
+pub struct ContactAngle {
+    // Degrees, with the jackknife error over the interface points
+    pub angle: f64,
+    pub error: f64,
+    pub radius: f64,
+    // Height of the fitted circle's centre above the wall surface
+    pub centre_height: f64,
+    // Width of the cap where the circle meets the wall surface
+    pub base_width: f64,
+}
+
+// Contact angle of a cylindrical droplet lying along y on a wall whose surface is at height
+// `surface`. The liquid atoms of all `frames` are centred in x and binned into a density map
+// in the xz plane; the liquid-vapour interface is where the density crosses half the liquid
+// density, and a circle fitted to it above `min_height` gives the angle it meets the surface
+// at. None if too few interface points are found.
+pub fn contact_angle(frames: &[Vec<[f64; 3]>], box_length: f64, surface: f64, bin: f64, min_height: f64) -> Option<ContactAngle> {
+    let nx = ((box_length / bin).floor() as usize).max(2);
+    let nz = ((box_length - surface) / bin).floor().max(1.0) as usize;
+    let (bx, bz) = (box_length / nx as f64, bin);
+    let mut density = vec![vec![0.0; nx]; nz];
+    let volume = bx * bz * box_length * frames.len() as f64;
+    for positions in frames {
+        // Circular mean, so a droplet across the periodic boundary is centred correctly
+        let (mut c, mut s) = (0.0, 0.0);
+        for pos in positions {
+            let angle = 2.0 * PI * pos[0] / box_length;
+            c += angle.cos();
+            s += angle.sin();
+        }
+        let centre = s.atan2(c) * box_length / (2.0 * PI);
+        for pos in positions {
+            let x = (pos[0] - centre + 0.5 * box_length).rem_euclid(box_length);
+            let z = pos[2] - surface;
+            if z < 0.0 {
+                continue;
+            }
+            let (ix, iz) = (((x / bx) as usize).min(nx - 1), (z / bz) as usize);
+            if iz < nz {
+                density[iz][ix] += 1.0 / volume;
+            }
+        }
+    }
+
+    let max = density.iter().flatten().cloned().fold(0.0, f64::max);
+    let bulk: Vec<f64> = density.iter().flatten().cloned().filter(|&d| d > 0.5 * max).collect();
+    let threshold = 0.5 * bulk.iter().sum::<f64>() / bulk.len().max(1) as f64;
+    if threshold <= 0.0 {
+        return None;
+    }
+    // Bin centres relative to the droplet centre and the surface, and the linear crossing
+    // of the threshold between two neighbouring bins
+    let x_of = |ix: usize| (ix as f64 + 0.5) * bx - 0.5 * box_length;
+    let z_of = |iz: usize| (iz as f64 + 0.5) * bz;
+    let crossing = |inside: f64, outside: f64| (inside - threshold) / (inside - outside);
+
+    let mut points = Vec::new();
+    let middle = nx / 2;
+    for (iz, row) in density.iter().enumerate() {
+        if z_of(iz) < min_height || row[middle] < threshold {
+            continue;
+        }
+        // Outwards from the middle to the first bin below the threshold on either side
+        if let Some(ix) = (middle + 1..nx).find(|&ix| row[ix] < threshold) {
+            points.push([x_of(ix - 1) + bx * crossing(row[ix - 1], row[ix]), z_of(iz)]);
+        }
+        if let Some(ix) = (0..middle).rev().find(|&ix| row[ix] < threshold) {
+            points.push([x_of(ix + 1) - bx * crossing(row[ix + 1], row[ix]), z_of(iz)]);
+        }
+    }
+    // Downwards through each column to the top of the liquid, which resolves the flat top of
+    // the cap that the row scans miss
+    let columns: Vec<Vec<f64>> = (0..nx).map(|ix| density.iter().map(|row| row[ix]).collect()).collect();
+    for (ix, column) in columns.iter().enumerate() {
+        if let Some(iz) = (0..nz - 1).rev().find(|&iz| column[iz] >= threshold) {
+            let z = z_of(iz) + bz * crossing(column[iz], column[iz + 1]);
+            if z >= min_height {
+                points.push([x_of(ix), z]);
+            }
+        }
+    }
+    if points.len() < 5 {
+        return None;
+    }
+
+    let angle_of = |circle: [f64; 3]| (-circle[1] / circle[2]).clamp(-1.0, 1.0).acos().to_degrees();
+    let circle = fit_circle(&points)?;
+    let angle = angle_of(circle);
+    let jackknife: Vec<f64> = (0..points.len()).filter_map(|i| {
+        let rest: Vec<[f64; 2]> = points.iter().enumerate().filter(|&(j, _)| j != i).map(|(_, p)| *p).collect();
+        fit_circle(&rest).map(angle_of)
+    }).collect();
+    let m = jackknife.len() as f64;
+    let mean = jackknife.iter().sum::<f64>() / m;
+    let error = ((m - 1.0) / m * jackknife.iter().map(|a| (a - mean).powi(2)).sum::<f64>()).sqrt();
+    let half_base = circle[2] * circle[2] - circle[1] * circle[1];
+    Some(ContactAngle {
+        angle,
+        error,
+        radius: circle[2],
+        centre_height: circle[1],
+        base_width: 2.0 * half_base.max(0.0).sqrt(),
+    })
+}
+
+// Algebraic least-squares circle (Kasa): minimises the residuals of x^2 + z^2 + D x + E z + F
+// and returns the centre and radius
+fn fit_circle(points: &[[f64; 2]]) -> Option<[f64; 3]> {
+    let mut normal = [[0.0; 3]; 3];
+    let mut rhs = [0.0; 3];
+    for p in points {
+        let row = [p[0], p[1], 1.0];
+        let b = -(p[0] * p[0] + p[1] * p[1]);
+        for a in 0..3 {
+            for c in 0..3 {
+                normal[a][c] += row[a] * row[c];
+            }
+            rhs[a] += row[a] * b;
+        }
+    }
+    let inverse = invert3(&normal)?;
+    let solution: Vec<f64> = inverse.iter().map(|row| (0..3).map(|c| row[c] * rhs[c]).sum()).collect();
+    let (xc, zc) = (-0.5 * solution[0], -0.5 * solution[1]);
+    let r_sq = xc * xc + zc * zc - solution[2];
+    (r_sq > 0.0).then(|| [xc, zc, r_sq.sqrt()])
+}
+
+// Clusters of atoms joined by chains of neighbours within `cutoff`, from a union-find over the
+// neighbour pairs. Returns the cluster of every atom, numbered from 0 by decreasing size.
+pub fn clusters(positions: &[[f64; 3]], box_length: f64, cutoff: f64) -> Vec<usize> {
+    let n = positions.len();
+    let cells = CellList::new(positions, box_length, cutoff);
+    let neighbours: Vec<Vec<usize>> = (0..n).into_par_iter().map(|i| {
+        cells.neighbours(positions, i).into_iter().map(|(j, _)| j).filter(|&j| j > i).collect()
+    }).collect();
+
+    let mut parent: Vec<usize> = (0..n).collect();
+    let mut size = vec![1; n];
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            // Path halving
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    for (i, list) in neighbours.iter().enumerate() {
+        for &j in list {
+            let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+            if a != b {
+                let (large, small) = if size[a] >= size[b] { (a, b) } else { (b, a) };
+                parent[small] = large;
+                size[large] += size[small];
+            }
+        }
+    }
+
+    let roots: Vec<usize> = (0..n).map(|i| root(&mut parent, i)).collect();
+    let mut order: Vec<usize> = (0..n).filter(|&i| roots[i] == i).collect();
+    // The sort is stable, so clusters of equal size keep a reproducible numbering
+    order.sort_by_key(|&r| std::cmp::Reverse(size[r]));
+    let mut label = vec![0; n];
+    for (number, &r) in order.iter().enumerate() {
+        label[r] = number;
+    }
+    roots.iter().map(|&r| label[r]).collect()
+}
+
+// Potential energy of each atom, taking half of every pair term within `cutoff`, so the
+// values sum to the total potential energy
+pub fn per_atom_energy(positions: &[[f64; 3]], box_length: f64, cutoff: f64) -> Vec<f64> {
+    positions.par_iter().enumerate().map(|(i, a)| {
+        positions.iter().enumerate()
+            .filter(|&(j, _)| j != i)
+            .map(|(_, b)| minimum_image_distance(a, b, box_length))
+            .filter(|&r| r <= cutoff)
+            .map(|r| 0.5 * lj_potential(r))
+            .sum()
+    }).collect()
+}
+
+// Non-affine squared displacement D2min (Falk & Langer, PRE 57, 7192) of every atom between
+// `reference` and `current`: the residual of the best local affine map of the separation
+// vectors to the neighbours that were within `cutoff` in the reference configuration
+pub fn d2min(reference: &[[f64; 3]], current: &[[f64; 3]], box_length: f64, cutoff: f64) -> Vec<f64> {
+    (0..reference.len()).into_par_iter().map(|i| {
+        let pairs: Vec<([f64; 3], [f64; 3])> = (0..reference.len())
+            .filter(|&j| j != i)
+            .map(|j| {
+                (minimum_image_vector(&reference[j], &reference[i], box_length),
+                 minimum_image_vector(&current[j], &current[i], box_length))
+            })
+            .filter(|(d0, _)| d0.iter().map(|x| x * x).sum::<f64>() <= cutoff * cutoff)
+            .collect();
+
+        // J = X Y^-1 with X = sum d d0^T and Y = sum d0 d0^T
+        let mut x = [[0.0; 3]; 3];
+        let mut y = [[0.0; 3]; 3];
+        for (d0, d) in &pairs {
+            for a in 0..3 {
+                for b in 0..3 {
+                    x[a][b] += d[a] * d0[b];
+                    y[a][b] += d0[a] * d0[b];
+                }
+            }
+        }
+        let y_inverse = match invert3(&y) {
+            Some(inverse) => inverse,
+            // Fewer than three non-coplanar neighbours leave the affine map undetermined
+            None => return f64::NAN,
+        };
+        let mut j = [[0.0; 3]; 3];
+        for a in 0..3 {
+            for b in 0..3 {
+                j[a][b] = (0..3).map(|c| x[a][c] * y_inverse[c][b]).sum();
+            }
+        }
+
+        pairs.iter().map(|(d0, d)| {
+            (0..3).map(|a| {
+                let affine: f64 = (0..3).map(|b| j[a][b] * d0[b]).sum();
+                (d[a] - affine).powi(2)
+            }).sum::<f64>()
+        }).sum()
+    }).collect()
+}
+
+// Least-squares slope of y against x
+pub fn linear_slope(x: &[f64], y: &[f64]) -> f64 {
+    let count = x.len() as f64;
+    let x_mean = x.iter().sum::<f64>() / count;
+    let y_mean = y.iter().sum::<f64>() / count;
+    let covariance: f64 = x.iter().zip(y).map(|(x, y)| (x - x_mean) * (y - y_mean)).sum();
+    let variance: f64 = x.iter().map(|x| (x - x_mean).powi(2)).sum();
+    covariance / variance
+}
+
+// Thermal conductivity in energy/(length time K) converted to reduced LJ units,
+// k* = k sigma^2 sqrt(m / epsilon) / kB with sigma and epsilon 1
+pub fn reduced_conductivity(conductivity: f64) -> f64 {
+    conductivity * MASS_ARGON.sqrt() / KB
+}
+
+// Shear viscosity in energy time/length^3 converted to reduced LJ units,
+// eta* = eta sigma^2 / sqrt(m epsilon) with sigma and epsilon 1
+pub fn reduced_viscosity(viscosity: f64) -> f64 {
+    viscosity / MASS_ARGON.sqrt()
+}
+
+// Mean and standard deviation
+pub fn mean_std(values: &[f64]) -> (f64, f64) {
+    let n = values.len().max(1) as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+    (mean, variance.sqrt())
+}
+
+// Mean of a correlated time series and its standard error from `blocks` consecutive block
+// means, which are close to independent once the blocks are longer than the correlation time.
+// Samples that do not fill the last block are left out.
+pub fn block_average(values: &[f64], blocks: usize) -> (f64, f64) {
+    let size = values.len() / blocks.max(1);
+    if size == 0 || blocks < 2 {
+        return (mean_std(values).0, f64::NAN);
+    }
+    let means: Vec<f64> = values.chunks_exact(size).take(blocks).map(|block| block.iter().sum::<f64>() / size as f64).collect();
+    let (mean, std) = mean_std(&means);
+    (mean, std / (blocks as f64 - 1.0).sqrt())
+}
@@ -0,0 +1,1214 @@
+use std::fs::File;
+use std::io::{BufWriter, Read};
+
+use crate::analysis;
+use crate::cli::Args;
+use crate::compress;
+use crate::droplet;
+use crate::geometry::Cell;
+use crate::input;
+use crate::order;
+use crate::plot::{save_plot, Panel, Series};
+use crate::simulation;
+use crate::table::TableWriter;
+use crate::trajectory;
+use crate::umbrella;
+use crate::xyz;
+
+const USAGE: &str = "Usage: sim analyze <analysis> <input> [options]
+  msd <trajectory.json> [--skip 0] [--output msd.csv] [--plot msd.png|msd.svg]
+  thermo <log.csv> [--plot thermo.png|thermo.svg]
+  vacf <trajectory.json|trajectory.extxyz> [--skip 0] [--max-lag frames/2] [--dt frame interval] [--output vacf.csv] [--plot vacf.png|vacf.svg]
+  thermal-conductivity <heat_current.csv> --box L (--temperature T | --thermo thermo.csv) [--skip 0] [--blocks 5] [--max-lag block/2] [--dt from log] [--output thermal_conductivity.csv] [--plot thermal_conductivity.png|thermal_conductivity.svg]
+  viscosity <thermo.csv> --box L [--temperature from log] [--skip 0] [--blocks 5] [--max-lag block/2] [--dt from log] [--output viscosity.csv] [--plot viscosity.png|viscosity.svg]
+  rdf <trajectory.json|config.xyz|config.data|config.pdb> [--bins 200] [--rmax L/2] [--frames start:end:stride] [--box L] [--output rdf.csv] [--plot rdf.png|rdf.svg]
+  peratom <config.xyz|config.data|config.pdb> [--reference ref.xyz] [--box L] [--cutoff 2.5] [--output peratom.xyz]
+  steinhardt <trajectory|config.xyz|config.data|config.pdb> [--cutoff 1.5] [--frames start:end:stride] [--box L] [--output steinhardt.extxyz] [--summary steinhardt.csv]
+  cna <trajectory|config.xyz|config.data|config.pdb> [--frames start:end:stride] [--box L] [--species-labels] [--output cna.extxyz] [--summary cna.csv]
+  clusters <trajectory|config.xyz|config.data|config.pdb> [--cutoff 1.5] [--min-size 2] [--frames start:end:stride] [--box L] [--output clusters.csv] [--sizes cluster_sizes.csv] [--labels clusters.extxyz] [--plot clusters.png|clusters.svg]
+  distance <trajectory> --pairs 3:17,5:20 [--frames start:end:stride] [--box L] [--output distance.csv] [--plot distance.png|distance.svg]
+  residence <trajectory.json|trajectory.extxyz> [--centers all|species] [--neighbors all|species] [--shell 1.5] [--tolerance 0] [--skip 0] [--max-lag frames/2] [--dt frame interval] [--box L] [--output residence.csv] [--plot residence.png|residence.svg]
+  wham <metadata.txt> [--temperature 87.3] [--bins 50] [--range min:max] [--skip 0] [--tolerance 1e-7] [--max-iterations 100000] [--output pmf.csv] [--plot pmf.png|pmf.svg]
+  contact-angle <droplet.extxyz> [--wall W] [--surface-offset 0.5] [--bin 1.0] [--min-height 2.0] [--skip 0] [--average 1] [--box L] [--output contact_angle.csv] [--plot contact_angle.png|contact_angle.svg]
+A <trajectory> is json, xyz, extxyz, dcd, xtc or lammpstrj; atoms are numbered from 0 in file order.";
+
+pub fn run(args: &[String]) {
+    let args = Args::parse(args);
+    if args.positional.len() != 2 {
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
+    }
+    let input = &args.positional[1];
+    match args.positional[0].as_str() {
+        "msd" => msd(input, &args),
+        "thermo" => thermo(input, &args),
+        "vacf" => vacf(input, &args),
+        "thermal-conductivity" => thermal_conductivity(input, &args),
+        "viscosity" => viscosity(input, &args),
+        "rdf" => rdf(input, &args),
+        "peratom" => peratom(input, &args),
+        "steinhardt" => steinhardt(input, &args),
+        "cna" => cna(input, &args),
+        "clusters" => clusters(input, &args),
+        "distance" => distance(input, &args),
+        "residence" => residence(input, &args),
+        "contact-angle" => contact_angle(input, &args),
+        "wham" => wham(input, &args),
+        other => {
+            eprintln!("Unknown analysis {}\n{}", other, USAGE);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn msd(input: &str, args: &Args) {
+    let skip: usize = args.get("skip", 0);
+    let output: String = args.get("output", "msd.csv".to_string());
+    let plot: Option<String> = args.get_opt("plot");
+
+    let data = trajectory::load_json(input).unwrap_or_else(|e| {
+        eprintln!("Failed to read {}: {}", input, e);
+        std::process::exit(1);
+    });
+    if data.trajectory.len() <= skip {
+        eprintln!("{} has {} frames, cannot skip {}", input, data.trajectory.len(), skip);
+        std::process::exit(1);
+    }
+
+    let box_length = data.cubic_box_length().unwrap_or_else(|e| {
+        eprintln!("{}: {}", input, e);
+        std::process::exit(1);
+    });
+    let msd = analysis::msd(&data.trajectory[skip..], box_length);
+    let points: Vec<(f64, f64)> = msd.iter().enumerate()
+        .map(|(lag, &value)| (lag as f64 * data.frame_interval(), value))
+        .collect();
+
+    write_table(&output, &["time", "msd"], &points);
+    if let Some(plot) = plot {
+        let series = Series { label: input.to_string(), points };
+        let panel = Panel { y_label: "MSD", series: vec![&series] };
+        save_or_exit(&plot, "Mean squared displacement", "time", &[panel]);
+    }
+}
+
+// Time series of every column of a CSV or TSV log against its first column
+fn thermo(input: &str, args: &Args) {
+    let plot: String = match args.get_opt("plot") {
+        Some(plot) => plot,
+        None => {
+            eprintln!("Nothing to do: pass --plot thermo.png|thermo.svg");
+            std::process::exit(1);
+        }
+    };
+
+    let (header, rows) = read_log(input);
+    if header.len() < 2 {
+        eprintln!("{} needs a header and at least two columns", input);
+        std::process::exit(1);
+    }
+
+    let series: Vec<Series> = header[1..].iter().enumerate().map(|(c, name)| Series {
+        label: name.to_string(),
+        points: rows.iter().filter(|row| row.len() == header.len()).map(|row| (row[0], row[c + 1])).collect(),
+    }).collect();
+    let panels: Vec<Panel> = series.iter().zip(&header[1..])
+        .map(|(s, name)| Panel { y_label: name.as_str(), series: vec![s] })
+        .collect();
+    save_or_exit(&plot, input, &header[0], &panels);
+}
+
+// Velocity autocorrelation and the Green-Kubo diffusion coefficient D = 1/3 int <v(0) . v(t)> dt
+// from the velocities stored in a JSON trajectory (`--dump-velocities`) or an extended XYZ one
+fn vacf(input: &str, args: &Args) {
+    let skip: usize = args.get("skip", 0);
+    let output: String = args.get("output", "vacf.csv".to_string());
+    let plot: Option<String> = args.get_opt("plot");
+
+    let (frames, frame_interval) = if compress::inner_extension(input) == "json" {
+        let data = trajectory::load_json(input).unwrap_or_else(|e| {
+            eprintln!("Failed to read {}: {}", input, e);
+            std::process::exit(1);
+        });
+        let frame_interval = data.frame_interval();
+        let Some(velocities) = data.velocities else {
+            eprintln!("{} has no velocities, run with --dump-velocities to store them", input);
+            std::process::exit(1);
+        };
+        (velocities, Some(frame_interval))
+    } else {
+        let frames = xyz::read_frames(input).unwrap_or_else(|e| {
+            eprintln!("Failed to read {}: {}", input, e);
+            std::process::exit(1);
+        });
+        let frame_interval = match (frames.first().and_then(|f| f.time), frames.get(1).and_then(|f| f.time)) {
+            (Some(first), Some(second)) => Some(second - first),
+            _ => None,
+        };
+        let velocities = frames.into_iter().map(|frame| frame.velocities).collect::<Option<Vec<_>>>();
+        let Some(velocities) = velocities else {
+            eprintln!("{} has frames without velocities", input);
+            std::process::exit(1);
+        };
+        (velocities, frame_interval)
+    };
+    let Some(dt) = args.get_opt("dt").or(frame_interval) else {
+        eprintln!("{} does not give the time between frames, pass --dt", input);
+        std::process::exit(1);
+    };
+    if frames.len() <= skip + 1 {
+        eprintln!("{} has {} frames, need at least two after skipping {}", input, frames.len(), skip);
+        std::process::exit(1);
+    }
+    let frames = &frames[skip..];
+    // Longer lags average over too few time origins to be worth integrating
+    let max_lag: usize = args.get("max-lag", frames.len() / 2).clamp(1, frames.len() - 1);
+
+    let mut correlation = analysis::vacf(frames);
+    correlation.truncate(max_lag + 1);
+    let diffusion: Vec<f64> = analysis::cumulative_integral(&correlation, dt).iter().map(|i| i / 3.0).collect();
+    let c0 = correlation[0];
+
+    let mut table = TableWriter::create(&output, &["time", "vacf", "vacf_normalized", "diffusion"]).unwrap_or_else(|e| {
+        eprintln!("Failed to create {}: {}", output, e);
+        std::process::exit(1);
+    });
+    for (lag, (&c, &d)) in correlation.iter().zip(&diffusion).enumerate() {
+        table.write_row(&[lag as f64 * dt, c, c / c0, d]).unwrap();
+    }
+    table.finish().unwrap();
+    println!(
+        "Green-Kubo diffusion coefficient from {} frames, integrated to time {:.3}: {:.6e} length^2/time",
+        frames.len(),
+        max_lag as f64 * dt,
+        diffusion[max_lag]
+    );
+    println!("Results saved to {}", output);
+
+    if let Some(plot) = plot {
+        let times = (0..=max_lag).map(|lag| lag as f64 * dt);
+        let normalized = Series { label: "C(t) / C(0)".to_string(), points: times.clone().zip(correlation.iter().map(|c| c / c0)).collect() };
+        let running = Series { label: "D(t)".to_string(), points: times.zip(diffusion).collect() };
+        let panels = [
+            Panel { y_label: "VACF", series: vec![&normalized] },
+            Panel { y_label: "D (length^2/time)", series: vec![&running] },
+        ];
+        save_or_exit(&plot, "Velocity autocorrelation", "time", &panels);
+    }
+}
+
+// Green-Kubo thermal conductivity k = 1/(3 V kB T^2) int <J(0) . J(t)> dt from the heat current
+// log of the main run (`--heat-current`). The log is cut into `--blocks` consecutive blocks;
+// the spread of their running integrals gives the error bars.
+fn thermal_conductivity(input: &str, args: &Args) {
+    let skip: usize = args.get("skip", 0);
+    let blocks: usize = args.get("blocks", 5);
+    let output: String = args.get("output", "thermal_conductivity.csv".to_string());
+    let plot: Option<String> = args.get_opt("plot");
+    let Some(box_length) = args.get_opt::<f64>("box") else {
+        eprintln!("Pass the box length of the run with --box");
+        std::process::exit(1);
+    };
+    if blocks == 0 {
+        eprintln!("--blocks must be at least 1");
+        std::process::exit(1);
+    }
+    let temperature: f64 = match (args.get_opt("temperature"), args.get_opt::<String>("thermo")) {
+        (Some(temperature), _) => temperature,
+        (None, Some(thermo)) => {
+            let (header, rows) = read_log(&thermo);
+            let Some(column) = header.iter().position(|name| name == "temperature") else {
+                eprintln!("{} has no temperature column", thermo);
+                std::process::exit(1);
+            };
+            let temperatures: Vec<f64> = rows.iter().filter_map(|row| row.get(column).copied()).filter(|t| t.is_finite()).collect();
+            let mean = analysis::mean_std(&temperatures).0;
+            println!("Mean temperature {:.2} K from {}", mean, thermo);
+            mean
+        }
+        (None, None) => {
+            eprintln!("Pass the temperature with --temperature or a thermo log with --thermo");
+            std::process::exit(1);
+        }
+    };
+
+    let (header, rows) = read_log(input);
+    let column = |name: &str| header.iter().position(|c| c == name).unwrap_or_else(|| {
+        eprintln!("{} has no {} column, expected a heat current log", input, name);
+        std::process::exit(1);
+    });
+    let (time, jx, jy, jz) = (column("time"), column("jx"), column("jy"), column("jz"));
+    let rows: Vec<&Vec<f64>> = rows.iter().filter(|row| row.len() == header.len()).skip(skip).collect();
+    let Some(dt) = args.get_opt("dt").or(rows.get(1).map(|row| row[time] - rows[0][time])) else {
+        eprintln!("{} has fewer than two samples after skipping {}", input, skip);
+        std::process::exit(1);
+    };
+    let block_length = rows.len() / blocks;
+    if block_length < 2 {
+        eprintln!("{} samples are too few for {} blocks", rows.len(), blocks);
+        std::process::exit(1);
+    }
+    let max_lag: usize = args.get("max-lag", block_length / 2).clamp(1, block_length - 1);
+
+    // <J(0) . J(t)> and its running integral in every block
+    let volume = box_length.powi(3);
+    let prefactor = 1.0 / (3.0 * volume * simulation::KB * temperature * temperature);
+    let (correlations, integrals) = green_kubo_blocks(&rows, &[jx, jy, jz], block_length, blocks, max_lag, dt, prefactor);
+    let at_lag = |curves: &[Vec<f64>], lag: usize| -> Vec<f64> { curves.iter().map(|curve| curve[lag]).collect() };
+    let error = |values: &[f64]| if blocks > 1 { analysis::mean_std(values).1 / (blocks as f64 - 1.0).sqrt() } else { f64::NAN };
+
+    let mut table = TableWriter::create(&output, &["time", "hcacf", "hcacf_normalized", "conductivity", "conductivity_error"]).unwrap_or_else(|e| {
+        eprintln!("Failed to create {}: {}", output, e);
+        std::process::exit(1);
+    });
+    let c0 = analysis::mean_std(&at_lag(&correlations, 0)).0;
+    let mut points = Vec::with_capacity(max_lag + 1);
+    for lag in 0..=max_lag {
+        let c = analysis::mean_std(&at_lag(&correlations, lag)).0;
+        let k = at_lag(&integrals, lag);
+        let (mean, err) = (analysis::mean_std(&k).0, error(&k));
+        table.write_row(&[lag as f64 * dt, c, c / c0, mean, err]).unwrap();
+        points.push((lag as f64 * dt, c / c0, mean));
+    }
+    table.finish().unwrap();
+
+    let k = at_lag(&integrals, max_lag);
+    let (kappa, kappa_error) = (analysis::mean_std(&k).0, error(&k));
+    println!(
+        "Green-Kubo thermal conductivity from {} blocks of {} samples, integrated to time {:.3}: {:.6e} +- {:.2e} energy/(length time K), reduced {:.4} +- {:.4}",
+        blocks,
+        block_length,
+        max_lag as f64 * dt,
+        kappa,
+        kappa_error,
+        analysis::reduced_conductivity(kappa),
+        analysis::reduced_conductivity(kappa_error)
+    );
+    println!("Results saved to {}", output);
+
+    if let Some(plot) = plot {
+        let normalized = Series { label: "C(t) / C(0)".to_string(), points: points.iter().map(|&(t, c, _)| (t, c)).collect() };
+        let running = Series { label: "k(t)".to_string(), points: points.iter().map(|&(t, _, k)| (t, k)).collect() };
+        let panels = [
+            Panel { y_label: "HCACF", series: vec![&normalized] },
+            Panel { y_label: "k (energy/(length time K))", series: vec![&running] },
+        ];
+        save_or_exit(&plot, "Heat current autocorrelation", "time", &panels);
+    }
+}
+
+// Green-Kubo shear viscosity eta = V / (kB T) int <P_ab(0) P_ab(t)> dt from the pressure tensor
+// columns a main run with --stress writes to its thermo log, averaged over the three
+// off-diagonal components pxy, pxz and pyz. The log is cut into blocks like the heat current
+// for thermal-conductivity, giving the error of the running integral.
+fn viscosity(input: &str, args: &Args) {
+    let skip: usize = args.get("skip", 0);
+    let blocks: usize = args.get("blocks", 5);
+    let output: String = args.get("output", "viscosity.csv".to_string());
+    let plot: Option<String> = args.get_opt("plot");
+    let Some(box_length) = args.get_opt::<f64>("box") else {
+        eprintln!("Pass the box length of the run with --box");
+        std::process::exit(1);
+    };
+    if blocks == 0 {
+        eprintln!("--blocks must be at least 1");
+        std::process::exit(1);
+    }
+
+    let (header, rows) = read_log(input);
+    let column = |name: &str| header.iter().position(|c| c == name).unwrap_or_else(|| {
+        eprintln!("{} has no {} column, expected the thermo log of a run with --stress", input, name);
+        std::process::exit(1);
+    });
+    let (time, pxy, pxz, pyz) = (column("time"), column("pxy"), column("pxz"), column("pyz"));
+    let rows: Vec<&Vec<f64>> = rows.iter().filter(|row| row.len() == header.len()).skip(skip).collect();
+    let temperature: f64 = args.get_opt("temperature").unwrap_or_else(|| {
+        let column = column("temperature");
+        let temperatures: Vec<f64> = rows.iter().map(|row| row[column]).filter(|t| t.is_finite()).collect();
+        let mean = analysis::mean_std(&temperatures).0;
+        println!("Mean temperature {:.2} K from {}", mean, input);
+        mean
+    });
+    let Some(dt) = args.get_opt("dt").or(rows.get(1).map(|row| row[time] - rows[0][time])) else {
+        eprintln!("{} has fewer than two samples after skipping {}", input, skip);
+        std::process::exit(1);
+    };
+    let block_length = rows.len() / blocks;
+    if block_length < 2 {
+        eprintln!("{} samples are too few for {} blocks", rows.len(), blocks);
+        std::process::exit(1);
+    }
+    let max_lag: usize = args.get("max-lag", block_length / 2).clamp(1, block_length - 1);
+
+    // <P_ab(0) P_ab(t)> summed over the components and its running integral in every block
+    let volume = box_length.powi(3);
+    let prefactor = volume / (3.0 * simulation::KB * temperature);
+    let (correlations, integrals) = green_kubo_blocks(&rows, &[pxy, pxz, pyz], block_length, blocks, max_lag, dt, prefactor);
+    let at_lag = |curves: &[Vec<f64>], lag: usize| -> Vec<f64> { curves.iter().map(|curve| curve[lag]).collect() };
+    let error = |values: &[f64]| if blocks > 1 { analysis::mean_std(values).1 / (blocks as f64 - 1.0).sqrt() } else { f64::NAN };
+
+    let mut table = TableWriter::create(&output, &["time", "sacf", "sacf_normalized", "viscosity", "viscosity_error"]).unwrap_or_else(|e| {
+        eprintln!("Failed to create {}: {}", output, e);
+        std::process::exit(1);
+    });
+    let c0 = analysis::mean_std(&at_lag(&correlations, 0)).0;
+    let mut points = Vec::with_capacity(max_lag + 1);
+    for lag in 0..=max_lag {
+        let c = analysis::mean_std(&at_lag(&correlations, lag)).0;
+        let eta = at_lag(&integrals, lag);
+        let (mean, err) = (analysis::mean_std(&eta).0, error(&eta));
+        table.write_row(&[lag as f64 * dt, c / 3.0, c / c0, mean, err]).unwrap();
+        points.push((lag as f64 * dt, c / c0, mean));
+    }
+    table.finish().unwrap();
+
+    let eta = at_lag(&integrals, max_lag);
+    let (viscosity, viscosity_error) = (analysis::mean_std(&eta).0, error(&eta));
+    println!(
+        "Green-Kubo shear viscosity from {} blocks of {} samples, integrated to time {:.3}: {:.6e} +- {:.2e} energy time/length^3, reduced {:.4} +- {:.4}",
+        blocks,
+        block_length,
+        max_lag as f64 * dt,
+        viscosity,
+        viscosity_error,
+        analysis::reduced_viscosity(viscosity),
+        analysis::reduced_viscosity(viscosity_error)
+    );
+    println!("Results saved to {}", output);
+
+    if let Some(plot) = plot {
+        let normalized = Series { label: "C(t) / C(0)".to_string(), points: points.iter().map(|&(t, c, _)| (t, c)).collect() };
+        let running = Series { label: "eta(t)".to_string(), points: points.iter().map(|&(t, _, eta)| (t, eta)).collect() };
+        let panels = [
+            Panel { y_label: "SACF", series: vec![&normalized] },
+            Panel { y_label: "eta (energy time/length^3)", series: vec![&running] },
+        ];
+        save_or_exit(&plot, "Stress autocorrelation", "time", &panels);
+    }
+}
+
+// Autocorrelation of the given columns, summed over them, and its running integral scaled by
+// `prefactor`, in each of `blocks` consecutive blocks of `block_length` rows
+fn green_kubo_blocks(
+    rows: &[&Vec<f64>],
+    columns: &[usize],
+    block_length: usize,
+    blocks: usize,
+    max_lag: usize,
+    dt: f64,
+    prefactor: f64,
+) -> (Vec<Vec<f64>>, Vec<Vec<f64>>) {
+    rows.chunks_exact(block_length).take(blocks).map(|block| {
+        let mut correlation = vec![0.0; max_lag + 1];
+        for &c in columns {
+            let series: Vec<f64> = block.iter().map(|row| row[c]).collect();
+            for (total, value) in correlation.iter_mut().zip(analysis::autocorrelation(&series)) {
+                *total += value;
+            }
+        }
+        let integral = analysis::cumulative_integral(&correlation, dt).iter().map(|i| prefactor * i).collect();
+        (correlation, integral)
+    }).unzip()
+}
+
+// g(r) over the selected frames of a JSON trajectory or of a single configuration. Atoms of a
+// configuration with several elements or types also get a partial g(r) per species pair.
+fn rdf(input: &str, args: &Args) {
+    let bins: usize = args.get("bins", 200);
+    let output: String = args.get("output", "rdf.csv".to_string());
+    let plot: Option<String> = args.get_opt("plot");
+    if bins == 0 {
+        eprintln!("--bins must be at least 1");
+        std::process::exit(1);
+    }
+
+    let (frames, box_length, labels, species) = if compress::inner_extension(input) == "json" {
+        let data = trajectory::load_json(input).unwrap_or_else(|e| {
+            eprintln!("Failed to read {}: {}", input, e);
+            std::process::exit(1);
+        });
+        let box_length = data.cubic_box_length().unwrap_or_else(|e| {
+            eprintln!("{}: {}", input, e);
+            std::process::exit(1);
+        });
+        let frames = select_frames(data.trajectory, args.get_opt("frames"));
+        (frames, box_length, vec!["Ar".to_string()], vec![0; data.num_atoms])
+    } else {
+        let frame = input::read_structure(input).unwrap_or_else(|e| {
+            eprintln!("Failed to read {}: {}", input, e);
+            std::process::exit(1);
+        });
+        let box_length = input::cubic_box_length(input, frame.box_lengths, args.get_opt("box")).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+        let names = frame.species.unwrap_or_else(|| vec!["Ar".to_string(); frame.positions.len()]);
+        let mut labels: Vec<String> = names.clone();
+        labels.sort();
+        labels.dedup();
+        let species = names.iter().map(|name| labels.binary_search(name).unwrap()).collect();
+        let positions = frame.positions.iter().map(|pos| pos.map(|coord| coord.rem_euclid(box_length))).collect();
+        (vec![positions], box_length, labels, species)
+    };
+    if frames.is_empty() {
+        eprintln!("No frames selected from {}", input);
+        std::process::exit(1);
+    }
+    let r_max: f64 = args.get("rmax", 0.5 * box_length);
+    if r_max > 0.5 * box_length {
+        eprintln!("Warning: --rmax {} is beyond half the box, using {}", r_max, 0.5 * box_length);
+    }
+
+    let (r, partials) = analysis::partial_rdf(&frames, &species, box_length, bins, r_max);
+    // Total g(r), the partials weighted by the fractions of both species
+    let n = species.len() as f64;
+    let fraction = |s: usize| species.iter().filter(|&&t| t == s).count() as f64 / n;
+    let total: Vec<f64> = (0..bins).map(|bin| {
+        partials.iter().map(|&((a, b), ref g)| {
+            let weight = if a == b { 1.0 } else { 2.0 } * fraction(a) * fraction(b);
+            weight * g[bin]
+        }).sum()
+    }).collect();
+
+    let mut columns = vec!["r".to_string(), "g".to_string()];
+    if partials.len() > 1 {
+        columns.extend(partials.iter().map(|((a, b), _)| format!("g_{}_{}", labels[*a], labels[*b])));
+    }
+    let column_names: Vec<&str> = columns.iter().map(String::as_str).collect();
+    let mut table = TableWriter::create(&output, &column_names).unwrap_or_else(|e| {
+        eprintln!("Failed to create {}: {}", output, e);
+        std::process::exit(1);
+    });
+    for bin in 0..bins {
+        let mut row = vec![r[bin], total[bin]];
+        if partials.len() > 1 {
+            row.extend(partials.iter().map(|(_, g)| g[bin]));
+        }
+        table.write_row(&row).unwrap();
+    }
+    table.finish().unwrap();
+    println!("g(r) from {} frames saved to {}", frames.len(), output);
+
+    if let Some(plot) = plot {
+        let mut series = vec![Series { label: "g(r)".to_string(), points: r.iter().copied().zip(total).collect() }];
+        if partials.len() > 1 {
+            series.extend(partials.iter().map(|((a, b), g)| Series {
+                label: format!("{}-{}", labels[*a], labels[*b]),
+                points: r.iter().copied().zip(g.iter().copied()).collect(),
+            }));
+        }
+        let panel = Panel { y_label: "g(r)", series: series.iter().collect() };
+        save_or_exit(&plot, "Radial distribution function", "r", &[panel]);
+    }
+}
+
+// Frames picked by a `start:end:stride` selection, Python slice style with every part optional
+fn select_frames<T>(frames: Vec<T>, selection: Option<String>) -> Vec<T> {
+    let Some(selection) = selection else { return frames };
+    let parts: Vec<&str> = selection.split(':').collect();
+    let part = |i: usize| -> Option<usize> {
+        let text = parts.get(i).map_or("", |p| p.trim());
+        if text.is_empty() {
+            return None;
+        }
+        Some(text.parse().unwrap_or_else(|_| {
+            eprintln!("Invalid --frames {}, expected start:end:stride", selection);
+            std::process::exit(1);
+        }))
+    };
+    if parts.len() > 3 {
+        eprintln!("Invalid --frames {}, expected start:end:stride", selection);
+        std::process::exit(1);
+    }
+    let start = part(0).unwrap_or(0);
+    let end = part(1).unwrap_or(frames.len()).min(frames.len());
+    let stride = part(2).unwrap_or(1).max(1);
+    frames.into_iter().enumerate()
+        .filter(|&(i, _)| i >= start && i < end && (i - start) % stride == 0)
+        .map(|(_, frame)| frame)
+        .collect()
+}
+
+// Writes a configuration as extended XYZ with per-atom analysis columns, so OVITO can color
+// atoms by them without any scripting
+fn peratom(input: &str, args: &Args) {
+    let cutoff: f64 = args.get("cutoff", 2.5);
+    let output: String = args.get("output", "peratom.xyz".to_string());
+    let reference_path: Option<String> = args.get_opt("reference");
+
+    let frame = read_or_exit(input);
+    let box_length = input::cubic_box_length(input, frame.box_lengths, args.get_opt("box")).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+
+    let energy = analysis::per_atom_energy(&frame.positions, box_length, cutoff);
+    let mut columns: Vec<(&str, &[f64])> = vec![("energy", &energy)];
+    let d2min;
+    if let Some(reference_path) = &reference_path {
+        let reference = read_or_exit(reference_path);
+        if reference.positions.len() != frame.positions.len() {
+            eprintln!("{} has {} atoms but {} has {}", reference_path, reference.positions.len(), input, frame.positions.len());
+            std::process::exit(1);
+        }
+        d2min = analysis::d2min(&reference.positions, &frame.positions, box_length, cutoff);
+        columns.push(("d2min", &d2min));
+    }
+
+    let mut file = BufWriter::new(File::create(&output).unwrap_or_else(|e| {
+        eprintln!("Failed to create {}: {}", output, e);
+        std::process::exit(1);
+    }));
+    xyz::write_frame_with_columns(&mut file, &frame.positions, frame.species.as_deref(), frame.velocities.as_deref(), &columns, [box_length; 3], "")
+        .expect("Failed to write per-atom output");
+    println!("Per-atom {} saved to {}", columns.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(", "), output);
+}
+
+// Steinhardt q4, q6 and w6 of every atom in every selected frame, written back as extended XYZ
+// with the order parameters as per-atom columns, plus a per-frame summary with the fraction of
+// solid-like atoms for following crystallization
+fn steinhardt(input: &str, args: &Args) {
+    let cutoff: f64 = args.get("cutoff", order::NEIGHBOUR_CUTOFF);
+    let output: String = args.get("output", "steinhardt.extxyz".to_string());
+    let summary: String = args.get("summary", "steinhardt.csv".to_string());
+
+    let frames = read_selected_frames(input, args);
+
+    let mut writer = compress::CompressedWriter::create(&output).unwrap_or_else(|e| {
+        eprintln!("Failed to create {}: {}", output, e);
+        std::process::exit(1);
+    });
+    let mut table = TableWriter::create(&summary, &["frame", "time", "q4", "q6", "w6", "solid_fraction"]).unwrap_or_else(|e| {
+        eprintln!("Failed to create {}: {}", summary, e);
+        std::process::exit(1);
+    });
+    for (index, frame) in &frames {
+        let box_length = input::cubic_box_length(input, frame.box_lengths, args.get_opt("box")).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+        let result = order::bond_order(&frame.positions, box_length, cutoff);
+        let neighbours: Vec<f64> = result.neighbours.iter().map(|&n| n as f64).collect();
+        let solid_bonds: Vec<f64> = result.solid_bonds.iter().map(|&n| n as f64).collect();
+        let columns: [(&str, &[f64]); 5] = [
+            ("q4", &result.q4),
+            ("q6", &result.q6),
+            ("w6", &result.w6),
+            ("neighbours", &neighbours),
+            ("solid_bonds", &solid_bonds),
+        ];
+        let comment = frame.time.map_or(String::new(), |time| format!("Time={}", time));
+        xyz::write_frame_with_columns(&mut writer, &frame.positions, frame.species.as_deref(), frame.velocities.as_deref(), &columns, [box_length; 3], &comment)
+            .expect("Failed to write order parameters");
+        let mean = |values: &[f64]| analysis::mean_std(values).0;
+        table.write_row(&[
+            *index as f64,
+            frame.time.unwrap_or(f64::NAN),
+            mean(&result.q4),
+            mean(&result.q6),
+            mean(&result.w6),
+            result.solid_fraction(),
+        ]).unwrap();
+        if frames.len() == 1 {
+            println!(
+                "Mean q4 {:.4}, q6 {:.4}, w6 {:.5}; {:.1}% of atoms solid-like",
+                mean(&result.q4),
+                mean(&result.q6),
+                mean(&result.w6),
+                100.0 * result.solid_fraction()
+            );
+        }
+    }
+    writer.finish().expect("Failed to write order parameters");
+    table.finish().unwrap();
+    println!("Per-atom order parameters of {} frames saved to {}, averages per frame to {}", frames.len(), output, summary);
+}
+
+// Adaptive common neighbour analysis of every atom in every selected frame, written back as
+// extended XYZ with a `structure` column numbered like OVITO's (0 other, 1 fcc, 2 hcp, 3 bcc,
+// 4 icosahedral), plus the fraction of each structure per frame for following nucleation.
+// With --species-labels the structure names replace the species, so viewers color by them
+// as atom types.
+fn cna(input: &str, args: &Args) {
+    let output: String = args.get("output", "cna.extxyz".to_string());
+    let summary: String = args.get("summary", "cna.csv".to_string());
+    let species_labels = args.has("species-labels");
+
+    let frames = read_selected_frames(input, args);
+    let mut writer = compress::CompressedWriter::create(&output).unwrap_or_else(|e| {
+        eprintln!("Failed to create {}: {}", output, e);
+        std::process::exit(1);
+    });
+    let mut columns = vec!["frame", "time"];
+    let names: Vec<String> = order::Structure::ALL.iter().map(|s| s.name().to_lowercase()).collect();
+    columns.extend(names.iter().map(|name| name.as_str()));
+    let mut table = TableWriter::create(&summary, &columns).unwrap_or_else(|e| {
+        eprintln!("Failed to create {}: {}", summary, e);
+        std::process::exit(1);
+    });
+    for (index, frame) in &frames {
+        let box_length = input::cubic_box_length(input, frame.box_lengths, args.get_opt("box")).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+        let structures = order::adaptive_cna(&frame.positions, box_length);
+        let codes: Vec<f64> = structures.iter().map(|&s| s as i32 as f64).collect();
+        let labels: Option<Vec<String>> = species_labels.then(|| structures.iter().map(|s| s.name().to_string()).collect());
+        let species = labels.as_deref().or(frame.species.as_deref());
+        let comment = frame.time.map_or(String::new(), |time| format!("Time={}", time));
+        xyz::write_frame_with_columns(&mut writer, &frame.positions, species, frame.velocities.as_deref(), &[("structure", &codes)], [box_length; 3], &comment)
+            .expect("Failed to write structure types");
+        let fractions: Vec<f64> = order::Structure::ALL.iter()
+            .map(|&kind| structures.iter().filter(|&&s| s == kind).count() as f64 / structures.len().max(1) as f64)
+            .collect();
+        let mut row = vec![*index as f64, frame.time.unwrap_or(f64::NAN)];
+        row.extend(&fractions);
+        table.write_row(&row).unwrap();
+        if frames.len() == 1 {
+            let parts: Vec<String> = order::Structure::ALL.iter().zip(&fractions)
+                .map(|(kind, fraction)| format!("{} {:.1}%", kind.name(), 100.0 * fraction))
+                .collect();
+            println!("{}", parts.join(", "));
+        }
+    }
+    writer.finish().expect("Failed to write structure types");
+    table.finish().unwrap();
+    println!("Structure types of {} frames saved to {}, fractions per frame to {}", frames.len(), output, summary);
+}
+
+// Clusters of atoms within `--cutoff` of each other, for condensation and aggregation. Per
+// frame: the number of clusters of at least `--min-size` atoms, the largest, the monomers and
+// the weight-average size; `--sizes` gets the full size distribution of every frame.
+fn clusters(input: &str, args: &Args) {
+    let cutoff: f64 = args.get("cutoff", 1.5);
+    let min_size: usize = args.get("min-size", 2);
+    let output: String = args.get("output", "clusters.csv".to_string());
+    let sizes_path: String = args.get("sizes", "cluster_sizes.csv".to_string());
+    let labels: Option<String> = args.get_opt("labels");
+    let plot: Option<String> = args.get_opt("plot");
+    if cutoff <= 0.0 {
+        eprintln!("--cutoff must be positive");
+        std::process::exit(1);
+    }
+
+    let frames = read_selected_frames(input, args);
+    let create = |path: &str, columns: &[&str]| TableWriter::create(path, columns).unwrap_or_else(|e| {
+        eprintln!("Failed to create {}: {}", path, e);
+        std::process::exit(1);
+    });
+    let mut table = create(&output, &["frame", "time", "clusters", "largest", "monomers", "mean_size"]);
+    let mut sizes_table = create(&sizes_path, &["frame", "size", "count"]);
+    let mut writer = labels.as_ref().map(|path| compress::CompressedWriter::create(path).unwrap_or_else(|e| {
+        eprintln!("Failed to create {}: {}", path, e);
+        std::process::exit(1);
+    }));
+    let has_time = frames.iter().all(|(_, frame)| frame.time.is_some());
+    let mut largest_series = Vec::with_capacity(frames.len());
+    let mut count_series = Vec::with_capacity(frames.len());
+    for (index, frame) in &frames {
+        let box_length = input::cubic_box_length(input, frame.box_lengths, args.get_opt("box")).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+        let cluster = analysis::clusters(&frame.positions, box_length, cutoff);
+        let mut sizes = vec![0usize; cluster.iter().max().map_or(0, |&c| c + 1)];
+        for &c in &cluster {
+            sizes[c] += 1;
+        }
+        let largest = sizes.first().copied().unwrap_or(0);
+        let count = sizes.iter().filter(|&&s| s >= min_size).count();
+        let monomers = sizes.iter().filter(|&&s| s == 1).count();
+        // Size of the cluster a randomly picked atom belongs to
+        let mean_size = sizes.iter().map(|&s| (s * s) as f64).sum::<f64>() / frame.positions.len().max(1) as f64;
+        let time = frame.time.unwrap_or(f64::NAN);
+        table.write_row(&[*index as f64, time, count as f64, largest as f64, monomers as f64, mean_size]).unwrap();
+
+        // Sizes come out largest first
+        let mut distribution: Vec<(usize, usize)> = Vec::new();
+        for &s in sizes.iter().rev() {
+            match distribution.last_mut() {
+                Some((size, n)) if *size == s => *n += 1,
+                _ => distribution.push((s, 1)),
+            }
+        }
+        for (size, n) in distribution {
+            sizes_table.write_row(&[*index as f64, size as f64, n as f64]).unwrap();
+        }
+
+        if let Some(writer) = writer.as_mut() {
+            let numbers: Vec<f64> = cluster.iter().map(|&c| c as f64).collect();
+            let comment = frame.time.map_or(String::new(), |time| format!("Time={}", time));
+            xyz::write_frame_with_columns(writer, &frame.positions, frame.species.as_deref(), frame.velocities.as_deref(), &[("cluster", &numbers)], [box_length; 3], &comment)
+                .expect("Failed to write cluster labels");
+        }
+        let x = if has_time { time } else { *index as f64 };
+        largest_series.push((x, largest as f64));
+        count_series.push((x, count as f64));
+        if frames.len() == 1 {
+            println!(
+                "{} clusters of at least {} atoms, largest {} of {} atoms, {} monomers",
+                count,
+                min_size,
+                largest,
+                frame.positions.len(),
+                monomers
+            );
+        }
+    }
+    table.finish().unwrap();
+    sizes_table.finish().unwrap();
+    if let Some(writer) = writer {
+        writer.finish().expect("Failed to write cluster labels");
+    }
+    if frames.len() > 1 {
+        let largest: Vec<f64> = largest_series.iter().map(|&(_, s)| s).collect();
+        let counts: Vec<f64> = count_series.iter().map(|&(_, c)| c).collect();
+        println!(
+            "Over {} frames: {:.2} clusters of at least {} atoms, largest {:.1} atoms on average",
+            frames.len(),
+            analysis::mean_std(&counts).0,
+            min_size,
+            analysis::mean_std(&largest).0
+        );
+    }
+    println!("Results saved to {} and {}", output, sizes_path);
+    if let Some(labels) = labels {
+        println!("Cluster numbers saved to {}", labels);
+    }
+
+    if let Some(plot) = plot {
+        let largest = Series { label: "largest cluster".to_string(), points: largest_series };
+        let count = Series { label: format!("clusters of {}+ atoms", min_size), points: count_series };
+        let panels = [
+            Panel { y_label: "atoms", series: vec![&largest] },
+            Panel { y_label: "clusters", series: vec![&count] },
+        ];
+        save_or_exit(&plot, "Clusters", if has_time { "time" } else { "frame" }, &panels);
+    }
+}
+
+// Time series of the minimum-image distances between the atom pairs of `--pairs i:j,...`,
+// with atoms numbered from 0 in file order (LAMMPS id - 1 for a dump)
+fn distance(input: &str, args: &Args) {
+    let output: String = args.get("output", "distance.csv".to_string());
+    let plot: Option<String> = args.get_opt("plot");
+    let requested: Option<f64> = args.get_opt("box");
+    let Some(pairs) = args.get_opt::<String>("pairs") else {
+        eprintln!("Pass the atom pairs with --pairs i:j,...");
+        std::process::exit(1);
+    };
+    let pairs: Vec<(usize, usize)> = pairs.split(',').map(|pair| {
+        match pair.split_once(':').map(|(i, j)| (i.trim().parse(), j.trim().parse())) {
+            Some((Ok(i), Ok(j))) => (i, j),
+            _ => {
+                eprintln!("Invalid pair {:?} in --pairs, expected i:j", pair);
+                std::process::exit(1);
+            }
+        }
+    }).collect();
+
+    let frames = read_selected_frames(input, args);
+    let largest = pairs.iter().map(|&(i, j)| i.max(j)).max().unwrap();
+    if let Some((index, frame)) = frames.iter().find(|(_, frame)| frame.positions.len() <= largest) {
+        eprintln!("Frame {} of {} has {} atoms, no atom {}", index, input, frame.positions.len(), largest);
+        std::process::exit(1);
+    }
+    let has_time = frames.iter().all(|(_, frame)| frame.time.is_some());
+
+    let names: Vec<String> = pairs.iter().map(|(i, j)| format!("d_{}_{}", i, j)).collect();
+    let mut columns = vec![if has_time { "time" } else { "frame" }];
+    columns.extend(names.iter().map(|name| name.as_str()));
+    let mut table = TableWriter::create(&output, &columns).unwrap_or_else(|e| {
+        eprintln!("Failed to create {}: {}", output, e);
+        std::process::exit(1);
+    });
+    let mut series: Vec<Vec<(f64, f64)>> = vec![Vec::with_capacity(frames.len()); pairs.len()];
+    for (index, frame) in &frames {
+        let cell = match (requested, frame.box_lengths) {
+            (Some(length), _) => Cell::cubic(length),
+            (None, Some(lengths)) => Cell::orthorhombic(lengths),
+            (None, None) => {
+                eprintln!("{} has no box, pass --box", input);
+                std::process::exit(1);
+            }
+        };
+        let x = if has_time { frame.time.unwrap() } else { *index as f64 };
+        let mut row = vec![x];
+        for (points, &(i, j)) in series.iter_mut().zip(&pairs) {
+            let r = cell.distance(&frame.positions[i], &frame.positions[j]);
+            row.push(r);
+            points.push((x, r));
+        }
+        table.write_row(&row).unwrap();
+    }
+    table.finish().unwrap();
+
+    for (name, points) in names.iter().zip(&series) {
+        let distances: Vec<f64> = points.iter().map(|&(_, d)| d).collect();
+        let (mean, std) = analysis::mean_std(&distances);
+        let (min, max) = distances.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &d| (lo.min(d), hi.max(d)));
+        println!("{}: mean {:.4} +- {:.4}, range {:.4} to {:.4}", name, mean, std, min, max);
+    }
+    println!("{} frames, results saved to {}", frames.len(), output);
+
+    if let Some(plot) = plot {
+        let series: Vec<Series> = names.into_iter().zip(series).map(|(label, points)| Series { label, points }).collect();
+        let panels = [Panel { y_label: "distance", series: series.iter().collect() }];
+        save_or_exit(&plot, "Pair distances", if has_time { "time" } else { "frame" }, &panels);
+    }
+}
+
+// How long neighbours stay in the first shell of tagged atoms, from the residence correlation
+// functions over a JSON or extended XYZ trajectory. Centres and neighbours are all atoms or
+// those of one species.
+fn residence(input: &str, args: &Args) {
+    let centres: String = args.get("centers", "all".to_string());
+    let neighbours: String = args.get("neighbors", "all".to_string());
+    let shell: f64 = args.get("shell", 1.5);
+    let tolerance: usize = args.get("tolerance", 0);
+    let skip: usize = args.get("skip", 0);
+    let output: String = args.get("output", "residence.csv".to_string());
+    let plot: Option<String> = args.get_opt("plot");
+    if shell <= 0.0 {
+        eprintln!("--shell must be positive");
+        std::process::exit(1);
+    }
+
+    let (frames, box_length, species, frame_interval) = if compress::inner_extension(input) == "json" {
+        let data = trajectory::load_json(input).unwrap_or_else(|e| {
+            eprintln!("Failed to read {}: {}", input, e);
+            std::process::exit(1);
+        });
+        let frame_interval = data.frame_interval();
+        let box_length = data.cubic_box_length().unwrap_or_else(|e| {
+            eprintln!("{}: {}", input, e);
+            std::process::exit(1);
+        });
+        (data.trajectory, box_length, vec!["Ar".to_string(); data.num_atoms], Some(frame_interval))
+    } else {
+        let frames = xyz::read_frames(input).unwrap_or_else(|e| {
+            eprintln!("Failed to read {}: {}", input, e);
+            std::process::exit(1);
+        });
+        let Some(first) = frames.first() else {
+            eprintln!("{} has no frames", input);
+            std::process::exit(1);
+        };
+        let box_length = input::cubic_box_length(input, first.box_lengths, args.get_opt("box")).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+        let species = first.species.clone().unwrap_or_else(|| vec!["Ar".to_string(); first.positions.len()]);
+        let frame_interval = match (first.time, frames.get(1).and_then(|f| f.time)) {
+            (Some(first), Some(second)) => Some(second - first),
+            _ => None,
+        };
+        if frames.iter().any(|frame| frame.positions.len() != species.len()) {
+            eprintln!("{} has frames with different numbers of atoms", input);
+            std::process::exit(1);
+        }
+        (frames.into_iter().map(|frame| frame.positions).collect(), box_length, species, frame_interval)
+    };
+    let Some(dt) = args.get_opt("dt").or(frame_interval) else {
+        eprintln!("{} does not give the time between frames, pass --dt", input);
+        std::process::exit(1);
+    };
+    if frames.len() <= skip + 1 {
+        eprintln!("{} has {} frames, need at least two after skipping {}", input, frames.len(), skip);
+        std::process::exit(1);
+    }
+    let frames = &frames[skip..];
+    let max_lag: usize = args.get("max-lag", frames.len() / 2).clamp(1, frames.len() - 1);
+    let select = |label: &str| -> Vec<usize> {
+        let selected: Vec<usize> = (0..species.len()).filter(|&i| label == "all" || species[i] == label).collect();
+        if selected.is_empty() {
+            eprintln!("{} has no atoms of species {}", input, label);
+            std::process::exit(1);
+        }
+        selected
+    };
+    let (centres, neighbours) = (select(&centres), select(&neighbours));
+
+    let (continuous, intermittent, coordination) =
+        analysis::residence_correlation(frames, box_length, &centres, &neighbours, shell, max_lag, tolerance);
+    let mut table = TableWriter::create(&output, &["time", "continuous", "intermittent"]).unwrap_or_else(|e| {
+        eprintln!("Failed to create {}: {}", output, e);
+        std::process::exit(1);
+    });
+    for (lag, (&c, &i)) in continuous.iter().zip(&intermittent).enumerate() {
+        table.write_row(&[lag as f64 * dt, c, i]).unwrap();
+    }
+    table.finish().unwrap();
+    // Integrals up to the longest lag, short of the full residence time if C has not decayed
+    let tau_continuous = analysis::cumulative_integral(&continuous, dt)[max_lag];
+    let tau_intermittent = analysis::cumulative_integral(&intermittent, dt)[max_lag];
+    println!(
+        "{:.3} neighbours within {} of {} centres on average over {} frames",
+        coordination,
+        shell,
+        centres.len(),
+        frames.len()
+    );
+    println!(
+        "Residence time integrated to time {:.3}: continuous {:.4}, intermittent {:.4}",
+        max_lag as f64 * dt,
+        tau_continuous,
+        tau_intermittent
+    );
+    if continuous[max_lag] > 0.05 {
+        eprintln!("Warning: the continuous function is still at {:.2} at the longest lag, pass a longer trajectory or --max-lag", continuous[max_lag]);
+    }
+    println!("Results saved to {}", output);
+
+    if let Some(plot) = plot {
+        let times = (0..=max_lag).map(|lag| lag as f64 * dt);
+        let continuous = Series { label: "continuous".to_string(), points: times.clone().zip(continuous).collect() };
+        let intermittent = Series { label: "intermittent".to_string(), points: times.zip(intermittent).collect() };
+        let panels = [Panel { y_label: "C(t)", series: vec![&continuous, &intermittent] }];
+        save_or_exit(&plot, "Shell residence", "time", &panels);
+    }
+}
+
+// Contact angle of a cylindrical droplet on a wall, as set up by `sim droplet`, for every
+// frame or every `--average` consecutive frames of an extended XYZ trajectory. The wall surface
+// is `--surface-offset` above the highest wall atom.
+fn contact_angle(input: &str, args: &Args) {
+    let wall: String = args.get("wall", droplet::WALL_SPECIES.to_string());
+    let offset: f64 = args.get("surface-offset", 0.5);
+    let bin: f64 = args.get("bin", 1.0);
+    let min_height: f64 = args.get("min-height", 2.0);
+    let skip: usize = args.get("skip", 0);
+    let average: usize = args.get("average", 1);
+    let output: String = args.get("output", "contact_angle.csv".to_string());
+    let plot: Option<String> = args.get_opt("plot");
+    if bin <= 0.0 || average == 0 {
+        eprintln!("--bin must be positive and --average at least 1");
+        std::process::exit(1);
+    }
+
+    let frames = xyz::read_frames(input).unwrap_or_else(|e| {
+        eprintln!("Failed to read {}: {}", input, e);
+        std::process::exit(1);
+    });
+    if frames.len() <= skip {
+        eprintln!("{} has {} frames, none left after skipping {}", input, frames.len(), skip);
+        std::process::exit(1);
+    }
+    let box_length = input::cubic_box_length(input, frames[0].box_lengths, args.get_opt("box")).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+
+    let mut table = TableWriter::create(
+        &output,
+        &["time", "contact_angle", "contact_angle_error", "radius", "centre_height", "base_width"],
+    ).unwrap_or_else(|e| {
+        eprintln!("Failed to create {}: {}", output, e);
+        std::process::exit(1);
+    });
+    let mut angles = Vec::new();
+    let mut points = Vec::new();
+    let mut failed = 0;
+    for (block, chunk) in frames[skip..].chunks(average).enumerate() {
+        let mut liquid = Vec::with_capacity(chunk.len());
+        let mut surface = f64::NEG_INFINITY;
+        for frame in chunk {
+            let Some(species) = &frame.species else {
+                eprintln!("{} has frames without species, the wall atoms cannot be told apart", input);
+                std::process::exit(1);
+            };
+            let mut positions = Vec::new();
+            for (pos, name) in frame.positions.iter().zip(species) {
+                if *name == wall {
+                    surface = surface.max(pos[2] + offset);
+                } else {
+                    positions.push(*pos);
+                }
+            }
+            liquid.push(positions);
+        }
+        if !surface.is_finite() {
+            eprintln!("{} has no atoms of the wall species {}, pass --wall", input, wall);
+            std::process::exit(1);
+        }
+        // Time of the middle of the block, or the frame index without a Time key
+        let middle = &chunk[chunk.len() / 2];
+        let time = middle.time.unwrap_or((skip + block * average + chunk.len() / 2) as f64);
+        match analysis::contact_angle(&liquid, box_length, surface, bin, min_height) {
+            Some(fit) => {
+                table.write_row(&[time, fit.angle, fit.error, fit.radius, fit.centre_height, fit.base_width]).unwrap();
+                angles.push(fit.angle);
+                points.push((time, fit.angle));
+            }
+            None => {
+                table.write_row(&[time, f64::NAN, f64::NAN, f64::NAN, f64::NAN, f64::NAN]).unwrap();
+                failed += 1;
+            }
+        }
+    }
+    table.finish().unwrap();
+
+    if angles.is_empty() {
+        eprintln!("No liquid-vapour interface found in {}, check --wall and --min-height", input);
+        std::process::exit(1);
+    }
+    if failed > 0 {
+        eprintln!("Warning: no interface fit in {} of {} samples", failed, failed + angles.len());
+    }
+    // Successive frames are correlated, so the error of the mean comes from block averages
+    let (mean, error) = analysis::block_average(&angles, angles.len().min(10));
+    println!("Contact angle {:.2} +- {:.2} degrees from {} samples", mean, error, angles.len());
+    println!("Results saved to {}", output);
+
+    if let Some(plot) = plot {
+        let series = Series { label: "contact angle".to_string(), points };
+        let panels = [Panel { y_label: "angle (degrees)", series: vec![&series] }];
+        let x_label = if frames[skip].time.is_some() { "time" } else { "frame" };
+        save_or_exit(&plot, "Contact angle", x_label, &panels);
+    }
+}
+
+// The frames of a trajectory in any output format, or a single configuration, selected with `--frames`
+// and paired with their index in the input
+// Free energy profile along a collective variable from umbrella windows, by WHAM over the
+// colvars logs listed in a metadata file written by `sim umbrella`
+fn wham(input: &str, args: &Args) {
+    // The thermostat target of the main run
+    let temperature: f64 = args.get("temperature", 87.3);
+    let bins: usize = args.get("bins", 50);
+    let skip: usize = args.get("skip", 0);
+    let tolerance: f64 = args.get("tolerance", 1e-7);
+    let max_iterations: usize = args.get("max-iterations", 100000);
+    let output: String = args.get("output", "pmf.csv".to_string());
+    let plot: Option<String> = args.get_opt("plot");
+    let windows = umbrella::read_metadata(input).unwrap_or_else(|e| {
+        eprintln!("Failed to read {}: {}", input, e);
+        std::process::exit(1);
+    });
+    if windows.is_empty() || bins == 0 {
+        eprintln!("{} lists no windows, or --bins is 0", input);
+        std::process::exit(1);
+    }
+
+    let samples: Vec<Vec<f64>> = windows.iter().map(|window| {
+        let path = window.path.to_string_lossy();
+        let (header, rows) = read_log(&path);
+        let Some(column) = header.iter().position(|name| *name == window.colvar) else {
+            eprintln!("{} has no column {}", path, window.colvar);
+            std::process::exit(1);
+        };
+        rows.iter().skip(skip).filter_map(|row| row.get(column).copied()).filter(|s| s.is_finite()).collect()
+    }).collect();
+    let all = samples.iter().flatten().copied();
+    let range = match args.get_opt::<String>("range") {
+        Some(text) => match text.split_once(':').map(|(lo, hi)| (lo.parse::<f64>(), hi.parse::<f64>())) {
+            Some((Ok(lo), Ok(hi))) if lo < hi => (lo, hi),
+            _ => {
+                eprintln!("Invalid value for --range: {} (expected min:max)", text);
+                std::process::exit(1);
+            }
+        },
+        // Just past the largest sample, so it falls in the last bin
+        None => {
+            let (lo, hi) = all.fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), s| (lo.min(s), hi.max(s)));
+            (lo, hi + 1e-9 * (hi - lo).max(1.0))
+        }
+    };
+    if !range.0.is_finite() || !range.1.is_finite() {
+        eprintln!("No samples in the windows of {}", input);
+        std::process::exit(1);
+    }
+
+    let biases: Vec<(f64, f64)> = windows.iter().map(|window| (window.centre, window.spring)).collect();
+    let pmf = umbrella::wham(&samples, &biases, range, bins, simulation::KB * temperature, tolerance, max_iterations);
+    if !pmf.converged {
+        eprintln!("Warning: WHAM did not converge to {} in {} iterations", tolerance, max_iterations);
+    }
+    let name = &windows[0].colvar;
+    let mut table = TableWriter::create(&output, &[name.as_str(), "free_energy", "probability"]).unwrap_or_else(|e| {
+        eprintln!("Failed to create {}: {}", output, e);
+        std::process::exit(1);
+    });
+    for b in 0..bins {
+        table.write_row(&[pmf.colvar[b], pmf.free_energy[b], pmf.probability[b]]).unwrap();
+    }
+    table.finish().unwrap();
+    println!(
+        "{} windows, {} samples, {} WHAM iterations; free energy spans {:.4} over {} from {:.4} to {:.4}",
+        windows.len(),
+        samples.iter().map(Vec::len).sum::<usize>(),
+        pmf.iterations,
+        pmf.free_energy.iter().copied().filter(|f| f.is_finite()).fold(0.0, f64::max),
+        name,
+        range.0,
+        range.1
+    );
+    println!("Results saved to {}", output);
+
+    if let Some(plot) = plot {
+        let series = Series {
+            label: "PMF".to_string(),
+            points: pmf.colvar.iter().zip(&pmf.free_energy).filter(|(_, f)| f.is_finite()).map(|(&s, &f)| (s, f)).collect(),
+        };
+        save_or_exit(&plot, "Potential of mean force", name, &[Panel { y_label: "free energy", series: vec![&series] }]);
+    }
+}
+
+fn read_selected_frames(input: &str, args: &Args) -> Vec<(usize, xyz::Frame)> {
+    let frames = input::read_trajectory(input).unwrap_or_else(|e| {
+        eprintln!("Failed to read {}: {}", input, e);
+        std::process::exit(1);
+    });
+    let frames: Vec<(usize, xyz::Frame)> = select_frames(frames.into_iter().enumerate().collect(), args.get_opt("frames"));
+    if frames.is_empty() {
+        eprintln!("No frames selected from {}", input);
+        std::process::exit(1);
+    }
+    frames
+}
+
+// Header and rows of a CSV or TSV log, compressed or not; unparsable values become NaN
+fn read_log(input: &str) -> (Vec<String>, Vec<Vec<f64>>) {
+    let mut contents = String::new();
+    if let Err(e) = compress::open(input).and_then(|mut reader| reader.read_to_string(&mut contents)) {
+        eprintln!("Failed to read {}: {}", input, e);
+        std::process::exit(1);
+    }
+    let mut lines = contents.lines();
+    let first = lines.next().unwrap_or("");
+    let separator = if first.contains('\t') { '\t' } else { ',' };
+    let header: Vec<String> = first.split(separator).map(|name| name.trim().to_string()).collect();
+    let rows = lines.filter(|line| !line.trim().is_empty()).map(|line| {
+        line.split(separator).map(|v| v.trim().parse().unwrap_or(f64::NAN)).collect()
+    }).collect();
+    (header, rows)
+}
+
+fn read_or_exit(path: &str) -> xyz::Frame {
+    input::read_configuration(path).unwrap_or_else(|e| {
+        eprintln!("Failed to read {}: {}", path, e);
+        std::process::exit(1);
+    })
+}
+
+fn write_table(path: &str, columns: &[&str], points: &[(f64, f64)]) {
+    let mut table = TableWriter::create(path, columns).unwrap_or_else(|e| {
+        eprintln!("Failed to create {}: {}", path, e);
+        std::process::exit(1);
+    });
+    for &(x, y) in points {
+        table.write_row(&[x, y]).unwrap();
+    }
+    table.finish().unwrap();
+    println!("Results saved to {}", path);
+}
+
+fn save_or_exit(path: &str, title: &str, x_label: &str, panels: &[Panel]) {
+    match save_plot(path, title, x_label, panels) {
+        Ok(()) => println!("Plot saved to {}", path),
+        Err(e) => {
+            eprintln!("Failed to plot {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+}
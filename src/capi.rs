@@ -0,0 +1,310 @@
+// C ABI over `Simulation`, behind the `capi` feature, for embedding the engine in C, C++ and
+// Fortran codes. A simulation lives behind an opaque handle from `sim_create` until
+// `sim_destroy`. Arrays cross the boundary as flat doubles, x y z per atom. Functions that can
+// fail return one of the SIM_* status codes; a panic inside the engine is caught and reported as
+// SIM_PANIC instead of unwinding into foreign code. `include/sim.h` is generated from this file by
+// cbindgen when the crate is built with the feature.
+use std::ffi::{c_char, CStr};
+use std::panic::{self, AssertUnwindSafe};
+use std::slice;
+
+use crate::simulation::{BerendsenThermostat, LangevinThermostat, SaveState, Simulation};
+
+/// Version of this interface, raised whenever a function changes in an incompatible way
+pub const SIM_ABI_VERSION: u32 = 1;
+
+/// Success
+pub const SIM_OK: i32 = 0;
+/// A handle or array pointer was null
+pub const SIM_NULL_POINTER: i32 = -1;
+/// An argument was out of range, or an array had the wrong length
+pub const SIM_INVALID_ARGUMENT: i32 = -2;
+/// The engine panicked; the simulation should be destroyed
+pub const SIM_PANIC: i32 = -3;
+/// A state file could not be written
+pub const SIM_IO_ERROR: i32 = -4;
+
+/// Opaque simulation handle
+pub struct SimHandle {
+    sim: Simulation,
+}
+
+/// Version of the interface the library was built with, to compare with SIM_ABI_VERSION
+#[no_mangle]
+pub extern "C" fn sim_abi_version() -> u32 {
+    SIM_ABI_VERSION
+}
+
+/// Creates a Lennard-Jones simulation of `num_atoms` argon atoms in a periodic cubic box, in
+/// the units of the `sim` command (sigma = epsilon = 1, engine time units). `velocities` may be null for atoms
+/// at rest. Returns null if an argument is invalid or the engine fails.
+///
+/// # Safety
+///
+/// `positions`, and `velocities` unless null, must point to `3 * num_atoms` doubles.
+#[no_mangle]
+pub unsafe extern "C" fn sim_create(
+    num_atoms: usize,
+    positions: *const f64,
+    velocities: *const f64,
+    box_length: f64,
+    timestep: f64,
+    cutoff: f64,
+) -> *mut SimHandle {
+    if positions.is_null() || !(box_length > 0.0 && timestep > 0.0 && cutoff > 0.0) {
+        return std::ptr::null_mut();
+    }
+    let rows = |data: *const f64| -> Vec<[f64; 3]> {
+        slice::from_raw_parts(data, 3 * num_atoms).chunks_exact(3).map(|row| [row[0], row[1], row[2]]).collect()
+    };
+    let positions = rows(positions);
+    let velocities = if velocities.is_null() { vec![[0.0; 3]; num_atoms] } else { rows(velocities) };
+    match panic::catch_unwind(|| Simulation::new(positions, velocities, box_length, timestep, cutoff)) {
+        Ok(sim) => Box::into_raw(Box::new(SimHandle { sim })),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Frees a simulation. Null is ignored.
+///
+/// # Safety
+///
+/// `handle` must come from `sim_create` and not be used again.
+#[no_mangle]
+pub unsafe extern "C" fn sim_destroy(handle: *mut SimHandle) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+// Runs `f` on the simulation behind `handle`, turning a null handle and panics into status codes
+unsafe fn with_sim(handle: *mut SimHandle, f: impl FnOnce(&mut Simulation) -> i32) -> i32 {
+    let Some(handle) = handle.as_mut() else {
+        return SIM_NULL_POINTER;
+    };
+    panic::catch_unwind(AssertUnwindSafe(|| f(&mut handle.sim))).unwrap_or(SIM_PANIC)
+}
+
+/// Sets the timestep in engine time units.
+///
+/// # Safety
+///
+/// `handle` must come from `sim_create`.
+#[no_mangle]
+pub unsafe extern "C" fn sim_set_timestep(handle: *mut SimHandle, timestep: f64) -> i32 {
+    if timestep <= 0.0 || timestep.is_nan() {
+        return SIM_INVALID_ARGUMENT;
+    }
+    with_sim(handle, |sim| {
+        sim.dt = timestep;
+        SIM_OK
+    })
+}
+
+/// Sets the interaction cutoff, cut back to half the box as in the engine.
+///
+/// # Safety
+///
+/// `handle` must come from `sim_create`.
+#[no_mangle]
+pub unsafe extern "C" fn sim_set_cutoff(handle: *mut SimHandle, cutoff: f64) -> i32 {
+    if cutoff <= 0.0 || cutoff.is_nan() {
+        return SIM_INVALID_ARGUMENT;
+    }
+    with_sim(handle, |sim| {
+        sim.set_cutoff(cutoff);
+        SIM_OK
+    })
+}
+
+/// Couples the temperature to `temperature` (K) with a Berendsen thermostat of time constant
+/// `tau` (engine time units). A `tau` of zero removes the thermostat.
+///
+/// # Safety
+///
+/// `handle` must come from `sim_create`.
+#[no_mangle]
+pub unsafe extern "C" fn sim_set_berendsen(handle: *mut SimHandle, temperature: f64, tau: f64) -> i32 {
+    if !(temperature > 0.0 && tau >= 0.0) {
+        return SIM_INVALID_ARGUMENT;
+    }
+    with_sim(handle, |sim| {
+        sim.thermostat = (tau > 0.0).then_some(BerendsenThermostat { target_temperature: temperature, tau });
+        SIM_OK
+    })
+}
+
+/// Adds a Langevin thermostat at `temperature` (K) with collision frequency `friction` (per engine time unit)
+/// and noise from `seed`. A `friction` of zero removes it.
+///
+/// # Safety
+///
+/// `handle` must come from `sim_create`.
+#[no_mangle]
+pub unsafe extern "C" fn sim_set_langevin(handle: *mut SimHandle, temperature: f64, friction: f64, seed: u64) -> i32 {
+    if !(temperature > 0.0 && friction >= 0.0) {
+        return SIM_INVALID_ARGUMENT;
+    }
+    with_sim(handle, |sim| {
+        sim.langevin = (friction > 0.0).then_some(LangevinThermostat { target_temperature: temperature, friction, seed });
+        SIM_OK
+    })
+}
+
+/// Advances the simulation by `steps` velocity Verlet steps.
+///
+/// # Safety
+///
+/// `handle` must come from `sim_create`.
+#[no_mangle]
+pub unsafe extern "C" fn sim_step(handle: *mut SimHandle, steps: u64) -> i32 {
+    with_sim(handle, |sim| {
+        for _ in 0..steps {
+            sim.step();
+        }
+        SIM_OK
+    })
+}
+
+/// Number of atoms, or 0 for a null handle.
+///
+/// # Safety
+///
+/// `handle` must come from `sim_create`.
+#[no_mangle]
+pub unsafe extern "C" fn sim_num_atoms(handle: *const SimHandle) -> usize {
+    handle.as_ref().map_or(0, |handle| handle.sim.num_atoms())
+}
+
+// Copies `rows` into the `len` doubles at `out`, which must hold exactly all of them
+unsafe fn copy_rows(rows: &[[f64; 3]], out: *mut f64, len: usize) -> i32 {
+    if out.is_null() {
+        return SIM_NULL_POINTER;
+    }
+    if len != 3 * rows.len() {
+        return SIM_INVALID_ARGUMENT;
+    }
+    slice::from_raw_parts_mut(out, len).copy_from_slice(rows.as_flattened());
+    SIM_OK
+}
+
+/// Copies the positions into `out`, `len` = 3 * sim_num_atoms doubles.
+///
+/// # Safety
+///
+/// `handle` must come from `sim_create` and `out` must point to `len` writable doubles.
+#[no_mangle]
+pub unsafe extern "C" fn sim_get_positions(handle: *const SimHandle, out: *mut f64, len: usize) -> i32 {
+    match handle.as_ref() {
+        Some(handle) => copy_rows(handle.sim.positions(), out, len),
+        None => SIM_NULL_POINTER,
+    }
+}
+
+/// Copies the velocities into `out`, `len` = 3 * sim_num_atoms doubles.
+///
+/// # Safety
+///
+/// `handle` must come from `sim_create` and `out` must point to `len` writable doubles.
+#[no_mangle]
+pub unsafe extern "C" fn sim_get_velocities(handle: *const SimHandle, out: *mut f64, len: usize) -> i32 {
+    match handle.as_ref() {
+        Some(handle) => copy_rows(handle.sim.velocities(), out, len),
+        None => SIM_NULL_POINTER,
+    }
+}
+
+/// Copies the forces into `out`, `len` = 3 * sim_num_atoms doubles.
+///
+/// # Safety
+///
+/// `handle` must come from `sim_create` and `out` must point to `len` writable doubles.
+#[no_mangle]
+pub unsafe extern "C" fn sim_get_forces(handle: *const SimHandle, out: *mut f64, len: usize) -> i32 {
+    match handle.as_ref() {
+        Some(handle) => copy_rows(handle.sim.forces(), out, len),
+        None => SIM_NULL_POINTER,
+    }
+}
+
+/// Thermodynamic state of a simulation
+#[repr(C)]
+pub struct SimThermo {
+    /// Simulated time, engine time units
+    pub time: f64,
+    /// K
+    pub temperature: f64,
+    pub kinetic_energy: f64,
+    /// Inside the cutoff
+    pub potential_energy: f64,
+    /// Inside the cutoff
+    pub pressure: f64,
+}
+
+/// Fills `out` with the current time, temperature, energies and pressure.
+///
+/// # Safety
+///
+/// `handle` must come from `sim_create` and `out` must point to a writable SimThermo.
+#[no_mangle]
+pub unsafe extern "C" fn sim_get_thermo(handle: *const SimHandle, out: *mut SimThermo) -> i32 {
+    let (Some(handle), Some(out)) = (handle.as_ref(), out.as_mut()) else {
+        return SIM_NULL_POINTER;
+    };
+    let sim = &handle.sim;
+    *out = SimThermo {
+        time: sim.time(),
+        temperature: sim.temperature(),
+        kinetic_energy: sim.kinetic_energy(),
+        potential_energy: sim.potential_energy(),
+        pressure: sim.pressure(),
+    };
+    SIM_OK
+}
+
+/// Saves the full state of a simulation (configuration, step, couplings and their seeds) as JSON
+/// to the UTF-8 path `path`, for `sim_load_state`.
+///
+/// # Safety
+///
+/// `handle` must come from `sim_create` or `sim_load_state` and `path` must be a NUL-terminated
+/// string.
+#[no_mangle]
+pub unsafe extern "C" fn sim_save_state(handle: *const SimHandle, path: *const c_char) -> i32 {
+    let Some(handle) = handle.as_ref() else {
+        return SIM_NULL_POINTER;
+    };
+    if path.is_null() {
+        return SIM_NULL_POINTER;
+    }
+    let Ok(path) = CStr::from_ptr(path).to_str() else {
+        return SIM_INVALID_ARGUMENT;
+    };
+    match handle.sim.save_state().write_json(path) {
+        Ok(()) => SIM_OK,
+        Err(_) => SIM_IO_ERROR,
+    }
+}
+
+/// Creates a simulation from a state saved by `sim_save_state` or `SaveState::write_json`; it
+/// continues exactly as the saved one would have. Returns null if the file cannot be read.
+///
+/// # Safety
+///
+/// `path` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn sim_load_state(path: *const c_char) -> *mut SimHandle {
+    if path.is_null() {
+        return std::ptr::null_mut();
+    }
+    let Ok(path) = CStr::from_ptr(path).to_str() else {
+        return std::ptr::null_mut();
+    };
+    let Ok(state) = SaveState::read_json(path) else {
+        return std::ptr::null_mut();
+    };
+    match panic::catch_unwind(|| Simulation::from_state(state)) {
+        Ok(sim) => Box::into_raw(Box::new(SimHandle { sim })),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
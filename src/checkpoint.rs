@@ -0,0 +1,188 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use crate::cli;
+use crate::colvars::Colvar;
+use crate::equilibration::EquilibrationDetector;
+use crate::geometry::Boundary;
+use crate::lammps::DumpColumns;
+use crate::metadynamics::Metadynamics;
+use crate::monitor::{Anomaly, MonitorSettings};
+use crate::profile::Profile;
+use crate::regions::Region;
+use crate::restraints::Restraint;
+use crate::steering::Pull;
+use crate::units::Units;
+use crate::walls::Wall;
+
+// Start of every checkpoint file, with the layout version in the last byte
+const MAGIC: &[u8; 8] = b"SIMCKPTP";
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub enum CheckpointSchedule {
+    Never,
+    // Every this many steps
+    Steps(usize),
+    // Once this much wall-clock time has passed since the last checkpoint, for batch jobs
+    WallSeconds(f64),
+}
+
+impl FromStr for CheckpointSchedule {
+    type Err = String;
+
+    // A step count (`10000`, 0 for never) or a duration (`15m`)
+    fn from_str(text: &str) -> Result<Self, String> {
+        match text.parse::<usize>() {
+            Ok(0) => Ok(CheckpointSchedule::Never),
+            Ok(steps) => Ok(CheckpointSchedule::Steps(steps)),
+            Err(_) => cli::parse_duration(text)
+                .map(|duration| CheckpointSchedule::WallSeconds(duration.as_secs_f64()))
+                .ok_or_else(|| format!("{} is neither a step count nor a duration", text)),
+        }
+    }
+}
+
+// Complete state of a main run before `step`: its parameters, the Verlet state, the running
+// averages and how much trajectory output belongs to the steps already taken. Random numbers
+// are only drawn while setting up the run, so there is no generator state to keep.
+#[derive(Serialize, Deserialize)]
+pub struct Checkpoint {
+    // Box edges along x, y and z
+    pub box_lengths: [f64; 3],
+    pub boundaries: [Boundary; 3],
+    pub walls: Vec<Wall>,
+    // Atoms that never move, when there are any
+    pub frozen: Option<Vec<bool>>,
+    // Constant force on every mobile atom
+    pub body_force: [f64; 3],
+    pub restraints: Vec<Restraint>,
+    // Keeps atoms out of a region, possibly moving, and in another one
+    pub indenter: Option<Region>,
+    pub container: Option<Region>,
+    // Steered group, with the work done on it so far
+    pub pull: Option<Pull>,
+    pub timestep: f64,
+    pub total_steps: usize,
+    pub snapshot_interval: usize,
+    pub target_temperature: f64,
+    pub tau: f64,
+    pub remove_com_every: usize,
+    // Whether the kinetic energy is summed in a fixed order, for runs that are bitwise the same
+    // on any number of threads
+    pub deterministic: bool,
+    // Whether the pair kernel works in f32, with the energies and virials still summed in f64
+    pub single_precision: bool,
+    pub init_from: Option<String>,
+    // Seed of the initial placement and velocities, or of the velocities of a fork branch;
+    // None when they were replayed
+    pub seed: Option<u64>,
+    // Units the outputs are written in
+    pub units: Units,
+    pub output_format: String,
+    pub output_path: String,
+    pub dump_columns: DumpColumns,
+    // Thermodynamic log every this many steps, 0 for none
+    pub thermo_every: usize,
+    pub thermo_path: String,
+    // Whether the pressure tensor is computed
+    pub stress: bool,
+    // Heat current log every this many steps, 0 for none
+    pub heat_current_every: usize,
+    pub heat_current_path: String,
+    // Collective variables, biased or not, logged every this many steps, 0 for none
+    pub colvars: Vec<Colvar>,
+    pub colvars_every: usize,
+    pub colvars_path: String,
+    // Metadynamics on some of the colvars, with the hills dropped so far, logged to `hills_path`;
+    // the free energy estimate goes to `fes_path` every `fes_every` steps and at the end
+    pub metadynamics: Option<Metadynamics>,
+    pub hills_path: String,
+    pub fes_path: String,
+    pub fes_every: usize,
+    pub fes_bins: usize,
+    // Density and temperature profile sampled every this many steps, 0 for none, with the
+    // sums accumulated so far
+    pub profile_every: usize,
+    pub profile_path: String,
+    pub profile: Option<Profile>,
+    // Rhai hooks called every `script_every` steps, their observables logged to `script_log_path`
+    pub script: Option<String>,
+    pub script_every: usize,
+    pub script_log_path: String,
+    pub monitor: Option<MonitorSettings>,
+    pub equilibration: Option<EquilibrationDetector>,
+    pub status_path: Option<String>,
+    pub checkpoint_every: CheckpointSchedule,
+    pub checkpoint_path: String,
+    // Next step to run
+    pub step: usize,
+    pub positions: Vec<[f64; 3]>,
+    pub positions_old: Vec<[f64; 3]>,
+    pub velocities: Vec<[f64; 3]>,
+    pub temperature_sum: f64,
+    // Step the mean temperature counts from, moved to the detected equilibration with
+    // `--auto-production`
+    pub statistics_from: usize,
+    pub final_temperature: f64,
+    // Length of the trajectory file after the last frame before `step`
+    pub trajectory_bytes: u64,
+    // Same for the thermodynamic, heat current, colvars, hills and script logs
+    pub thermo_bytes: u64,
+    pub heat_current_bytes: u64,
+    pub colvars_bytes: u64,
+    pub hills_bytes: u64,
+    pub script_bytes: u64,
+    // Pressure and pressure tensor of the frames written so far, which the JSON trajectory
+    // stores after its frames
+    pub frame_pressure: Vec<f64>,
+    pub frame_stress: Vec<[f64; 6]>,
+    // Everything the monitor reported so far
+    pub anomalies: Vec<Anomaly>,
+    pub started_at: SystemTime,
+    // Wall-clock time spent on the run before this checkpoint
+    pub wall_seconds: f64,
+}
+
+impl Checkpoint {
+    pub fn num_atoms(&self) -> usize {
+        self.positions.len()
+    }
+
+    // Trajectory frames taken before `step`; none when the run writes no frames
+    pub fn frames_written(&self) -> usize {
+        if self.snapshot_interval == 0 { 0 } else { self.step.div_ceil(self.snapshot_interval) }
+    }
+
+    // Written next to `path` and renamed over it, so an interruption while writing leaves the
+    // previous checkpoint intact
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        {
+            let mut writer = BufWriter::new(File::create(&temporary)?);
+            writer.write_all(MAGIC)?;
+            writer.write_all(&postcard::to_stdvec(self).map_err(io::Error::other)?)?;
+            writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        }
+        fs::rename(&temporary, path)
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        BufReader::new(File::open(path)?).read_to_end(&mut bytes)?;
+        let Some(payload) = bytes.strip_prefix(MAGIC.as_slice()) else {
+            let message = if bytes.starts_with(&MAGIC[..7]) {
+                "checkpoint written by a different version of sim"
+            } else {
+                "not a sim checkpoint"
+            };
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        };
+        postcard::from_bytes(payload).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::forces::{cutoff_for_accuracy, LennardJones};
+
+// Command line arguments split into positional values and `--name value` options.
+// An option without a value (followed by another option or nothing) is stored as an empty string.
+pub struct Args {
+    pub positional: Vec<String>,
+    options: HashMap<String, String>,
+}
+
+impl Args {
+    pub fn parse(args: &[String]) -> Self {
+        let mut positional = Vec::new();
+        let mut options = HashMap::new();
+        let mut iter = args.iter().peekable();
+        while let Some(arg) = iter.next() {
+            if let Some(name) = arg.strip_prefix("--") {
+                let value = match iter.peek() {
+                    Some(next) if !next.starts_with("--") => iter.next().unwrap().clone(),
+                    _ => String::new(),
+                };
+                options.insert(name.to_string(), value);
+            } else {
+                positional.push(arg.clone());
+            }
+        }
+        Args { positional, options }
+    }
+
+    pub fn has(&self, name: &str) -> bool {
+        self.options.contains_key(name)
+    }
+
+    pub fn get_opt<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.options.get(name).map(|value| parse_or_exit(name, value))
+    }
+
+    pub fn get<T: FromStr>(&self, name: &str, default: T) -> T {
+        self.get_opt(name).unwrap_or(default)
+    }
+
+    // Wall-clock duration such as `--max-walltime 47h30m`
+    pub fn get_duration(&self, name: &str) -> Option<Duration> {
+        let value = self.options.get(name)?;
+        Some(parse_duration(value).unwrap_or_else(|| {
+            eprintln!("Invalid duration for --{}: {} (expected e.g. 90s, 15m or 47h30m)", name, value);
+            std::process::exit(1);
+        }))
+    }
+
+    // Comma separated list, e.g. `--rates 1.0,0.1,0.01`
+    pub fn get_list<T: FromStr>(&self, name: &str, default: Vec<T>) -> Vec<T> {
+        match self.options.get(name) {
+            Some(value) => value.split(',').map(|item| parse_or_exit(name, item.trim())).collect(),
+            None => default,
+        }
+    }
+}
+
+// Durations written as numbers with d, h, m or s units, e.g. `15m`, `1h30m` or `2d`
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    let mut seconds = 0.0;
+    let mut number = String::new();
+    for c in text.trim().chars() {
+        if c.is_ascii_digit() || c == '.' {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            'd' => 86400.0,
+            'h' => 3600.0,
+            'm' => 60.0,
+            's' => 1.0,
+            _ => return None,
+        };
+        seconds += number.parse::<f64>().ok()? * unit;
+        number.clear();
+    }
+    // A trailing number without a unit is ambiguous
+    if !number.is_empty() || seconds <= 0.0 {
+        return None;
+    }
+    Some(Duration::from_secs_f64(seconds))
+}
+
+// Box edges written as one length for a cubic box or as `Lx,Ly,Lz`, all positive
+pub fn parse_box_lengths(text: &str) -> Option<[f64; 3]> {
+    let lengths: Vec<f64> = text.split(',').map(|item| item.trim().parse().ok()).collect::<Option<_>>()?;
+    let lengths = match lengths[..] {
+        [l] => [l; 3],
+        [lx, ly, lz] => [lx, ly, lz],
+        _ => return None,
+    };
+    lengths.iter().all(|&l| l > 0.0 && l.is_finite()).then_some(lengths)
+}
+
+// Atom indices written as a comma separated list of indices and inclusive ranges, e.g. `0-99,150`
+pub fn parse_indices(text: &str) -> Option<Vec<usize>> {
+    let mut indices = Vec::new();
+    for item in text.split(',') {
+        match item.trim().split_once('-') {
+            Some((first, last)) => {
+                let (first, last): (usize, usize) = (first.trim().parse().ok()?, last.trim().parse().ok()?);
+                if first > last {
+                    return None;
+                }
+                indices.extend(first..=last);
+            }
+            None => indices.push(item.trim().parse().ok()?),
+        }
+    }
+    Some(indices)
+}
+
+// `--cutoff`, or with `--cutoff-accuracy` the shortest cutoff whose estimated Lennard-Jones
+// truncation error stays below that fraction of epsilon/sigma for `num_atoms` in a cubic box
+pub fn cutoff_from_args(args: &Args, default: f64, num_atoms: usize, box_length: f64) -> f64 {
+    let Some(accuracy) = args.get_opt::<f64>("cutoff-accuracy") else {
+        return args.get("cutoff", default);
+    };
+    if args.has("cutoff") {
+        eprintln!("--cutoff and --cutoff-accuracy are mutually exclusive");
+        std::process::exit(1);
+    }
+    if !(accuracy > 0.0 && accuracy < 1.0) {
+        eprintln!("--cutoff-accuracy must be between 0 and 1");
+        std::process::exit(1);
+    }
+    let density = num_atoms as f64 / box_length.powi(3);
+    let cutoff = cutoff_for_accuracy(&LennardJones::default(), density, accuracy);
+    println!("Cutoff {:.3} for a relative force accuracy of {:e} at density {:.4}", cutoff, accuracy, density);
+    if cutoff > 0.5 * box_length {
+        eprintln!(
+            "Warning: the box only allows a cutoff of {:.3}, use more atoms to reach this accuracy",
+            0.5 * box_length
+        );
+    }
+    cutoff
+}
+
+// `--threads N` for any command: sizes the global rayon pool, which otherwise starts a thread
+// per core (or RAYON_NUM_THREADS). The calling thread counts as one of the N and runs parallel
+// loops itself, so with `--threads 1` no worker threads are started and everything runs serially
+// in the main thread, without handing work between threads.
+pub fn configure_threads(args: &Args) {
+    let Some(threads) = args.get_opt::<usize>("threads") else {
+        return;
+    };
+    if threads == 0 {
+        eprintln!("--threads must be at least 1");
+        std::process::exit(1);
+    }
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .use_current_thread()
+        .build_global()
+        .expect("Failed to set up the thread pool");
+}
+
+fn parse_or_exit<T: FromStr>(name: &str, value: &str) -> T {
+    value.parse().unwrap_or_else(|_| {
+        eprintln!("Invalid value for --{}: {}", name, value);
+        std::process::exit(1);
+    })
+}
@@ -0,0 +1,207 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::cli;
+use crate::steering::group_com;
+
+// Collective variables s(r) of the atom positions, each optionally biased by a harmonic
+// potential E = k/2 (s - s0)^2, read from a TOML file of `[[colvar]]` tables:
+//
+//   [[colvar]]
+//   name = "gap"
+//   kind = "com_distance"      # distance, com_distance or coordination
+//   group1 = "0-9"
+//   group2 = "10-19"
+//   r0 = 1.5                   # coordination only
+//   bias_centre = 3.0          # optional harmonic bias
+//   bias_k = 50.0
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ColvarFile {
+    colvar: Vec<ColvarConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ColvarConfig {
+    name: String,
+    kind: String,
+    group1: String,
+    group2: String,
+    r0: Option<f64>,
+    bias_centre: Option<f64>,
+    bias_k: Option<f64>,
+}
+
+pub const KINDS: [&str; 3] = ["distance", "com_distance", "coordination"];
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ColvarKind {
+    // Distance between two atoms
+    Distance(usize, usize),
+    // Distance between the centres of mass of two groups
+    ComDistance(Vec<usize>, Vec<usize>),
+    // Number of pairs between two groups closer than about r0, with each pair counted through
+    // the smooth switching function 1 / (1 + (r / r0)^6)
+    Coordination { group1: Vec<usize>, group2: Vec<usize>, r0: f64 },
+}
+
+// Harmonic bias E = k/2 (s - centre)^2
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Bias {
+    pub centre: f64,
+    pub spring: f64,
+}
+
+// Derivatives of a colvar with respect to the positions of the atoms it depends on
+pub type Gradient = Vec<(usize, [f64; 3])>;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Colvar {
+    pub name: String,
+    pub kind: ColvarKind,
+    pub bias: Option<Bias>,
+}
+
+pub fn read_colvars<P: AsRef<Path>>(path: P) -> Result<Vec<Colvar>, String> {
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let file: ColvarFile = toml::from_str(&text).map_err(|e| e.to_string())?;
+    let mut colvars: Vec<Colvar> = Vec::new();
+    for config in file.colvar {
+        if colvars.iter().any(|c| c.name == config.name) {
+            return Err(format!("colvar {} is defined twice", config.name));
+        }
+        let group = |text: &str| {
+            cli::parse_indices(text).filter(|atoms| !atoms.is_empty()).ok_or_else(|| format!("colvar {}: invalid group {}", config.name, text))
+        };
+        let (group1, group2) = (group(&config.group1)?, group(&config.group2)?);
+        let kind = match config.kind.as_str() {
+            "distance" => match (&group1[..], &group2[..]) {
+                (&[a], &[b]) => ColvarKind::Distance(a, b),
+                _ => return Err(format!("colvar {}: a distance takes one atom in each group", config.name)),
+            },
+            "com_distance" => ColvarKind::ComDistance(group1, group2),
+            "coordination" => match config.r0 {
+                Some(r0) if r0 > 0.0 => ColvarKind::Coordination { group1, group2, r0 },
+                _ => return Err(format!("colvar {}: a coordination number needs a positive r0", config.name)),
+            },
+            kind => return Err(format!("colvar {}: kind {} is not one of {}", config.name, kind, KINDS.join(", "))),
+        };
+        let bias = match (config.bias_centre, config.bias_k) {
+            (Some(centre), Some(spring)) if spring > 0.0 => Some(Bias { centre, spring }),
+            (None, None) => None,
+            _ => return Err(format!("colvar {}: a bias takes bias_centre and a positive bias_k", config.name)),
+        };
+        colvars.push(Colvar { name: config.name, kind, bias });
+    }
+    Ok(colvars)
+}
+
+impl Colvar {
+    pub fn atoms(&self) -> Vec<usize> {
+        match &self.kind {
+            ColvarKind::Distance(a, b) => vec![*a, *b],
+            ColvarKind::ComDistance(group1, group2) | ColvarKind::Coordination { group1, group2, .. } => {
+                group1.iter().chain(group2).copied().collect()
+            }
+        }
+    }
+
+    // Value and its gradient with respect to the positions of the atoms it depends on.
+    // Separations along the `periodic` axes are taken to the nearest image.
+    pub fn evaluate(&self, positions: &[[f64; 3]], box_lengths: [f64; 3], periodic: [bool; 3]) -> (f64, Gradient) {
+        let separation = |from: [f64; 3], to: [f64; 3]| -> [f64; 3] {
+            std::array::from_fn(|k| {
+                let delta = to[k] - from[k];
+                if periodic[k] { delta - (delta / box_lengths[k]).round() * box_lengths[k] } else { delta }
+            })
+        };
+        let norm = |v: [f64; 3]| (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+        match &self.kind {
+            ColvarKind::Distance(a, b) => {
+                let delta = separation(positions[*a], positions[*b]);
+                let r = norm(delta);
+                let unit = if r > 0.0 { delta.map(|d| d / r) } else { [0.0; 3] };
+                (r, vec![(*a, unit.map(|u| -u)), (*b, unit)])
+            }
+            ColvarKind::ComDistance(group1, group2) => {
+                let com1 = group_com(group1, positions, box_lengths, periodic);
+                let com2 = group_com(group2, positions, box_lengths, periodic);
+                let delta = separation(com1, com2);
+                let r = norm(delta);
+                let unit = if r > 0.0 { delta.map(|d| d / r) } else { [0.0; 3] };
+                let (n1, n2) = (group1.len() as f64, group2.len() as f64);
+                let gradient = group1.iter().map(|&i| (i, unit.map(|u| -u / n1)))
+                    .chain(group2.iter().map(|&j| (j, unit.map(|u| u / n2))))
+                    .collect();
+                (r, gradient)
+            }
+            ColvarKind::Coordination { group1, group2, r0 } => {
+                let mut value = 0.0;
+                let mut gradient = Vec::new();
+                for &i in group1 {
+                    for &j in group2.iter().filter(|&&j| j != i) {
+                        let delta = separation(positions[i], positions[j]);
+                        let r = norm(delta);
+                        let x6 = (r / r0).powi(6);
+                        value += 1.0 / (1.0 + x6);
+                        // d/dr of 1 / (1 + x^6)
+                        let slope = -6.0 * x6 / (r * (1.0 + x6).powi(2));
+                        let g = delta.map(|d| slope * d / r);
+                        gradient.push((i, g.map(|x| -x)));
+                        gradient.push((j, g));
+                    }
+                }
+                (value, gradient)
+            }
+        }
+    }
+}
+
+// Adds -dE/ds times the gradient of s to `forces`
+pub fn add_gradient_forces(gradient: &[(usize, [f64; 3])], energy_slope: f64, forces: &mut [[f64; 3]]) {
+    for (i, g) in gradient {
+        for k in 0..3 {
+            forces[*i][k] -= energy_slope * g[k];
+        }
+    }
+}
+
+// Evaluates every colvar and adds the forces of the biased ones to `forces`, their energies
+// shared equally among the atoms they act on to `atom_energies` unless that is empty. Returns
+// the values with their gradients, for further biases, and the total bias energy.
+pub fn apply_biases(
+    colvars: &[Colvar],
+    positions: &[[f64; 3]],
+    box_lengths: [f64; 3],
+    periodic: [bool; 3],
+    forces: &mut [[f64; 3]],
+    atom_energies: &mut [f64],
+) -> (Vec<(f64, Gradient)>, f64) {
+    let mut values = Vec::with_capacity(colvars.len());
+    let mut total = 0.0;
+    for colvar in colvars {
+        let (value, gradient) = colvar.evaluate(positions, box_lengths, periodic);
+        if let Some(bias) = colvar.bias {
+            let offset = value - bias.centre;
+            let energy = 0.5 * bias.spring * offset * offset;
+            add_gradient_forces(&gradient, bias.spring * offset, forces);
+            share_energy(colvar, energy, atom_energies);
+            total += energy;
+        }
+        values.push((value, gradient));
+    }
+    (values, total)
+}
+
+// Adds `energy` to `atom_energies`, shared equally among the atoms of `colvar`, unless that is
+// empty
+pub fn share_energy(colvar: &Colvar, energy: f64, atom_energies: &mut [f64]) {
+    if !atom_energies.is_empty() {
+        let atoms = colvar.atoms();
+        for &i in &atoms {
+            atom_energies[i] += energy / atoms.len() as f64;
+        }
+    }
+}
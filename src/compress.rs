@@ -0,0 +1,165 @@
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    // Chosen by the last extension: `.gz` for gzip, `.zst` for Zstandard
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+}
+
+// Extension of the file inside a compressed one, e.g. `xyz` for `glass.xyz.gz`
+pub fn inner_extension(path: &str) -> &str {
+    let path = Path::new(path);
+    let inner = match Compression::from_path(path) {
+        Compression::None => path,
+        _ => Path::new(path.file_stem().unwrap_or_default()),
+    };
+    inner.extension().and_then(|ext| ext.to_str()).unwrap_or("")
+}
+
+// Opens a file for reading, decompressing it on the fly when the extension says so
+pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Box<dyn BufRead>> {
+    let file = File::open(&path)?;
+    Ok(match Compression::from_path(&path) {
+        Compression::None => Box::new(BufReader::new(file)),
+        Compression::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(file))),
+        #[cfg(not(target_arch = "wasm32"))]
+        Compression::Zstd => Box::new(BufReader::new(zstd::Decoder::new(file)?)),
+        #[cfg(target_arch = "wasm32")]
+        Compression::Zstd => return Err(zstd_unsupported()),
+    })
+}
+
+// The Zstandard library is C, which the WebAssembly build leaves out
+#[cfg(target_arch = "wasm32")]
+fn zstd_unsupported() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "Zstandard compression is not available in WebAssembly builds")
+}
+
+enum Encoder {
+    Plain(File),
+    Gzip(GzEncoder<File>),
+    #[cfg(not(target_arch = "wasm32"))]
+    Zstd(zstd::Encoder<'static, File>),
+}
+
+impl Encoder {
+    fn new(file: File, compression: Compression) -> io::Result<Self> {
+        Ok(match compression {
+            Compression::None => Encoder::Plain(file),
+            Compression::Gzip => Encoder::Gzip(GzEncoder::new(file, flate2::Compression::default())),
+            #[cfg(not(target_arch = "wasm32"))]
+            Compression::Zstd => Encoder::Zstd(zstd::Encoder::new(file, 0)?),
+            #[cfg(target_arch = "wasm32")]
+            Compression::Zstd => return Err(zstd_unsupported()),
+        })
+    }
+
+    fn file(&mut self) -> &mut File {
+        match self {
+            Encoder::Plain(file) => file,
+            Encoder::Gzip(encoder) => encoder.get_mut(),
+            #[cfg(not(target_arch = "wasm32"))]
+            Encoder::Zstd(encoder) => encoder.get_mut(),
+        }
+    }
+}
+
+impl Write for Encoder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::Plain(file) => file.write(buf),
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            #[cfg(not(target_arch = "wasm32"))]
+            Encoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Plain(file) => file.flush(),
+            Encoder::Gzip(encoder) => encoder.flush(),
+            #[cfg(not(target_arch = "wasm32"))]
+            Encoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+// Buffered output file, compressed according to its extension. `finish` must be called to
+// write the end of the compressed stream; a file dropped without it is truncated.
+pub struct CompressedWriter {
+    writer: BufWriter<Encoder>,
+}
+
+impl CompressedWriter {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let encoder = Encoder::new(File::create(&path)?, Compression::from_path(&path))?;
+        Ok(CompressedWriter { writer: BufWriter::new(encoder) })
+    }
+
+    // Continues a file at byte `offset`, as returned by `sync`, dropping anything after it
+    pub fn append<P: AsRef<Path>>(path: P, offset: u64) -> io::Result<Self> {
+        let mut file = OpenOptions::new().write(true).open(&path)?;
+        file.set_len(offset)?;
+        file.seek(SeekFrom::End(0))?;
+        let encoder = Encoder::new(file, Compression::from_path(&path))?;
+        Ok(CompressedWriter { writer: BufWriter::new(encoder) })
+    }
+
+    // Puts everything written so far on disk as a complete file and returns its length.
+    // A compressed stream is ended and a new one started; gzip and Zstandard readers decode
+    // the concatenation as one.
+    pub fn sync(&mut self) -> io::Result<u64> {
+        self.writer.flush()?;
+        let encoder = self.writer.get_mut();
+        match encoder {
+            Encoder::Plain(_) => {}
+            Encoder::Gzip(gzip) => {
+                gzip.try_finish()?;
+                let file = gzip.get_ref().try_clone()?;
+                *encoder = Encoder::new(file, Compression::Gzip)?;
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            Encoder::Zstd(zstd) => {
+                zstd.do_finish()?;
+                let file = zstd.get_ref().try_clone()?;
+                *encoder = Encoder::new(file, Compression::Zstd)?;
+            }
+        }
+        encoder.file().stream_position()
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        match self.writer.into_inner().map_err(|e| e.into_error())? {
+            Encoder::Plain(mut file) => file.flush(),
+            Encoder::Gzip(encoder) => encoder.finish().map(drop),
+            #[cfg(not(target_arch = "wasm32"))]
+            Encoder::Zstd(encoder) => encoder.finish().map(drop),
+        }
+    }
+}
+
+impl Write for CompressedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
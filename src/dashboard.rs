@@ -0,0 +1,289 @@
+#[cfg(feature = "tui")]
+use std::io::IsTerminal;
+#[cfg(feature = "tui")]
+use std::time::{Duration, Instant};
+
+use indicatif::{ProgressBar, ProgressStyle};
+#[cfg(feature = "tui")]
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Style},
+    text::Line,
+    widgets::{Block, Gauge, Paragraph, Sparkline, Widget, Wrap},
+    Frame, Terminal, TerminalOptions, Viewport,
+};
+
+use crate::cli::Args;
+
+// Terminal dashboard of the main run, behind `--tui` and the `tui` feature: the progress gauge
+// and the bar's status line above sparklines of temperature, pressure, kinetic, potential and
+// total energy and the step rate. It is drawn with ratatui in a fixed area at the bottom of the
+// terminal, and messages of the run scroll by above it. A new point goes on the plots a few times
+// a second, the mean of the steps since the last one, so the plots cover the last minutes of the
+// run whatever its speed.
+
+pub fn available() -> bool {
+    cfg!(feature = "tui")
+}
+
+// Whether the run asked for the dashboard with `--tui`
+pub fn from_args(args: &Args) -> bool {
+    if args.has("tui") && !available() {
+        eprintln!("--tui requires a build with --features tui");
+        std::process::exit(1);
+    }
+    args.has("tui")
+}
+
+// Progress display of the main run
+pub enum Progress {
+    Bar(ProgressBar),
+    #[cfg(feature = "tui")]
+    Dashboard(Box<Dashboard>),
+}
+
+impl Progress {
+    // The dashboard if asked for and both output streams are terminals, the bar otherwise. A
+    // resumed run starts at `first_step`.
+    pub fn new(first_step: usize, total_steps: usize, dashboard: bool) -> Self {
+        #[cfg(feature = "tui")]
+        if dashboard && std::io::stdout().is_terminal() && std::io::stderr().is_terminal() {
+            match Dashboard::new(first_step, total_steps) {
+                Ok(dashboard) => return Progress::Dashboard(Box::new(dashboard)),
+                Err(e) => eprintln!("Failed to start the dashboard, showing the progress bar instead: {}", e),
+            }
+        }
+        // Never asked for without the feature
+        let _ = (first_step, dashboard);
+        let pb = ProgressBar::new(total_steps as u64);
+        pb.set_style(ProgressStyle::default_bar()
+            .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}")
+            .unwrap()
+            .progress_chars("##-"));
+        Progress::Bar(pb)
+    }
+
+    pub fn set_position(&mut self, step: usize) {
+        match self {
+            Progress::Bar(pb) => pb.set_position(step as u64),
+            #[cfg(feature = "tui")]
+            Progress::Dashboard(dashboard) => dashboard.set_position(step),
+        }
+    }
+
+    // Temperature, potential energy, total energy and pressure at the current step
+    pub fn observe(&mut self, observables: &[f64; 4]) {
+        match self {
+            // The bar has no plots
+            Progress::Bar(_) => {
+                let _ = observables;
+            }
+            #[cfg(feature = "tui")]
+            Progress::Dashboard(dashboard) => dashboard.observe(observables),
+        }
+    }
+
+    pub fn set_message(&mut self, message: String) {
+        match self {
+            Progress::Bar(pb) => pb.set_message(message),
+            #[cfg(feature = "tui")]
+            Progress::Dashboard(dashboard) => dashboard.message = message,
+        }
+    }
+
+    // Prints a line to standard output without tearing the display
+    pub fn println(&mut self, line: &str) {
+        match self {
+            Progress::Bar(pb) => pb.suspend(|| println!("{}", line)),
+            #[cfg(feature = "tui")]
+            Progress::Dashboard(dashboard) => dashboard.print(line),
+        }
+    }
+
+    // Prints a line to standard error without tearing the display
+    pub fn eprintln(&mut self, line: &str) {
+        match self {
+            Progress::Bar(pb) => pb.suspend(|| eprintln!("{}", line)),
+            #[cfg(feature = "tui")]
+            Progress::Dashboard(dashboard) => dashboard.print(line),
+        }
+    }
+
+    // Leaves the display as it is, for a run that stopped early
+    pub fn abandon_with_message(&mut self, message: &'static str) {
+        match self {
+            Progress::Bar(pb) => pb.abandon_with_message(message),
+            #[cfg(feature = "tui")]
+            Progress::Dashboard(dashboard) => dashboard.finish(message),
+        }
+    }
+
+    pub fn finish_with_message(&mut self, message: &'static str) {
+        match self {
+            Progress::Bar(pb) => pb.finish_with_message(message),
+            #[cfg(feature = "tui")]
+            Progress::Dashboard(dashboard) => {
+                dashboard.position = dashboard.total_steps;
+                dashboard.finish(message);
+            }
+        }
+    }
+}
+
+// Rows of the dashboard: gauge, status line and three rows of two plots
+#[cfg(feature = "tui")]
+const HEIGHT: u16 = 2 + 3 * PLOT_HEIGHT;
+#[cfg(feature = "tui")]
+const PLOT_HEIGHT: u16 = 5;
+// Wall time between points on the plots
+#[cfg(feature = "tui")]
+const POINT_INTERVAL: Duration = Duration::from_millis(250);
+// Points kept, more than a wide terminal shows
+#[cfg(feature = "tui")]
+const HISTORY: usize = 1000;
+
+#[cfg(feature = "tui")]
+const PLOTS: [(&str, &str, Color); 6] = [
+    ("Temperature", " K", Color::Red),
+    ("Pressure", "", Color::Magenta),
+    ("Kinetic energy", "", Color::Yellow),
+    ("Potential energy", "", Color::Cyan),
+    ("Total energy", "", Color::Green),
+    ("Step rate", " steps/s", Color::Blue),
+];
+
+#[cfg(feature = "tui")]
+pub struct Dashboard {
+    terminal: Terminal<CrosstermBackend<std::io::Stderr>>,
+    total_steps: usize,
+    position: usize,
+    message: String,
+    // Sums of temperature, pressure, kinetic and potential energy since the last point, and
+    // how many steps they cover
+    sums: [f64; 4],
+    samples: usize,
+    // Points of each plot, in the order of PLOTS
+    history: [Vec<f64>; 6],
+    last_point: Instant,
+    last_point_step: usize,
+}
+
+#[cfg(feature = "tui")]
+impl Dashboard {
+    fn new(first_step: usize, total_steps: usize) -> std::io::Result<Self> {
+        let backend = CrosstermBackend::new(std::io::stderr());
+        let terminal = Terminal::with_options(backend, TerminalOptions { viewport: Viewport::Inline(HEIGHT) })?;
+        Ok(Dashboard {
+            terminal,
+            total_steps,
+            position: first_step,
+            message: String::new(),
+            sums: [0.0; 4],
+            samples: 0,
+            history: Default::default(),
+            last_point: Instant::now(),
+            last_point_step: first_step,
+        })
+    }
+
+    fn set_position(&mut self, step: usize) {
+        self.position = step;
+        if self.last_point.elapsed() >= POINT_INTERVAL {
+            self.add_point();
+            self.draw();
+        }
+    }
+
+    fn observe(&mut self, observables: &[f64; 4]) {
+        let [temperature, potential_energy, total_energy, pressure] = *observables;
+        let values = [temperature, pressure, total_energy - potential_energy, potential_energy];
+        for (sum, value) in self.sums.iter_mut().zip(values) {
+            *sum += value;
+        }
+        self.samples += 1;
+    }
+
+    fn add_point(&mut self) {
+        let elapsed = self.last_point.elapsed().as_secs_f64();
+        if self.samples > 0 {
+            let mean = self.sums.map(|sum| sum / self.samples as f64);
+            let [temperature, pressure, kinetic_energy, potential_energy] = mean;
+            let rate = (self.position - self.last_point_step) as f64 / elapsed;
+            let point = [temperature, pressure, kinetic_energy, potential_energy, kinetic_energy + potential_energy, rate];
+            for (history, value) in self.history.iter_mut().zip(point) {
+                if history.len() == HISTORY {
+                    history.remove(0);
+                }
+                history.push(value);
+            }
+        }
+        self.sums = [0.0; 4];
+        self.samples = 0;
+        self.last_point = Instant::now();
+        self.last_point_step = self.position;
+    }
+
+    // Errors while drawing only cost the display, so they are ignored here and below
+    fn draw(&mut self) {
+        let (position, total_steps, message, history) = (self.position, self.total_steps, &self.message, &self.history);
+        let _ = self.terminal.draw(|frame| render(frame, position, total_steps, message, history));
+        // Keeps the cursor visible should the run exit without finishing the dashboard
+        let _ = self.terminal.show_cursor();
+    }
+
+    fn print(&mut self, line: &str) {
+        let width = self.terminal.size().map_or(80, |size| size.width.max(1)) as usize;
+        let height = line.chars().count().div_ceil(width).max(1) as u16;
+        let _ = self.terminal.insert_before(height, |buffer| {
+            Paragraph::new(line).wrap(Wrap { trim: false }).render(buffer.area, buffer);
+        });
+    }
+
+    // Draws the final state and moves the cursor below it, for whatever the run prints next
+    fn finish(&mut self, message: &str) {
+        self.add_point();
+        self.message = message.to_string();
+        self.draw();
+        let bottom = self.terminal.get_frame().area().bottom();
+        let _ = self.terminal.set_cursor_position((0, bottom.saturating_sub(1)));
+        eprintln!();
+    }
+}
+
+#[cfg(feature = "tui")]
+fn render(frame: &mut Frame, position: usize, total_steps: usize, message: &str, history: &[Vec<f64>; 6]) {
+    let [gauge, status, rows @ ..] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Length(1),
+        Constraint::Length(PLOT_HEIGHT),
+        Constraint::Length(PLOT_HEIGHT),
+        Constraint::Length(PLOT_HEIGHT),
+    ]).areas::<5>(frame.area());
+    let ratio = if total_steps == 0 { 1.0 } else { (position as f64 / total_steps as f64).min(1.0) };
+    frame.render_widget(
+        Gauge::default()
+            .ratio(ratio)
+            .label(format!("{}/{}", position, total_steps))
+            .gauge_style(Style::default().fg(Color::Cyan).bg(Color::Black)),
+        gauge,
+    );
+    frame.render_widget(Line::raw(message), status);
+    let cells: Vec<Rect> = rows.iter().flat_map(|&row| Layout::horizontal([Constraint::Ratio(1, 2); 2]).areas::<2>(row)).collect();
+    for ((&(name, unit, color), points), &cell) in PLOTS.iter().zip(history).zip(&cells) {
+        let title = match points.last() {
+            Some(value) => format!(" {} {:.4}{} ", name, value, unit),
+            None => format!(" {} ", name),
+        };
+        // The latest points that fit, scaled between their own extremes
+        let shown = &points[points.len().saturating_sub(cell.width.saturating_sub(2) as usize)..];
+        let low = shown.iter().copied().fold(f64::INFINITY, f64::min);
+        let high = shown.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let range = (high - low).max(f64::MIN_POSITIVE);
+        let data: Vec<u64> = shown.iter().map(|value| 1 + (99.0 * (value - low) / range) as u64).collect();
+        frame.render_widget(
+            Sparkline::default().block(Block::bordered().title(title)).data(&data).max(100).style(Style::default().fg(color)),
+            cell,
+        );
+    }
+}
@@ -0,0 +1,257 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::xyz::Frame;
+
+// CHARMM time unit (AKMA) in ps, the unit of the DELTA field
+const AKMA_PS: f64 = 0.04888821;
+
+// Byte offset of the frame count (NSET) in the header
+const NSET_OFFSET: u64 = 8;
+
+// CHARMM/NAMD DCD trajectory in native byte order with a unit-cell record in every frame,
+// as read by VMD and MDAnalysis. The frame count in the header is filled in by `finish`.
+pub struct DcdWriter {
+    writer: BufWriter<File>,
+    num_atoms: usize,
+    frames: u32,
+}
+
+impl DcdWriter {
+    // `first_step` and `interval` are the step of the first frame and the steps between
+    // frames; `timestep` is in ps
+    pub fn create<P: AsRef<Path>>(path: P, num_atoms: usize, first_step: usize, interval: usize, timestep: f64) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        let mut control = [0i32; 20];
+        control[1] = first_step as i32;
+        control[2] = interval as i32;
+        // Unit cell present
+        control[10] = 1;
+        // CHARMM version, which tells readers to expect the unit cell flag
+        control[19] = 24;
+        record(&mut writer, |w| {
+            w.write_all(b"CORD")?;
+            for (i, value) in control.iter().enumerate() {
+                if i == 9 {
+                    w.write_all(&((timestep / AKMA_PS) as f32).to_ne_bytes())?;
+                } else {
+                    w.write_all(&value.to_ne_bytes())?;
+                }
+            }
+            Ok(())
+        })?;
+
+        let mut title = [b' '; 80];
+        let text = b"Created by sim";
+        title[..text.len()].copy_from_slice(text);
+        record(&mut writer, |w| {
+            w.write_all(&1i32.to_ne_bytes())?;
+            w.write_all(&title)
+        })?;
+
+        record(&mut writer, |w| w.write_all(&(num_atoms as i32).to_ne_bytes()))?;
+
+        Ok(DcdWriter { writer, num_atoms, frames: 0 })
+    }
+
+    // Continues a file that held `frames` frames up to byte `offset`, as returned by `sync`
+    pub fn append<P: AsRef<Path>>(path: P, num_atoms: usize, frames: u32, offset: u64) -> io::Result<Self> {
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        file.set_len(offset)?;
+        file.seek(SeekFrom::End(0))?;
+        Ok(DcdWriter { writer: BufWriter::new(file), num_atoms, frames })
+    }
+
+    pub fn write_frame(&mut self, positions: &[[f64; 3]], box_lengths: [f64; 3]) -> io::Result<()> {
+        if positions.len() != self.num_atoms {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("frame has {} atoms, DCD header says {}", positions.len(), self.num_atoms),
+            ));
+        }
+        // Unit cell in CHARMM order A, gamma, B, beta, alpha, C, with angles in degrees
+        let cell = [box_lengths[0], 90.0, box_lengths[1], 90.0, 90.0, box_lengths[2]];
+        record(&mut self.writer, |w| {
+            for value in cell {
+                w.write_all(&value.to_ne_bytes())?;
+            }
+            Ok(())
+        })?;
+        for k in 0..3 {
+            record(&mut self.writer, |w| {
+                for pos in positions {
+                    w.write_all(&(pos[k] as f32).to_ne_bytes())?;
+                }
+                Ok(())
+            })?;
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    // Writes out the buffered frames and the frame count so far, and returns the file length
+    pub fn sync(&mut self) -> io::Result<u64> {
+        let end = self.writer.stream_position()?;
+        self.writer.seek(SeekFrom::Start(NSET_OFFSET))?;
+        self.writer.write_all(&(self.frames as i32).to_ne_bytes())?;
+        self.writer.seek(SeekFrom::Start(end))?;
+        Ok(end)
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.writer.seek(SeekFrom::Start(NSET_OFFSET))?;
+        self.writer.write_all(&(self.frames as i32).to_ne_bytes())?;
+        self.writer.flush()
+    }
+}
+
+// Reads every complete frame of a DCD file in either byte order, with times from the header's
+// first step, interval and timestep. A truncated last frame is dropped.
+pub fn read_frames<P: AsRef<Path>>(path: P) -> io::Result<Vec<Frame>> {
+    let bytes = fs::read(path)?;
+    let swap = match bytes.get(..4) {
+        Some(length) if i32::from_ne_bytes(length.try_into().unwrap()) == 84 => false,
+        Some(length) if i32::from_ne_bytes(length.try_into().unwrap()).swap_bytes() == 84 => true,
+        _ => return Err(invalid("not a DCD file")),
+    };
+    let mut reader = RecordReader { bytes: &bytes, offset: 0, swap };
+
+    let header = reader.record()?;
+    if &header[..4] != b"CORD" {
+        return Err(invalid("not a DCD coordinate file"));
+    }
+    let control: Vec<[u8; 4]> = header[4..84].chunks_exact(4).map(|word| word.try_into().unwrap()).collect();
+    let first_step = reader.int(control[1]) as f64;
+    let interval = reader.int(control[2]) as f64;
+    let timestep = reader.float(control[9]) as f64 * AKMA_PS;
+    let has_cell = reader.int(control[10]) != 0;
+    // Title lines
+    reader.record()?;
+    let atoms_record = reader.record()?;
+    let num_atoms = reader.int(atoms_record.get(..4).ok_or_else(|| invalid("empty atom count record"))?.try_into().unwrap());
+    if num_atoms < 0 {
+        return Err(invalid("negative atom count"));
+    }
+    let num_atoms = num_atoms as usize;
+
+    let mut frames = Vec::new();
+    while reader.offset < bytes.len() {
+        let time = (first_step + frames.len() as f64 * interval) * timestep;
+        let Ok(frame) = reader.frame(num_atoms, has_cell, time) else { break };
+        frames.push(frame);
+    }
+    Ok(frames)
+}
+
+struct RecordReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    swap: bool,
+}
+
+impl<'a> RecordReader<'a> {
+    fn int(&self, word: [u8; 4]) -> i32 {
+        let value = i32::from_ne_bytes(word);
+        if self.swap { value.swap_bytes() } else { value }
+    }
+
+    fn float(&self, word: [u8; 4]) -> f32 {
+        f32::from_bits(self.int(word) as u32)
+    }
+
+    // Payload of the next record, checked against the trailing length
+    fn record(&mut self) -> io::Result<&'a [u8]> {
+        let word = |offset: usize| -> io::Result<[u8; 4]> {
+            self.bytes.get(offset..offset + 4).map(|w| w.try_into().unwrap()).ok_or_else(|| invalid("truncated record"))
+        };
+        let length = self.int(word(self.offset)?) as u32 as usize;
+        let start = self.offset + 4;
+        if length > self.bytes.len() - start.min(self.bytes.len()) || self.int(word(start + length)?) as u32 as usize != length {
+            return Err(invalid("record lengths do not match"));
+        }
+        self.offset = start + length + 4;
+        Ok(&self.bytes[start..start + length])
+    }
+
+    fn frame(&mut self, num_atoms: usize, has_cell: bool, time: f64) -> io::Result<Frame> {
+        let box_lengths = if has_cell {
+            let cell = self.record()?;
+            if cell.len() != 48 {
+                return Err(invalid("unit cell record is not six doubles"));
+            }
+            let value = |i: usize| {
+                let bits = u64::from_ne_bytes(cell[8 * i..8 * i + 8].try_into().unwrap());
+                f64::from_bits(if self.swap { bits.swap_bytes() } else { bits })
+            };
+            // CHARMM order A, gamma, B, beta, alpha, C
+            Some([value(0), value(2), value(5)])
+        } else {
+            None
+        };
+        // The coordinate records are read before anything is allocated, so the atom count is
+        // bounded by the size of the file
+        let columns = [self.record()?, self.record()?, self.record()?];
+        if columns.iter().any(|column| column.len() / 4 != num_atoms || column.len() % 4 != 0) {
+            return Err(invalid("coordinate record has the wrong atom count"));
+        }
+        let coordinate = |column: &[u8], i: usize| {
+            let bits = u32::from_ne_bytes(column[4 * i..4 * i + 4].try_into().unwrap());
+            f32::from_bits(if self.swap { bits.swap_bytes() } else { bits }) as f64
+        };
+        let positions = (0..num_atoms).map(|i| columns.map(|column| coordinate(column, i))).collect();
+        Ok(Frame { positions, velocities: None, box_lengths, species: None, time: Some(time) })
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// Fortran unformatted record: the payload framed by its byte length on both sides
+fn record<W: Write, F: FnOnce(&mut Vec<u8>) -> io::Result<()>>(writer: &mut W, payload: F) -> io::Result<()> {
+    let mut buffer = Vec::new();
+    payload(&mut buffer)?;
+    let length = (buffer.len() as i32).to_ne_bytes();
+    writer.write_all(&length)?;
+    writer.write_all(&buffer)?;
+    writer.write_all(&length)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_read_back_with_cell_and_time() {
+        let path = std::env::temp_dir().join(format!("sim-dcd-{}.dcd", std::process::id()));
+        let frames: Vec<Vec<[f64; 3]>> = (0..3).map(|f| {
+            (0..7).map(|i| [i as f64 * 1.25, f as f64 - 0.5 * i as f64, 0.1 * (i * f) as f64]).collect()
+        }).collect();
+        let mut writer = DcdWriter::create(&path, 7, 100, 50, 0.002).unwrap();
+        for frame in &frames {
+            writer.write_frame(frame, [10.0, 11.0, 12.0]).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let read = read_frames(&path).unwrap();
+        assert_eq!(read.len(), 3);
+        for (i, (frame, written)) in read.iter().zip(&frames).enumerate() {
+            assert_eq!(frame.positions, written.iter().map(|pos| pos.map(|x| x as f32 as f64)).collect::<Vec<_>>());
+            assert_eq!(frame.box_lengths, Some([10.0, 11.0, 12.0]));
+            let time = (100.0 + 50.0 * i as f64) * 0.002;
+            assert!((frame.time.unwrap() - time).abs() < 1e-6 * time, "{:?} against {}", frame.time, time);
+        }
+
+        // An atom count far beyond the file finds no complete frame instead of allocating it
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[188..192].copy_from_slice(&i32::MAX.to_ne_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(read_frames(&path).unwrap().is_empty());
+        bytes[188..192].copy_from_slice(&(-1i32).to_ne_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+        assert_eq!(read_frames(&path).err().unwrap().kind(), io::ErrorKind::InvalidData);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,157 @@
+use std::io::Write;
+
+use crate::cli::Args;
+use crate::compress::CompressedWriter;
+use crate::glass::progress_bar;
+use crate::random::Philox;
+use crate::simulation::{maxwell_boltzmann_velocities, remove_com_velocity, BerendsenThermostat, LangevinThermostat, Simulation, MASS_ARGON};
+use crate::xyz;
+
+const USAGE: &str = "Usage: sim droplet [--box 30] [--radius 6] [--density 0.8] [--wall-lattice 1.55] [--wall-layers 3] \
+[--wall-epsilon 0.6] [--temperature 87.3] [--timestep 0.005] [--cutoff 2.5] [--steps 50000] [--snapshot-interval 500] \
+[--thermostat berendsen|langevin] [--friction 1.0] [--seed S] [--output droplet.extxyz]";
+
+// Species label of the wall atoms in the output
+pub const WALL_SPECIES: &str = "W";
+
+// A cylindrical liquid droplet on a structured wall, the usual setup for contact angles: the
+// cylinder axis runs along y through the whole periodic box, so there is no three-phase line
+// curvature, and its cross-section in xz relaxes to a circular cap. The wall is a rigid
+// fcc(100) slab at the bottom of the box whose attraction to the liquid (`--wall-epsilon`,
+// relative to the liquid-liquid well depth) sets the wetting.
+pub fn run(args: &[String]) {
+    let args = Args::parse(args);
+    if !args.positional.is_empty() {
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
+    }
+    let l: f64 = args.get("box", 30.0);
+    let radius: f64 = args.get("radius", 6.0);
+    let density: f64 = args.get("density", 0.8);
+    let wall_lattice: f64 = args.get("wall-lattice", 1.55);
+    let wall_layers: usize = args.get("wall-layers", 3);
+    let wall_epsilon: f64 = args.get("wall-epsilon", 0.6);
+    let temperature: f64 = args.get("temperature", 87.3);
+    let dt: f64 = args.get("timestep", 0.005);
+    let cutoff: f64 = args.get("cutoff", 2.5);
+    let steps: usize = args.get("steps", 50000);
+    let snapshot_interval: usize = args.get("snapshot-interval", 500);
+    let thermostat: String = args.get("thermostat", "berendsen".to_string());
+    let friction: f64 = args.get("friction", 1.0);
+    let seed: u64 = args.get("seed", rand::random());
+    let output: String = args.get("output", "droplet.extxyz".to_string());
+
+    if thermostat != "berendsen" && thermostat != "langevin" {
+        eprintln!("--thermostat must be berendsen or langevin");
+        std::process::exit(1);
+    }
+    if wall_layers == 0 || snapshot_interval == 0 || wall_epsilon < 0.0 || friction <= 0.0 {
+        eprintln!("--wall-layers and --snapshot-interval must be at least 1, --wall-epsilon non-negative and --friction positive");
+        std::process::exit(1);
+    }
+
+    let (wall, wall_top) = wall_atoms(l, wall_lattice, wall_layers);
+    // The droplet starts as a full cylinder one sigma above the wall
+    let centre_height = wall_top + 1.0 + radius;
+    if centre_height + radius + cutoff > l {
+        eprintln!(
+            "A droplet of radius {} on {} wall layers needs a box of at least {:.1}",
+            radius,
+            wall_layers,
+            centre_height + radius + cutoff
+        );
+        std::process::exit(1);
+    }
+    let liquid = cylinder_atoms(l, radius, density, [0.5 * l, centre_height]);
+
+    let mut velocities = maxwell_boltzmann_velocities(liquid.len(), temperature, MASS_ARGON, &Philox::new(seed));
+    remove_com_velocity(&mut velocities);
+    velocities.resize(liquid.len() + wall.len(), [0.0; 3]);
+    let n_liquid = liquid.len();
+    let n_wall = wall.len();
+    let positions: Vec<[f64; 3]> = liquid.into_iter().chain(wall).collect();
+    let species: Vec<String> = (0..n_liquid + n_wall)
+        .map(|i| if i < n_liquid { "Ar" } else { WALL_SPECIES }.to_string())
+        .collect();
+
+    let mut sim = Simulation::new(positions, velocities, l, dt, cutoff);
+    sim.frozen = Some((0..n_liquid + n_wall).map(|i| i >= n_liquid).collect());
+    // Berthelot mixing gives the wall-liquid pairs sqrt(eps_wall * 1) = wall_epsilon
+    sim.set_atom_epsilons(Some((0..n_liquid + n_wall).map(|i| if i < n_liquid { 1.0 } else { wall_epsilon.powi(2) }).collect()));
+    if thermostat == "langevin" {
+        sim.langevin = Some(LangevinThermostat { target_temperature: temperature, friction, seed });
+    } else {
+        sim.thermostat = Some(BerendsenThermostat { target_temperature: temperature, tau: 0.1 });
+    }
+    println!(
+        "Droplet of {} atoms (radius {}) on a wall of {} atoms in {} layers, top at z = {:.3}, wall epsilon {}, seed {}",
+        n_liquid, radius, n_wall, wall_layers, wall_top, wall_epsilon, seed
+    );
+
+    let mut writer = CompressedWriter::create(&output).unwrap_or_else(|e| {
+        eprintln!("Failed to create {}: {}", output, e);
+        std::process::exit(1);
+    });
+    let pb = progress_bar(steps);
+    for step in 0..=steps {
+        if step > 0 {
+            sim.step();
+            pb.inc(1);
+        }
+        if step % snapshot_interval == 0 {
+            let comment = format!("Step={} Time={}", step, step as f64 * dt);
+            xyz::write_frame_with_columns(&mut writer, sim.positions(), Some(&species), Some(sim.velocities()), &[], sim.box_lengths(), &comment)
+                .and_then(|_| writer.flush())
+                .expect("Failed to write droplet trajectory");
+            pb.set_message(format!("T = {:.2} K", sim.temperature()));
+        }
+    }
+    pb.finish();
+    writer.finish().expect("Failed to write droplet trajectory");
+    println!(
+        "Trajectory saved to {}. Contact angles: sim analyze contact-angle {}",
+        output, output
+    );
+}
+
+// Rigid fcc(100) layers at the bottom of the box with the lattice constant adjusted to fit the
+// box a whole number of times. Returns the atoms and the height of the top layer.
+pub(crate) fn wall_atoms(l: f64, lattice: f64, layers: usize) -> (Vec<[f64; 3]>, f64) {
+    let cells = (l / lattice).round().max(1.0) as usize;
+    let a = l / cells as f64;
+    let mut atoms = Vec::new();
+    for layer in 0..layers {
+        let z = 0.5 + layer as f64 * 0.5 * a;
+        for p in 0..2 * cells {
+            for q in 0..2 * cells {
+                if (p + q + layer) % 2 == 0 {
+                    atoms.push([p as f64 * 0.5 * a, q as f64 * 0.5 * a, z]);
+                }
+            }
+        }
+    }
+    (atoms, 0.5 + (layers - 1) as f64 * 0.5 * a)
+}
+
+// Liquid atoms on a simple cubic lattice of the given density inside a cylinder along y with
+// its axis at `centre` (x, z)
+fn cylinder_atoms(l: f64, radius: f64, density: f64, centre: [f64; 2]) -> Vec<[f64; 3]> {
+    let spacing = density.powf(-1.0 / 3.0);
+    // Commensurate with the box along the axis, so the periodic cylinder has no seam
+    let rows = (l / spacing).round().max(1.0) as usize;
+    let y_spacing = l / rows as f64;
+    let span = (radius / spacing).ceil() as i64;
+    let mut atoms = Vec::new();
+    for i in -span..=span {
+        for k in -span..=span {
+            let (dx, dz) = (i as f64 * spacing, k as f64 * spacing);
+            if dx * dx + dz * dz >= radius * radius {
+                continue;
+            }
+            for j in 0..rows {
+                atoms.push([(centre[0] + dx).rem_euclid(l), (j as f64 + 0.5) * y_spacing, centre[1] + dz]);
+            }
+        }
+    }
+    atoms
+}
@@ -0,0 +1,195 @@
+use crate::simulation::{BerendsenBarostat, BerendsenThermostat, SaveState, Simulation};
+
+// Something the controller does to the system before the next batch of steps
+pub enum Action {
+    // Extra force on every atom, held until replaced or cleared
+    ExternalForces(Vec<[f64; 3]>),
+    ClearExternalForces,
+    // Retargets the thermostat in use, or turns the Berendsen one on with time constant 0.1 if none is
+    TargetTemperature(f64),
+    // Turns the Berendsen barostat on with time constant 2.0 and compressibility 0.1 if it is off
+    TargetPressure(f64),
+    Timestep(f64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Observable {
+    Temperature,
+    Pressure,
+    PotentialEnergy,
+    KineticEnergy,
+    TotalEnergy,
+    Density,
+    BoxLength,
+    Time,
+    // Per-atom vectors, flattened to x0 y0 z0 x1 ...
+    Positions,
+    Velocities,
+    Forces,
+}
+
+impl Observable {
+    pub fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "temperature" => Observable::Temperature,
+            "pressure" => Observable::Pressure,
+            "potential_energy" => Observable::PotentialEnergy,
+            "kinetic_energy" => Observable::KineticEnergy,
+            "total_energy" => Observable::TotalEnergy,
+            "density" => Observable::Density,
+            "box_length" => Observable::BoxLength,
+            "time" => Observable::Time,
+            "positions" => Observable::Positions,
+            "velocities" => Observable::Velocities,
+            "forces" => Observable::Forces,
+            _ => return None,
+        })
+    }
+}
+
+// Gym-style wrapper for control experiments: `reset` restores the initial state, `step` applies
+// actions and advances `steps_per_action` MD steps, and both return the observation vector made
+// of the configured observables in order. Both the initial state and snapshots of an episode
+// go through `SaveState`, so an episode can be saved to JSON and picked up again.
+pub struct Environment {
+    initial: SaveState,
+    sim: Simulation,
+    observables: Vec<Observable>,
+    steps_per_action: usize,
+    // Actions taken since the last reset
+    actions: u64,
+}
+
+impl Environment {
+    pub fn new(initial: Simulation, observables: Vec<Observable>, steps_per_action: usize) -> Self {
+        Environment {
+            initial: initial.save_state(),
+            sim: initial,
+            observables,
+            steps_per_action: steps_per_action.max(1),
+            actions: 0,
+        }
+    }
+
+    // An environment that starts, and resets, from a saved state
+    pub fn from_state(initial: SaveState, observables: Vec<Observable>, steps_per_action: usize) -> Self {
+        Environment::new(Simulation::from_state(initial), observables, steps_per_action)
+    }
+
+    pub fn reset(&mut self) -> Vec<f64> {
+        self.sim = Simulation::from_state(self.initial.clone());
+        self.actions = 0;
+        self.observe()
+    }
+
+    pub fn step(&mut self, actions: &[Action]) -> Vec<f64> {
+        for action in actions {
+            self.apply(action);
+        }
+        for _ in 0..self.steps_per_action {
+            self.sim.step();
+        }
+        self.actions += 1;
+        self.observe()
+    }
+
+    // The current state of the episode, with the number of actions taken in `accumulators`
+    pub fn save_state(&self) -> SaveState {
+        let mut state = self.sim.save_state();
+        state.accumulators.insert("actions".to_string(), self.actions as f64);
+        state
+    }
+
+    // Continues the episode from a state saved by `save_state`; `reset` still returns to the
+    // initial state
+    pub fn load_state(&mut self, state: SaveState) {
+        self.actions = state.accumulators.get("actions").map_or(0, |&actions| actions as u64);
+        self.sim = Simulation::from_state(state);
+    }
+
+    pub fn actions(&self) -> u64 {
+        self.actions
+    }
+
+    pub fn observe(&self) -> Vec<f64> {
+        let sim = &self.sim;
+        let mut observation = Vec::new();
+        for observable in &self.observables {
+            match observable {
+                Observable::Temperature => observation.push(sim.temperature()),
+                Observable::Pressure => observation.push(sim.pressure()),
+                Observable::PotentialEnergy => observation.push(sim.potential_energy()),
+                Observable::KineticEnergy => observation.push(sim.kinetic_energy()),
+                Observable::TotalEnergy => observation.push(sim.potential_energy() + sim.kinetic_energy()),
+                Observable::Density => observation.push(sim.density()),
+                Observable::BoxLength => observation.push(sim.box_length()),
+                Observable::Time => observation.push(sim.time()),
+                Observable::Positions => observation.extend(sim.positions().iter().flatten()),
+                Observable::Velocities => observation.extend(sim.velocities().iter().flatten()),
+                Observable::Forces => observation.extend(sim.forces().iter().flatten()),
+            }
+        }
+        observation
+    }
+
+    pub fn simulation(&self) -> &Simulation {
+        &self.sim
+    }
+
+    // Direct access for changes no action covers
+    pub fn simulation_mut(&mut self) -> &mut Simulation {
+        &mut self.sim
+    }
+
+    fn apply(&mut self, action: &Action) {
+        match action {
+            Action::ExternalForces(forces) => {
+                assert_eq!(forces.len(), self.sim.num_atoms(), "one external force per atom expected");
+                self.sim.external_forces = Some(forces.clone());
+            }
+            Action::ClearExternalForces => self.sim.external_forces = None,
+            Action::TargetTemperature(target_temperature) => {
+                if self.sim.thermostat.is_none() && self.sim.langevin.is_none() {
+                    self.sim.thermostat = Some(BerendsenThermostat { target_temperature: 0.0, tau: 0.1 });
+                }
+                self.sim.set_target_temperature(*target_temperature);
+            }
+            Action::TargetPressure(target_pressure) => {
+                let barostat = self.sim.barostat.get_or_insert(BerendsenBarostat {
+                    target_pressure: 0.0,
+                    tau: 2.0,
+                    compressibility: 0.1,
+                });
+                barostat.target_pressure = *target_pressure;
+            }
+            Action::Timestep(dt) => self.sim.dt = *dt,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::initial_state;
+
+    #[test]
+    fn saved_episode_continues_and_reset_returns_to_start() {
+        let observables = vec![Observable::Temperature, Observable::Positions, Observable::Velocities];
+        let mut env = Environment::new(initial_state(32, 0.8, 100.0, 0.002, 2.5, 7), observables.clone(), 5);
+        let start = env.reset();
+        env.step(&[Action::TargetTemperature(150.0)]);
+        env.step(&[Action::Timestep(0.001)]);
+
+        let saved = env.save_state();
+        assert_eq!(saved.accumulators["actions"], 2.0);
+        let mut copy = Environment::from_state(saved.clone(), observables, 5);
+        copy.load_state(saved);
+        assert_eq!(copy.actions(), 2);
+        assert_eq!(copy.observe(), env.observe());
+        let action = [Action::ExternalForces(vec![[0.1, 0.0, 0.0]; 32])];
+        assert_eq!(copy.step(&action), env.step(&action));
+
+        assert_eq!(env.reset(), start);
+        assert_eq!(env.actions(), 0);
+    }
+}
@@ -0,0 +1,102 @@
+use serde::{Deserialize, Serialize};
+
+use crate::cli::Args;
+
+// Samples averaged into one batch before the truncation search (MSER-5)
+const BATCH: usize = 5;
+// Fewest batches worth testing, and the number of new batches between tests
+const MIN_BATCHES: usize = 20;
+const CHECK_EVERY: usize = 10;
+
+// Online equilibration detection with the marginal standard error rule (MSER-5). The series of
+// an observable, in batch means of five samples, is truncated at the point d that minimises
+// the squared standard error of the mean of what is left,
+//   MSER(d) = sum_{i >= d} (y_i - mean_d)^2 / (k - d)^2,
+// searched over the first half of the k batches. While the series still drifts, cutting more
+// always helps and the minimum sits at the end of that half. Once the minimum falls inside it,
+// the series from d on is taken to be steady and the run has equilibrated.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EquilibrationDetector {
+    // Steps between samples
+    pub every: usize,
+    // Whether production statistics restart once equilibration is detected
+    pub production: bool,
+    // First step and mean of every full batch
+    batches: Vec<(usize, f64)>,
+    // First step, sum and count of the batch being filled
+    pending: (usize, f64, usize),
+    // First step of the steady part and the step it was detected at
+    pub equilibrated: Option<(usize, usize)>,
+}
+
+impl EquilibrationDetector {
+    // From `--detect-equilibration` with `--equilibration-every 10` and `--auto-production`
+    pub fn from_args(args: &Args) -> Option<Self> {
+        if !args.has("detect-equilibration") {
+            if args.has("auto-production") {
+                eprintln!("--auto-production needs --detect-equilibration");
+                std::process::exit(1);
+            }
+            return None;
+        }
+        let every: usize = args.get("equilibration-every", 10);
+        if every == 0 {
+            eprintln!("--equilibration-every must be at least 1");
+            std::process::exit(1);
+        }
+        Some(EquilibrationDetector::new(every, args.has("auto-production")))
+    }
+
+    pub fn new(every: usize, production: bool) -> Self {
+        EquilibrationDetector { every, production, batches: Vec::new(), pending: (0, 0.0, 0), equilibrated: None }
+    }
+
+    // Whether `step` is one the detector samples, until it has detected equilibration
+    pub fn due(&self, step: usize) -> bool {
+        self.equilibrated.is_none() && step.is_multiple_of(self.every)
+    }
+
+    // Takes the value at `step`. Returns the first step of the steady part when this sample
+    // completes the detection.
+    pub fn observe(&mut self, step: usize, value: f64) -> Option<usize> {
+        if self.equilibrated.is_some() || !value.is_finite() {
+            return None;
+        }
+        if self.pending.2 == 0 {
+            self.pending.0 = step;
+        }
+        self.pending.1 += value;
+        self.pending.2 += 1;
+        if self.pending.2 < BATCH {
+            return None;
+        }
+        self.batches.push((self.pending.0, self.pending.1 / BATCH as f64));
+        self.pending = (0, 0.0, 0);
+        let k = self.batches.len();
+        if k < MIN_BATCHES || !k.is_multiple_of(CHECK_EVERY) {
+            return None;
+        }
+
+        // Sums of the batch means from d to the end, for every d
+        let (mut sum, mut squares) = (0.0, 0.0);
+        let mut best = (f64::INFINITY, k / 2);
+        for d in (0..k).rev() {
+            let y = self.batches[d].1;
+            sum += y;
+            squares += y * y;
+            if d <= k / 2 {
+                let m = (k - d) as f64;
+                let mser = (squares - sum * sum / m).max(0.0) / (m * m);
+                if mser <= best.0 {
+                    best = (mser, d);
+                }
+            }
+        }
+        if best.1 >= k / 2 {
+            return None;
+        }
+        let start = self.batches[best.1].0;
+        self.equilibrated = Some((start, step));
+        Some(start)
+    }
+}
@@ -0,0 +1,240 @@
+use std::fs;
+use std::io::Write;
+
+use crate::analysis::block_average;
+use crate::cli::Args;
+use crate::compress::CompressedWriter;
+use crate::droplet::{wall_atoms, WALL_SPECIES};
+use crate::geometry::minimum_image_distance;
+use crate::glass::progress_bar;
+use crate::profile::Profile;
+use crate::random::Philox;
+use crate::simulation::{maxwell_boltzmann_velocities, remove_com_velocity, BerendsenThermostat, LangevinThermostat, Simulation, KB, MASS_ARGON};
+use crate::table::TableWriter;
+use crate::xyz;
+
+const USAGE: &str = "Usage: sim evaporate [--box 20] [--film 6] [--density 0.75] [--wall-lattice 1.55] [--wall-layers 3] \
+[--wall-epsilon 1.0] [--temperature 110] [--timestep 0.005] [--cutoff 2.5] [--steps 50000] [--delete-above L-2.5] \
+[--insertion-rate 0] [--vapor-temperature T] [--thermostat berendsen|langevin] [--friction 1.0] [--seed S] \
+[--log-interval 100] [--profile-bins 100] [--profile-every 10] [--snapshot-interval 0] [--output-dir evaporation]";
+
+// Closest approach to an existing atom at which an inserted vapor atom is accepted
+const INSERTION_CLEARANCE: f64 = 0.9;
+// Positions tried for one inserted atom before it is put off to the next step
+const INSERTION_ATTEMPTS: u32 = 10;
+
+// Steady-state evaporation from a liquid film on a rigid wall. Atoms that cross the deletion
+// plane near the top of the box are removed, so the vapor above the film never saturates and
+// nothing re-enters through the periodic boundary below the wall. Optionally vapor atoms are
+// inserted just below the plane at a fixed rate, moving down with the flux-weighted velocity
+// distribution of a gas at `--vapor-temperature`, which sets up condensation against the
+// evaporation. Removed and inserted atoms are counted for the mass-flux balance.
+pub fn run(args: &[String]) {
+    let args = Args::parse(args);
+    if !args.positional.is_empty() {
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
+    }
+    let l: f64 = args.get("box", 20.0);
+    let film: f64 = args.get("film", 6.0);
+    let density: f64 = args.get("density", 0.75);
+    let wall_lattice: f64 = args.get("wall-lattice", 1.55);
+    let wall_layers: usize = args.get("wall-layers", 3);
+    let wall_epsilon: f64 = args.get("wall-epsilon", 1.0);
+    let temperature: f64 = args.get("temperature", 110.0);
+    let dt: f64 = args.get("timestep", 0.005);
+    let cutoff: f64 = args.get("cutoff", 2.5);
+    let steps: usize = args.get("steps", 50000);
+    let delete_above: f64 = args.get("delete-above", l - cutoff);
+    let insertion_rate: f64 = args.get("insertion-rate", 0.0);
+    let vapor_temperature: f64 = args.get("vapor-temperature", temperature);
+    let thermostat: String = args.get("thermostat", "berendsen".to_string());
+    let friction: f64 = args.get("friction", 1.0);
+    let seed: u64 = args.get("seed", rand::random());
+    let log_interval: usize = args.get("log-interval", 100);
+    let profile_bins: usize = args.get("profile-bins", 100);
+    let profile_every: usize = args.get("profile-every", 10);
+    let snapshot_interval: usize = args.get("snapshot-interval", 0);
+    let output_dir: String = args.get("output-dir", "evaporation".to_string());
+
+    if thermostat != "berendsen" && thermostat != "langevin" {
+        eprintln!("--thermostat must be berendsen or langevin");
+        std::process::exit(1);
+    }
+    if wall_layers == 0 || log_interval == 0 || profile_bins == 0 || profile_every == 0 || friction <= 0.0 || insertion_rate < 0.0 || vapor_temperature <= 0.0 {
+        eprintln!("--wall-layers, --log-interval, --profile-bins and --profile-every must be at least 1, --friction and --vapor-temperature positive and --insertion-rate non-negative");
+        std::process::exit(1);
+    }
+
+    let (wall, wall_top) = wall_atoms(l, wall_lattice, wall_layers);
+    let film_bottom = wall_top + 1.0;
+    // Room for the vapor between the film and the plane, and for the plane below the top face
+    if film_bottom + film + 2.0 * cutoff > delete_above || delete_above > l - 1.0 {
+        eprintln!(
+            "The deletion plane at {} must be at least {:.2} above the wall and at most {:.2}, the top of the box less one sigma",
+            delete_above,
+            film_bottom + film + 2.0 * cutoff,
+            l - 1.0
+        );
+        std::process::exit(1);
+    }
+    let liquid = film_atoms(l, film, density, film_bottom);
+    let n_liquid = liquid.len();
+    let n_wall = wall.len();
+    let mut velocities = maxwell_boltzmann_velocities(n_liquid, temperature, MASS_ARGON, &Philox::new(seed));
+    remove_com_velocity(&mut velocities);
+    velocities.resize(n_liquid + n_wall, [0.0; 3]);
+    let positions: Vec<[f64; 3]> = liquid.into_iter().chain(wall).collect();
+
+    let mut sim = Simulation::new(positions, velocities, l, dt, cutoff);
+    sim.frozen = Some((0..n_liquid + n_wall).map(|i| i >= n_liquid).collect());
+    sim.set_atom_epsilons(Some((0..n_liquid + n_wall).map(|i| if i < n_liquid { 1.0 } else { wall_epsilon.powi(2) }).collect()));
+    // The thermostat keeps the film at temperature, resupplying the latent heat the evaporating
+    // atoms carry off
+    if thermostat == "langevin" {
+        sim.langevin = Some(LangevinThermostat { target_temperature: temperature, friction, seed });
+    } else {
+        sim.thermostat = Some(BerendsenThermostat { target_temperature: temperature, tau: 0.1 });
+    }
+
+    fs::create_dir_all(&output_dir).expect("Failed to create output directory");
+    let log_path = format!("{}/flux.csv", output_dir);
+    let mut log = TableWriter::create(
+        &log_path,
+        &["time", "atoms", "removed", "inserted", "evaporation_flux", "condensation_flux", "net_flux", "temperature"],
+    ).unwrap_or_else(|e| {
+        eprintln!("Failed to create {}: {}", log_path, e);
+        std::process::exit(1);
+    });
+    let mut trajectory = (snapshot_interval > 0).then(|| {
+        let path = format!("{}/evaporation.extxyz", output_dir);
+        CompressedWriter::create(&path).unwrap_or_else(|e| {
+            eprintln!("Failed to create {}: {}", path, e);
+            std::process::exit(1);
+        })
+    });
+    println!(
+        "Film of {} atoms, {} thick, on a wall of {} atoms at {} K; deleting above z = {}, inserting {} atoms per time unit at {} K, seed {}",
+        n_liquid, film, n_wall, temperature, delete_above, insertion_rate, vapor_temperature, seed
+    );
+
+    let area = l * l;
+    let rng = Philox::new(seed ^ 0x5eed_1a5e);
+    let vapor_sigma = (KB * vapor_temperature / MASS_ARGON).sqrt();
+    let insertion_height = delete_above - 1.0;
+    let (mut removed, mut inserted) = (0usize, 0usize);
+    let (mut removed_logged, mut inserted_logged) = (0usize, 0usize);
+    let mut pending = 0.0;
+    let mut net_fluxes = Vec::new();
+    // Density and temperature across the film, the interfaces and the vapor
+    let mut profile = Profile::new(2, profile_bins);
+    let pb = progress_bar(steps);
+    for step in 0..=steps {
+        if step > 0 {
+            sim.step();
+            pb.inc(1);
+
+            let frozen = sim.frozen.as_deref().unwrap();
+            let crossed: Vec<usize> = (0..sim.num_atoms()).filter(|&i| !frozen[i] && sim.positions()[i][2] > delete_above).collect();
+            if !crossed.is_empty() {
+                removed += crossed.len();
+                sim.remove_atoms(&crossed);
+            }
+
+            pending += insertion_rate * dt;
+            let mut attempt = 0;
+            while pending >= 1.0 && attempt < INSERTION_ATTEMPTS {
+                let u = rng.uniforms(step as u64, attempt, 1);
+                let normals = rng.normals(step as u64, attempt, 2);
+                let candidate = [u[0] * l, u[1] * l, insertion_height];
+                attempt += 1;
+                if sim.positions().iter().any(|pos| minimum_image_distance(pos, &candidate, l) < INSERTION_CLEARANCE) {
+                    continue;
+                }
+                // Atoms crossing a plane have a flux-weighted (Rayleigh) normal velocity
+                let velocity = [vapor_sigma * normals[0], vapor_sigma * normals[1], -vapor_sigma * (-2.0 * u[2].ln()).sqrt()];
+                sim.insert_atoms(&[candidate], &[velocity]);
+                inserted += 1;
+                pending -= 1.0;
+            }
+        }
+
+        if step > 0 && step % profile_every == 0 {
+            profile.sample(sim.positions(), sim.velocities(), sim.box_lengths(), sim.mass, sim.frozen.as_deref());
+        }
+        if step > 0 && step % log_interval == 0 {
+            let interval = log_interval as f64 * dt;
+            let evaporation = (removed - removed_logged) as f64 / (area * interval);
+            let condensation = (inserted - inserted_logged) as f64 / (area * interval);
+            net_fluxes.push(evaporation - condensation);
+            log.write_row(&[
+                step as f64 * dt,
+                sim.num_atoms() as f64 - n_wall as f64,
+                removed as f64,
+                inserted as f64,
+                evaporation,
+                condensation,
+                evaporation - condensation,
+                sim.temperature(),
+            ]).expect("Failed to write flux log");
+            (removed_logged, inserted_logged) = (removed, inserted);
+            pb.set_message(format!("T = {:.2} K, {} removed, {} inserted", sim.temperature(), removed, inserted));
+        }
+        if let Some(writer) = trajectory.as_mut().filter(|_| step % snapshot_interval == 0) {
+            let frozen = sim.frozen.as_deref().unwrap();
+            let species: Vec<String> = frozen.iter().map(|&f| if f { WALL_SPECIES } else { "Ar" }.to_string()).collect();
+            let comment = format!("Step={} Time={}", step, step as f64 * dt);
+            xyz::write_frame_with_columns(writer, sim.positions(), Some(&species), Some(sim.velocities()), &[], sim.box_lengths(), &comment)
+                .and_then(|_| writer.flush())
+                .expect("Failed to write evaporation trajectory");
+        }
+    }
+    pb.finish();
+    log.finish().expect("Failed to write flux log");
+    if let Some(writer) = trajectory {
+        writer.finish().expect("Failed to write evaporation trajectory");
+    }
+
+    println!(
+        "{} atoms removed and {} inserted over {:.3} time units, {} liquid and vapor atoms left",
+        removed,
+        inserted,
+        steps as f64 * dt,
+        sim.num_atoms() - n_wall
+    );
+    // The first half is taken as the approach to the steady state
+    let steady = &net_fluxes[net_fluxes.len() / 2..];
+    if steady.len() >= 2 {
+        let (mean, error) = block_average(steady, steady.len().min(10));
+        println!(
+            "Steady-state net evaporation flux over the second half: {:.4e} +- {:.1e} atoms/(sigma^2 time unit), {:.4e} g/mol/(sigma^2 time unit)",
+            mean,
+            error,
+            mean * MASS_ARGON
+        );
+    }
+    let profile_path = format!("{}/profile.csv", output_dir);
+    profile.write(&profile_path).expect("Failed to write profile");
+    println!("Flux log saved to {}, density and temperature profile along z to {}", log_path, profile_path);
+}
+
+// Liquid atoms on a simple cubic lattice of the given density in a layer `thickness` thick
+// starting at `bottom`, spanning the box in x and y
+fn film_atoms(l: f64, thickness: f64, density: f64, bottom: f64) -> Vec<[f64; 3]> {
+    let spacing = density.powf(-1.0 / 3.0);
+    // Commensurate with the box across the film, so it has no seam at the periodic boundary
+    let rows = (l / spacing).round().max(1.0) as usize;
+    let lateral = l / rows as f64;
+    // Keep the density by matching the layer spacing to the adjusted lateral one
+    let layer_spacing = 1.0 / (density * lateral * lateral);
+    let layers = (thickness / layer_spacing).round().max(1.0) as usize;
+    let mut atoms = Vec::with_capacity(rows * rows * layers);
+    for k in 0..layers {
+        for i in 0..rows {
+            for j in 0..rows {
+                atoms.push([(i as f64 + 0.5) * lateral, (j as f64 + 0.5) * lateral, bottom + k as f64 * layer_spacing]);
+            }
+        }
+    }
+    atoms
+}
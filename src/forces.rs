@@ -0,0 +1,245 @@
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+
+use crate::geometry::Cell;
+use crate::simulation::ordered_sum;
+
+pub fn lj_potential(r: f64) -> f64 {
+    let sigma = 1.0;
+    let epsilon = 1.0;
+    4.0 * epsilon * ((sigma / r).powi(12) - (sigma / r).powi(6))
+}
+
+// Magnitude of -dU/dr for the Lennard-Jones potential
+pub fn lj_force(r: f64) -> f64 {
+    let sigma = 1.0;
+    let epsilon = 1.0;
+    24.0 * epsilon * (2.0 * (sigma / r).powi(12) - (sigma / r).powi(6)) / r
+}
+
+// A spherically symmetric pair interaction. Besides the pair terms it supplies the long-range
+// tail corrections for the interactions a cutoff leaves out, assuming a uniform fluid (g(r) = 1)
+// beyond it.
+pub trait PairPotential: Sync {
+    fn energy(&self, r: f64) -> f64;
+
+    // Magnitude of -dU/dr
+    fn force(&self, r: f64) -> f64;
+
+    // Missing potential energy per atom, 2 pi rho int_rc^inf r^2 U(r) dr
+    fn tail_energy(&self, cutoff: f64, density: f64) -> f64;
+
+    // Missing pressure, (2/3) pi rho^2 int_rc^inf r^3 F(r) dr
+    fn tail_pressure(&self, cutoff: f64, density: f64) -> f64;
+
+    // RMS force per atom from the pairs beyond the cutoff, sqrt(4 pi rho int_rc^inf r^2 F(r)^2 dr)
+    fn truncation_force_error(&self, cutoff: f64, density: f64) -> f64;
+
+    // Reference force that accuracies are relative to, like the unit-charge force of Ewald
+    // accuracy settings
+    fn force_unit(&self) -> f64;
+}
+
+// Smallest cutoff whose truncation force error is at most `accuracy` force units at `density`
+pub fn cutoff_for_accuracy<P: PairPotential>(potential: &P, density: f64, accuracy: f64) -> f64 {
+    let target = accuracy * potential.force_unit();
+    let accurate = |cutoff: f64| potential.truncation_force_error(cutoff, density) <= target;
+    let (mut lo, mut hi) = (1.0, 1.0);
+    while !accurate(hi) {
+        lo = hi;
+        hi *= 2.0;
+    }
+    while accurate(lo) && lo > 1e-3 {
+        hi = lo;
+        lo *= 0.5;
+    }
+    for _ in 0..60 {
+        let mid = 0.5 * (lo + hi);
+        if accurate(mid) {
+            hi = mid;
+        } else {
+            lo = mid;
+        }
+    }
+    hi
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LennardJones {
+    pub sigma: f64,
+    pub epsilon: f64,
+}
+
+impl Default for LennardJones {
+    fn default() -> Self {
+        LennardJones { sigma: 1.0, epsilon: 1.0 }
+    }
+}
+
+impl PairPotential for LennardJones {
+    fn energy(&self, r: f64) -> f64 {
+        let s6 = (self.sigma / r).powi(6);
+        4.0 * self.epsilon * (s6 * s6 - s6)
+    }
+
+    fn force(&self, r: f64) -> f64 {
+        let s6 = (self.sigma / r).powi(6);
+        24.0 * self.epsilon * (2.0 * s6 * s6 - s6) / r
+    }
+
+    fn tail_energy(&self, cutoff: f64, density: f64) -> f64 {
+        let s3 = (self.sigma / cutoff).powi(3);
+        8.0 / 3.0 * PI * density * self.epsilon * self.sigma.powi(3) * (s3.powi(3) / 3.0 - s3)
+    }
+
+    fn tail_pressure(&self, cutoff: f64, density: f64) -> f64 {
+        let s3 = (self.sigma / cutoff).powi(3);
+        16.0 / 3.0 * PI * density * density * self.epsilon * self.sigma.powi(3) * (2.0 / 3.0 * s3.powi(3) - s3)
+    }
+
+    fn truncation_force_error(&self, cutoff: f64, density: f64) -> f64 {
+        // F^2 = 576 eps^2 (4 s^24 / r^26 - 4 s^18 / r^20 + s^12 / r^14) with s = sigma
+        let x = self.sigma / cutoff;
+        let integral = 576.0 * self.epsilon.powi(2) / cutoff.powi(11) * self.sigma.powi(12)
+            * (4.0 * x.powi(12) / 23.0 - 4.0 * x.powi(6) / 17.0 + 1.0 / 11.0);
+        (4.0 * PI * density * integral).sqrt()
+    }
+
+    fn force_unit(&self) -> f64 {
+        self.epsilon / self.sigma
+    }
+}
+
+pub struct ForceOutput {
+    pub forces: Vec<[f64; 3]>,
+    pub potential_energy: f64,
+    // Sum of r_ij . f_ij over all pairs
+    pub virial: f64,
+    // Sum of the outer products r_ij f_ij over all pairs, as [xx, yy, zz, xy, xz, yz]
+    pub virial_tensor: [f64; 6],
+    // Potential energy and virial tensor of each atom, half of every pair it is in
+    pub atom_energies: Vec<f64>,
+    pub atom_virials: Vec<[f64; 6]>,
+}
+
+// Pairwise Lennard-Jones (sigma = epsilon = 1) forces with the minimum image convention in a cubic periodic box.
+// Pairs further apart than `cutoff` are ignored.
+pub fn compute_forces(positions: &[[f64; 3]], box_length: f64, cutoff: f64) -> ForceOutput {
+    compute_forces_switched(&LennardJones::default(), positions, box_length, cutoff, f64::INFINITY)
+}
+
+// Adds the outer product r f of a pair with force f = `force_over_r` * r to a virial tensor
+// stored as [xx, yy, zz, xy, xz, yz]
+pub fn add_pair_virial(virial: &mut [f64; 6], r: [f64; 3], force_over_r: f64) {
+    virial[0] += force_over_r * r[0] * r[0];
+    virial[1] += force_over_r * r[1] * r[1];
+    virial[2] += force_over_r * r[2] * r[2];
+    virial[3] += force_over_r * r[0] * r[1];
+    virial[4] += force_over_r * r[0] * r[2];
+    virial[5] += force_over_r * r[1] * r[2];
+}
+
+// Smoothstep switching factor S and dS/dr between `start` and `end`. S falls from 1 to 0 with
+// zero first and second derivatives at both ends, so energy and force both vanish continuously.
+fn switching(r: f64, start: f64, end: f64) -> (f64, f64) {
+    if r <= start {
+        return (1.0, 0.0);
+    }
+    let width = end - start;
+    let x = (r - start) / width;
+    let s = 1.0 - x.powi(3) * (10.0 - 15.0 * x + 6.0 * x * x);
+    let ds = -30.0 * x * x * (1.0 - x).powi(2) / width;
+    (s, ds)
+}
+
+// Pair energy as the switched force loops count it: switched to zero between `switch_start`
+// and `cutoff`, and nothing beyond
+pub fn switched_energy<P: PairPotential>(potential: &P, r: f64, cutoff: f64, switch_start: f64) -> f64 {
+    if r > cutoff {
+        return 0.0;
+    }
+    switching(r, switch_start, cutoff).0 * potential.energy(r)
+}
+
+// Like `compute_forces` for any pair potential, with the potential switched smoothly to zero between `switch_start`
+// and `cutoff` instead of truncated. Plain truncation leaves a force jump at the cutoff that
+// makes the energy drift in NVE runs. A `switch_start` at or beyond the cutoff switches nothing.
+pub fn compute_forces_switched<P: PairPotential>(
+    potential: &P,
+    positions: &[[f64; 3]],
+    box_length: f64,
+    cutoff: f64,
+    switch_start: f64,
+) -> ForceOutput {
+    compute_forces_sheared(potential, positions, &Cell::cubic(box_length), cutoff, switch_start, 0.0, None)
+}
+
+// Like `compute_forces_switched` in any periodic cell, with Lees-Edwards boundaries: the
+// periodic images one box up in y are displaced by `image_offset` along x, which is how a box
+// under steady shear flow in x with the gradient along y sees its neighbours. A nonzero offset
+// needs an orthorhombic cell, and the cutoff has to fit in half of every cell width.
+// `atom_epsilons` scales the well depth per atom, with sqrt(eps_i eps_j) for a pair (Berthelot
+// mixing).
+pub fn compute_forces_sheared<P: PairPotential>(
+    potential: &P,
+    positions: &[[f64; 3]],
+    cell: &Cell,
+    cutoff: f64,
+    switch_start: f64,
+    image_offset: f64,
+    atom_epsilons: Option<&[f64]>,
+) -> ForceOutput {
+    let n = positions.len();
+    let cutoff_sq = cutoff * cutoff;
+    let box_y = cell.vectors()[1][1];
+    let weights: Option<Vec<f64>> = atom_epsilons.map(|epsilons| epsilons.iter().map(|e| e.sqrt()).collect());
+    let weights = weights.as_deref();
+
+    let per_atom: Vec<([f64; 3], f64, [f64; 6])> = (0..n).into_par_iter().map(|i| {
+        let mut force = [0.0; 3];
+        let mut energy = 0.0;
+        let mut virial = [0.0; 6];
+        for j in 0..n {
+            if i != j {
+                let mut r_ij = [0.0; 3];
+                for k in 0..3 {
+                    r_ij[k] = positions[i][k] - positions[j][k];
+                }
+                r_ij[0] -= (r_ij[1] / box_y).round() * image_offset;
+                let r_ij = cell.nearest_image(&r_ij);
+                let r_sq = r_ij[0].powi(2) + r_ij[1].powi(2) + r_ij[2].powi(2);
+                if r_sq > cutoff_sq {
+                    continue;
+                }
+                let r = r_sq.sqrt();
+                let (pair_energy, force_r) = if r > switch_start {
+                    let (s, ds) = switching(r, switch_start, cutoff);
+                    let pair_energy = potential.energy(r);
+                    (s * pair_energy, s * potential.force(r) - ds * pair_energy)
+                } else {
+                    (potential.energy(r), potential.force(r))
+                };
+                let weight = weights.map_or(1.0, |w| w[i] * w[j]);
+                let (pair_energy, force_r) = (weight * pair_energy, weight * force_r);
+                let force_magnitude = force_r / r;
+                for k in 0..3 {
+                    force[k] += force_magnitude * r_ij[k];
+                }
+                // Every pair is visited twice, so each visit carries half the pair terms
+                energy += 0.5 * pair_energy;
+                add_pair_virial(&mut virial, r_ij, 0.5 * force_magnitude);
+            }
+        }
+        (force, energy, virial)
+    }).collect();
+
+    let [potential_energy, xx, yy, zz, xy, xz, yz] = ordered_sum(&per_atom, |(_, e, w)| [*e, w[0], w[1], w[2], w[3], w[4], w[5]]);
+    let virial_tensor = [xx, yy, zz, xy, xz, yz];
+    let virial = virial_tensor[0] + virial_tensor[1] + virial_tensor[2];
+    let atom_energies = per_atom.iter().map(|(_, e, _)| *e).collect();
+    let atom_virials = per_atom.iter().map(|(_, _, w)| *w).collect();
+    let forces = per_atom.into_iter().map(|(f, _, _)| f).collect();
+
+    ForceOutput { forces, potential_energy, virial, virial_tensor, atom_energies, atom_virials }
+}
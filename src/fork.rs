@@ -0,0 +1,232 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::checkpoint::Checkpoint;
+use crate::cli::Args;
+use crate::equilibration::EquilibrationDetector;
+use crate::profile::Profile;
+use crate::random::Philox;
+use crate::regions::Region;
+use crate::simulation::{maxwell_boltzmann_velocities, remove_com_velocity, remove_mobile_com_velocity, MASS_ARGON};
+
+const USAGE: &str = "Usage: sim fork <checkpoint.bin> --reseed [--count 10] [--seed S] [--temperature T] \
+[--steps N] [--jobs 1] [--prepare-only] [--output-dir fork]";
+
+// `sim fork checkpoint.bin --count 10 --reseed`: branches one saved main-run state into
+// independent continuations. Every branch keeps the positions, draws fresh Maxwell-Boltzmann
+// velocities from its own seed and runs as a new main run in `<output-dir>/branch_<i>`.
+pub fn run(args: &[String]) {
+    let args = Args::parse(args);
+    if args.positional.len() != 1 {
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
+    }
+    let path = &args.positional[0];
+    let count: usize = args.get("count", 10);
+    let seed: u64 = args.get("seed", rand::random());
+    let jobs: usize = args.get("jobs", 1);
+    let output_dir: String = args.get("output-dir", "fork".to_string());
+    if !args.has("reseed") {
+        // The main run has no random forces, so branches with the saved velocities would all
+        // repeat the same trajectory
+        eprintln!("--reseed is required: without new velocities every branch follows the same deterministic trajectory");
+        std::process::exit(1);
+    }
+    if count == 0 || jobs == 0 {
+        eprintln!("--count and --jobs must be at least 1");
+        std::process::exit(1);
+    }
+
+    let state = Checkpoint::read(path).unwrap_or_else(|e| {
+        eprintln!("Failed to read {}: {}", path, e);
+        std::process::exit(1);
+    });
+    let temperature: f64 = args.get("temperature", state.target_temperature);
+    let steps: usize = args.get("steps", state.total_steps - state.step);
+    if steps == 0 {
+        eprintln!("{} is at the end of its run, give the branch length with --steps", path);
+        std::process::exit(1);
+    }
+
+    println!(
+        "Forking {} atoms at step {} ({:.3} ps) into {} branches of {} steps at {} K, seeds {}..{}",
+        state.num_atoms(),
+        state.step,
+        state.step as f64 * state.timestep,
+        count,
+        steps,
+        temperature,
+        seed,
+        seed.wrapping_add(count as u64 - 1)
+    );
+    let width = (count - 1).to_string().len();
+    let branches: Vec<String> = (0..count).map(|i| {
+        let dir = format!("{}/branch_{:0width$}", output_dir, i, width = width);
+        fs::create_dir_all(&dir).unwrap_or_else(|e| {
+            eprintln!("Failed to create {}: {}", dir, e);
+            std::process::exit(1);
+        });
+        let branch = branch_state(&state, path, &dir, steps, temperature, seed.wrapping_add(i as u64));
+        if let Err(e) = branch.write(&branch.checkpoint_path) {
+            eprintln!("Failed to write {}: {}", branch.checkpoint_path, e);
+            std::process::exit(1);
+        }
+        branch.checkpoint_path
+    }).collect();
+
+    if args.has("prepare-only") {
+        println!("Wrote {} branch checkpoints to {}. Run each with: sim resume <checkpoint>", count, output_dir);
+        return;
+    }
+
+    // Branches run as separate `sim resume` processes, `jobs` at a time, splitting the threads between them
+    let exe = env::current_exe().expect("Failed to locate the sim executable");
+    let threads = rayon::current_num_threads();
+    let threads_per_job = (threads / jobs).max(1);
+    let next = AtomicUsize::new(0);
+    let failed = Mutex::new(Vec::new());
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.min(count) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(checkpoint) = branches.get(i) else { break };
+                let dir = Path::new(checkpoint).parent().unwrap();
+                let log = dir.join("log.txt");
+                let status = fs::File::create(&log).and_then(|file| {
+                    Command::new(&exe)
+                        .arg("resume")
+                        .arg(checkpoint)
+                        .env("RAYON_NUM_THREADS", threads_per_job.to_string())
+                        .stdout(Stdio::from(file.try_clone()?))
+                        .stderr(Stdio::from(file))
+                        .status()
+                });
+                match status {
+                    Ok(status) if status.success() => println!("Branch {} done, log in {}", i, log.display()),
+                    Ok(status) => {
+                        eprintln!("Branch {} failed ({}), see {}", i, status, log.display());
+                        failed.lock().unwrap().push(i);
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to start branch {}: {}", i, e);
+                        failed.lock().unwrap().push(i);
+                    }
+                }
+            });
+        }
+    });
+
+    let failed = failed.into_inner().unwrap();
+    if !failed.is_empty() {
+        eprintln!("{} of {} branches failed. Continue each with: sim resume {}/branch_<i>/checkpoint.bin", failed.len(), count, output_dir);
+        std::process::exit(1);
+    }
+    println!("All {} branches completed in {}", count, output_dir);
+}
+
+// A fresh main run of `steps` steps starting from the configuration in `state`, with new
+// velocities and its output in `dir`
+fn branch_state(state: &Checkpoint, source: &str, dir: &str, steps: usize, temperature: f64, seed: u64) -> Checkpoint {
+    let n = state.num_atoms();
+    let dt = state.timestep;
+    let mut velocities = maxwell_boltzmann_velocities(n, temperature, MASS_ARGON, &Philox::new(seed));
+    match &state.frozen {
+        Some(frozen) => {
+            for (vel, _) in velocities.iter_mut().zip(frozen).filter(|(_, &f)| f) {
+                *vel = [0.0; 3];
+            }
+            remove_mobile_com_velocity(&mut velocities, frozen);
+        }
+        None => {
+            remove_com_velocity(&mut velocities);
+        }
+    }
+    // Seed the previous positions so the first Verlet step starts from the new velocities
+    let positions_old = state.positions.iter().zip(velocities.iter()).map(|(pos, vel)| {
+        [pos[0] - vel[0] * dt, pos[1] - vel[1] * dt, pos[2] - vel[2] * dt]
+    }).collect();
+    let output_name = Path::new(&state.output_path).file_name().unwrap().to_string_lossy();
+    let thermo_name = Path::new(&state.thermo_path).file_name().unwrap().to_string_lossy();
+    let heat_current_name = Path::new(&state.heat_current_path).file_name().unwrap().to_string_lossy();
+    let colvars_name = Path::new(&state.colvars_path).file_name().unwrap().to_string_lossy();
+    let hills_name = Path::new(&state.hills_path).file_name().unwrap().to_string_lossy();
+    let fes_name = Path::new(&state.fes_path).file_name().unwrap().to_string_lossy();
+    let profile_name = Path::new(&state.profile_path).file_name().unwrap().to_string_lossy();
+    let script_log_name = Path::new(&state.script_log_path).file_name().unwrap().to_string_lossy();
+    Checkpoint {
+        box_lengths: state.box_lengths,
+        boundaries: state.boundaries,
+        walls: state.walls.clone(),
+        frozen: state.frozen.clone(),
+        body_force: state.body_force,
+        restraints: state.restraints.clone(),
+        // The branch starts its clock at 0 where the indenter has got to
+        indenter: state.indenter.map(|indenter| Region { shape: indenter.shape_at(state.step as f64 * dt), ..indenter }),
+        container: state.container,
+        pull: state.pull.as_ref().map(|pull| pull.restarted(state.step as f64 * dt)),
+        timestep: dt,
+        total_steps: steps,
+        snapshot_interval: state.snapshot_interval,
+        target_temperature: temperature,
+        tau: state.tau,
+        remove_com_every: state.remove_com_every,
+        deterministic: state.deterministic,
+        single_precision: state.single_precision,
+        init_from: Some(source.to_string()),
+        seed: Some(seed),
+        units: state.units,
+        output_format: state.output_format.clone(),
+        output_path: format!("{}/{}", dir, output_name),
+        dump_columns: state.dump_columns,
+        thermo_every: state.thermo_every,
+        thermo_path: format!("{}/{}", dir, thermo_name),
+        stress: state.stress,
+        heat_current_every: state.heat_current_every,
+        heat_current_path: format!("{}/{}", dir, heat_current_name),
+        colvars: state.colvars.clone(),
+        colvars_every: state.colvars_every,
+        colvars_path: format!("{}/{}", dir, colvars_name),
+        // Branches carry on under the bias built so far
+        metadynamics: state.metadynamics.clone(),
+        hills_path: format!("{}/{}", dir, hills_name),
+        fes_path: format!("{}/{}", dir, fes_name),
+        fes_every: state.fes_every,
+        fes_bins: state.fes_bins,
+        profile_every: state.profile_every,
+        profile_path: format!("{}/{}", dir, profile_name),
+        profile: state.profile.as_ref().map(|profile| Profile::new(profile.axis, profile.bins)),
+        script: state.script.clone(),
+        script_every: state.script_every,
+        script_log_path: format!("{}/{}", dir, script_log_name),
+        monitor: state.monitor,
+        equilibration: state.equilibration.as_ref().map(|detector| EquilibrationDetector::new(detector.every, detector.production)),
+        status_path: state.status_path.as_ref().map(|path| {
+            format!("{}/{}", dir, Path::new(path).file_name().unwrap().to_string_lossy())
+        }),
+        checkpoint_every: state.checkpoint_every,
+        checkpoint_path: format!("{}/checkpoint.bin", dir),
+        step: 0,
+        positions: state.positions.clone(),
+        positions_old,
+        velocities,
+        temperature_sum: 0.0,
+        statistics_from: 0,
+        final_temperature: 0.0,
+        trajectory_bytes: 0,
+        thermo_bytes: 0,
+        heat_current_bytes: 0,
+        colvars_bytes: 0,
+        hills_bytes: 0,
+        script_bytes: 0,
+        frame_pressure: Vec::new(),
+        frame_stress: Vec::new(),
+        anomalies: Vec::new(),
+        started_at: SystemTime::now(),
+        wall_seconds: 0.0,
+    }
+}
@@ -0,0 +1,279 @@
+use std::fs::File;
+use std::io::BufWriter;
+
+use crate::cli::{self, Args};
+use crate::glass::progress_bar;
+use crate::input;
+use crate::neighbors::CellList;
+use crate::random::Philox;
+use crate::simulation::{LangevinThermostat, Simulation, KB};
+use crate::table::TableWriter;
+use crate::xyz;
+
+const USAGE: &str = "Usage: sim gcmc [input.xyz|input.data|input.pdb] --chemical-potential MU [--box L] \
+[--frozen INDICES] [--temperature 87.3] [--timestep 0.005] [--cutoff 2.5] [--friction 1.0] [--cycles 1000] \
+[--equilibration 200] [--md-steps 100] [--exchanges 100] [--log-every 1] [--log gcmc.csv] [--seed S] [--output gcmc.xyz]";
+
+// Grand-canonical exchange moves at fixed chemical potential, temperature and volume. The
+// chemical potential is measured from an ideal gas at unit number density, so an ideal gas at
+// density rho has mu = kT ln rho and the thermal wavelength drops out:
+//   insertion: min(1, V / (N + 1) exp[(mu - dU) / kT])
+//   deletion:  min(1, N / V exp[-(mu + dU) / kT])
+// with N the number of mobile atoms. Frozen atoms, like an adsorbent, are never deleted.
+pub struct Exchanger {
+    pub chemical_potential: f64,
+    pub temperature: f64,
+    pub insertions: (u64, u64),
+    pub deletions: (u64, u64),
+    rng: Philox,
+    attempts: u64,
+}
+
+impl Exchanger {
+    pub fn new(chemical_potential: f64, temperature: f64, seed: u64) -> Self {
+        Exchanger {
+            chemical_potential,
+            temperature,
+            insertions: (0, 0),
+            deletions: (0, 0),
+            rng: Philox::new(seed),
+            attempts: 0,
+        }
+    }
+
+    // Energy of the atom at `i` in `positions` with all the others, through the pair potential
+    // of `sim` with its cutoff and switching, so the moves see the energy the dynamics does. Well
+    // depths are mixed as in the engine; atoms past the end of its `atom_epsilons` have depth 1.
+    fn atom_energy(sim: &Simulation, cells: &CellList, positions: &[[f64; 3]], i: usize) -> f64 {
+        let epsilons = sim.atom_epsilons();
+        let depth = |j: usize| epsilons.and_then(|e| e.get(j)).copied().unwrap_or(1.0);
+        cells.neighbours(positions, i).iter()
+            .map(|&(j, delta)| (depth(i) * depth(j)).sqrt() * sim.pair_energy((delta[0] * delta[0] + delta[1] * delta[1] + delta[2] * delta[2]).sqrt()))
+            .sum()
+    }
+
+    // `count` insertion or deletion attempts, each equally likely, on the atoms of `sim`.
+    // Inserted atoms get Maxwell-Boltzmann velocities at the temperature. Returns the number
+    // of atoms inserted minus the number deleted.
+    pub fn exchange(&mut self, sim: &mut Simulation, count: usize) -> i64 {
+        assert!(sim.cell().is_orthorhombic() && sim.box_lengths().iter().all(|&l| l == sim.box_length()), "GCMC needs a cubic box");
+        let l = sim.box_length();
+        let volume = sim.volume();
+        let cutoff = sim.effective_cutoff();
+        let kt = KB * self.temperature;
+        let sigma = (kt / sim.mass).sqrt();
+        let mut cells = CellList::new(sim.positions(), l, cutoff);
+        let mut change = 0;
+        for _ in 0..count {
+            let u = self.rng.uniforms(self.attempts, 0, 0);
+            let normals = self.rng.normals(self.attempts, 0, 1);
+            let accept = self.rng.uniforms(self.attempts, 0, 2)[0];
+            self.attempts += 1;
+            let frozen = sim.frozen.as_deref();
+            let mobile: Vec<usize> = (0..sim.num_atoms()).filter(|&i| !frozen.is_some_and(|f| f[i])).collect();
+            let n = mobile.len() as f64;
+            if u[0] < 0.5 {
+                let candidate = [u[1] * l, u[2] * l, u[3] * l];
+                let mut positions = sim.positions().to_vec();
+                positions.push(candidate);
+                let energy = Self::atom_energy(sim, &cells, &positions, positions.len() - 1);
+                self.insertions.0 += 1;
+                let probability = volume / (n + 1.0) * ((self.chemical_potential - energy) / kt).exp();
+                if accept < probability {
+                    self.insertions.1 += 1;
+                    sim.insert_atoms(&[candidate], &[[sigma * normals[0], sigma * normals[1], sigma * normals[2]]]);
+                    cells = CellList::new(sim.positions(), l, cutoff);
+                    change += 1;
+                }
+            } else if !mobile.is_empty() {
+                let i = mobile[((u[1] * n) as usize).min(mobile.len() - 1)];
+                // Deleting the atom takes away its energy, dU = -energy
+                let energy = Self::atom_energy(sim, &cells, sim.positions(), i);
+                self.deletions.0 += 1;
+                let probability = n / volume * (-(self.chemical_potential - energy) / kt).exp();
+                if accept < probability {
+                    self.deletions.1 += 1;
+                    sim.remove_atoms(&[i]);
+                    cells = CellList::new(sim.positions(), l, cutoff);
+                    change -= 1;
+                }
+            }
+        }
+        change
+    }
+}
+
+// Hybrid GCMC/MD: cycles of `--md-steps` Langevin NVT steps followed by `--exchanges`
+// insertion and deletion attempts, from a configuration file or an empty box. Averages of the
+// number of mobile atoms over the cycles after `--equilibration` give one point of an
+// adsorption isotherm.
+pub fn run(args: &[String]) {
+    let args = Args::parse(args);
+    if args.positional.len() > 1 || !args.has("chemical-potential") {
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
+    }
+    let chemical_potential: f64 = args.get("chemical-potential", 0.0);
+    let temperature: f64 = args.get("temperature", 87.3);
+    let dt: f64 = args.get("timestep", 0.005);
+    let friction: f64 = args.get("friction", 1.0);
+    let cycles: usize = args.get("cycles", 1000);
+    let equilibration: usize = args.get("equilibration", 200);
+    let md_steps: usize = args.get("md-steps", 100);
+    let exchanges: usize = args.get("exchanges", 100);
+    let log_every: usize = args.get("log-every", 1);
+    let log_path: String = args.get("log", "gcmc.csv".to_string());
+    let seed: u64 = args.get("seed", rand::random());
+    let output: String = args.get("output", "gcmc.xyz".to_string());
+    if temperature <= 0.0 || friction <= 0.0 {
+        eprintln!("--temperature and --friction must be positive");
+        std::process::exit(1);
+    }
+    if log_every == 0 || equilibration >= cycles {
+        eprintln!("--log-every must be at least 1 and --equilibration less than --cycles");
+        std::process::exit(1);
+    }
+
+    let (positions, velocities, box_length) = match args.positional.first() {
+        Some(path) => {
+            let frame = input::read_configuration(path).unwrap_or_else(|e| {
+                eprintln!("Failed to read {}: {}", path, e);
+                std::process::exit(1);
+            });
+            let box_length = input::cubic_box_length(path, frame.box_lengths, args.get_opt("box")).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            });
+            let velocities = frame.velocities.unwrap_or_else(|| vec![[0.0; 3]; frame.positions.len()]);
+            (frame.positions, velocities, box_length)
+        }
+        None => {
+            let Some(box_length) = args.get_opt::<f64>("box") else {
+                eprintln!("Starting from an empty box needs --box");
+                std::process::exit(1);
+            };
+            (Vec::new(), Vec::new(), box_length)
+        }
+    };
+    let n = positions.len();
+    let cutoff = cli::cutoff_from_args(&args, 2.5, n.max(1), box_length);
+    let mut sim = Simulation::new(positions, velocities, box_length, dt, cutoff);
+    if let Some(text) = args.get_opt::<String>("frozen") {
+        let frozen = cli::parse_indices(&text).filter(|atoms| atoms.iter().all(|&i| i < n)).unwrap_or_else(|| {
+            eprintln!("Invalid --frozen atoms {}", text);
+            std::process::exit(1);
+        });
+        let mut flags = vec![false; n];
+        for i in frozen {
+            flags[i] = true;
+        }
+        sim.frozen = Some(flags);
+    }
+    sim.langevin = Some(LangevinThermostat { target_temperature: temperature, friction, seed });
+    let mut exchanger = Exchanger::new(chemical_potential, temperature, seed ^ 0x9c3c_e4c4);
+    let fixed = n - sim.mobile_atoms();
+    println!(
+        "GCMC/MD at mu = {} and {} K in a box of {}: {} mobile and {} frozen atoms to start, seed {}",
+        chemical_potential, temperature, box_length, n - fixed, fixed, seed
+    );
+
+    let columns = ["cycle", "time", "atoms", "density", "insertion_acceptance", "deletion_acceptance", "potential_energy", "temperature"];
+    let mut log = TableWriter::create(&log_path, &columns).unwrap_or_else(|e| {
+        eprintln!("Failed to create {}: {}", log_path, e);
+        std::process::exit(1);
+    });
+    let (mut insertions, mut deletions) = (exchanger.insertions, exchanger.deletions);
+    let ratio = |(attempted, accepted): (u64, u64), (attempted_before, accepted_before): (u64, u64)| {
+        if attempted > attempted_before {
+            (accepted - accepted_before) as f64 / (attempted - attempted_before) as f64
+        } else {
+            f64::NAN
+        }
+    };
+    let (mut atoms_sum, mut samples) = (0.0, 0);
+    let pb = progress_bar(cycles);
+    for cycle in 1..=cycles {
+        for _ in 0..md_steps {
+            sim.step();
+        }
+        exchanger.exchange(&mut sim, exchanges);
+        let mobile = sim.mobile_atoms();
+        if cycle > equilibration {
+            atoms_sum += mobile as f64;
+            samples += 1;
+        }
+        if cycle.is_multiple_of(log_every) {
+            log.write_row(&[
+                cycle as f64,
+                sim.time(),
+                mobile as f64,
+                mobile as f64 / sim.volume(),
+                ratio(exchanger.insertions, insertions),
+                ratio(exchanger.deletions, deletions),
+                sim.potential_energy(),
+                sim.temperature(),
+            ]).expect("Failed to write GCMC log");
+            (insertions, deletions) = (exchanger.insertions, exchanger.deletions);
+            pb.set_message(format!("{} atoms", mobile));
+        }
+        pb.inc(1);
+    }
+    pb.finish_and_clear();
+    log.finish().expect("Failed to write GCMC log");
+
+    let mean = atoms_sum / samples as f64;
+    println!(
+        "Insertions accepted {} of {}, deletions {} of {}",
+        exchanger.insertions.1, exchanger.insertions.0, exchanger.deletions.1, exchanger.deletions.0
+    );
+    println!(
+        "Mean over the last {} cycles: {:.3} mobile atoms, density {:.5}",
+        samples,
+        mean,
+        mean / sim.volume()
+    );
+
+    let mut file = BufWriter::new(File::create(&output).expect("Failed to create output file"));
+    let comment = format!("Temperature={} ChemicalPotential={} Time={}", temperature, chemical_potential, sim.time());
+    xyz::write_frame(&mut file, sim.positions(), Some(sim.velocities()), box_length, &comment).unwrap();
+    println!("Final configuration saved to {}, log in {}", output, log_path);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::forces::LennardJones;
+    use crate::simulation::initial_state;
+
+    #[test]
+    fn ideal_gas_fills_to_the_chemical_potential() {
+        // With epsilon = 0 every move is ideal and <N> = V exp(mu / kT)
+        let (box_length, temperature, density) = (10.0, 87.3, 0.02);
+        let mut sim = Simulation::new(Vec::new(), Vec::new(), box_length, 0.005, 2.5);
+        sim.set_potential(LennardJones { sigma: 1.0, epsilon: 0.0 });
+        let mut exchanger = Exchanger::new(KB * temperature * f64::ln(density), temperature, 5);
+        exchanger.exchange(&mut sim, 2000);
+        let samples = 5000;
+        let mut sum = 0.0;
+        for _ in 0..samples {
+            exchanger.exchange(&mut sim, 10);
+            sum += sim.num_atoms() as f64;
+        }
+        let expected = density * sim.volume();
+        let mean = sum / samples as f64;
+        assert!((mean - expected).abs() < 0.05 * expected, "<N> = {} against {}", mean, expected);
+    }
+
+    #[test]
+    fn deletion_energy_is_the_switched_energy() {
+        let mut sim = initial_state(108, 0.8, 87.3, 0.005, 2.5, 3);
+        sim.set_switch_fraction(Some(0.8));
+        let cutoff = sim.effective_cutoff();
+        let cells = CellList::new(sim.positions(), sim.box_length(), cutoff);
+        let energy = Exchanger::atom_energy(&sim, &cells, sim.positions(), 17);
+        let before = sim.potential_energy();
+        sim.remove_atoms(&[17]);
+        let change = before - sim.potential_energy();
+        assert!((energy - change).abs() < 1e-9 * change.abs(), "{} against {}", energy, change);
+    }
+}
@@ -0,0 +1,425 @@
+// Periodic box geometry: minimum-image separations, wrapping into the box and conversions
+// between Cartesian and fractional coordinates, for cubic, orthorhombic and triclinic cells.
+// The free functions cover the cubic box of the simulation engine; `Cell` handles the rest.
+
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+// What happens at the two faces of the box normal to one axis: periodic images, specular
+// reflection back into the box, or nothing, letting atoms leave it
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Boundary {
+    Periodic,
+    Reflective,
+    Open,
+}
+
+impl FromStr for Boundary {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String> {
+        match text {
+            "p" | "periodic" => Ok(Boundary::Periodic),
+            "r" | "reflective" => Ok(Boundary::Reflective),
+            "o" | "open" => Ok(Boundary::Open),
+            _ => Err(format!("unknown boundary {}, expected periodic, reflective or open", text)),
+        }
+    }
+}
+
+// Separation `a - b` reduced to its nearest periodic image in a cubic box
+pub fn minimum_image_vector(a: &[f64; 3], b: &[f64; 3], box_length: f64) -> [f64; 3] {
+    let mut delta = [0.0; 3];
+    for k in 0..3 {
+        delta[k] = a[k] - b[k];
+        delta[k] -= (delta[k] / box_length).round() * box_length;
+    }
+    delta
+}
+
+pub fn minimum_image_distance(a: &[f64; 3], b: &[f64; 3], box_length: f64) -> f64 {
+    let mut r_sq = 0.0;
+    for k in 0..3 {
+        let mut delta = a[k] - b[k];
+        delta -= (delta / box_length).round() * box_length;
+        r_sq += delta * delta;
+    }
+    r_sq.sqrt()
+}
+
+// Position mapped into the cubic box [0, box_length)
+pub fn wrap(pos: &[f64; 3], box_length: f64) -> [f64; 3] {
+    pos.map(|coord| wrap_coordinate(coord, box_length))
+}
+
+// `x` modulo `period` in [0, period). A plain rem_euclid rounds a tiny negative `x` up to
+// `period` itself, which is outside the range and the same point as 0.
+fn wrap_coordinate(x: f64, period: f64) -> f64 {
+    let wrapped = x.rem_euclid(period);
+    if wrapped < period {
+        wrapped
+    } else {
+        0.0
+    }
+}
+
+// Inverse of a 3x3 matrix, or None when it is singular to rounding
+pub fn invert3(m: &[[f64; 3]; 3]) -> Option<[[f64; 3]; 3]> {
+    let det = determinant(m);
+    let scale = m[0][0].abs() + m[1][1].abs() + m[2][2].abs();
+    if det.abs() <= 1e-12 * scale.powi(3) {
+        return None;
+    }
+    let mut inverse = [[0.0; 3]; 3];
+    for (a, row) in inverse.iter_mut().enumerate() {
+        for (b, value) in row.iter_mut().enumerate() {
+            // Cofactor of element (b, a)
+            let (r1, r2) = ((b + 1) % 3, (b + 2) % 3);
+            let (c1, c2) = ((a + 1) % 3, (a + 2) % 3);
+            *value = (m[r1][c1] * m[r2][c2] - m[r1][c2] * m[r2][c1]) / det;
+        }
+    }
+    Some(inverse)
+}
+
+fn determinant(m: &[[f64; 3]; 3]) -> f64 {
+    m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+        - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+}
+
+// Periodic cell spanned by the edge vectors a, b and c, the rows of `vectors`, with the origin
+// at a corner. A position is f0 a + f1 b + f2 c for fractional coordinates f in [0, 1).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cell {
+    vectors: [[f64; 3]; 3],
+    inverse: [[f64; 3]; 3],
+    orthorhombic: bool,
+}
+
+impl Cell {
+    pub fn cubic(length: f64) -> Self {
+        Cell::orthorhombic([length; 3])
+    }
+
+    pub fn orthorhombic(lengths: [f64; 3]) -> Self {
+        let mut vectors = [[0.0; 3]; 3];
+        let mut inverse = [[0.0; 3]; 3];
+        for k in 0..3 {
+            vectors[k][k] = lengths[k];
+            inverse[k][k] = 1.0 / lengths[k];
+        }
+        Cell { vectors, inverse, orthorhombic: true }
+    }
+
+    // Any cell from its edge vectors; None when they are left-handed or (nearly) coplanar
+    pub fn triclinic(vectors: [[f64; 3]; 3]) -> Option<Self> {
+        if determinant(&vectors) <= 0.0 {
+            return None;
+        }
+        let inverse = invert3(&vectors)?;
+        let orthorhombic = (0..3).all(|a| (0..3).all(|b| a == b || vectors[a][b] == 0.0));
+        Some(Cell { vectors, inverse, orthorhombic })
+    }
+
+    // LAMMPS convention: a = (lx, 0, 0), b = (xy, ly, 0), c = (xz, yz, lz), with the tilt
+    // factors given as [xy, xz, yz]
+    pub fn from_lammps(lengths: [f64; 3], tilts: [f64; 3]) -> Option<Self> {
+        let [lx, ly, lz] = lengths;
+        let [xy, xz, yz] = tilts;
+        Cell::triclinic([[lx, 0.0, 0.0], [xy, ly, 0.0], [xz, yz, lz]])
+    }
+
+    // Edge lengths a, b, c and angles alpha (b-c), beta (a-c), gamma (a-b) in degrees, as in
+    // PDB CRYST1 records and DCD unit cells, with a along x and b in the xy plane
+    pub fn from_parameters(lengths: [f64; 3], angles: [f64; 3]) -> Option<Self> {
+        // Exactly zero at right angles, so an orthorhombic cell is recognised as one
+        let cos = |degrees: f64| if degrees == 90.0 { 0.0 } else { degrees.to_radians().cos() };
+        let [a, b, c] = lengths;
+        let (cos_alpha, cos_beta, cos_gamma) = (cos(angles[0]), cos(angles[1]), cos(angles[2]));
+        let sin_gamma = (1.0 - cos_gamma * cos_gamma).sqrt();
+        let cy = (cos_alpha - cos_beta * cos_gamma) / sin_gamma;
+        let cz_sq = 1.0 - cos_beta * cos_beta - cy * cy;
+        if cz_sq.is_nan() || cz_sq <= 0.0 {
+            return None;
+        }
+        Cell::triclinic([
+            [a, 0.0, 0.0],
+            [b * cos_gamma, b * sin_gamma, 0.0],
+            [c * cos_beta, c * cy, c * cz_sq.sqrt()],
+        ])
+    }
+
+    pub fn vectors(&self) -> [[f64; 3]; 3] {
+        self.vectors
+    }
+
+    // Lengths of the edge vectors a, b and c
+    pub fn lengths(&self) -> [f64; 3] {
+        self.vectors.map(|v| norm(&v))
+    }
+
+    // Angles alpha, beta and gamma in degrees
+    pub fn angles(&self) -> [f64; 3] {
+        let [a, b, c] = self.vectors;
+        let angle = |u: &[f64; 3], v: &[f64; 3]| (dot(u, v) / (norm(u) * norm(v))).clamp(-1.0, 1.0).acos().to_degrees();
+        [angle(&b, &c), angle(&a, &c), angle(&a, &b)]
+    }
+
+    pub fn volume(&self) -> f64 {
+        determinant(&self.vectors)
+    }
+
+    // Distances between opposite faces, the widths across which a cutoff sphere has to fit
+    pub fn widths(&self) -> [f64; 3] {
+        if self.orthorhombic {
+            return std::array::from_fn(|k| self.vectors[k][k]);
+        }
+        // The rows of the transposed inverse are the reciprocal vectors, of length 1 / width
+        std::array::from_fn(|k| 1.0 / norm(&std::array::from_fn(|j| self.inverse[j][k])))
+    }
+
+    // The same cell with every edge vector multiplied by `factor`
+    pub fn scaled(&self, factor: f64) -> Self {
+        let vectors = self.vectors.map(|v| v.map(|x| x * factor));
+        let inverse = self.inverse.map(|v| v.map(|x| x / factor));
+        Cell { vectors, inverse, orthorhombic: self.orthorhombic }
+    }
+
+    pub fn is_orthorhombic(&self) -> bool {
+        self.orthorhombic
+    }
+
+    pub fn to_fractional(&self, pos: &[f64; 3]) -> [f64; 3] {
+        std::array::from_fn(|k| (0..3).map(|j| pos[j] * self.inverse[j][k]).sum())
+    }
+
+    pub fn to_cartesian(&self, fractional: &[f64; 3]) -> [f64; 3] {
+        std::array::from_fn(|j| (0..3).map(|k| fractional[k] * self.vectors[k][j]).sum())
+    }
+
+    // Position mapped into the cell, fractional coordinates in [0, 1)
+    pub fn wrap(&self, pos: &[f64; 3]) -> [f64; 3] {
+        if self.orthorhombic {
+            return std::array::from_fn(|k| wrap_coordinate(pos[k], self.vectors[k][k]));
+        }
+        self.to_cartesian(&self.to_fractional(pos).map(|f| wrap_coordinate(f, 1.0)))
+    }
+
+    // Shortest periodic image of a separation vector
+    pub fn minimum_image(&self, delta: &[f64; 3]) -> [f64; 3] {
+        if self.orthorhombic {
+            return std::array::from_fn(|k| {
+                let l = self.vectors[k][k];
+                delta[k] - (delta[k] / l).round() * l
+            });
+        }
+        // Rounding the fractional coordinates is only a first guess in a tilted cell; the
+        // shortest image is then among the neighbouring ones
+        let reduced = self.to_cartesian(&self.to_fractional(delta).map(|f| f - f.round()));
+        let mut best = reduced;
+        let mut best_sq = dot(&reduced, &reduced);
+        for i in -1..=1 {
+            for j in -1..=1 {
+                for k in -1..=1 {
+                    let shift = self.to_cartesian(&[i as f64, j as f64, k as f64]);
+                    let image = std::array::from_fn(|d| reduced[d] + shift[d]);
+                    let r_sq = dot(&image, &image);
+                    if r_sq < best_sq {
+                        best = image;
+                        best_sq = r_sq;
+                    }
+                }
+            }
+        }
+        best
+    }
+
+    // Image of a separation with fractional coordinates in [-1/2, 1/2]. Cheaper than
+    // `minimum_image` and the same whenever some image is within half the smallest of the
+    // `widths`, which is all a pair interaction with a cutoff that short needs.
+    pub fn nearest_image(&self, delta: &[f64; 3]) -> [f64; 3] {
+        if self.orthorhombic {
+            return std::array::from_fn(|k| {
+                let l = self.vectors[k][k];
+                delta[k] - (delta[k] / l).round() * l
+            });
+        }
+        self.to_cartesian(&self.to_fractional(delta).map(|f| f - f.round()))
+    }
+
+    // Minimum-image vector from `from` to `to`
+    pub fn displacement(&self, from: &[f64; 3], to: &[f64; 3]) -> [f64; 3] {
+        self.minimum_image(&std::array::from_fn(|k| to[k] - from[k]))
+    }
+
+    pub fn distance(&self, a: &[f64; 3], b: &[f64; 3]) -> f64 {
+        norm(&self.displacement(a, b))
+    }
+}
+
+fn dot(u: &[f64; 3], v: &[f64; 3]) -> f64 {
+    u[0] * v[0] + u[1] * v[1] + u[2] * v[2]
+}
+
+fn norm(v: &[f64; 3]) -> f64 {
+    dot(v, v).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn close(a: &[f64; 3], b: &[f64; 3], tolerance: f64) -> bool {
+        (0..3).all(|k| (a[k] - b[k]).abs() <= tolerance)
+    }
+
+    // Cells with tilts up to half an edge and far from right angles
+    fn skewed_cells() -> Vec<Cell> {
+        vec![
+            Cell::from_lammps([10.0, 8.0, 6.0], [5.0, -3.0, 4.0]).unwrap(),
+            Cell::from_lammps([4.0, 4.0, 9.0], [-2.0, 2.0, -2.0]).unwrap(),
+            Cell::from_parameters([5.0, 6.0, 7.0], [70.0, 100.0, 115.0]).unwrap(),
+            Cell::from_parameters([3.0, 3.0, 3.0], [60.0, 60.0, 60.0]).unwrap(),
+        ]
+    }
+
+    #[test]
+    fn minimum_image_vector_is_within_half_a_box() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let box_length = 7.5;
+        for _ in 0..1000 {
+            let a: [f64; 3] = std::array::from_fn(|_| rng.gen_range(-2.0..3.0) * box_length);
+            let b: [f64; 3] = std::array::from_fn(|_| rng.gen_range(-2.0..3.0) * box_length);
+            let delta = minimum_image_vector(&a, &b, box_length);
+            for k in 0..3 {
+                assert!(delta[k].abs() <= 0.5 * box_length + 1e-12);
+                let shift = (a[k] - b[k] - delta[k]) / box_length;
+                assert!((shift - shift.round()).abs() < 1e-9);
+            }
+            assert!((minimum_image_distance(&a, &b, box_length) - norm(&delta)).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn minimum_image_at_exactly_half_a_box() {
+        let box_length = 10.0;
+        let delta = minimum_image_vector(&[5.0, 0.0, 15.0], &[0.0, 5.0, 0.0], box_length);
+        assert_eq!(delta.map(f64::abs), [5.0; 3]);
+        assert_eq!(minimum_image_distance(&[5.0, 0.0, 0.0], &[0.0, 0.0, 0.0], box_length), 5.0);
+        assert_eq!(minimum_image_distance(&[10.0, 0.0, 0.0], &[0.0, 0.0, 0.0], box_length), 0.0);
+    }
+
+    #[test]
+    fn wrap_maps_boundary_points_into_the_box() {
+        let box_length = 4.0;
+        assert_eq!(wrap(&[0.0, 4.0, -4.0], box_length), [0.0; 3]);
+        assert_eq!(wrap(&[8.0 + 1.0, -0.5, 3.999], box_length), [1.0, 3.5, 3.999]);
+        // Rounding would put a point just below zero on the far face itself
+        for x in [-1e-17, -f64::MIN_POSITIVE, -4.0 - 1e-16] {
+            let wrapped = wrap(&[x; 3], box_length);
+            assert!(wrapped.iter().all(|&c| (0.0..box_length).contains(&c)), "{} wrapped to {:?}", x, wrapped);
+        }
+
+        let cell = Cell::orthorhombic([4.0, 5.0, 6.0]);
+        assert_eq!(cell.wrap(&[4.0, -5.0, 12.0]), [0.0; 3]);
+        assert_eq!(cell.wrap(&[-1.0, 5.5, -0.5]), [3.0, 0.5, 5.5]);
+        let wrapped = cell.wrap(&[-1e-17; 3]);
+        assert!((0..3).all(|k| (0.0..cell.lengths()[k]).contains(&wrapped[k])), "{:?}", wrapped);
+    }
+
+    #[test]
+    fn wrap_in_a_skewed_cell_moves_by_lattice_vectors() {
+        let mut rng = StdRng::seed_from_u64(2);
+        for cell in skewed_cells() {
+            let mut points: Vec<[f64; 3]> = (0..200).map(|_| std::array::from_fn(|_| rng.gen_range(-30.0..30.0))).collect();
+            // The corners and faces of the cell itself
+            points.extend([[0.0; 3], [1.0, 0.0, 0.0], [0.0, 1.0, 1.0], [1.0; 3], [-1e-17, 0.5, 1.0]].map(|f| cell.to_cartesian(&f)));
+            for pos in points {
+                let wrapped = cell.wrap(&pos);
+                // Converting back may round a fraction just below 1 up to it
+                let fractional = cell.to_fractional(&wrapped);
+                assert!(fractional.iter().all(|&f| (-1e-12..=1.0).contains(&f)), "{:?} wrapped to {:?}", pos, fractional);
+                let shift = cell.to_fractional(&std::array::from_fn(|k| pos[k] - wrapped[k]));
+                assert!(close(&shift, &shift.map(f64::round), 1e-9), "{:?}", shift);
+            }
+        }
+    }
+
+    #[test]
+    fn fractional_and_cartesian_round_trip() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut cells = skewed_cells();
+        cells.push(Cell::orthorhombic([3.0, 4.0, 5.0]));
+        for cell in cells {
+            let vectors = cell.vectors();
+            for k in 0..3 {
+                let mut unit = [0.0; 3];
+                unit[k] = 1.0;
+                assert!(close(&cell.to_cartesian(&unit), &vectors[k], 1e-12));
+                assert!(close(&cell.to_fractional(&vectors[k]), &unit, 1e-12));
+            }
+            for _ in 0..200 {
+                // Every coordinate on a face, inside or beyond the cell
+                let fractional: [f64; 3] = std::array::from_fn(|_| match rng.gen_range(0..4) {
+                    0 => 0.0,
+                    1 => 1.0,
+                    _ => rng.gen_range(-2.0..3.0),
+                });
+                assert!(close(&cell.to_fractional(&cell.to_cartesian(&fractional)), &fractional, 1e-12));
+                let pos: [f64; 3] = std::array::from_fn(|_| rng.gen_range(-20.0..20.0));
+                assert!(close(&cell.to_cartesian(&cell.to_fractional(&pos)), &pos, 1e-11));
+            }
+        }
+    }
+
+    #[test]
+    fn minimum_image_matches_a_search_over_images() {
+        let mut rng = StdRng::seed_from_u64(4);
+        for cell in skewed_cells() {
+            let vectors = cell.vectors();
+            let mut deltas: Vec<[f64; 3]> = (0..300).map(|_| std::array::from_fn(|_| rng.gen_range(-25.0..25.0))).collect();
+            // Half a lattice vector, which has two images of equal length
+            deltas.extend((0..3).map(|k| vectors[k].map(|x| 0.5 * x)));
+            let half_width = cell.widths().into_iter().fold(f64::INFINITY, f64::min) / 2.0;
+            for delta in deltas {
+                let fractional = cell.to_fractional(&delta).map(|f| f - f.round());
+                let mut shortest = f64::INFINITY;
+                for i in -4..=4 {
+                    for j in -4..=4 {
+                        for k in -4..=4 {
+                            let image = cell.to_cartesian(&[fractional[0] + i as f64, fractional[1] + j as f64, fractional[2] + k as f64]);
+                            shortest = shortest.min(norm(&image));
+                        }
+                    }
+                }
+                let image = cell.minimum_image(&delta);
+                assert!((norm(&image) - shortest).abs() < 1e-9, "{:?}: {} against {}", delta, norm(&image), shortest);
+                let shift = cell.to_fractional(&std::array::from_fn(|k| delta[k] - image[k]));
+                assert!(close(&shift, &shift.map(f64::round), 1e-9));
+
+                let nearest = cell.nearest_image(&delta);
+                assert!(cell.to_fractional(&nearest).iter().all(|f| f.abs() <= 0.5 + 1e-12));
+                if shortest < half_width {
+                    assert!(close(&nearest, &image, 1e-9));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cell_parameters_round_trip() {
+        let cell = Cell::from_parameters([5.0, 6.0, 7.0], [70.0, 100.0, 115.0]).unwrap();
+        assert!(close(&cell.lengths(), &[5.0, 6.0, 7.0], 1e-12));
+        assert!(close(&cell.angles(), &[70.0, 100.0, 115.0], 1e-9));
+        assert!(!cell.is_orthorhombic());
+        assert!(Cell::from_parameters([2.0, 3.0, 4.0], [90.0; 3]).unwrap().is_orthorhombic());
+        assert!((Cell::from_lammps([2.0, 3.0, 4.0], [1.0, 1.5, -1.0]).unwrap().volume() - 24.0).abs() < 1e-12);
+        // Left-handed, coplanar and impossible angles
+        assert!(Cell::triclinic([[1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]]).is_none());
+        assert!(Cell::triclinic([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [1.0, 1.0, 0.0]]).is_none());
+        assert!(Cell::from_parameters([1.0; 3], [10.0, 10.0, 170.0]).is_none());
+    }
+}
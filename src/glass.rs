@@ -0,0 +1,267 @@
+use indicatif::{HumanDuration, ProgressBar, ProgressStyle};
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+use std::time::{Instant, SystemTime};
+
+use crate::cli::{self, Args};
+use crate::notify::{Event, Notifier};
+use crate::results_db::{self, RunRecord};
+use crate::simulation::{initial_state, BerendsenBarostat, BerendsenThermostat, LangevinThermostat, Simulation};
+use crate::table::TableWriter;
+use crate::xyz;
+
+const USAGE: &str = "Usage: sim glass <num_atoms> [--density 0.8] [--t-melt 150] [--t-end 10] \
+[--rates 1.0,0.1] [--save-at 80,40] [--pressure 0.0] [--timestep 0.005] [--cutoff 2.5 | --cutoff-accuracy 1e-2] [--switch-fraction 0.9] \
+[--melt-steps 5000] [--thermostat berendsen|langevin] [--friction 1.0] \
+[--seed-init S] [--seed-equilibration S] [--seed-production S] [--log-interval 100] [--log-format csv|parquet] [--output-dir glass] [--results-db results.sqlite] [--notify-url URL] [--notify-on completed,failed]";
+
+// Glass preparation: melt and equilibrate in NPT, then quench the same melt at each requested
+// cooling rate (K per time unit) with a continuous linear temperature ramp, logging the density along the
+// way and saving configurations as the target temperature passes each requested value.
+pub fn run(args: &[String]) {
+    let args = Args::parse(args);
+    if args.positional.len() != 1 {
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
+    }
+
+    let n: usize = args.positional[0].parse().expect("Invalid number of atoms");
+    let density: f64 = args.get("density", 0.8);
+    let t_melt: f64 = args.get("t-melt", 150.0);
+    let t_end: f64 = args.get("t-end", 10.0);
+    let rates: Vec<f64> = args.get_list("rates", vec![1.0]);
+    let mut save_at: Vec<f64> = args.get_list("save-at", Vec::new());
+    let pressure: f64 = args.get("pressure", 0.0);
+    let dt: f64 = args.get("timestep", 0.005);
+    let cutoff = cli::cutoff_from_args(&args, 2.5, n, (n as f64 / density).cbrt());
+    let switch_fraction: Option<f64> = args.get_opt("switch-fraction");
+    let melt_steps: usize = args.get("melt-steps", 5000);
+    let thermostat: String = args.get("thermostat", "berendsen".to_string());
+    let friction: f64 = args.get("friction", 1.0);
+    // Independent streams: the initial velocities, the melt's thermal noise and the quenches'
+    // thermal noise. Fixing the first two and varying the third branches one equilibrated melt
+    // into independent quenches.
+    let seed_init: u64 = args.get("seed-init", rand::random());
+    let seed_equilibration: u64 = args.get("seed-equilibration", rand::random());
+    let seed_production: u64 = args.get("seed-production", rand::random());
+    let log_interval: usize = args.get("log-interval", 100);
+    let log_format: String = args.get("log-format", "csv".to_string());
+    let output_dir: String = args.get("output-dir", "glass".to_string());
+    let results_db_path: Option<String> = args.get_opt("results-db");
+
+    if [t_melt, t_end].iter().chain(&save_at).any(|t| !t.is_finite()) {
+        eprintln!("--t-melt, --t-end and --save-at temperatures must be finite");
+        std::process::exit(1);
+    }
+    if t_end >= t_melt {
+        eprintln!("--t-end must be below --t-melt");
+        std::process::exit(1);
+    }
+    if log_format != "csv" && log_format != "parquet" {
+        eprintln!("--log-format must be csv or parquet");
+        std::process::exit(1);
+    }
+    if results_db_path.is_some() && !results_db::available() {
+        eprintln!("--results-db requires a build with --features sqlite");
+        std::process::exit(1);
+    }
+    if switch_fraction.is_some_and(|f| !(0.0..1.0).contains(&f)) {
+        eprintln!("--switch-fraction must be in [0, 1)");
+        std::process::exit(1);
+    }
+    if thermostat != "berendsen" && thermostat != "langevin" {
+        eprintln!("--thermostat must be berendsen or langevin");
+        std::process::exit(1);
+    }
+    if thermostat != "langevin" && (args.has("seed-equilibration") || args.has("seed-production")) {
+        eprintln!("--seed-equilibration and --seed-production need --thermostat langevin, the Berendsen thermostat is deterministic");
+        std::process::exit(1);
+    }
+    if friction <= 0.0 {
+        eprintln!("--friction must be positive");
+        std::process::exit(1);
+    }
+    if rates.iter().any(|&rate| rate <= 0.0) {
+        eprintln!("Cooling rates must be positive");
+        std::process::exit(1);
+    }
+
+    let notifier = Notifier::from_args(&args);
+    if let Some(notifier) = &notifier {
+        notifier.install();
+    }
+
+    // The final glass is always saved; the other temperatures are visited from hot to cold
+    save_at.retain(|&t| t < t_melt && t > t_end);
+    save_at.push(t_end);
+    save_at.sort_by(|a, b| b.total_cmp(a));
+    save_at.dedup();
+
+    fs::create_dir_all(&output_dir).expect("Failed to create output directory");
+    let started_at = SystemTime::now();
+    let start_time = Instant::now();
+
+    let mut melt = initial_state(n, density, t_melt, dt, cutoff, seed_init);
+    if switch_fraction.is_some() {
+        melt.set_switch_fraction(switch_fraction);
+    }
+    if thermostat == "langevin" {
+        melt.langevin = Some(LangevinThermostat { target_temperature: t_melt, friction, seed: seed_equilibration });
+        println!("Seeds: init {}, equilibration {}, production {}", seed_init, seed_equilibration, seed_production);
+    } else {
+        melt.thermostat = Some(BerendsenThermostat { target_temperature: t_melt, tau: 0.1 });
+        println!("Seed: init {}", seed_init);
+    }
+    melt.barostat = Some(BerendsenBarostat { target_pressure: pressure, tau: 2.0, compressibility: 0.1 });
+
+    println!("Equilibrating melt at {} K for {} steps ({:.3} ps)", t_melt, melt_steps, melt_steps as f64 * dt);
+    let pb = progress_bar(melt_steps);
+    for _ in 0..melt_steps {
+        melt.step();
+        pb.inc(1);
+    }
+    pb.finish_with_message(format!("density {:.4}", melt.density()));
+    let mut observables = vec![("melt_density".to_string(), melt.density())];
+
+    let protocol = Protocol { t_melt, t_end, save_at, log_interval, log_format, output_dir };
+    for (index, &rate) in rates.iter().enumerate() {
+        let mut sim = melt.clone();
+        if let Some(langevin) = &mut sim.langevin {
+            // Every quench gets its own noise stream
+            langevin.seed = seed_production.wrapping_add(index as u64);
+        }
+        let sim = quench(sim, rate, &protocol);
+        println!(
+            "Rate {} K per time unit: final density {:.4}, potential energy per atom {:.4} ({:.4} tail-corrected)",
+            rate,
+            sim.density(),
+            sim.potential_energy() / n as f64,
+            sim.corrected_potential_energy() / n as f64
+        );
+        observables.push((format!("density_rate_{}", rate), sim.density()));
+        observables.push((format!("potential_energy_per_atom_rate_{}", rate), sim.potential_energy() / n as f64));
+    }
+
+    println!("Glass preparation completed. Output saved to {}", protocol.output_dir);
+
+    if let Some(notifier) = &notifier {
+        notifier.send(Event::Completed, &format!(
+            "Glass preparation of {} atoms at {} rate(s) completed in {}",
+            n,
+            rates.len(),
+            HumanDuration(start_time.elapsed())
+        ));
+    }
+
+    if let Some(path) = &results_db_path {
+        let mut run = RunRecord {
+            command: std::env::args().collect::<Vec<_>>().join(" "),
+            started_at,
+            wall_seconds: start_time.elapsed().as_secs_f64(),
+            parameters: vec![
+                ("protocol".to_string(), "glass".to_string()),
+                ("num_atoms".to_string(), n.to_string()),
+                ("density".to_string(), density.to_string()),
+                ("t_melt".to_string(), t_melt.to_string()),
+                ("t_end".to_string(), t_end.to_string()),
+                ("rates".to_string(), rates.iter().map(|r| r.to_string()).collect::<Vec<_>>().join(",")),
+                ("pressure".to_string(), pressure.to_string()),
+                ("timestep".to_string(), dt.to_string()),
+                ("cutoff".to_string(), cutoff.to_string()),
+                ("melt_steps".to_string(), melt_steps.to_string()),
+                ("thermostat".to_string(), thermostat.clone()),
+                ("seed_init".to_string(), seed_init.to_string()),
+            ],
+            observables,
+        };
+        if thermostat == "langevin" {
+            run.parameters.push(("friction".to_string(), friction.to_string()));
+            run.parameters.push(("seed_equilibration".to_string(), seed_equilibration.to_string()));
+            run.parameters.push(("seed_production".to_string(), seed_production.to_string()));
+        }
+        match results_db::record_run(path, &run) {
+            Ok(run_id) => println!("Run recorded as id {} in {}", run_id, path),
+            Err(e) => eprintln!("Failed to record run in {}: {}", path, e),
+        }
+    }
+}
+
+// Quench settings shared by every cooling rate
+struct Protocol {
+    t_melt: f64,
+    t_end: f64,
+    save_at: Vec<f64>,
+    log_interval: usize,
+    log_format: String,
+    output_dir: String,
+}
+
+fn quench(mut sim: Simulation, rate: f64, protocol: &Protocol) -> Simulation {
+    let Protocol { t_melt, t_end, ref save_at, log_interval, ref log_format, ref output_dir } = *protocol;
+    let output_dir = Path::new(output_dir);
+    let log_path = output_dir.join(format!("density_rate_{}.{}", rate, log_format));
+
+    let steps = ((t_melt - t_end) / (rate * sim.dt)).ceil() as usize;
+    println!("Quenching at {} K/ps over {} steps ({:.3} ps)", rate, steps, steps as f64 * sim.dt);
+
+    // Pressure and energy are logged both as computed within the cutoff and with the tail correction
+    let columns = [
+        "time",
+        "target_temperature",
+        "temperature",
+        "pressure",
+        "pressure_corrected",
+        "density",
+        "potential_energy_per_atom",
+        "potential_energy_per_atom_corrected",
+    ];
+    let mut log = TableWriter::create(&log_path, &columns).unwrap_or_else(|e| {
+        eprintln!("Failed to create {}: {}", log_path.display(), e);
+        std::process::exit(1);
+    });
+
+    let mut next_save = 0;
+    let pb = progress_bar(steps);
+    for step in 1..=steps {
+        let target_temperature = (t_melt - rate * step as f64 * sim.dt).max(t_end);
+        sim.set_target_temperature(target_temperature);
+        sim.step();
+
+        if step % log_interval == 0 || step == steps {
+            log.write_row(&[
+                step as f64 * sim.dt,
+                target_temperature,
+                sim.temperature(),
+                sim.pressure(),
+                sim.corrected_pressure(),
+                sim.density(),
+                sim.potential_energy() / sim.num_atoms() as f64,
+                sim.corrected_potential_energy() / sim.num_atoms() as f64,
+            ]).unwrap();
+        }
+
+        while next_save < save_at.len() && target_temperature <= save_at[next_save] {
+            let path = output_dir.join(format!("glass_rate_{}_T_{}.xyz", rate, save_at[next_save]));
+            let mut file = BufWriter::new(File::create(path).expect("Failed to create configuration file"));
+            let comment = format!("Temperature={} Time={}", save_at[next_save], step as f64 * sim.dt);
+            xyz::write_frame(&mut file, sim.positions(), Some(sim.velocities()), sim.box_length(), &comment).unwrap();
+            next_save += 1;
+        }
+
+        pb.inc(1);
+    }
+    pb.finish_with_message(format!("density {:.4}", sim.density()));
+    log.finish().unwrap();
+
+    sim
+}
+
+pub(crate) fn progress_bar(steps: usize) -> ProgressBar {
+    let pb = ProgressBar::new(steps as u64);
+    pb.set_style(ProgressStyle::default_bar()
+        .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}")
+        .unwrap()
+        .progress_chars("##-"));
+    pb
+}
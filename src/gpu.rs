@@ -0,0 +1,504 @@
+use rayon::prelude::*;
+
+use crate::cli::Args;
+use crate::forces::add_pair_virial;
+use crate::notify;
+
+// Main-run pair forces on a GPU through wgpu, behind `--backend gpu`. A WGSL compute shader
+// runs one invocation per atom over all the others, the positions streamed through workgroup
+// memory a tile at a time, the usual all-pairs N-body scheme. Every pair is evaluated from both
+// of its atoms, which costs twice the arithmetic but needs no atomics on floats. The GPU works
+// in single precision; the per-atom sums come back and are added up in f64 on the CPU, which
+// also keeps the integration. Like the CPU kernel there is no cutoff and no neighbour list.
+//
+// `--backend mixed` splits the pairs by distance instead: the CPU takes those closer than
+// `--mixed-cutoff` through a cell list, in f64, while the GPU works through all the farther
+// ones; the CPU work runs between submitting the shader and reading its results back, so the
+// two overlap every step.
+#[cfg(feature = "gpu")]
+pub struct GpuForces {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+    bind_group: wgpu::BindGroup,
+    params: wgpu::Buffer,
+    positions: wgpu::Buffer,
+    terms: wgpu::Buffer,
+    readback: wgpu::Buffer,
+    n: usize,
+    // Staging memory for the positions, reused every step
+    upload: Vec<u8>,
+    // The CPU's share of the pairs with `--backend mixed`
+    short_range: Option<ShortRange>,
+    // Name and API of the adapter, for the log
+    pub adapter: String,
+}
+
+#[cfg(not(feature = "gpu"))]
+pub struct GpuForces {
+    pub adapter: String,
+}
+
+// Force, potential energy, scalar virial and virial tensor of one atom
+type AtomTerms = ([f64; 3], f64, f64, [f64; 6]);
+
+#[cfg(feature = "gpu")]
+const SHADER: &str = r#"
+struct Params {
+    // Box lengths, and in w the squared distance below which pairs are left to the CPU
+    lengths: vec4<f32>,
+    // 1 for periodic axes, and the number of atoms in w
+    periodic: vec4<u32>,
+}
+
+struct AtomTerms {
+    force_energy: vec4<f32>,
+    // xx, yy, zz and the scalar virial
+    diagonal_virial: vec4<f32>,
+    // xy, xz, yz
+    off_diagonal: vec4<f32>,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> positions: array<vec4<f32>>;
+@group(0) @binding(2) var<storage, read_write> terms: array<AtomTerms>;
+
+const TILE: u32 = 64u;
+var<workgroup> tile: array<vec4<f32>, 64>;
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) global: vec3<u32>, @builtin(local_invocation_id) local: vec3<u32>) {
+    let n = params.periodic.w;
+    let i = global.x;
+    let in_range = i < n;
+    var position = vec3<f32>(0.0);
+    if in_range {
+        position = positions[i].xyz;
+    }
+    let lengths = params.lengths.xyz;
+    let periodic = vec3<f32>(params.periodic.xyz);
+    var force = vec3<f32>(0.0);
+    var energy = 0.0;
+    var virial = 0.0;
+    var diagonal = vec3<f32>(0.0);
+    var off_diagonal = vec3<f32>(0.0);
+    for (var start = 0u; start < n; start += TILE) {
+        if start + local.x < n {
+            tile[local.x] = positions[start + local.x];
+        }
+        workgroupBarrier();
+        let count = min(TILE, n - start);
+        for (var t = 0u; t < count; t++) {
+            if !in_range || start + t == i {
+                continue;
+            }
+            var d = position - tile[t].xyz;
+            d -= periodic * round(d / lengths) * lengths;
+            let r_sq = dot(d, d);
+            if r_sq < params.lengths.w {
+                continue;
+            }
+            let s6 = 1.0 / (r_sq * r_sq * r_sq);
+            let potential = 4.0 * (s6 * s6 - s6);
+            // -dU/dr / r, the same force as the CPU kernel
+            let magnitude = 24.0 * (2.0 * s6 * s6 - s6) / r_sq;
+            force += magnitude * d;
+            energy += 0.5 * potential;
+            virial += 0.5 * magnitude * r_sq;
+            let pair_scale = 0.5 * magnitude;
+            diagonal += pair_scale * d * d;
+            off_diagonal += pair_scale * vec3<f32>(d.x * d.y, d.x * d.z, d.y * d.z);
+        }
+        workgroupBarrier();
+    }
+    if in_range {
+        terms[i] = AtomTerms(vec4<f32>(force, energy), vec4<f32>(diagonal, virial), vec4<f32>(off_diagonal, 0.0));
+    }
+}
+"#;
+
+#[cfg(feature = "gpu")]
+const WORKGROUP: usize = 64;
+// f32 components of one atom's terms on the GPU
+#[cfg(feature = "gpu")]
+const TERMS: usize = 12;
+
+pub fn available() -> bool {
+    cfg!(feature = "gpu")
+}
+
+// From `--backend cpu|gpu|mixed` (cpu by default) and `--mixed-cutoff`, for `n` atoms
+pub fn from_args(args: &Args, n: usize) -> Option<GpuForces> {
+    let backend: String = args.get("backend", "cpu".to_string());
+    let short_range = match backend.as_str() {
+        "cpu" => return None,
+        "gpu" => None,
+        "mixed" => {
+            let cutoff: f64 = args.get("mixed-cutoff", 2.5);
+            if !(cutoff > 0.0 && cutoff.is_finite()) {
+                notify::fail("--mixed-cutoff must be positive");
+            }
+            Some(cutoff)
+        }
+        _ => {
+            notify::fail(&format!("Unknown --backend {}, expected cpu, gpu or mixed", backend));
+        }
+    };
+    if !available() {
+        notify::fail(&format!("--backend {} requires a build with --features gpu", backend));
+    }
+    let mut gpu = GpuForces::new(n).unwrap_or_else(|e| {
+        notify::fail(&format!("Failed to set up the GPU backend: {}", e));
+    });
+    match short_range {
+        Some(cutoff) => {
+            gpu.split_at(cutoff);
+            println!("Computing pairs closer than {} on the CPU and the rest on {}", cutoff, gpu.adapter);
+        }
+        None => println!("Computing forces on {}", gpu.adapter),
+    }
+    Some(gpu)
+}
+
+#[cfg(feature = "gpu")]
+impl GpuForces {
+    // Picks the high-performance adapter and sizes the buffers for `n` atoms
+    pub fn new(n: usize) -> Result<Self, String> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::new_without_display_handle_from_env());
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            ..Default::default()
+        })).map_err(|e| e.to_string())?;
+        let info = adapter.get_info();
+        let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+            label: Some("sim"),
+            ..Default::default()
+        })).map_err(|e| e.to_string())?;
+
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("pair forces"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("pair forces"),
+            layout: None,
+            module: &module,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+        let buffer = |label, size: usize, usage| device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: size.max(1) as u64,
+            usage,
+            mapped_at_creation: false,
+        });
+        let terms_size = n * TERMS * 4;
+        let params = buffer("params", 32, wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST);
+        let positions = buffer("positions", n * 16, wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST);
+        let terms = buffer("terms", terms_size, wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC);
+        let readback = buffer("readback", terms_size, wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("pair forces"),
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: params.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: positions.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 2, resource: terms.as_entire_binding() },
+            ],
+        });
+        let adapter = format!("{} ({:?})", info.name, info.backend);
+        Ok(GpuForces {
+            device,
+            queue,
+            pipeline,
+            bind_group,
+            params,
+            positions,
+            terms,
+            readback,
+            n,
+            upload: Vec::new(),
+            short_range: None,
+            adapter,
+        })
+    }
+
+    // Leaves the pairs closer than `cutoff` to the CPU from now on
+    pub fn split_at(&mut self, cutoff: f64) {
+        self.short_range = Some(ShortRange::new(cutoff));
+    }
+
+    // Force, potential energy, scalar virial and virial tensor of every atom into `terms`, each
+    // pair sharing its energy and virials equally between its two atoms, as the CPU kernel
+    // gives them
+    pub fn compute(&mut self, positions: &[[f64; 3]], l: [f64; 3], periodic: [bool; 3], terms: &mut Vec<AtomTerms>) {
+        assert_eq!(positions.len(), self.n, "the GPU buffers were sized for another number of atoms");
+        terms.clear();
+        if self.n == 0 {
+            return;
+        }
+        self.submit(positions, l, periodic);
+        let mut short_range = self.short_range.take();
+        if let Some(short_range) = &mut short_range {
+            short_range.compute(positions, l, periodic);
+        }
+        self.collect(terms);
+        if let Some(short_range) = &short_range {
+            for (total, part) in terms.iter_mut().zip(&short_range.terms) {
+                add_terms(total, part);
+            }
+        }
+        self.short_range = short_range;
+    }
+
+    // Uploads the positions and starts the shader, without waiting for it
+    fn submit(&mut self, positions: &[[f64; 3]], l: [f64; 3], periodic: [bool; 3]) {
+        let split = self.short_range.as_ref().map_or(0.0, |short_range| short_range.cutoff * short_range.cutoff);
+        let mut params = [0u8; 32];
+        let words = [l[0] as f32, l[1] as f32, l[2] as f32, split as f32].map(f32::to_ne_bytes).into_iter()
+            .chain([periodic[0] as u32, periodic[1] as u32, periodic[2] as u32, self.n as u32].map(u32::to_ne_bytes));
+        for (chunk, word) in params.chunks_exact_mut(4).zip(words) {
+            chunk.copy_from_slice(&word);
+        }
+        self.upload.clear();
+        self.upload.extend(positions.iter()
+            .flat_map(|pos| [pos[0] as f32, pos[1] as f32, pos[2] as f32, 0.0])
+            .flat_map(f32::to_ne_bytes));
+        self.queue.write_buffer(&self.params, 0, &params);
+        self.queue.write_buffer(&self.positions, 0, &self.upload);
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("pair forces") });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: Some("pair forces"), timestamp_writes: None });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &self.bind_group, &[]);
+            pass.dispatch_workgroups(self.n.div_ceil(WORKGROUP) as u32, 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(&self.terms, 0, &self.readback, 0, (self.n * TERMS * 4) as u64);
+        self.queue.submit([encoder.finish()]);
+    }
+
+    // Waits for the shader started by `submit` and reads its terms back
+    fn collect(&mut self, terms: &mut Vec<AtomTerms>) {
+        let slice = self.readback.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |result| result.expect("Failed to read forces back from the GPU"));
+        self.device.poll(wgpu::PollType::wait_indefinitely()).expect("Failed to wait for the GPU");
+        {
+            let bytes = slice.get_mapped_range();
+            terms.extend(bytes.chunks_exact(TERMS * 4).map(|atom| {
+                let v: [f64; TERMS] = std::array::from_fn(|c| f32::from_ne_bytes(atom[4 * c..4 * c + 4].try_into().unwrap()) as f64);
+                ([v[0], v[1], v[2]], v[3], v[7], [v[4], v[5], v[6], v[8], v[9], v[10]])
+            }));
+        }
+        self.readback.unmap();
+    }
+}
+
+#[cfg(not(feature = "gpu"))]
+impl GpuForces {
+    pub fn new(_n: usize) -> Result<Self, String> {
+        Err("built without GPU support".to_string())
+    }
+
+    pub fn compute(&mut self, _positions: &[[f64; 3]], _l: [f64; 3], _periodic: [bool; 3], _terms: &mut Vec<AtomTerms>) {
+        unreachable!("GpuForces cannot be created without the gpu feature")
+    }
+
+    pub fn split_at(&mut self, _cutoff: f64) {
+        unreachable!("GpuForces cannot be created without the gpu feature")
+    }
+}
+
+#[cfg(feature = "gpu")]
+fn add_terms(total: &mut AtomTerms, part: &AtomTerms) {
+    for k in 0..3 {
+        total.0[k] += part.0[k];
+    }
+    total.1 += part.1;
+    total.2 += part.2;
+    for c in 0..6 {
+        total.3[c] += part.3[c];
+    }
+}
+
+// The pairs closer than `cutoff`, for the CPU's share of `--backend mixed`. Atoms are sorted
+// into a grid of cells at least `cutoff` wide along each axis, and every atom sums over its own
+// and the neighbouring cells, so each pair is evaluated from both of its atoms as on the GPU.
+// An axis too short for three cells gets one; along open axes the grid does not wrap.
+pub struct ShortRange {
+    cutoff: f64,
+    // Atoms of each cell, as ranges of `order` starting at `starts`
+    starts: Vec<usize>,
+    order: Vec<usize>,
+    cell_of: Vec<usize>,
+    pub terms: Vec<AtomTerms>,
+}
+
+impl ShortRange {
+    pub fn new(cutoff: f64) -> Self {
+        ShortRange { cutoff, starts: Vec::new(), order: Vec::new(), cell_of: Vec::new(), terms: Vec::new() }
+    }
+
+    // Terms of every atom from its pairs closer than the cutoff into `terms`
+    pub fn compute(&mut self, positions: &[[f64; 3]], l: [f64; 3], periodic: [bool; 3]) {
+        let n = positions.len();
+        let cells = l.map(|length| match (length / self.cutoff).floor() as usize {
+            count if count < 3 => 1,
+            count => count,
+        });
+        let cell_coordinates = |pos: &[f64; 3]| -> [usize; 3] {
+            std::array::from_fn(|k| {
+                let coord = if periodic[k] { pos[k].rem_euclid(l[k]) } else { pos[k] };
+                ((coord / l[k] * cells[k] as f64).max(0.0) as usize).min(cells[k] - 1)
+            })
+        };
+        let index = |c: [usize; 3]| (c[0] * cells[1] + c[1]) * cells[2] + c[2];
+
+        // Counting sort of the atoms by cell
+        let count = cells.iter().product::<usize>();
+        self.cell_of.clear();
+        self.cell_of.extend(positions.iter().map(|pos| index(cell_coordinates(pos))));
+        self.starts.clear();
+        self.starts.resize(count + 1, 0);
+        for &cell in &self.cell_of {
+            self.starts[cell + 1] += 1;
+        }
+        for cell in 0..count {
+            self.starts[cell + 1] += self.starts[cell];
+        }
+        self.order.resize(n, 0);
+        let mut next = self.starts.clone();
+        for (i, &cell) in self.cell_of.iter().enumerate() {
+            self.order[next[cell]] = i;
+            next[cell] += 1;
+        }
+
+        let cutoff_sq = self.cutoff * self.cutoff;
+        let (starts, order) = (&self.starts, &self.order);
+        self.terms.resize(n, ([0.0; 3], 0.0, 0.0, [0.0; 6]));
+        self.terms.par_iter_mut().enumerate().for_each(|(i, total)| {
+            *total = ([0.0; 3], 0.0, 0.0, [0.0; 6]);
+            let home = cell_coordinates(&positions[i]);
+            // Cell coordinates next to the atom's own along each axis, each cell once
+            let spans: [Vec<usize>; 3] = std::array::from_fn(|k| {
+                let c = cells[k];
+                if c == 1 {
+                    vec![0]
+                } else if periodic[k] {
+                    vec![(home[k] + c - 1) % c, home[k], (home[k] + 1) % c]
+                } else {
+                    (home[k].saturating_sub(1)..=(home[k] + 1).min(c - 1)).collect()
+                }
+            });
+            for &x in &spans[0] {
+                for &y in &spans[1] {
+                    for &z in &spans[2] {
+                        let cell = index([x, y, z]);
+                        for &j in &order[starts[cell]..starts[cell + 1]] {
+                            if j == i {
+                                continue;
+                            }
+                            let mut r_ij = [0.0; 3];
+                            for k in 0..3 {
+                                r_ij[k] = positions[i][k] - positions[j][k];
+                                if periodic[k] {
+                                    r_ij[k] -= (r_ij[k] / l[k]).round() * l[k];
+                                }
+                            }
+                            let r_sq = r_ij[0] * r_ij[0] + r_ij[1] * r_ij[1] + r_ij[2] * r_ij[2];
+                            if r_sq >= cutoff_sq {
+                                continue;
+                            }
+                            let s6 = 1.0 / (r_sq * r_sq * r_sq);
+                            let potential = 4.0 * (s6 * s6 - s6);
+                            let magnitude = 24.0 * (2.0 * s6 * s6 - s6) / r_sq;
+                            for (f, d) in total.0.iter_mut().zip(r_ij) {
+                                *f += magnitude * d;
+                            }
+                            total.1 += 0.5 * potential;
+                            total.2 += 0.5 * magnitude * r_sq;
+                            add_pair_virial(&mut total.3, r_ij, 0.5 * magnitude);
+                        }
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::forces;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    // Every pair closer than `cutoff`, the slow way
+    fn brute_force(positions: &[[f64; 3]], l: [f64; 3], periodic: [bool; 3], cutoff: f64) -> Vec<AtomTerms> {
+        positions.iter().enumerate().map(|(i, a)| {
+            let mut total = ([0.0; 3], 0.0, 0.0, [0.0; 6]);
+            for (j, b) in positions.iter().enumerate() {
+                let r_ij: [f64; 3] = std::array::from_fn(|k| {
+                    let d = a[k] - b[k];
+                    if periodic[k] { d - (d / l[k]).round() * l[k] } else { d }
+                });
+                let r = r_ij.iter().map(|d| d * d).sum::<f64>().sqrt();
+                if j == i || r >= cutoff {
+                    continue;
+                }
+                let magnitude = forces::lj_force(r) / r;
+                for (f, d) in total.0.iter_mut().zip(r_ij) {
+                    *f += magnitude * d;
+                }
+                total.1 += 0.5 * forces::lj_potential(r);
+                total.2 += 0.5 * magnitude * r * r;
+                add_pair_virial(&mut total.3, r_ij, 0.5 * magnitude);
+            }
+            total
+        }).collect()
+    }
+
+    // A jittered lattice filling the box, so no two atoms get close enough to blow up
+    fn lattice(l: [f64; 3], seed: u64) -> Vec<[f64; 3]> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let counts = l.map(|length| (length / 1.1) as usize);
+        let mut positions = Vec::new();
+        for x in 0..counts[0] {
+            for y in 0..counts[1] {
+                for z in 0..counts[2] {
+                    let site = [x, y, z];
+                    positions.push(std::array::from_fn(|k| {
+                        (site[k] as f64 + 0.5) * l[k] / counts[k] as f64 + rng.gen_range(-0.2..0.2)
+                    }));
+                }
+            }
+        }
+        positions
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() <= 1e-10 * a.abs().max(b.abs()).max(1.0), "{} != {}", a, b);
+    }
+
+    #[test]
+    fn short_range_matches_brute_force() {
+        let l = [9.0, 7.5, 5.0];
+        for (periodic, cutoff) in [([true; 3], 2.5), ([true, false, true], 2.0), ([false, true, false], 3.0)] {
+            let positions = lattice(l, 1);
+            let mut short_range = ShortRange::new(cutoff);
+            short_range.compute(&positions, l, periodic);
+            let expected = brute_force(&positions, l, periodic, cutoff);
+            for (got, want) in short_range.terms.iter().zip(&expected) {
+                for k in 0..3 {
+                    assert_close(got.0[k], want.0[k]);
+                }
+                assert_close(got.1, want.1);
+                assert_close(got.2, want.2);
+                for c in 0..6 {
+                    assert_close(got.3[c], want.3[c]);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,372 @@
+use std::io;
+
+#[cfg(feature = "h5md")]
+use hdf5_metno as hdf5;
+
+use crate::trajectory::{Frame, SimulationData};
+
+// Trajectory output in H5MD 1.1 (https://www.nongnu.org/h5md/), an HDF5 layout that VMD,
+// MDAnalysis and h5py read. Every time series is a group with `step`, `time` and `value`
+// datasets that grow by one row per frame, chunked and deflate-compressed:
+// - /particles/all/position, velocity and, with --dump-forces, force
+// - /particles/all/box/edges, the orthorhombic box of every frame
+// - /observables/pressure, temperature, potential_energy, kinetic_energy and, with --stress, the
+//   pressure tensor as [xx, yy, zz, xy, xz, yz]
+// - /parameters holds the timestep, snapshot interval, seed and units of the run
+// Behind the `h5md` feature, since it links the system HDF5 library.
+#[cfg(feature = "h5md")]
+pub struct H5mdWriter {
+    file: hdf5::File,
+    position: Series,
+    velocity: Series,
+    force: Option<Series>,
+    edges: Series,
+    pressure: Series,
+    temperature: Series,
+    potential_energy: Series,
+    kinetic_energy: Series,
+    stress: Option<Series>,
+    frames: usize,
+}
+
+#[cfg(not(feature = "h5md"))]
+pub enum H5mdWriter {}
+
+// Lets callers refuse `--output-format h5md` up front instead of failing when the file is opened
+pub fn available() -> bool {
+    cfg!(feature = "h5md")
+}
+
+// Compression level of every dataset, the usual trade-off between speed and size
+#[cfg(feature = "h5md")]
+const DEFLATE_LEVEL: u8 = 4;
+
+// The `step`, `time` and `value` datasets of one H5MD time series
+#[cfg(feature = "h5md")]
+struct Series {
+    step: hdf5::Dataset,
+    time: hdf5::Dataset,
+    value: hdf5::Dataset,
+}
+
+#[cfg(feature = "h5md")]
+impl Series {
+    // `shape` is the shape of one frame's value, empty for a scalar
+    fn create(parent: &hdf5::Group, name: &str, shape: &[usize]) -> hdf5::Result<Self> {
+        let group = parent.create_group(name)?;
+        let step = group.new_dataset::<i64>().chunk(1024).shape(0..).deflate(DEFLATE_LEVEL).create("step")?;
+        let time = group.new_dataset::<f64>().chunk(1024).shape(0..).deflate(DEFLATE_LEVEL).create("time")?;
+        let mut extents = vec![hdf5::Extent::resizable(0)];
+        extents.extend(shape.iter().map(|&n| hdf5::Extent::fixed(n)));
+        // Small values are batched many frames to a chunk, large ones one frame each
+        let frame_size: usize = shape.iter().product();
+        let mut chunk = vec![(4096 / frame_size.max(1)).max(1)];
+        chunk.extend(shape);
+        let value = group.new_dataset::<f64>().chunk(chunk).shape(extents).deflate(DEFLATE_LEVEL).create("value")?;
+        Ok(Series { step, time, value })
+    }
+
+    fn open(parent: &hdf5::Group, name: &str) -> hdf5::Result<Self> {
+        let group = parent.group(name)?;
+        Ok(Series { step: group.dataset("step")?, time: group.dataset("time")?, value: group.dataset("value")? })
+    }
+
+    // Cuts the series back to its first `frames` rows
+    fn truncate(&self, frames: usize) -> hdf5::Result<()> {
+        self.step.resize(frames)?;
+        self.time.resize(frames)?;
+        let mut shape = self.value.shape();
+        shape[0] = frames;
+        self.value.resize(shape)
+    }
+
+    // Writes row `index`, `value` holding the whole frame in row-major order
+    fn write(&self, index: usize, step: usize, time: f64, value: &[f64]) -> hdf5::Result<()> {
+        self.truncate(index + 1)?;
+        self.step.write_slice(&[step as i64], index..index + 1)?;
+        self.time.write_slice(&[time], index..index + 1)?;
+        let shape = &self.value.shape()[1..];
+        let row = ndarray::ArrayView::from_shape(shape, value).map_err(|e| hdf5::Error::from(e.to_string()))?;
+        let mut selection = vec![hdf5::SliceOrIndex::Index(index)];
+        selection.extend(shape.iter().map(|_| hdf5::SliceOrIndex::from(..)));
+        self.value.write_slice(row, hdf5::Hyperslab::from(selection))
+    }
+}
+
+#[cfg(feature = "h5md")]
+fn h5_error(e: hdf5::Error) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+#[cfg(feature = "h5md")]
+impl H5mdWriter {
+    // `header` supplies the run parameters; `forces` adds the force of every atom
+    pub fn create(path: &str, header: &SimulationData, forces: bool) -> io::Result<Self> {
+        Self::build(path, header, forces).map_err(h5_error)
+    }
+
+    fn build(path: &str, header: &SimulationData, forces: bool) -> hdf5::Result<Self> {
+        use hdf5::types::VarLenUnicode;
+        let text = |value: &str| -> hdf5::Result<VarLenUnicode> { value.parse().map_err(|e: hdf5::types::StringError| e.to_string().into()) };
+        let file = hdf5::File::create(path)?;
+
+        let h5md = file.create_group("h5md")?;
+        h5md.new_attr::<i32>().shape(2).create("version")?.write(&[1, 1])?;
+        let author = h5md.create_group("author")?;
+        author.new_attr::<VarLenUnicode>().create("name")?.write_scalar(&text(&whoami())?)?;
+        let creator = h5md.create_group("creator")?;
+        creator.new_attr::<VarLenUnicode>().create("name")?.write_scalar(&text("sim")?)?;
+        creator.new_attr::<VarLenUnicode>().create("version")?.write_scalar(&text(env!("CARGO_PKG_VERSION"))?)?;
+
+        let parameters = file.create_group("parameters")?;
+        parameters.new_attr::<f64>().create("timestep")?.write_scalar(&header.timestep)?;
+        parameters.new_attr::<u64>().create("snapshot_interval")?.write_scalar(&(header.snapshot_interval as u64))?;
+        parameters.new_attr::<u64>().create("total_steps")?.write_scalar(&(header.total_steps as u64))?;
+        if let Some(seed) = header.seed {
+            parameters.new_attr::<u64>().create("seed")?.write_scalar(&seed)?;
+        }
+        parameters.new_attr::<VarLenUnicode>().create("units")?.write_scalar(&text(header.units.describe())?)?;
+
+        let all = file.create_group("particles")?.create_group("all")?;
+        let n = header.num_atoms;
+        let position = Series::create(&all, "position", &[n, 3])?;
+        let velocity = Series::create(&all, "velocity", &[n, 3])?;
+        let force = forces.then(|| Series::create(&all, "force", &[n, 3])).transpose()?;
+        let cell = all.create_group("box")?;
+        cell.new_attr::<i32>().create("dimension")?.write_scalar(&3)?;
+        let periodic = text("periodic")?;
+        cell.new_attr::<VarLenUnicode>().shape(3).create("boundary")?.write(&[periodic.clone(), periodic.clone(), periodic])?;
+        let edges = Series::create(&cell, "edges", &[3])?;
+
+        let observables = file.create_group("observables")?;
+        let scalar = |name| Series::create(&observables, name, &[]);
+        let stress = header.stress.is_some().then(|| Series::create(&observables, "stress", &[6])).transpose()?;
+        Ok(H5mdWriter {
+            position,
+            velocity,
+            force,
+            edges,
+            pressure: scalar("pressure")?,
+            temperature: scalar("temperature")?,
+            potential_energy: scalar("potential_energy")?,
+            kinetic_energy: scalar("kinetic_energy")?,
+            stress,
+            file,
+            frames: 0,
+        })
+    }
+
+    // Continues the file of an interrupted run after its first `frames` frames
+    pub fn append(path: &str, frames: usize, forces: bool, stress: bool) -> io::Result<Self> {
+        Self::reopen(path, frames, forces, stress).map_err(h5_error)
+    }
+
+    fn reopen(path: &str, frames: usize, forces: bool, stress: bool) -> hdf5::Result<Self> {
+        let file = hdf5::File::open_rw(path)?;
+        let all = file.group("particles/all")?;
+        let observables = file.group("observables")?;
+        let writer = H5mdWriter {
+            position: Series::open(&all, "position")?,
+            velocity: Series::open(&all, "velocity")?,
+            force: forces.then(|| Series::open(&all, "force")).transpose()?,
+            edges: Series::open(&all.group("box")?, "edges")?,
+            pressure: Series::open(&observables, "pressure")?,
+            temperature: Series::open(&observables, "temperature")?,
+            potential_energy: Series::open(&observables, "potential_energy")?,
+            kinetic_energy: Series::open(&observables, "kinetic_energy")?,
+            stress: stress.then(|| Series::open(&observables, "stress")).transpose()?,
+            file,
+            frames,
+        };
+        for series in writer.series() {
+            series.truncate(frames)?;
+        }
+        Ok(writer)
+    }
+
+    fn series(&self) -> impl Iterator<Item = &Series> {
+        [&self.position, &self.velocity, &self.edges, &self.pressure, &self.temperature, &self.potential_energy, &self.kinetic_energy]
+            .into_iter()
+            .chain(self.force.as_ref())
+            .chain(self.stress.as_ref())
+    }
+
+    pub fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        let (index, step, time) = (self.frames, frame.step, frame.time);
+        let write = |series: &Series, value: &[f64]| series.write(index, step, time, value).map_err(h5_error);
+        write(&self.position, frame.positions.as_flattened())?;
+        write(&self.velocity, frame.velocities.as_flattened())?;
+        if let Some(force) = &self.force {
+            write(force, frame.forces.as_flattened())?;
+        }
+        write(&self.edges, &frame.box_lengths)?;
+        write(&self.pressure, &[frame.pressure])?;
+        write(&self.temperature, &[frame.temperature])?;
+        write(&self.potential_energy, &[frame.potential_energy])?;
+        write(&self.kinetic_energy, &[frame.kinetic_energy])?;
+        if let (Some(stress), Some(tensor)) = (&self.stress, frame.stress) {
+            write(stress, &tensor)?;
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    // Puts the frames written so far on disk and returns how many there are, which `append`
+    // continues from
+    pub fn sync(&mut self) -> io::Result<u64> {
+        self.file.flush().map_err(h5_error)?;
+        Ok(self.frames as u64)
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        self.file.flush().map_err(h5_error)
+    }
+}
+
+// The author H5MD asks for: the user running the simulation
+#[cfg(feature = "h5md")]
+fn whoami() -> String {
+    std::env::var("USER").or_else(|_| std::env::var("USERNAME")).unwrap_or_else(|_| "unknown".to_string())
+}
+
+#[cfg(not(feature = "h5md"))]
+impl H5mdWriter {
+    pub fn create(_path: &str, _header: &SimulationData, _forces: bool) -> io::Result<Self> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "h5md output requires a build with --features h5md"))
+    }
+
+    pub fn append(_path: &str, _frames: usize, _forces: bool, _stress: bool) -> io::Result<Self> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "h5md output requires a build with --features h5md"))
+    }
+
+    pub fn write_frame(&mut self, _frame: &Frame) -> io::Result<()> {
+        match *self {}
+    }
+
+    pub fn sync(&mut self) -> io::Result<u64> {
+        match *self {}
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        match self {}
+    }
+}
+
+#[cfg(all(test, feature = "h5md"))]
+mod tests {
+    use super::*;
+    use crate::units::Units;
+
+    const ATOMS: usize = 4;
+
+    fn header() -> SimulationData {
+        SimulationData {
+            box_length: 3.0,
+            cell: None,
+            num_atoms: ATOMS,
+            timestep: 0.002,
+            total_steps: 30,
+            snapshot_interval: 10,
+            seed: Some(299),
+            units: Units::Native,
+            trajectory: Vec::new(),
+            pressure: Vec::new(),
+            stress: Some(Vec::new()),
+            velocities: None,
+        }
+    }
+
+    // Frame `index`, every value different and telling which frame it came from
+    fn frame(index: usize) -> Frame {
+        let vectors = |offset: f64| (0..ATOMS).map(|i| std::array::from_fn(|k| offset + index as f64 + 0.1 * i as f64 + 0.01 * k as f64)).collect();
+        Frame {
+            step: 10 * index,
+            time: 0.02 * index as f64,
+            positions: vectors(0.0),
+            velocities: vectors(100.0),
+            forces: vectors(200.0),
+            box_lengths: [3.0, 3.1, 3.2 + index as f64],
+            pressure: 1.5 + index as f64,
+            stress: Some(std::array::from_fn(|c| c as f64 + index as f64)),
+            temperature: 80.0 + index as f64,
+            potential_energy: -50.0 - index as f64,
+            kinetic_energy: 20.0 + index as f64,
+            ..Frame::default()
+        }
+    }
+
+    // The step, time and value datasets of one series, read back
+    fn read_series(file: &hdf5::File, name: &str) -> (Vec<i64>, Vec<f64>, Vec<usize>, Vec<f64>) {
+        let value = file.dataset(&format!("{}/value", name)).unwrap();
+        (
+            file.dataset(&format!("{}/step", name)).unwrap().read_raw().unwrap(),
+            file.dataset(&format!("{}/time", name)).unwrap().read_raw().unwrap(),
+            value.shape(),
+            value.read_raw().unwrap(),
+        )
+    }
+
+    // Every series of `path` holds `frames` in order, in the H5MD layout
+    fn check_frames(path: &std::path::Path, frames: &[Frame]) {
+        let file = hdf5::File::open(path).unwrap();
+        let steps: Vec<i64> = frames.iter().map(|f| f.step as i64).collect();
+        let times: Vec<f64> = frames.iter().map(|f| f.time).collect();
+        let vectors = |get: fn(&Frame) -> &Vec<[f64; 3]>| -> Vec<f64> { frames.iter().flat_map(|f| get(f).as_flattened().to_vec()).collect() };
+        let scalars = |get: fn(&Frame) -> f64| -> Vec<f64> { frames.iter().map(get).collect() };
+        let expected: [(&str, Vec<usize>, Vec<f64>); 9] = [
+            ("particles/all/position", vec![frames.len(), ATOMS, 3], vectors(|f| &f.positions)),
+            ("particles/all/velocity", vec![frames.len(), ATOMS, 3], vectors(|f| &f.velocities)),
+            ("particles/all/force", vec![frames.len(), ATOMS, 3], vectors(|f| &f.forces)),
+            ("particles/all/box/edges", vec![frames.len(), 3], frames.iter().flat_map(|f| f.box_lengths).collect()),
+            ("observables/pressure", vec![frames.len()], scalars(|f| f.pressure)),
+            ("observables/temperature", vec![frames.len()], scalars(|f| f.temperature)),
+            ("observables/potential_energy", vec![frames.len()], scalars(|f| f.potential_energy)),
+            ("observables/kinetic_energy", vec![frames.len()], scalars(|f| f.kinetic_energy)),
+            ("observables/stress", vec![frames.len(), 6], frames.iter().flat_map(|f| f.stress.unwrap()).collect()),
+        ];
+        for (name, shape, values) in expected {
+            assert_eq!(read_series(&file, name), (steps.clone(), times.clone(), shape, values), "{}", name);
+        }
+    }
+
+    #[test]
+    fn frames_read_back_in_the_h5md_layout() {
+        let path = std::env::temp_dir().join(format!("sim-h5md-{}.h5", std::process::id()));
+        let frames = [frame(0), frame(1), frame(2)];
+        let mut writer = H5mdWriter::create(path.to_str().unwrap(), &header(), true).unwrap();
+        for frame in &frames {
+            writer.write_frame(frame).unwrap();
+        }
+        writer.finish().unwrap();
+
+        check_frames(&path, &frames);
+        let file = hdf5::File::open(&path).unwrap();
+        assert_eq!(file.group("h5md").unwrap().attr("version").unwrap().read_raw::<i32>().unwrap(), [1, 1]);
+        let parameters = file.group("parameters").unwrap();
+        assert_eq!(parameters.attr("timestep").unwrap().read_scalar::<f64>().unwrap(), 0.002);
+        assert_eq!(parameters.attr("snapshot_interval").unwrap().read_scalar::<u64>().unwrap(), 10);
+        assert_eq!(parameters.attr("seed").unwrap().read_scalar::<u64>().unwrap(), 299);
+        let cell = file.group("particles/all/box").unwrap();
+        assert_eq!(cell.attr("dimension").unwrap().read_scalar::<i32>().unwrap(), 3);
+        drop((file, parameters, cell));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn append_continues_after_the_synced_frames() {
+        let path = std::env::temp_dir().join(format!("sim-h5md-append-{}.h5", std::process::id()));
+        let mut writer = H5mdWriter::create(path.to_str().unwrap(), &header(), true).unwrap();
+        writer.write_frame(&frame(0)).unwrap();
+        writer.write_frame(&frame(1)).unwrap();
+        assert_eq!(writer.sync().unwrap(), 2);
+        // A frame written after the checkpoint, which the resumed run writes anew
+        writer.write_frame(&frame(5)).unwrap();
+        writer.finish().unwrap();
+
+        let mut writer = H5mdWriter::append(path.to_str().unwrap(), 2, true, true).unwrap();
+        writer.write_frame(&frame(2)).unwrap();
+        writer.finish().unwrap();
+        check_frames(&path, &[frame(0), frame(1), frame(2)]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
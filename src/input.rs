@@ -0,0 +1,101 @@
+use std::collections::HashSet;
+use std::io;
+
+use crate::compress;
+use crate::dcd;
+use crate::lammps;
+use crate::pdb;
+use crate::trajectory;
+use crate::xtc;
+use crate::xyz;
+
+// Loads a starting configuration, choosing the reader from the file extension (ignoring a
+// `.gz` or `.zst` compression suffix).
+// LAMMPS data files are shifted so the box starts at the origin; PDB files carry no velocities.
+// Warns about whatever the simulation ignores: other elements or atom types, and bonds.
+pub fn read_configuration(path: &str) -> io::Result<xyz::Frame> {
+    read(path, true)
+}
+
+// Same as `read_configuration` without the warnings, for analyses that use the atom types
+pub fn read_structure(path: &str) -> io::Result<xyz::Frame> {
+    read(path, false)
+}
+
+fn read(path: &str, warn: bool) -> io::Result<xyz::Frame> {
+    let extension = compress::inner_extension(path);
+    match extension {
+        "data" | "lmp" | "lammps" => {
+            let data = lammps::read_data(path)?;
+            let distinct_types: HashSet<_> = data.types.iter().collect();
+            if warn && (distinct_types.len() > 1 || data.masses.values().any(|&m| (m - 39.95).abs() > 0.01)) {
+                eprintln!("Warning: {} has {} atom types, all atoms are simulated as argon", path, distinct_types.len());
+            }
+            if warn && !data.bonds.is_empty() {
+                eprintln!("Warning: ignoring {} bonds in {}", data.bonds.len(), path);
+            }
+            let positions = data.positions.iter().map(|pos| {
+                [pos[0] - data.box_lo[0], pos[1] - data.box_lo[1], pos[2] - data.box_lo[2]]
+            }).collect();
+            let box_lengths = [
+                data.box_hi[0] - data.box_lo[0],
+                data.box_hi[1] - data.box_lo[1],
+                data.box_hi[2] - data.box_lo[2],
+            ];
+            let species = Some(data.types.iter().map(|t| t.to_string()).collect());
+            Ok(xyz::Frame { positions, velocities: data.velocities, box_lengths: Some(box_lengths), species, time: None })
+        }
+        "pdb" => {
+            let structure = pdb::read_pdb(path)?;
+            let mut other_elements: Vec<&String> = structure.elements.iter().filter(|e| e.as_str() != "Ar").collect();
+            other_elements.sort();
+            other_elements.dedup();
+            if warn && !other_elements.is_empty() {
+                eprintln!("Warning: {} contains {:?}, all atoms are simulated as argon", path, other_elements);
+            }
+            Ok(xyz::Frame {
+                positions: structure.positions,
+                velocities: None,
+                box_lengths: structure.box_lengths,
+                species: Some(structure.elements),
+                time: None,
+            })
+        }
+        _ => xyz::read_frame(path),
+    }
+}
+
+// Every frame of a trajectory in any of the main run's output formats (`.lammpstrj` for the
+// dump), chosen by the extension; any other configuration file reads as a single frame
+pub fn read_trajectory(path: &str) -> io::Result<Vec<xyz::Frame>> {
+    match compress::inner_extension(path) {
+        "json" => {
+            let data = trajectory::load_json(path)?;
+            let frame_interval = data.frame_interval();
+            let box_lengths = data.cell().widths();
+            Ok(data.trajectory.into_iter().enumerate().map(|(i, positions)| xyz::Frame {
+                positions,
+                velocities: None,
+                box_lengths: Some(box_lengths),
+                species: None,
+                time: Some(i as f64 * frame_interval),
+            }).collect())
+        }
+        "xyz" | "extxyz" => xyz::read_frames(path),
+        "dcd" => dcd::read_frames(path),
+        "xtc" => xtc::read_frames(path),
+        "lammpstrj" | "dump" => lammps::read_dump(path),
+        _ => Ok(vec![read_configuration(path)?]),
+    }
+}
+
+// Cubic box edge for a configuration: `requested` when given, otherwise the file's box,
+// which must then exist and be cubic
+pub fn cubic_box_length(path: &str, box_lengths: Option<[f64; 3]>, requested: Option<f64>) -> Result<f64, String> {
+    match (requested, box_lengths) {
+        (Some(box_length), _) => Ok(box_length),
+        (None, Some(lengths)) if lengths.iter().all(|&b| (b - lengths[0]).abs() <= 1e-6 * lengths[0]) => Ok(lengths[0]),
+        (None, Some(lengths)) => Err(format!("{} has a non-cubic box {:?}, pass --box", path, lengths)),
+        (None, None) => Err(format!("{} has no box, pass --box", path)),
+    }
+}
@@ -0,0 +1,283 @@
+use rayon::prelude::*;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::cli::{self, Args};
+use crate::glass::progress_bar;
+use crate::simulation::{initial_state, LangevinThermostat, KB};
+
+const USAGE: &str = "Usage: sim ladder <t_min> <t_max> [--acceptance 0.2] [--num-atoms 256] [--heat-capacity 1.5] \
+[--refine] [--density 0.8] [--timestep 0.005] [--cutoff 2.5 | --cutoff-accuracy 1e-2] [--trial-equilibration 5000] \
+[--trial-steps 5000] [--seed S] [--output ladder.txt]";
+
+// Temperature ladder for replica exchange: the fewest temperatures from `t_min` to `t_max` with
+// at least the target swap acceptance between neighbours. Without `--refine` the potential
+// energy is modelled with a constant heat capacity, which makes the ladder geometric. With
+// `--refine` short NVT runs at the model ladder's temperatures measure the mean and variance of
+// the potential energy, and the ladder is rebuilt from those, placing more replicas where the
+// heat capacity peaks.
+pub fn run(args: &[String]) {
+    let args = Args::parse(args);
+    if args.positional.len() != 2 {
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
+    }
+    let t_min: f64 = args.positional[0].parse().expect("Invalid minimum temperature");
+    let t_max: f64 = args.positional[1].parse().expect("Invalid maximum temperature");
+    let acceptance: f64 = args.get("acceptance", 0.2);
+    let n: usize = args.get("num-atoms", 256);
+    // Configurational heat capacity per atom in units of k_B; 1.5 for a harmonic solid
+    let heat_capacity: f64 = args.get("heat-capacity", 1.5);
+    let output: String = args.get("output", "ladder.txt".to_string());
+    if t_min <= 0.0 || t_max <= t_min {
+        eprintln!("Temperatures must satisfy 0 < t_min < t_max");
+        std::process::exit(1);
+    }
+    if acceptance <= 0.0 || acceptance >= 1.0 {
+        eprintln!("--acceptance must be between 0 and 1");
+        std::process::exit(1);
+    }
+
+    let capacity = heat_capacity * n as f64;
+    let model = |t: f64| (capacity * KB * t, capacity * (KB * t).powi(2));
+    let model_ladder = geometric(t_min, t_max, geometric_count(capacity, acceptance, t_max / t_min));
+    println!(
+        "Constant heat capacity {} k_B per atom, {} atoms: {} temperatures",
+        heat_capacity,
+        n,
+        model_ladder.len()
+    );
+
+    let (ladder, energies): (Vec<f64>, Vec<(f64, f64)>) = if args.has("refine") {
+        let samples = trial_runs(&args, n, &model_ladder);
+        if samples.windows(2).any(|pair| pair[1].1 <= pair[0].1) {
+            eprintln!("Warning: the potential energy does not rise with temperature everywhere; the trials are probably too short (--trial-equilibration, --trial-steps)");
+        }
+        let measured = |t: f64| interpolate(&samples, t);
+        let ladder = balance(t_min, t_max, acceptance, &measured);
+        println!("Refined from trial runs: {} temperatures", ladder.len());
+        let energies = ladder.iter().map(|&t| measured(t)).collect();
+        (ladder, energies)
+    } else {
+        let energies = model_ladder.iter().map(|&t| model(t)).collect();
+        (model_ladder, energies)
+    };
+
+    println!("{:>4} {:>12} {:>18}", "i", "T (K)", "swap acceptance");
+    for i in 0..ladder.len() {
+        match energies.get(i + 1) {
+            Some(&next) => {
+                let p = swap_acceptance(ladder[i], energies[i], ladder[i + 1], next);
+                println!("{:>4} {:>12.4} {:>18.3}", i, ladder[i], p);
+            }
+            None => println!("{:>4} {:>12.4}", i, ladder[i]),
+        }
+    }
+
+    if let Err(e) = write_ladder(&output, &ladder, acceptance) {
+        eprintln!("Failed to write {}: {}", output, e);
+        std::process::exit(1);
+    }
+    println!("Ladder written to {}", output);
+}
+
+// `count` temperatures from `t_min` to `t_max` with a constant ratio between neighbours
+pub fn geometric(t_min: f64, t_max: f64, count: usize) -> Vec<f64> {
+    if count < 2 {
+        return vec![t_min, t_max];
+    }
+    (0..count).map(|i| t_min * (t_max / t_min).powf(i as f64 / (count - 1) as f64)).collect()
+}
+
+// Number of temperatures a geometric ladder spanning `span` = t_max / t_min needs for the target
+// acceptance when the configurational heat capacity is `capacity` k_B. The acceptance then
+// depends on the temperature ratio r alone and falls as r grows, so r is found by bisection.
+fn geometric_count(capacity: f64, acceptance: f64, span: f64) -> usize {
+    let energies = |t: f64| (capacity * KB * t, capacity * (KB * t).powi(2));
+    let accepts = |r: f64| swap_acceptance(1.0, energies(1.0), r, energies(r)) >= acceptance;
+    let (mut lo, mut hi) = (1.0, span);
+    if accepts(hi) {
+        return 2;
+    }
+    for _ in 0..100 {
+        let mid = 0.5 * (lo + hi);
+        if accepts(mid) {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    (span.ln() / lo.ln()).ceil() as usize + 1
+}
+
+// Expected Metropolis acceptance of swapping replicas at temperatures `t1` < `t2`, each given
+// the mean and variance of its potential energy. The exponent (b1 - b2)(U1 - U2) is treated as
+// Gaussian, which gives E[min(1, e^x)] in closed form.
+pub fn swap_acceptance(t1: f64, (mean1, var1): (f64, f64), t2: f64, (mean2, var2): (f64, f64)) -> f64 {
+    let dbeta = 1.0 / (KB * t1) - 1.0 / (KB * t2);
+    let mu = dbeta * (mean1 - mean2);
+    let s = dbeta.abs() * (var1 + var2).sqrt();
+    if s == 0.0 {
+        return mu.exp().min(1.0);
+    }
+    // The second term in log space: its factors overflow and underflow when the means are far apart
+    (normal_cdf(mu / s) + (mu + 0.5 * s * s + ln_normal_cdf(-(mu + s * s) / s)).exp()).min(1.0)
+}
+
+// Ladder with as few temperatures as the greedy one for `acceptance`, but with the acceptance
+// raised as far as that count allows, so the last gap does not take all the slack
+fn balance<F: Fn(f64) -> (f64, f64)>(t_min: f64, t_max: f64, acceptance: f64, energies: &F) -> Vec<f64> {
+    let Some(mut ladder) = build(t_min, t_max, acceptance, energies, usize::MAX) else {
+        eprintln!("The target acceptance cannot be reached with the measured energies");
+        std::process::exit(1);
+    };
+    let (mut lo, mut hi) = (acceptance, 1.0);
+    for _ in 0..40 {
+        let mid = 0.5 * (lo + hi);
+        match build(t_min, t_max, mid, energies, ladder.len()) {
+            Some(balanced) => {
+                ladder = balanced;
+                lo = mid;
+            }
+            None => hi = mid,
+        }
+    }
+    ladder
+}
+
+// Greedy ladder: from `t_min`, each next temperature is the highest one still reaching the target
+// acceptance, until `t_max` is reached. None if that takes more than `max_len` temperatures.
+fn build<F: Fn(f64) -> (f64, f64)>(t_min: f64, t_max: f64, acceptance: f64, energies: &F, max_len: usize) -> Option<Vec<f64>> {
+    let mut ladder = vec![t_min];
+    let mut t = t_min;
+    while t < t_max {
+        if ladder.len() >= max_len {
+            return None;
+        }
+        let accepts = |next: f64| swap_acceptance(t, energies(t), next, energies(next)) >= acceptance;
+        if accepts(t_max) {
+            ladder.push(t_max);
+            break;
+        }
+        let (mut lo, mut hi) = (t, t_max);
+        for _ in 0..60 {
+            let mid = 0.5 * (lo + hi);
+            if accepts(mid) {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        if lo <= t * (1.0 + 1e-9) {
+            return None;
+        }
+        ladder.push(lo);
+        t = lo;
+    }
+    Some(ladder)
+}
+
+// Mean and variance of the potential energy from short Langevin runs at `temperatures`
+fn trial_runs(args: &Args, n: usize, temperatures: &[f64]) -> Vec<(f64, f64, f64)> {
+    let density: f64 = args.get("density", 0.8);
+    let dt: f64 = args.get("timestep", 0.005);
+    let cutoff = cli::cutoff_from_args(args, 2.5, n, (n as f64 / density).cbrt());
+    let equilibration: usize = args.get("trial-equilibration", 5000);
+    let steps: usize = args.get("trial-steps", 5000);
+    let seed: u64 = args.get("seed", rand::random());
+    if steps < 2 {
+        eprintln!("--trial-steps must be at least 2");
+        std::process::exit(1);
+    }
+
+    temperatures.iter().enumerate().map(|(i, &t)| {
+        let seed = seed.wrapping_add(i as u64);
+        let mut sim = initial_state(n, density, t, dt, cutoff, seed);
+        sim.langevin = Some(LangevinThermostat { target_temperature: t, friction: 1.0, seed });
+        let pb = progress_bar(equilibration + steps);
+        pb.set_message(format!("trial at {:.2} K", t));
+        for _ in 0..equilibration {
+            sim.step();
+            pb.inc(1);
+        }
+        let mut energies = Vec::with_capacity(steps);
+        for _ in 0..steps {
+            sim.step();
+            energies.push(sim.potential_energy());
+            pb.inc(1);
+        }
+        pb.finish_and_clear();
+        let mean = energies.par_iter().sum::<f64>() / steps as f64;
+        let variance = energies.par_iter().map(|e| (e - mean).powi(2)).sum::<f64>() / (steps - 1) as f64;
+        println!(
+            "Trial at {:.2} K: potential energy {:.4} +- {:.4}, heat capacity {:.3} k_B per atom",
+            t,
+            mean,
+            variance.sqrt(),
+            variance / (KB * t).powi(2) / n as f64
+        );
+        (t, mean, variance)
+    }).collect()
+}
+
+// Mean and variance at `t`, linear between the sampled temperatures and extrapolated from the
+// nearest pair outside them
+fn interpolate(samples: &[(f64, f64, f64)], t: f64) -> (f64, f64) {
+    let i = samples.partition_point(|&(ts, _, _)| ts < t).clamp(1, samples.len() - 1);
+    let (t0, mean0, var0) = samples[i - 1];
+    let (t1, mean1, var1) = samples[i];
+    let x = (t - t0) / (t1 - t0);
+    (mean0 + x * (mean1 - mean0), (var0 + x * (var1 - var0)).max(0.0))
+}
+
+// One temperature per line after `#` comment lines, the format `read_ladder` takes
+fn write_ladder(path: &str, ladder: &[f64], acceptance: f64) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "# Temperature ladder (K), {} replicas, target swap acceptance {}", ladder.len(), acceptance)?;
+    for t in ladder {
+        writeln!(writer, "{}", t)?;
+    }
+    writer.flush()
+}
+
+// Reads a ladder written by `sim ladder`, or any file with one temperature per line
+pub fn read_ladder<P: AsRef<Path>>(path: P) -> io::Result<Vec<f64>> {
+    fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("invalid temperature {}", line))))
+        .collect()
+}
+
+// Standard normal CDF through erfc, Numerical Recipes' Chebyshev fit (relative error below 1.2e-7)
+fn normal_cdf(x: f64) -> f64 {
+    let erfc = ln_erfc(x.abs() / std::f64::consts::SQRT_2).exp();
+    if x >= 0.0 {
+        1.0 - 0.5 * erfc
+    } else {
+        0.5 * erfc
+    }
+}
+
+// Logarithm of the standard normal CDF, accurate far into the lower tail
+fn ln_normal_cdf(x: f64) -> f64 {
+    if x >= 0.0 {
+        normal_cdf(x).ln()
+    } else {
+        0.5f64.ln() + ln_erfc(-x / std::f64::consts::SQRT_2)
+    }
+}
+
+// ln erfc(z) for z >= 0
+fn ln_erfc(z: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.5 * z);
+    t.ln() + (-z * z - 1.26551223
+        + t * (1.00002368
+            + t * (0.37409196
+                + t * (0.09678418
+                    + t * (-0.18628806
+                        + t * (0.27886807
+                            + t * (-1.13520398 + t * (1.48851587 + t * (-0.82215223 + t * 0.17087277)))))))))
+}
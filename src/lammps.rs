@@ -0,0 +1,408 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use crate::compress;
+use crate::xyz::Frame;
+
+// Atoms or bonds reserved up front from a count in a file
+const RESERVE: usize = 1 << 20;
+
+// Contents of a LAMMPS data file. Atoms are ordered by their LAMMPS id and
+// types keep their LAMMPS numbering (starting at 1).
+pub struct DataFile {
+    pub positions: Vec<[f64; 3]>,
+    pub velocities: Option<Vec<[f64; 3]>>,
+    pub types: Vec<usize>,
+    pub masses: HashMap<usize, f64>,
+    // (bond type, first atom index, second atom index), with indices into `positions`
+    pub bonds: Vec<(usize, usize, usize)>,
+    pub box_lo: [f64; 3],
+    pub box_hi: [f64; 3],
+}
+
+// Optional per-atom columns of a dump file beyond `id type x y z`
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+pub struct DumpColumns {
+    pub velocities: bool,
+    pub forces: bool,
+    // Potential energy of each atom, also written to extended XYZ
+    pub energy: bool,
+    // Steinhardt q4, q6 and w6 of each atom, also written to extended XYZ
+    pub order: bool,
+}
+
+// Per-atom data of one dump frame; the optional parts become extra columns
+pub struct DumpAtoms<'a> {
+    pub positions: &'a [[f64; 3]],
+    pub velocities: Option<&'a [[f64; 3]]>,
+    pub forces: Option<&'a [[f64; 3]]>,
+    pub energies: Option<&'a [f64]>,
+    // q4, q6 and w6
+    pub order: Option<&'a [[f64; 3]]>,
+}
+
+// Writes one frame in LAMMPS text dump format (`dump atom/custom`), which OVITO and VMD read
+// natively. All atoms are type 1 and ids start at 1; the box is periodic from the origin.
+// The simulated time goes in an `ITEM: TIME` section, as with `dump_modify time yes`.
+pub fn write_dump_frame<W: Write>(
+    writer: &mut W,
+    step: usize,
+    time: f64,
+    atoms: &DumpAtoms,
+    box_lengths: [f64; 3],
+) -> io::Result<()> {
+    let DumpAtoms { positions, velocities, forces, energies, order } = *atoms;
+    writeln!(writer, "ITEM: TIME\n{}", time)?;
+    writeln!(writer, "ITEM: TIMESTEP\n{}", step)?;
+    writeln!(writer, "ITEM: NUMBER OF ATOMS\n{}", positions.len())?;
+    writeln!(writer, "ITEM: BOX BOUNDS pp pp pp")?;
+    for length in box_lengths {
+        writeln!(writer, "0 {}", length)?;
+    }
+    let mut header = String::from("ITEM: ATOMS id type x y z");
+    if velocities.is_some() {
+        header.push_str(" vx vy vz");
+    }
+    if forces.is_some() {
+        header.push_str(" fx fy fz");
+    }
+    if energies.is_some() {
+        header.push_str(" energy");
+    }
+    if order.is_some() {
+        header.push_str(" q4 q6 w6");
+    }
+    writeln!(writer, "{}", header)?;
+    for (i, pos) in positions.iter().enumerate() {
+        write!(writer, "{} 1 {:.8} {:.8} {:.8}", i + 1, pos[0], pos[1], pos[2])?;
+        for columns in [velocities, forces].into_iter().flatten() {
+            write!(writer, " {:.8} {:.8} {:.8}", columns[i][0], columns[i][1], columns[i][2])?;
+        }
+        if let Some(energies) = energies {
+            write!(writer, " {:.8}", energies[i])?;
+        }
+        if let Some(order) = order {
+            write!(writer, " {:.8} {:.8} {:.8}", order[i][0], order[i][1], order[i][2])?;
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+// Reads a LAMMPS text dump (`dump atom` or `dump custom`) with plain, unwrapped (`xu`) or
+// scaled (`xs`) coordinates. Atoms are ordered by id, species are the atom types and
+// positions are shifted so the box starts at the origin. A truncated last frame is dropped.
+pub fn read_dump<P: AsRef<Path>>(path: P) -> io::Result<Vec<Frame>> {
+    let mut contents = String::new();
+    match compress::open(path)?.read_to_string(&mut contents) {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {}
+        result => {
+            result?;
+        }
+    }
+    let mut lines = contents.lines();
+    let mut frames = Vec::new();
+    let mut time = None;
+    let mut num_atoms = 0;
+    let mut box_lo = [0.0; 3];
+    let mut box_lengths = [0.0; 3];
+    while let Some(line) = lines.next() {
+        // Values of the items not used here, such as TIMESTEP and UNITS
+        let Some(item) = line.trim().strip_prefix("ITEM:") else { continue };
+        let item = item.trim();
+        let mut value = || lines.next().ok_or(io::ErrorKind::UnexpectedEof);
+        if item == "TIME" {
+            time = value().ok().map(parse).transpose()?;
+        } else if item == "NUMBER OF ATOMS" {
+            num_atoms = value().ok().map(parse).transpose()?.unwrap_or(0);
+        } else if let Some(flags) = item.strip_prefix("BOX BOUNDS") {
+            if flags.contains("xy") {
+                return Err(invalid("triclinic boxes are not supported"));
+            }
+            for axis in 0..3 {
+                let Ok(line) = value() else { return Ok(frames) };
+                let words: Vec<&str> = line.split_whitespace().collect();
+                if words.len() < 2 {
+                    return Err(invalid("box bounds need two values"));
+                }
+                box_lo[axis] = parse(words[0])?;
+                box_lengths[axis] = parse::<f64>(words[1])? - box_lo[axis];
+            }
+        } else if let Some(header) = item.strip_prefix("ATOMS") {
+            let columns: Vec<&str> = header.split_whitespace().collect();
+            let find = |names: &[&str]| names.iter().find_map(|name| columns.iter().position(|c| c == name));
+            let id = find(&["id"]).ok_or_else(|| invalid("dump has no id column"))?;
+            let atom_type = find(&["type"]);
+            let (position, scaled) = match (find(&["x", "xu"]), find(&["xs", "xsu"])) {
+                (Some(x), _) => (x, false),
+                (None, Some(xs)) => (xs, true),
+                _ => return Err(invalid("dump has no coordinate columns")),
+            };
+            let velocity = find(&["vx"]);
+            let mut atoms = Vec::with_capacity(num_atoms.min(RESERVE));
+            for _ in 0..num_atoms {
+                let Ok(line) = value() else { return Ok(frames) };
+                let words: Vec<&str> = line.split_whitespace().collect();
+                if words.len() < columns.len() {
+                    return Ok(frames);
+                }
+                let vector = |first: usize| -> io::Result<[f64; 3]> {
+                    Ok([parse(words[first])?, parse(words[first + 1])?, parse(words[first + 2])?])
+                };
+                let mut pos = vector(position)?;
+                for k in 0..3 {
+                    pos[k] = if scaled { pos[k] * box_lengths[k] } else { pos[k] - box_lo[k] };
+                }
+                let species = atom_type.map_or("1", |column| words[column]).to_string();
+                atoms.push((parse::<usize>(words[id])?, species, pos, velocity.map(vector).transpose()?));
+            }
+            atoms.sort_by_key(|atom| atom.0);
+            let velocities = velocity.map(|_| atoms.iter().map(|atom| atom.3.unwrap()).collect());
+            frames.push(Frame {
+                positions: atoms.iter().map(|atom| atom.2).collect(),
+                velocities,
+                box_lengths: Some(box_lengths),
+                species: Some(atoms.into_iter().map(|atom| atom.1).collect()),
+                time: time.take(),
+            });
+        }
+    }
+    Ok(frames)
+}
+
+// Column layout of the Atoms section: (type column, first coordinate column, minimum column count)
+fn atom_style_columns(style: &str) -> Option<(usize, usize, usize)> {
+    match style {
+        "atomic" => Some((1, 2, 5)),
+        "charge" => Some((1, 3, 6)),
+        "bond" | "angle" | "molecular" => Some((2, 3, 6)),
+        "full" => Some((2, 4, 7)),
+        _ => None,
+    }
+}
+
+// Reads the header, Masses, Atoms, Velocities and Bonds sections of a LAMMPS data file.
+// The atom style is taken from the `Atoms # style` comment or guessed from the column count.
+pub fn read_data<P: AsRef<Path>>(path: P) -> io::Result<DataFile> {
+    let mut contents = String::new();
+    compress::open(path)?.read_to_string(&mut contents)?;
+    let mut lines = contents.lines().skip(1).peekable();
+
+    let mut num_atoms = 0;
+    let mut num_bonds = 0;
+    let mut box_lo = [0.0; 3];
+    let mut box_hi = [0.0; 3];
+    let mut found_box = [false; 3];
+
+    // Header: everything up to the first section keyword
+    while let Some(line) = lines.peek() {
+        let line = strip_comment(line);
+        if line.is_empty() {
+            lines.next();
+            continue;
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words[0].parse::<f64>().is_err() {
+            break;
+        }
+        match words[1..] {
+            ["atoms"] => num_atoms = parse(words[0])?,
+            ["bonds"] => num_bonds = parse(words[0])?,
+            [_, "xlo", "xhi"] | [_, "ylo", "yhi"] | [_, "zlo", "zhi"] => {
+                let axis = (words[2].as_bytes()[0] - b'x') as usize;
+                box_lo[axis] = parse(words[0])?;
+                box_hi[axis] = parse(words[1])?;
+                found_box[axis] = true;
+            }
+            [_, _, _, "xy", "xz", "yz"] => {
+                return Err(invalid("triclinic boxes are not supported"));
+            }
+            _ => {}
+        }
+        lines.next();
+    }
+    if found_box.contains(&false) {
+        return Err(invalid("header is missing box bounds"));
+    }
+
+    // The header counts only size what is reserved, a corrupt file may claim any number
+    let mut atoms: Vec<(usize, usize, [f64; 3])> = Vec::with_capacity(num_atoms.min(RESERVE));
+    let mut velocities: HashMap<usize, [f64; 3]> = HashMap::new();
+    let mut raw_bonds: Vec<(usize, usize, usize)> = Vec::with_capacity(num_bonds.min(RESERVE));
+    let mut masses = HashMap::new();
+
+    while let Some(line) = lines.next() {
+        let (section, comment) = match line.split_once('#') {
+            Some((section, comment)) => (section.trim(), comment.trim()),
+            None => (line.trim(), ""),
+        };
+        if section.is_empty() {
+            continue;
+        }
+        let body = section_body(&mut lines);
+        match section {
+            "Masses" => {
+                for words in &body {
+                    if words.len() < 2 {
+                        return Err(invalid("too few columns in Masses"));
+                    }
+                    let atom_type: usize = parse(words[0])?;
+                    if masses.insert(atom_type, parse(words[1])?).is_some() {
+                        return Err(invalid(&format!("duplicate mass for atom type {}", atom_type)));
+                    }
+                }
+            }
+            "Atoms" => {
+                let style = if comment.is_empty() {
+                    guess_atom_style(body.first().map_or(0, |words| words.len()))?
+                } else {
+                    comment
+                };
+                let (type_column, pos_column, min_columns) = atom_style_columns(style)
+                    .ok_or_else(|| invalid(&format!("unsupported atom style {}", style)))?;
+                for words in &body {
+                    if words.len() < min_columns {
+                        return Err(invalid(&format!("too few columns for atom style {}", style)));
+                    }
+                    let position = [parse(words[pos_column])?, parse(words[pos_column + 1])?, parse(words[pos_column + 2])?];
+                    atoms.push((parse(words[0])?, parse(words[type_column])?, position));
+                }
+            }
+            "Velocities" => {
+                for words in &body {
+                    if words.len() < 4 {
+                        return Err(invalid("too few columns in Velocities"));
+                    }
+                    let id: usize = parse(words[0])?;
+                    if velocities.insert(id, [parse(words[1])?, parse(words[2])?, parse(words[3])?]).is_some() {
+                        return Err(invalid(&format!("duplicate velocity for atom {}", id)));
+                    }
+                }
+            }
+            "Bonds" => {
+                for words in &body {
+                    if words.len() < 4 {
+                        return Err(invalid("too few columns in Bonds"));
+                    }
+                    raw_bonds.push((parse(words[1])?, parse(words[2])?, parse(words[3])?));
+                }
+            }
+            // Coefficient and angle/dihedral sections are not used by this engine
+            _ => {}
+        }
+    }
+
+    if atoms.len() != num_atoms {
+        return Err(invalid(&format!("header declares {} atoms, Atoms section has {}", num_atoms, atoms.len())));
+    }
+    atoms.sort_by_key(|&(id, _, _)| id);
+    if let Some(pair) = atoms.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+        return Err(invalid(&format!("duplicate atom id {}", pair[0].0)));
+    }
+    let index: HashMap<usize, usize> = atoms.iter().enumerate().map(|(i, &(id, _, _))| (id, i)).collect();
+
+    let velocities = if velocities.is_empty() {
+        None
+    } else {
+        Some(atoms.iter().map(|(id, _, _)| {
+            velocities.get(id).copied().ok_or_else(|| invalid(&format!("no velocity for atom {}", id)))
+        }).collect::<io::Result<Vec<_>>>()?)
+    };
+
+    let bonds = raw_bonds.into_iter().map(|(bond_type, a, b)| {
+        match (index.get(&a), index.get(&b)) {
+            (Some(&i), Some(&j)) => Ok((bond_type, i, j)),
+            _ => Err(invalid(&format!("bond between unknown atoms {} and {}", a, b))),
+        }
+    }).collect::<io::Result<Vec<_>>>()?;
+
+    Ok(DataFile {
+        positions: atoms.iter().map(|&(_, _, pos)| pos).collect(),
+        velocities,
+        types: atoms.iter().map(|&(_, atom_type, _)| atom_type).collect(),
+        masses,
+        bonds,
+        box_lo,
+        box_hi,
+    })
+}
+
+// Lines of a section up to the next keyword, split into words with comments removed
+fn section_body<'a, I: Iterator<Item = &'a str>>(lines: &mut std::iter::Peekable<I>) -> Vec<Vec<&'a str>> {
+    let mut body = Vec::new();
+    while let Some(line) = lines.peek() {
+        let line = strip_comment(line);
+        if line.is_empty() {
+            lines.next();
+            continue;
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words[0].parse::<f64>().is_err() {
+            break;
+        }
+        body.push(words);
+        lines.next();
+    }
+    body
+}
+
+fn guess_atom_style(columns: usize) -> io::Result<&'static str> {
+    // Image flags add three trailing columns
+    match columns {
+        5 | 8 => Ok("atomic"),
+        7 | 10 => Ok("full"),
+        _ => Err(invalid("cannot infer the atom style, add a comment such as `Atoms # charge`")),
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    line.split('#').next().unwrap_or("").trim()
+}
+
+fn parse<T: std::str::FromStr>(word: &str) -> io::Result<T> {
+    word.parse().map_err(|_| invalid(&format!("cannot parse {:?}", word)))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt::Write as _;
+
+    #[test]
+    fn data_file_reads_back() {
+        let positions = [[0.5, 1.25, -2.0], [3.0, 0.0, 1.5], [-1.75, 2.5, 0.125], [2.0, -0.5, 3.25]];
+        let velocities = [[0.1, -0.2, 0.3], [0.0, 0.5, -0.25], [1.0, 0.0, 0.0], [-0.125, 0.25, 0.75]];
+        let types = [1, 2, 2, 1];
+        // Ids out of order, as packmol and moltemplate may write them
+        let ids = [3, 1, 4, 2];
+        let mut text = String::from("LAMMPS data file\n\n4 atoms\n1 bonds\n2 atom types\n\n");
+        text += "-4.0 4.0 xlo xhi\n-3.0 5.0 ylo yhi\n-2.5 6.0 zlo zhi\n\nMasses\n\n1 39.948\n2 83.798\n\nAtoms # full\n\n";
+        for (i, pos) in positions.iter().enumerate() {
+            writeln!(text, "{} 1 {} 0.0 {} {} {} 0 0 0", ids[i], types[i], pos[0], pos[1], pos[2]).unwrap();
+        }
+        text += "\nVelocities\n\n";
+        for (i, vel) in velocities.iter().enumerate() {
+            writeln!(text, "{} {} {} {}", ids[i], vel[0], vel[1], vel[2]).unwrap();
+        }
+        text += "\nBonds\n\n1 1 3 2\n";
+        let path = std::env::temp_dir().join(format!("sim-data-{}.data", std::process::id()));
+        std::fs::write(&path, text).unwrap();
+
+        let data = read_data(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // Sorted by id: 1, 2, 3, 4
+        let order = [1, 3, 0, 2];
+        assert_eq!(data.positions, order.map(|i| positions[i]));
+        assert_eq!(data.velocities.unwrap(), order.map(|i| velocities[i]));
+        assert_eq!(data.types, order.map(|i| types[i]));
+        assert_eq!(data.masses, HashMap::from([(1, 39.948), (2, 83.798)]));
+        assert_eq!(data.bonds, vec![(1, 2, 1)]);
+        assert_eq!((data.box_lo, data.box_hi), ([-4.0, -3.0, -2.5], [4.0, 5.0, 6.0]));
+    }
+}
@@ -0,0 +1,63 @@
+// The simulation engine and its I/O and analysis modules, usable from other crates. The
+// `sim` binary is a command line front end over them.
+pub mod analysis;
+pub mod analyze;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod checkpoint;
+pub mod cli;
+pub mod colvars;
+pub mod compress;
+pub mod dashboard;
+pub mod dcd;
+pub mod droplet;
+pub mod env;
+pub mod equilibration;
+pub mod evaporation;
+pub mod forces;
+pub mod fork;
+pub mod gcmc;
+pub mod geometry;
+pub mod glass;
+pub mod gpu;
+pub mod h5md;
+pub mod input;
+pub mod ladder;
+pub mod lammps;
+pub mod mc;
+pub mod metadynamics;
+pub mod metrics;
+pub mod minimize;
+pub mod monitor;
+pub mod neighbors;
+pub mod notify;
+pub mod observer;
+pub mod order;
+pub mod pdb;
+pub mod plot;
+pub mod profile;
+pub mod protocol;
+pub mod random;
+pub mod regions;
+pub mod remd;
+pub mod report;
+pub mod restraints;
+pub mod results_db;
+pub mod rnemd;
+pub mod script;
+#[cfg(feature = "server")]
+pub mod serve;
+pub mod shear;
+pub mod simulation;
+pub mod soa;
+pub mod steering;
+pub mod stream;
+pub mod table;
+pub mod timing;
+pub mod trajectory;
+pub mod umbrella;
+pub mod units;
+pub mod viz;
+pub mod walls;
+pub mod xtc;
+pub mod xyz;
@@ -1,175 +1,2105 @@
 use rayon::prelude::*;
-use rand::Rng;
-use serde::Serialize;
-use std::fs::File;
-use std::io::Write;
+use rand::{Rng, RngCore};
+use signal_hook::consts::{SIGINT, SIGTERM};
 use std::env;
-use indicatif::{ProgressBar, ProgressStyle, HumanDuration};
-use std::time::{Instant, Duration};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use indicatif::HumanDuration;
+use std::time::{Instant, Duration, SystemTime};
 
-#[derive(Serialize)]
-struct SimulationData {
-    box_length: f64,
-    num_atoms: usize,
-    timestep: f64,
-    total_steps: usize,
-    snapshot_interval: usize,
-    trajectory: Vec<Vec<[f64; 3]>>,
-}
+use sim::{analyze, cli, dashboard, droplet, evaporation, fork, gcmc, glass, gpu, h5md, input, ladder, mc, metrics, minimize, notify, order, profile, protocol, random, remd, report, results_db, rnemd, script, shear, simulation, stream, trajectory, umbrella, viz};
+use sim::colvars::{self, Colvar};
+use sim::equilibration::EquilibrationDetector;
+use sim::checkpoint::{Checkpoint, CheckpointSchedule};
+use sim::dashboard::Progress;
+use sim::forces::add_pair_virial;
+use sim::geometry::{Boundary, Cell};
+use sim::lammps::DumpColumns;
+use sim::metadynamics::Metadynamics;
+use sim::monitor::{Monitor, MonitorSettings, Status};
+use sim::profile::Profile;
+use sim::regions::{Region, Shape};
+use sim::restraints::{self, Restraint};
+use sim::script::{Hooks, ScriptState};
+use sim::soa::{Real, Vectors};
+use sim::steering::{Pull, Pulling};
+use sim::table::TableWriter;
+use sim::timing::PhaseTimer;
+use sim::trajectory::{BackgroundWriter, SimulationData, TrajectoryWriter};
+use sim::units::{self, Units};
+use sim::walls::{self, Wall};
+use tracing_subscriber::layer::SubscriberExt;
 
-fn lj_potential(r: f64) -> f64 {
-    let sigma = 1.0;
-    let epsilon = 1.0;
-    4.0 * epsilon * ((sigma / r).powi(12) - (sigma / r).powi(6))
-}
+// What the anomaly monitor watches and the status file reports
+const OBSERVABLES: [&str; 4] = ["temperature", "potential_energy", "total_energy", "pressure"];
+
+const THERMO_COLUMNS: [&str; 7] =
+    ["step", "time", "temperature", "kinetic_energy", "potential_energy", "total_energy", "pressure"];
+// Pressure tensor components added to the thermo log with --stress
+const STRESS_COLUMNS: [&str; 6] = ["pxx", "pyy", "pzz", "pxy", "pxz", "pyz"];
+
+// Force of the atoms on the indenter, added to the thermo log with --indenter
+const INDENTER_COLUMNS: [&str; 3] = ["indenter_fx", "indenter_fy", "indenter_fz"];
+
+// Steered centre of mass, added to the thermo log with --pull
+const PULL_COLUMNS: [&str; 3] = ["pull_displacement", "pull_force", "pull_work"];
+
+const HEAT_CURRENT_COLUMNS: [&str; 5] = ["step", "time", "jx", "jy", "jz"];
+
+// Blocks of rows the pair loop is split into, each with its own force buffers
+const PAIR_BLOCKS: usize = 32;
+
+// Force, potential energy, scalar virial and virial tensor of one atom
+type AtomTerms = ([f64; 3], f64, f64, [f64; 6]);
 
 fn main() {
+    let launched = Instant::now();
     let args: Vec<String> = env::args().collect();
-    
-    if args.len() != 6 {
-        eprintln!("Usage: {} <box_length> <num_atoms> <timestep> <total_steps> <snapshot_interval>", args[0]);
-        std::process::exit(1);
+    cli::configure_threads(&cli::Args::parse(&args[1..]));
+
+    if args.len() > 1 && args[1] == "glass" {
+        glass::run(&args[2..]);
+        return;
+    }
+    if args.len() > 1 && args[1] == "analyze" {
+        analyze::run(&args[2..]);
+        return;
+    }
+    if args.len() > 1 && args[1] == "minimize" {
+        minimize::run(&args[2..]);
+        return;
+    }
+    if args.len() > 1 && args[1] == "report" {
+        report::run(&args[2..]);
+        return;
+    }
+    if args.len() > 1 && args[1] == "resume" {
+        resume(&args[2..]);
+        return;
+    }
+    if args.len() > 1 && args[1] == "fork" {
+        fork::run(&args[2..]);
+        return;
+    }
+    if args.len() > 1 && args[1] == "ladder" {
+        ladder::run(&args[2..]);
+        return;
+    }
+    if args.len() > 1 && args[1] == "protocol" {
+        protocol::run(&args[2..]);
+        return;
+    }
+    if args.len() > 1 && args[1] == "rnemd" {
+        rnemd::run(&args[2..]);
+        return;
+    }
+    if args.len() > 1 && args[1] == "shear" {
+        shear::run(&args[2..]);
+        return;
+    }
+    if args.len() > 1 && args[1] == "droplet" {
+        droplet::run(&args[2..]);
+        return;
+    }
+    if args.len() > 1 && args[1] == "evaporate" {
+        evaporation::run(&args[2..]);
+        return;
+    }
+    if args.len() > 1 && args[1] == "umbrella" {
+        umbrella::run(&args[2..]);
+        return;
+    }
+    if args.len() > 1 && args[1] == "remd" {
+        remd::run(&args[2..]);
+        return;
+    }
+    if args.len() > 1 && args[1] == "gcmc" {
+        gcmc::run(&args[2..]);
+        return;
+    }
+    if args.len() > 1 && args[1] == "mc" {
+        mc::run(&args[2..]);
+        return;
+    }
+    if args.len() > 1 && args[1] == "serve" {
+        serve(&args[2..]);
+        return;
     }
 
-    let l: f64 = args[1].parse().expect("Invalid box length");
-    let n: usize = args[2].parse().expect("Invalid number of atoms");
-    let dt: f64 = args[3].parse().expect("Invalid timestep");
-    let steps: usize = args[4].parse().expect("Invalid total steps");
-    let snapshot_interval: usize = args[5].parse().expect("Invalid snapshot interval");
+    let options = cli::Args::parse(&args[1..]);
+    // Installed first so that every fatal error below is reported
+    let notifier = notify::Notifier::from_args(&options);
+    if let Some(notifier) = &notifier {
+        notifier.install();
+    }
+    if options.positional.len() != 5 {
+        notify::fail(&format!("Usage: {} <box_length|Lx,Ly,Lz> <num_atoms> <timestep> <total_steps> <snapshot_interval> [--boundary p,p,p] [--wall zlo:lj93:1:1:2.5,...] [--init-from config.xyz|system.data|structure.pdb] [--substrate substrate.xyz] [--frozen 0-99,150] [--body-force fx,fy,fz] [--restrain 0-99:50,...] [--restrain-reference reference.xyz] [--indenter sphere:x,y,z:R|cylinder:z:x,y:R|slab:z:lo:hi] [--indenter-k 10] [--indenter-velocity vx,vy,vz] [--container SHAPE] [--container-k 10] [--pull 0-9 --pull-velocity vx,vy,vz [--pull-k 10] | --pull-force fx,fy,fz] [--colvars colvars.toml] [--colvars-log colvars.csv] [--colvars-every 1] [--metad gap[,contacts] --metad-width 0.1[,1.0] [--metad-height 0.2] [--metad-bias-factor 10] [--metad-stride 500] [--metad-hills hills.csv] [--metad-fes fes.csv] [--metad-fes-every N] [--metad-bins 100]] [--output-format json|xyz|extxyz|dcd|xtc|dump|h5md] [--output path[.gz|.zst]] [--dump-velocities] [--dump-forces] [--thermo-every N] [--thermo thermo.csv|thermo.tsv] [--stress] [--heat-current heat_current.csv] [--heat-current-every N] [--dump-energy] [--dump-order] [--profile profile.csv] [--profile-axis x|y|z] [--profile-bins 50] [--profile-every 10] [--monitor] [--monitor-window 1000] [--monitor-jump 8] [--monitor-drift 0.2] [--monitor-every 1] [--detect-equilibration [--equilibration-every 10] [--auto-production]] [--status-file status.json] [--remove-com-every N] [--deterministic] [--precision double|single] [--backend cpu|gpu|mixed [--mixed-cutoff 2.5]] [--threads N] [--units native|reduced|real|metal] [--viz [--viz-every 10]] [--tui] [--stream ws://0.0.0.0:9000 [--stream-format json|msgpack] [--stream-every 10] [--stream-frames-every 100] [--stream-atoms 1000]] [--metrics 0.0.0.0:9464] [--checkpoint-every N|15m] [--checkpoint checkpoint.bin] [--max-walltime 47h30m] [--script hooks.rhai] [--script-every 100] [--script-log script.csv] [--seed S] [--record-random rng.bin | --replay-random rng.bin] [--minimize] [--minimizer sd|fire|cg] [--minimize-tolerance 10.0] [--minimize-max-iterations 10000] [--results-db results.sqlite] [--notify-url URL] [--notify-on completed,failed,interrupted,checkpoint,anomaly]", args[0]));
+    }
 
+    // The box, timestep and starting configurations are given in these units, the rest in
+    // the engine's own
+    let units: Units = options.get("units", Units::Native);
+    let l = cli::parse_box_lengths(&options.positional[0]).expect("Invalid box length").map(|l| l / units.length());
+    let n: usize = options.positional[1].parse().expect("Invalid number of atoms");
+    let dt: f64 = options.positional[2].parse::<f64>().expect("Invalid timestep") / units.time();
+    let steps: usize = options.positional[3].parse().expect("Invalid total steps");
+    let snapshot_interval: usize = options.positional[4].parse().expect("Invalid snapshot interval");
+    let init_from: Option<String> = options.get_opt("init-from");
+    let substrate_path: Option<String> = options.get_opt("substrate");
+    let frozen_indices: Vec<usize> = match options.get_opt::<String>("frozen") {
+        Some(text) => cli::parse_indices(&text).unwrap_or_else(|| {
+            notify::fail(&format!("Invalid value for --frozen: {} (expected indices and ranges, e.g. 0-99,150)", text));
+        }),
+        None => Vec::new(),
+    };
+    let restrained: Vec<(usize, f64)> = match options.get_opt::<String>("restrain") {
+        Some(text) => restraints::parse_groups(&text).unwrap_or_else(|| {
+            notify::fail(&format!("Invalid value for --restrain: {} (expected indices or ranges with a spring constant, e.g. 0-99:50,150:10)", text));
+        }),
+        None => Vec::new(),
+    };
+    let restraint_reference: Option<String> = options.get_opt("restrain-reference");
+    let boundaries: [Boundary; 3] = match options.get_list("boundary", vec![Boundary::Periodic])[..] {
+        [boundary] => [boundary; 3],
+        [x, y, z] => [x, y, z],
+        _ => {
+            notify::fail("--boundary takes one boundary for every axis or one per axis, e.g. p,p,r");
+        }
+    };
+    let walls: Vec<Wall> = options.get_list("wall", Vec::new());
+    let body_force: [f64; 3] = match options.get_list("body-force", vec![0.0; 3])[..] {
+        [fx, fy, fz] => [fx, fy, fz],
+        _ => {
+            notify::fail("--body-force takes three components, e.g. 0.01,0,0");
+        }
+    };
+    let region = |name: &str, container: bool, velocity: [f64; 3]| {
+        let shape: Shape = options.get_opt::<String>(name)?.parse().unwrap_or_else(|e| {
+            notify::fail(&format!("Invalid value for --{}: {}", name, e));
+        });
+        let stiffness: f64 = options.get(&format!("{}-k", name), 10.0);
+        if stiffness <= 0.0 || !stiffness.is_finite() {
+            notify::fail(&format!("--{}-k must be positive", name));
+        }
+        Some(Region { shape, container, stiffness, velocity })
+    };
+    let indenter_velocity: [f64; 3] = match options.get_list("indenter-velocity", vec![0.0; 3])[..] {
+        [vx, vy, vz] => [vx, vy, vz],
+        _ => {
+            notify::fail("--indenter-velocity takes three components, e.g. 0,0,-0.1");
+        }
+    };
+    let indenter = region("indenter", false, indenter_velocity);
+    let pulled: Option<Vec<usize>> = options.get_opt::<String>("pull").map(|text| {
+        cli::parse_indices(&text).filter(|atoms| !atoms.is_empty()).unwrap_or_else(|| {
+            notify::fail(&format!("Invalid value for --pull: {} (expected indices and ranges, e.g. 0-9)", text));
+        })
+    });
+    let vector = |name: &str| -> Option<[f64; 3]> {
+        match options.get_list::<f64>(name, Vec::new())[..] {
+            [] => None,
+            [x, y, z] => Some([x, y, z]),
+            _ => {
+                notify::fail(&format!("--{} takes three components, e.g. 0,0,0.1", name));
+            }
+        }
+    };
+    let pulling = match (vector("pull-velocity"), vector("pull-force")) {
+        (Some(velocity), None) => {
+            let spring: f64 = options.get("pull-k", 10.0);
+            if spring <= 0.0 || !spring.is_finite() {
+                notify::fail("--pull-k must be positive");
+            }
+            Some(Pulling::Velocity { velocity, spring })
+        }
+        (None, Some(force)) => Some(Pulling::Force(force)),
+        _ => None,
+    };
+    if pulled.is_some() != pulling.is_some() {
+        notify::fail("--pull takes the pulled atoms together with either --pull-velocity or --pull-force");
+    }
+    let container = region("container", true, [0.0; 3]);
+    // A wall at a periodic face would sit between atoms and the images they interact with
+    if let Some(wall) = walls.iter().find(|wall| boundaries[wall.axis] == Boundary::Periodic) {
+        notify::fail(&format!("The {} wall needs a reflective or open boundary along its axis (--boundary)", wall.face()));
+    }
+    // The pair kernel sums every pair in the box, so there is no cutoff to choose, switch off
+    // smoothly or correct for
+    if let Some(option) = ["cutoff", "cutoff-accuracy", "switch-fraction"].into_iter().find(|&option| options.has(option)) {
+        notify::fail(&format!("--{} only applies to the subcommands and the Simulation API; the main run has no cutoff", option));
+    }
+    let results_db_path: Option<String> = options.get_opt("results-db");
+    let output_format: String = options.get("output-format", "json".to_string());
+    if !trajectory::FORMATS.contains(&output_format.as_str()) {
+        notify::fail(&format!("--output-format must be one of {}", trajectory::FORMATS.join(", ")));
+    }
+    if output_format == "h5md" && !h5md::available() {
+        notify::fail("--output-format h5md requires a build with --features h5md");
+    }
+    let remove_com_every: usize = options.get("remove-com-every", 0);
+    if remove_com_every > 0 && options.has("body-force") {
+        notify::fail("--remove-com-every would take away the flow --body-force drives");
+    }
+    let thermo_every: usize = options.get("thermo-every", snapshot_interval);
+    let thermo_path: String = options.get("thermo", "thermo.csv".to_string());
+    if !["csv", "tsv"].contains(&sim::compress::inner_extension(&thermo_path)) {
+        notify::fail("--thermo must be a .csv or .tsv file, optionally compressed (.gz, .zst)");
+    }
+    // Sampled every step by default once a file is given, as Green-Kubo integrals need
+    let heat_current_every: usize = options.get("heat-current-every", if options.has("heat-current") { 1 } else { 0 });
+    let heat_current_path: String = options.get("heat-current", "heat_current.csv".to_string());
+    if !["csv", "tsv"].contains(&sim::compress::inner_extension(&heat_current_path)) {
+        notify::fail("--heat-current must be a .csv or .tsv file, optionally compressed (.gz, .zst)");
+    }
+    let colvars: Vec<Colvar> = match options.get_opt::<String>("colvars") {
+        Some(path) => colvars::read_colvars(&path).unwrap_or_else(|e| {
+            notify::fail(&format!("Invalid collective variables {}: {}", path, e));
+        }),
+        None => Vec::new(),
+    };
+    let colvars_every: usize = options.get("colvars-every", if colvars.is_empty() { 0 } else { 1 });
+    let colvars_path: String = options.get("colvars-log", "colvars.csv".to_string());
+    if !["csv", "tsv"].contains(&sim::compress::inner_extension(&colvars_path)) {
+        notify::fail("--colvars-log must be a .csv or .tsv file, optionally compressed (.gz, .zst)");
+    }
+    let script: Option<String> = options.get_opt("script");
+    if script.is_some() && !script::available() {
+        notify::fail("--script requires a build with --features scripting");
+    }
+    let script_every: usize = options.get("script-every", 100);
+    if script_every == 0 {
+        notify::fail("--script-every must be at least 1");
+    }
+    let script_log_path: String = options.get("script-log", "script.csv".to_string());
+    if !["csv", "tsv"].contains(&sim::compress::inner_extension(&script_log_path)) {
+        notify::fail("--script-log must be a .csv or .tsv file, optionally compressed (.gz, .zst)");
+    }
     let target_temperature: f64 = 87.3; // Target temperature
+    let metadynamics = options.has("metad").then(|| {
+        let names: Vec<String> = options.get_list("metad", Vec::new());
+        let widths: Vec<f64> = options.get_list("metad-width", Vec::new());
+        let indices: Vec<usize> = names.iter().map(|name| {
+            colvars.iter().position(|c| c.name == *name).unwrap_or_else(|| {
+                notify::fail(&format!("--metad colvar {} is not defined in --colvars", name));
+            })
+        }).collect();
+        if !(1..=2).contains(&indices.len()) || widths.len() != indices.len() || widths.iter().any(|&w| w <= 0.0) {
+            notify::fail("--metad takes one or two colvars and --metad-width a positive width for each");
+        }
+        let bias_factor: f64 = options.get("metad-bias-factor", 10.0);
+        let metadynamics = Metadynamics {
+            colvars: indices,
+            height: options.get("metad-height", 0.2),
+            widths,
+            bias_factor,
+            temperature: target_temperature,
+            stride: options.get("metad-stride", 500),
+            hills: Vec::new(),
+        };
+        if bias_factor.is_nan() || bias_factor <= 1.0 || metadynamics.height <= 0.0 || metadynamics.stride == 0 {
+            notify::fail("--metad-bias-factor must be above 1 (inf for plain metadynamics), --metad-height positive and --metad-stride at least 1");
+        }
+        metadynamics
+    });
+    let hills_path: String = options.get("metad-hills", "hills.csv".to_string());
+    let fes_path: String = options.get("metad-fes", "fes.csv".to_string());
+    let fes_every: usize = options.get("metad-fes-every", 0);
+    let fes_bins: usize = options.get("metad-bins", 100);
+    if fes_bins < 2 {
+        notify::fail("--metad-bins must be at least 2");
+    }
+    let profile_every: usize = options.get("profile-every", if options.has("profile") { 10 } else { 0 });
+    let profile_path: String = options.get("profile", "profile.csv".to_string());
+    let profile_axis: String = options.get("profile-axis", "z".to_string());
+    let profile_bins: usize = options.get("profile-bins", 50);
+    let Some(profile_axis) = profile::parse_axis(&profile_axis) else {
+        notify::fail(&format!("--profile-axis must be one of {}", profile::AXES.join(", ")));
+    };
+    if profile_bins == 0 {
+        notify::fail("--profile-bins must be at least 1");
+    }
+    let checkpoint_every: CheckpointSchedule = options.get("checkpoint-every", CheckpointSchedule::Never);
+    let checkpoint_path: String = options.get("checkpoint", "checkpoint.bin".to_string());
+    let max_walltime = options.get_duration("max-walltime");
+    let record_random: Option<String> = options.get_opt("record-random");
+    let replay_random: Option<String> = options.get_opt("replay-random");
+    if record_random.is_some() && replay_random.is_some() {
+        notify::fail("--record-random and --replay-random are mutually exclusive");
+    }
+    if replay_random.is_some() && options.has("seed") {
+        notify::fail("--seed has no effect with --replay-random, the numbers come from the recording");
+    }
+    // Seeds the initial placement and velocities, unless they are replayed
+    let seed: u64 = options.get("seed", rand::random());
+    let minimize_tolerance: Option<f64> = if options.has("minimize") {
+        Some(options.get("minimize-tolerance", 10.0))
+    } else {
+        None
+    };
+    let minimize_max_iterations: usize = options.get("minimize-max-iterations", 10000);
+    let minimizer: String = options.get("minimizer", "sd".to_string());
+    if !minimize::METHODS.contains(&minimizer.as_str()) {
+        notify::fail(&format!("--minimizer must be one of {}", minimize::METHODS.join(", ")));
+    }
+    // The minimizers and the neighbour cells of the order parameters assume a periodic cubic box
+    let cubic = l[0] == l[1] && l[1] == l[2] && boundaries.iter().all(|&b| b == Boundary::Periodic);
+    if !cubic && (options.has("minimize") || options.has("dump-order")) {
+        notify::fail("--minimize and --dump-order need a cubic box with periodic boundaries");
+    }
+    // The minimizers move every atom
+    if options.has("minimize") && (substrate_path.is_some() || !frozen_indices.is_empty()) {
+        notify::fail("--minimize cannot be combined with --substrate or --frozen");
+    }
+
+    if results_db_path.is_some() && !results_db::available() {
+        notify::fail("--results-db requires a build with --features sqlite");
+    }
+
+    let precision: String = options.get("precision", "double".to_string());
+    let single_precision = match precision.as_str() {
+        "double" => false,
+        "single" => true,
+        _ => {
+            notify::fail(&format!("Unknown --precision {}, expected double or single", precision));
+        }
+    };
+
+
     let tau: f64 = 0.1; // Coupling constant for the Berendsen thermostat
 
-    let mut rng = rand::thread_rng();
+    if replay_random.is_none() {
+        println!("Seed: {}", seed);
+    }
+    // Three coordinates per atom and the velocity seed, eight bytes each
+    let random_bytes = (3 * n as u64 + 1) * 8;
+    let mut rng: Box<dyn RngCore> = match (&record_random, &replay_random) {
+        (Some(path), _) => Box::new(random::RecordingRng::create(path, random::PhiloxRng::new(seed), n).unwrap_or_else(|e| {
+            notify::fail(&format!("Failed to create {}: {}", path, e));
+        })),
+        (None, Some(path)) => Box::new(random::ReplayRng::open(path, n, random_bytes).unwrap_or_else(|e| {
+            notify::fail(&format!("Failed to read {}: {}", path, e));
+        })),
+        (None, None) => Box::new(random::PhiloxRng::new(seed)),
+    };
     let mut positions = (0..n).map(|_| {
-        [rng.gen::<f64>() * l, rng.gen::<f64>() * l, rng.gen::<f64>() * l]
+        [rng.gen::<f64>() * l[0], rng.gen::<f64>() * l[1], rng.gen::<f64>() * l[2]]
     }).collect::<Vec<_>>();
 
-    let mass_argon: f64 = 39.95;
-    let kb: f64 = 0.0083144621;
-    let velocity_factor = (kb * target_temperature / mass_argon).sqrt();
-    let mut velocities = (0..n).map(|_| {
-        [
-            rng.gen::<f64>() * velocity_factor,
-            rng.gen::<f64>() * velocity_factor,
-            rng.gen::<f64>() * velocity_factor
-        ]
+    let mut velocities = simulation::maxwell_boltzmann_velocities(n, target_temperature, simulation::MASS_ARGON, &random::Philox::new(rng.gen()));
+    // No more random numbers are drawn, so a recording is complete on disk before the run starts
+    drop(rng);
+
+    // Atoms may sit anywhere along open axes
+    let place = |pos: &[f64; 3]| -> [f64; 3] {
+        std::array::from_fn(|k| if boundaries[k] == Boundary::Open { pos[k] } else { pos[k].rem_euclid(l[k]) })
+    };
+    // Configuration files are in the units of the run
+    let place_from_file = |pos: &[f64; 3]| place(&pos.map(|x| x / units.length()));
+
+    // Continue from an existing configuration instead of random placement
+    if let Some(path) = &init_from {
+        let frame = input::read_configuration(path).unwrap_or_else(|e| {
+            notify::fail(&format!("Failed to read {}: {}", path, e));
+        });
+        if frame.positions.len() != n {
+            notify::fail(&format!("{} contains {} atoms, expected {}", path, frame.positions.len(), n));
+        }
+        if let Some(box_lengths) = frame.box_lengths.map(|lengths| lengths.map(|b| b / units.length())) {
+            if box_lengths.iter().zip(&l).any(|(&b, &l)| (b - l).abs() > 1e-6 * l) {
+                eprintln!("Warning: box in {} is {:?}, running with box {:?}", path, box_lengths, l);
+            }
+        }
+        positions = frame.positions.iter().map(place_from_file).collect();
+        if let Some(frame_velocities) = frame.velocities {
+            velocities = frame_velocities.iter().map(|vel| vel.map(|v| v / units.velocity())).collect();
+        }
+    }
+
+    // Fixed wall or substrate atoms after the others, frozen along with those given by index
+    let mut frozen = vec![false; n];
+    if let Some(path) = &substrate_path {
+        let frame = input::read_configuration(path).unwrap_or_else(|e| {
+            notify::fail(&format!("Failed to read {}: {}", path, e));
+        });
+        println!("Added {} frozen substrate atoms from {}", frame.positions.len(), path);
+        positions.extend(frame.positions.iter().map(place_from_file));
+        velocities.resize(positions.len(), [0.0; 3]);
+        frozen.resize(positions.len(), true);
+    }
+    if let Some(&index) = frozen_indices.iter().find(|&&i| i >= positions.len()) {
+        notify::fail(&format!("--frozen index {} is out of range for {} atoms", index, positions.len()));
+    }
+    for &i in &frozen_indices {
+        frozen[i] = true;
+        velocities[i] = [0.0; 3];
+    }
+    let frozen = frozen.contains(&true).then_some(frozen);
+    if let Some(&(index, _)) = restrained.iter().find(|&&(i, _)| i >= positions.len()) {
+        notify::fail(&format!("--restrain index {} is out of range for {} atoms", index, positions.len()));
+    }
+
+    // Relax bad contacts from random placement before any dynamics
+    if let Some(tolerance) = minimize_tolerance {
+        let result = minimize::minimize(&minimizer, &mut positions, l[0], f64::INFINITY, tolerance, minimize_max_iterations);
+        println!(
+            "Minimization ({}) {} after {} iterations: potential energy {:.4}, max force {:.4e}",
+            minimizer,
+            if result.converged { "converged" } else { "stopped" },
+            result.iterations,
+            result.potential_energy,
+            result.max_force
+        );
+    }
+
+    // Tethered to where they start unless a reference configuration is given
+    let reference = match &restraint_reference {
+        Some(path) => {
+            let frame = input::read_configuration(path).unwrap_or_else(|e| {
+                notify::fail(&format!("Failed to read {}: {}", path, e));
+            });
+            if frame.positions.len() != positions.len() {
+                notify::fail(&format!("{} contains {} atoms, expected {}", path, frame.positions.len(), positions.len()));
+            }
+            frame.positions.iter().map(place_from_file).collect()
+        }
+        None => positions.clone(),
+    };
+    if let Some(atoms) = &pulled {
+        if let Some(&i) = atoms.iter().find(|&&i| i >= positions.len() || frozen.as_ref().is_some_and(|f| f[i])) {
+            notify::fail(&format!("--pull atom {} is out of range or frozen", i));
+        }
+    }
+    if let Some((colvar, i)) = colvars.iter().find_map(|c| c.atoms().into_iter().find(|&i| i >= positions.len()).map(|i| (c, i))) {
+        notify::fail(&format!("Colvar {} refers to atom {}, out of range for {} atoms", colvar.name, i, positions.len()));
+    }
+    let periodic = boundaries.map(|b| b == Boundary::Periodic);
+    let pull = pulled.zip(pulling).map(|(atoms, pulling)| Pull::new(atoms, pulling, &positions, l, periodic));
+    let restraints: Vec<Restraint> = restrained.iter().map(|&(atom, spring)| Restraint { atom, spring, reference: reference[atom] }).collect();
+
+    let com = match &frozen {
+        Some(frozen) => simulation::remove_mobile_com_velocity(&mut velocities, frozen),
+        None => simulation::remove_com_velocity(&mut velocities),
+    };
+    println!(
+        "Removed initial centre-of-mass velocity {:.3e}",
+        (com[0].powi(2) + com[1].powi(2) + com[2].powi(2)).sqrt()
+    );
+    if !units.is_native() {
+        println!("Box, timestep, configurations, trajectory and thermo log in {} units", units.describe());
+    }
+
+    // Seed the previous positions so the first Verlet step starts from the initial velocities
+    let positions_old = positions.iter().zip(velocities.iter()).map(|(pos, vel)| {
+        [pos[0] - vel[0] * dt, pos[1] - vel[1] * dt, pos[2] - vel[2] * dt]
     }).collect::<Vec<_>>();
 
-    let mut positions_old = positions.clone();
+    let state = Checkpoint {
+        box_lengths: l,
+        boundaries,
+        walls,
+        frozen,
+        body_force,
+        restraints,
+        indenter,
+        container,
+        pull,
+        timestep: dt,
+        total_steps: steps,
+        snapshot_interval,
+        target_temperature,
+        tau,
+        remove_com_every,
+        deterministic: options.has("deterministic"),
+        single_precision,
+        init_from,
+        seed: replay_random.is_none().then_some(seed),
+        units,
+        output_path: options.get("output", TrajectoryWriter::path_for(&output_format)),
+        output_format,
+        dump_columns: DumpColumns {
+            velocities: options.has("dump-velocities"),
+            forces: options.has("dump-forces"),
+            energy: options.has("dump-energy"),
+            order: options.has("dump-order"),
+        },
+        thermo_every,
+        thermo_path,
+        stress: options.has("stress"),
+        heat_current_every,
+        heat_current_path,
+        colvars,
+        colvars_every,
+        colvars_path,
+        metadynamics,
+        hills_path,
+        fes_path,
+        fes_every,
+        fes_bins,
+        profile_every,
+        profile_path,
+        profile: (profile_every > 0).then(|| Profile::new(profile_axis, profile_bins)),
+        script,
+        script_every,
+        script_log_path,
+        monitor: MonitorSettings::from_args(&options),
+        equilibration: EquilibrationDetector::from_args(&options),
+        status_path: options.get_opt("status-file"),
+        checkpoint_every,
+        checkpoint_path,
+        step: 0,
+        positions,
+        positions_old,
+        velocities,
+        temperature_sum: 0.0,
+        statistics_from: 0,
+        final_temperature: 0.0,
+        trajectory_bytes: 0,
+        thermo_bytes: 0,
+        heat_current_bytes: 0,
+        colvars_bytes: 0,
+        hills_bytes: 0,
+        script_bytes: 0,
+        frame_pressure: Vec::new(),
+        frame_stress: Vec::new(),
+        anomalies: Vec::new(),
+        started_at: SystemTime::now(),
+        wall_seconds: 0.0,
+    };
+    let gpu = gpu::from_args(&options, state.num_atoms());
+    let viz = viz::from_args(&options);
+    let tui = dashboard::from_args(&options);
+    let stream = stream::from_args(&options, stream::RunInfo {
+        num_atoms: state.num_atoms(),
+        box_lengths: state.box_lengths,
+        timestep: state.timestep,
+        total_steps: state.total_steps,
+    });
+    let metrics = metrics::from_args(&options, state.step, state.total_steps, state.num_atoms());
+    launch(state, RunSettings { notifier, results_db_path, max_walltime, gpu, viz, tui, stream, metrics, launched });
+}
+
+#[cfg(feature = "server")]
+fn serve(args: &[String]) {
+    sim::serve::run(args);
+}
+
+#[cfg(not(feature = "server"))]
+fn serve(_args: &[String]) {
+    notify::fail("sim serve requires a build with --features server");
+}
+
+// `sim resume checkpoint.bin`: continues an interrupted main run from its last checkpoint,
+// with the same result as if it had never stopped
+fn resume(args: &[String]) {
+    let launched = Instant::now();
+    let options = cli::Args::parse(args);
+    // Installed first so that every fatal error below is reported
+    let notifier = notify::Notifier::from_args(&options);
+    if let Some(notifier) = &notifier {
+        notifier.install();
+    }
+    if options.positional.len() != 1 {
+        notify::fail("Usage: sim resume <checkpoint.bin> [--backend cpu|gpu|mixed [--mixed-cutoff 2.5]] [--threads N] [--viz [--viz-every 10]] [--tui] [--stream ws://0.0.0.0:9000 [--stream-format json|msgpack] [--stream-every 10] [--stream-frames-every 100] [--stream-atoms 1000]] [--metrics 0.0.0.0:9464] [--max-walltime 47h30m] [--results-db results.sqlite] [--notify-url URL] [--notify-on completed,failed,interrupted,checkpoint,anomaly]");
+    }
+    let path = &options.positional[0];
+    let max_walltime = options.get_duration("max-walltime");
+    let state = Checkpoint::read(path).unwrap_or_else(|e| {
+        notify::fail(&format!("Failed to read {}: {}", path, e));
+    });
+
+    let results_db_path: Option<String> = options.get_opt("results-db");
+    if results_db_path.is_some() && !results_db::available() {
+        notify::fail("--results-db requires a build with --features sqlite");
+    }
+
+    println!(
+        "Resuming {} atoms at step {} of {} ({:.3} of {:.3} ps)",
+        state.num_atoms(),
+        state.step,
+        state.total_steps,
+        units::picoseconds(state.step as f64 * state.timestep),
+        units::picoseconds(state.total_steps as f64 * state.timestep)
+    );
+    let gpu = gpu::from_args(&options, state.num_atoms());
+    let viz = viz::from_args(&options);
+    let tui = dashboard::from_args(&options);
+    let stream = stream::from_args(&options, stream::RunInfo {
+        num_atoms: state.num_atoms(),
+        box_lengths: state.box_lengths,
+        timestep: state.timestep,
+        total_steps: state.total_steps,
+    });
+    let metrics = metrics::from_args(&options, state.step, state.total_steps, state.num_atoms());
+    launch(state, RunSettings { notifier, results_db_path, max_walltime, gpu, viz, tui, stream, metrics, launched });
+}
+
+// What a run does besides the simulation; these are given anew on every resume
+struct RunSettings {
+    notifier: Option<notify::Notifier>,
+    results_db_path: Option<String>,
+    // Stop with a checkpoint once this much time has passed since `launched`
+    max_walltime: Option<Duration>,
+    // Pair forces on the GPU instead of the CPU kernel
+    gpu: Option<gpu::GpuForces>,
+    // Live view the run offers its configurations to
+    viz: Option<Arc<viz::Link>>,
+    // Terminal dashboard instead of the progress bar
+    tui: bool,
+    // WebSocket clients watching the run
+    stream: Option<stream::Streamer>,
+    // Prometheus endpoint the run reports to
+    metrics: Option<Arc<metrics::Metrics>>,
+    launched: Instant,
+}
+
+// Runs the main simulation, on a worker thread when the live view needs the main thread
+fn launch(state: Checkpoint, settings: RunSettings) {
+    let Some(link) = settings.viz.clone() else {
+        return run(state, settings);
+    };
+    let frozen = state.frozen.clone();
+    let worker = std::thread::spawn(move || run(state, settings));
+    viz::show(&link, frozen.as_deref());
+    if let Err(panic) = worker.join() {
+        std::panic::resume_unwind(panic);
+    }
+}
+
+// Runs the main simulation from `state` to its last step, checkpointing along the way
+fn run(mut state: Checkpoint, settings: RunSettings) {
+    let RunSettings { notifier, results_db_path, max_walltime, mut gpu, viz, tui, stream, metrics, launched } = settings;
+    let l = state.box_lengths;
+    let boundaries = state.boundaries;
+    // Only periodic axes have images to take the minimum over
+    let periodic = boundaries.map(|b| b == Boundary::Periodic);
+    let n = state.num_atoms();
+    let frozen = state.frozen.clone();
+    let is_frozen = |i: usize| frozen.as_ref().is_some_and(|f| f[i]);
+    // Degrees of freedom of the temperature
+    let mobile = (0..n).filter(|&i| !is_frozen(i)).count();
+    let dt = state.timestep;
+    let steps = state.total_steps;
+    let snapshot_interval = state.snapshot_interval;
+    let remove_com_every = state.remove_com_every;
+    let mass_argon = simulation::MASS_ARGON;
+    let kb = simulation::KB;
+    let first_step = state.step;
+    // The state is held as x, y and z arrays while the run goes on, and as rows in the checkpoint
+    let mut positions = Vectors::from_rows(&std::mem::take(&mut state.positions));
+    let mut positions_old = Vectors::from_rows(&std::mem::take(&mut state.positions_old));
+    let mut velocities = Vectors::from_rows(&std::mem::take(&mut state.velocities));
+    let mut temperature_sum = state.temperature_sum;
+    let mut statistics_from = state.statistics_from;
+    let mut final_temperature = state.final_temperature;
 
-    let mut trajectory = Vec::new();
+    // Trajectory and thermo log are written in these; everything else stays in engine units
+    let units = state.units;
+    let header = SimulationData {
+        box_length: l[0] * units.length(),
+        cell: Some(Cell::orthorhombic(l.map(|l| l * units.length())).vectors()),
+        num_atoms: n,
+        timestep: dt * units.time(),
+        total_steps: steps,
+        snapshot_interval,
+        trajectory: Vec::new(),
+        pressure: state.frame_pressure.clone(),
+        stress: state.stress.then(|| state.frame_stress.clone()),
+        velocities: None,
+        seed: state.seed,
+        units,
+    };
+    let output_path = state.output_path.clone();
+    let trajectory = if first_step == 0 {
+        TrajectoryWriter::create(&state.output_format, &output_path, header, state.dump_columns)
+    } else {
+        TrajectoryWriter::append(
+            &state.output_format,
+            &output_path,
+            state.trajectory_bytes,
+            state.frames_written(),
+            header,
+            state.dump_columns,
+        )
+    };
+    let trajectory = trajectory.unwrap_or_else(|e| {
+        notify::fail(&format!("Failed to open {}: {}", output_path, e));
+    });
+    let stress = state.stress;
+    let mut thermo_columns = THERMO_COLUMNS.to_vec();
+    if stress {
+        thermo_columns.extend(STRESS_COLUMNS);
+    }
+    if state.indenter.is_some() {
+        thermo_columns.extend(INDENTER_COLUMNS);
+    }
+    if state.pull.is_some() {
+        thermo_columns.extend(PULL_COLUMNS);
+    }
+    let thermo_path = state.thermo_path.clone();
+    let mut thermo = if state.thermo_every == 0 {
+        None
+    } else if first_step == 0 {
+        Some(TableWriter::create(&thermo_path, &thermo_columns))
+    } else {
+        Some(TableWriter::append(&thermo_path, state.thermo_bytes))
+    }.transpose().unwrap_or_else(|e| {
+        notify::fail(&format!("Failed to open {}: {}", thermo_path, e));
+    });
+    let heat_current_every = state.heat_current_every;
+    let heat_current_path = state.heat_current_path.clone();
+    let mut heat_log = if heat_current_every == 0 {
+        None
+    } else if first_step == 0 {
+        Some(TableWriter::create(&heat_current_path, &HEAT_CURRENT_COLUMNS))
+    } else {
+        Some(TableWriter::append(&heat_current_path, state.heat_current_bytes))
+    }.transpose().unwrap_or_else(|e| {
+        notify::fail(&format!("Failed to open {}: {}", heat_current_path, e));
+    });
+    let colvars_every = state.colvars_every;
+    let colvars_path = state.colvars_path.clone();
+    let mut colvars_log = if colvars_every == 0 {
+        None
+    } else if first_step == 0 {
+        let mut columns = vec!["step", "time"];
+        columns.extend(state.colvars.iter().map(|c| c.name.as_str()));
+        columns.push("bias_energy");
+        Some(TableWriter::create(&colvars_path, &columns))
+    } else {
+        Some(TableWriter::append(&colvars_path, state.colvars_bytes))
+    }.transpose().unwrap_or_else(|e| {
+        notify::fail(&format!("Failed to open {}: {}", colvars_path, e));
+    });
+    let hills_path = state.hills_path.clone();
+    let mut hills_log = match &state.metadynamics {
+        None => None,
+        Some(metadynamics) if first_step == 0 => {
+            let mut columns = vec!["step", "time"];
+            columns.extend(metadynamics.colvars.iter().map(|&c| state.colvars[c].name.as_str()));
+            columns.push("height");
+            Some(TableWriter::create(&hills_path, &columns))
+        }
+        Some(_) => Some(TableWriter::append(&hills_path, state.hills_bytes)),
+    }.transpose().unwrap_or_else(|e| {
+        notify::fail(&format!("Failed to open {}: {}", hills_path, e));
+    });
+    let hooks = state.script.as_ref().map(|path| Hooks::load(path).unwrap_or_else(|e| {
+        notify::fail(&format!("Failed to load script {}: {}", path, e));
+    }));
+    // Opened at the first observables the script returns, which name its columns
+    let mut script_log: Option<TableWriter> = None;
+    let dump_energy = state.dump_columns.energy;
+    let profile_every = state.profile_every;
+    let mut profile = state.profile.take();
+    // Frames are written on a separate thread; up to this many can queue behind a slow disk
+    let mut trajectory = BackgroundWriter::spawn(trajectory, 8);
 
-    let pb = ProgressBar::new(steps as u64);
-    pb.set_style(ProgressStyle::default_bar()
-        .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}")
-        .unwrap()
-        .progress_chars("##-"));
+    let mut pb = Progress::new(first_step, steps, tui);
 
     let start_time = Instant::now();
+    let mut last_checkpoint = start_time;
+    // Time already spent before a resume
+    let wall_before = state.wall_seconds;
     let mut last_update = start_time;
     let update_interval = Duration::from_secs(1);
 
+    // SIGINT/SIGTERM end the run after the current step with a checkpoint; a second signal
+    // exits straight away
+    let interrupted = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM] {
+        signal_hook::flag::register_conditional_shutdown(signal, 130, Arc::clone(&interrupted))
+            .and_then(|_| signal_hook::flag::register(signal, Arc::clone(&interrupted)))
+            .expect("Failed to install signal handler");
+    }
+    let mut stopped_at = None;
+    // Last step of the run, earlier than planned if the script stops it
+    let mut last_step = steps;
+
+    let mut monitor = state.monitor.map(|settings| Monitor::new(settings, &OBSERVABLES));
+    // Latest values of OBSERVABLES, for the status file
+    let mut observables = [0.0; 4];
+
+    // Per-atom buffers filled anew every step, allocated once
+    let mut double_kernel = PairKernel::<f64>::default();
+    let mut single_kernel = PairKernel::<f32>::default();
+    let mut pair_terms = PairSums::<f64>::default();
+    let mut gpu_terms: Vec<AtomTerms> = Vec::new();
+    let mut forces = Vectors::<f64>::default();
+    let mut atom_energies: Vec<f64> = Vec::new();
+    let mut atom_virials: Vec<[f64; 6]> = Vec::new();
+    // Rows of the state for the code that takes `[f64; 3]` rows, filled on the steps it runs
+    let mut position_rows: Vec<[f64; 3]> = Vec::new();
+    let mut velocity_rows: Vec<[f64; 3]> = Vec::new();
+    let mut force_rows: Vec<[f64; 3]> = Vec::new();
+
+    // Wall time of each phase of the loop, from the spans it enters every step
+    let timer = PhaseTimer::default();
+    let _timing = tracing::subscriber::set_default(tracing_subscriber::registry().with(timer.clone()));
+    let forces_span = tracing::info_span!("forces");
+    let integration_span = tracing::info_span!("integration");
+    let thermostat_span = tracing::info_span!("thermostat");
+    let analysis_span = tracing::info_span!("analysis");
+    let output_span = tracing::info_span!("output");
+
     // Perform simulation
-    for step in 0..steps {
-        pb.set_position(step as u64);
+    for step in first_step..steps {
+        pb.set_position(step);
+        let heat_current_due = heat_current_every > 0 && step % heat_current_every == 0;
+        let snapshot_due = snapshot_interval > 0 && step % snapshot_interval == 0;
+        let thermo_due = state.thermo_every > 0 && step % state.thermo_every == 0;
+        // The pressure tensor only goes into thermo rows and frames
+        let stress_due = stress && (thermo_due || snapshot_due);
+        // Per-atom virials are only kept when something needs them
+        let atom_virials_needed = stress_due || heat_current_due;
 
         // Calculate forces in parallel
-        let forces: Vec<_> = (0..n).into_par_iter().map(|i| {
-            let mut force = [0.0; 3];
-            for j in 0..n {
-                if i != j {
-                    let mut r_ij = [0.0; 3];
-                    for k in 0..3 {
-                        r_ij[k] = positions[i][k] - positions[j][k];
-                        r_ij[k] -= (r_ij[k] / l).round() * l;
-                    }
-                    let r = (r_ij[0].powi(2) + r_ij[1].powi(2) + r_ij[2].powi(2)).sqrt();
-                    let force_magnitude = lj_potential(r) / r;
-                    for k in 0..3 {
-                        force[k] += force_magnitude * r_ij[k];
+        let phase = forces_span.enter();
+        match gpu.as_mut() {
+            Some(gpu) => {
+                positions.fill_rows(&mut position_rows);
+                gpu.compute(&position_rows, l, periodic, &mut gpu_terms);
+                unpack_terms(&gpu_terms, &mut pair_terms);
+            }
+            None if state.single_precision => pair_forces(&mut single_kernel, &positions, l, periodic, atom_virials_needed, &mut pair_terms),
+            None => pair_forces(&mut double_kernel, &positions, l, periodic, atom_virials_needed, &mut pair_terms),
+        }
+        // Fixed-order sums, so the totals do not depend on the number of threads
+        let [mut potential_energy] = simulation::ordered_sum(&pair_terms.energies, |e| [*e]);
+        let [virial] = simulation::ordered_sum(&pair_terms.virials, |w| [*w]);
+        let virial_tensor = if pair_terms.with_tensors { simulation::ordered_sum(&pair_terms.tensors, |t| *t) } else { [0.0; 6] };
+        atom_energies.clear();
+        if heat_current_due || (dump_energy && snapshot_due) {
+            atom_energies.extend_from_slice(&pair_terms.energies);
+        }
+        atom_virials.clear();
+        if heat_current_due {
+            atom_virials.extend_from_slice(&pair_terms.tensors);
+        }
+        // The kernel's force buffer becomes the step's, and the last one its buffer
+        std::mem::swap(&mut forces, &mut pair_terms.forces);
+        // External forces, left out of the virial. They work on rows, copied out and back only
+        // when there are any.
+        let external = !state.walls.is_empty() || !state.restraints.is_empty() || state.indenter.is_some()
+            || state.container.is_some() || state.pull.is_some() || !state.colvars.is_empty();
+        if external {
+            positions.fill_rows(&mut position_rows);
+            forces.fill_rows(&mut force_rows);
+        }
+        if !state.walls.is_empty() {
+            potential_energy += walls::add_wall_forces(&state.walls, &position_rows, l, &mut force_rows, &mut atom_energies).unwrap_or_else(|e| {
+                notify::fail(&format!("Step {}: {}", step, e));
+            });
+        }
+        if !state.restraints.is_empty() {
+            potential_energy += restraints::add_restraint_forces(&state.restraints, &position_rows, l, periodic, &mut force_rows, &mut atom_energies);
+        }
+        let time = step as f64 * dt;
+        let mut indenter_load = [0.0; 3];
+        if let Some(indenter) = &state.indenter {
+            let (energy, load) = indenter.add_forces(time, &position_rows, l, periodic, &mut force_rows, &mut atom_energies);
+            potential_energy += energy;
+            indenter_load = load;
+        }
+        if let Some(container) = &state.container {
+            potential_energy += container.add_forces(time, &position_rows, l, periodic, &mut force_rows, &mut atom_energies).0;
+        }
+        let mut pull_force = 0.0;
+        if let Some(pull) = &mut state.pull {
+            let (energy, force) = pull.apply(time, &position_rows, l, periodic, &mut force_rows, &mut atom_energies);
+            potential_energy += energy;
+            pull_force = force;
+        }
+        if !state.colvars.is_empty() {
+            let (evaluated, mut bias_energy) = colvars::apply_biases(&state.colvars, &position_rows, l, periodic, &mut force_rows, &mut atom_energies);
+            if let Some(metadynamics) = &mut state.metadynamics {
+                let s: Vec<f64> = metadynamics.colvars.iter().map(|&c| evaluated[c].0).collect();
+                let (energy, slope) = metadynamics.bias(&s);
+                for (&c, &ds) in metadynamics.colvars.iter().zip(&slope) {
+                    colvars::add_gradient_forces(&evaluated[c].1, ds, &mut force_rows);
+                    colvars::share_energy(&state.colvars[c], energy / s.len() as f64, &mut atom_energies);
+                }
+                bias_energy += energy;
+                if step % metadynamics.stride == 0 {
+                    let hill = metadynamics.deposit(&s);
+                    if let Some(hills_log) = &mut hills_log {
+                        let mut row = vec![step as f64, time];
+                        row.extend(&hill.centre);
+                        row.push(hill.height);
+                        hills_log.write_row(&row).expect("Failed to write hills log");
                     }
                 }
+                if state.fes_every > 0 && step > 0 && step % state.fes_every == 0 {
+                    write_free_energy(&state.metadynamics, &state.colvars, &state.fes_path, state.fes_bins);
+                }
             }
-            force
-        }).collect();
+            potential_energy += bias_energy;
+            if let Some(colvars_log) = &mut colvars_log {
+                if step % colvars_every == 0 {
+                    let mut row = vec![step as f64, time];
+                    row.extend(evaluated.iter().map(|(value, _)| value));
+                    row.push(bias_energy);
+                    colvars_log.write_row(&row).expect("Failed to write colvars log");
+                }
+            }
+        }
+        if external {
+            forces.set_rows(&force_rows);
+        }
+        // A uniform field has no potential energy that is periodic, so none is counted
+        if state.body_force != [0.0; 3] {
+            for (component, body) in forces.components_mut().into_iter().zip(state.body_force) {
+                component.par_iter_mut().enumerate().filter(|(i, _)| !is_frozen(*i)).for_each(|(_, f)| *f += body);
+            }
+        }
+        drop(phase);
 
-        // Verlet integration and boundary handling in parallel
-        let (positions_new, new_velocities): (Vec<_>, Vec<_>) = positions.par_iter().zip(positions_old.par_iter()).zip(forces.par_iter()).zip(velocities.par_iter())
-            .map(|(((pos, pos_old), force), _vel)| {
-                let mut pos_new = [0.0; 3];
-                let mut vel_new = [0.0; 3];
-                for j in 0..3 {
-                    pos_new[j] = 2.0 * pos[j] - pos_old[j] + force[j] * dt.powi(2);
-                    vel_new[j] = (pos_new[j] - pos_old[j]) / (2.0 * dt);
-
-                    if pos_new[j] >= l {
-                        pos_new[j] = 2.0 * l - pos_new[j];
-                        vel_new[j] = -vel_new[j];
-                    } else if pos_new[j] <= 0.0 {
-                        pos_new[j] = -pos_new[j];
-                        vel_new[j] = -vel_new[j];
+        // Verlet integration and boundary handling in parallel, in place, one component at a time.
+        // An atom that crosses a face is wrapped or mirrored together with its current position,
+        // which becomes the previous position of the next step, so the Verlet update carries on
+        // along the moved path.
+        let phase = integration_span.enter();
+        let components = positions.components_mut().into_iter().zip(positions_old.components_mut()).zip(velocities.components_mut()).zip(forces.components());
+        for (k, (((pos, pos_old), vel), force)) in components.enumerate() {
+            let (length, boundary) = (l[k], boundaries[k]);
+            pos.par_iter_mut().zip(pos_old.par_iter_mut()).zip(vel.par_iter_mut()).zip(force.par_iter()).enumerate()
+                .for_each(|(i, (((pos, pos_old), vel), force))| {
+                    if is_frozen(i) {
+                        *pos_old = *pos;
+                        *vel = 0.0;
+                        return;
                     }
-                }
-                (pos_new, vel_new)
-            }).unzip();
+                    let mut pos_current = *pos;
+                    let (mut pos_new, mut vel_new) = verlet_step(*pos, *pos_old, force / mass_argon, dt);
+                    match boundary {
+                        Boundary::Periodic => {
+                            let shift = pos_new.div_euclid(length) * length;
+                            pos_new -= shift;
+                            pos_current -= shift;
+                        }
+                        Boundary::Reflective if pos_new >= length || pos_new < 0.0 => {
+                            let face = if pos_new >= length { length } else { 0.0 };
+                            pos_new = 2.0 * face - pos_new;
+                            pos_current = 2.0 * face - pos_current;
+                            vel_new = -vel_new;
+                        }
+                        _ => {}
+                    }
+                    *pos_old = pos_current;
+                    *pos = pos_new;
+                    *vel = vel_new;
+                });
+        }
 
-        // Update positions and velocities
-        positions_old = positions;
-        positions = positions_new;
-        velocities = new_velocities;
+        if remove_com_every > 0 && (step + 1) % remove_com_every == 0 {
+            remove_drift(&positions, &mut positions_old, &mut velocities, frozen.as_deref());
+        }
+        drop(phase);
 
         // Calculate the current temperature
-        let kinetic_energy: f64 = velocities.par_iter().map(|vel| {
-            0.5 * mass_argon * (vel[0].powi(2) + vel[1].powi(2) + vel[2].powi(2))
-        }).sum();
-        let current_temperature = (2.0 * kinetic_energy) / (3.0 * n as f64 * kb);
+        let phase = thermostat_span.enter();
+        let kinetic_energy = kinetic_energy(&velocities, mass_argon, state.deterministic);
+        let current_temperature = (2.0 * kinetic_energy) / (3.0 * mobile as f64 * kb);
+        temperature_sum += current_temperature;
+        final_temperature = current_temperature;
+        drop(phase);
 
-        // Calculate the scaling factor and scale velocities
-        let scaling_factor = (1.0 + dt / tau * (target_temperature / current_temperature - 1.0)).sqrt();
-        velocities.par_iter_mut().for_each(|vel| {
-            for coord in vel.iter_mut() {
-                *coord *= scaling_factor;
-            }
+        // Virial pressure, and the full tensor on request, from the same velocities
+        let phase = analysis_span.enter();
+        // Rows for the analyses due this step
+        let profile_due = profile.is_some() && step % profile_every == 0;
+        let script_due = hooks.is_some() && step % state.script_every == 0;
+        let frame_streamed = stream.as_ref().is_some_and(|stream| stream.frame_due(step));
+        if stress_due || heat_current_due || profile_due || script_due {
+            velocities.fill_rows(&mut velocity_rows);
+        }
+        if profile_due || script_due || frame_streamed {
+            positions.fill_rows(&mut position_rows);
+        }
+        let volume = l[0] * l[1] * l[2];
+        let pressure = (2.0 * kinetic_energy + virial) / (3.0 * volume);
+        let pressure_tensor = stress_due.then(|| {
+            let kinetic = simulation::kinetic_tensor(&velocity_rows, mass_argon);
+            std::array::from_fn::<f64, 6, _>(|c| (kinetic[c] + virial_tensor[c]) / volume)
         });
 
+        if let Some(heat_log) = &mut heat_log {
+            if heat_current_due {
+                let current = simulation::heat_current(&velocity_rows, mass_argon, &atom_energies, &atom_virials);
+                let scale = units.energy() * units.velocity();
+                let row = [step as f64, step as f64 * dt * units.time(), current[0] * scale, current[1] * scale, current[2] * scale];
+                heat_log.write_row(&row).expect("Failed to write heat current log");
+            }
+        }
+
+        if let Some(profile) = profile.as_mut().filter(|_| profile_due) {
+            profile.sample(&position_rows, &velocity_rows, l, mass_argon, None);
+        }
+
+        observables = [current_temperature, potential_energy, kinetic_energy + potential_energy, pressure];
+        pb.observe(&observables);
+        if let Some(metrics) = &metrics {
+            metrics.update(step + 1, &observables);
+        }
+        if let Some(stream) = &stream {
+            if stream.thermo_due(step) {
+                stream.thermo(step, time, current_temperature, kinetic_energy, potential_energy, pressure);
+            }
+            if frame_streamed {
+                stream.frame(step, time, l, &position_rows);
+            }
+        }
+        if let Some(detector) = state.equilibration.as_mut().filter(|detector| detector.due(step)) {
+            if let Some(start) = detector.observe(step, potential_energy) {
+                pb.println(&format!("Potential energy equilibrated from step {} (detected at step {})", start, step));
+                // Statistics restart with the next step, this one being already counted
+                if detector.production {
+                    temperature_sum = 0.0;
+                    statistics_from = step + 1;
+                }
+            }
+        }
+        if let Some(monitor) = monitor.as_mut().filter(|monitor| monitor.due(step)) {
+            let anomalies = monitor.observe(step, &observables);
+            for anomaly in &anomalies {
+                pb.eprintln(&format!("Warning: step {}: {}", anomaly.step, anomaly.message));
+                if let Some(notifier) = &notifier {
+                    notifier.send(notify::Event::Anomaly, &format!(
+                        "Simulation of {} atoms at step {} of {}: {}",
+                        n, step, steps, anomaly.message
+                    ));
+                }
+            }
+            if !anomalies.is_empty() {
+                state.anomalies.extend(anomalies);
+                write_status(&state, "running", step + 1, observables);
+            }
+        }
+        let mut script_stop = false;
+        if let Some(hooks) = hooks.as_ref().filter(|_| script_due) {
+            let outcome = hooks.evaluate(&ScriptState {
+                step,
+                time,
+                temperature: current_temperature,
+                kinetic_energy,
+                potential_energy,
+                pressure,
+                box_lengths: l,
+                target_temperature: state.target_temperature,
+                tau: state.tau,
+                body_force: state.body_force,
+                positions: &position_rows,
+                velocities: &velocity_rows,
+            }).unwrap_or_else(|e| {
+                notify::fail(&format!("Step {}: script failed: {}", step, e));
+            });
+            if !outcome.observables.is_empty() {
+                let log = match &mut script_log {
+                    Some(log) => log,
+                    None => {
+                        let opened = if first_step > 0 && state.script_bytes > 0 {
+                            TableWriter::append(&state.script_log_path, state.script_bytes)
+                        } else {
+                            let mut columns = vec!["step", "time"];
+                            columns.extend(outcome.observables.iter().map(|(name, _)| name.as_str()));
+                            TableWriter::create(&state.script_log_path, &columns)
+                        };
+                        script_log.insert(opened.unwrap_or_else(|e| {
+                            notify::fail(&format!("Failed to open {}: {}", state.script_log_path, e));
+                        }))
+                    }
+                };
+                let mut row = vec![step as f64, time];
+                row.extend(outcome.observables.iter().map(|(_, value)| value));
+                log.write_row(&row).expect("Failed to write script log");
+            }
+            state.target_temperature = outcome.target_temperature.unwrap_or(state.target_temperature);
+            state.tau = outcome.tau.unwrap_or(state.tau);
+            state.body_force = outcome.body_force.unwrap_or(state.body_force);
+            script_stop = outcome.stop;
+        }
+        drop(phase);
+
+        // Berendsen thermostat
+        let phase = thermostat_span.enter();
+        let scaling_factor = (1.0 + dt / state.tau * (state.target_temperature / current_temperature - 1.0)).sqrt();
+        rescale_velocities(&positions, &mut positions_old, &mut velocities, scaling_factor);
+        drop(phase);
+
+        let _phase = output_span.enter();
+        if let Some(thermo) = &mut thermo {
+            if thermo_due {
+                let mut row = vec![
+                    step as f64,
+                    step as f64 * dt * units.time(),
+                    current_temperature * units.temperature(),
+                    kinetic_energy * units.energy(),
+                    potential_energy * units.energy(),
+                    (kinetic_energy + potential_energy) * units.energy(),
+                    pressure * units.pressure(),
+                ];
+                row.extend(pressure_tensor.iter().flatten().map(|p| p * units.pressure()));
+                if state.indenter.is_some() {
+                    row.extend(indenter_load.map(|f| f * units.force()));
+                }
+                if let Some(pull) = &state.pull {
+                    row.extend([pull.displacement() * units.length(), pull_force * units.force(), pull.work * units.energy()]);
+                }
+                thermo.write_row(&row).expect("Failed to write thermo log");
+            }
+        }
+
         // Store trajectory data
-        if step % snapshot_interval == 0 {
-            trajectory.push(positions.clone());
+        if snapshot_due {
+            state.frame_pressure.push(pressure * units.pressure());
+            state.frame_stress.extend(pressure_tensor.map(|tensor| tensor.map(|p| p * units.pressure())));
+            // A frame back from the writer lends its buffers, and takes the forces and energies
+            // in exchange for its own
+            let mut frame = trajectory.spare_frame();
+            frame.step = step;
+            frame.time = step as f64 * dt;
+            positions.fill_rows(&mut frame.positions);
+            velocities.fill_rows(&mut frame.velocities);
+            forces.fill_rows(&mut frame.forces);
+            std::mem::swap(&mut frame.energies, &mut atom_energies);
+            frame.order.clear();
+            if state.dump_columns.order {
+                let result = order::bond_order(&frame.positions, l[0], order::NEIGHBOUR_CUTOFF);
+                frame.order.extend((0..n).map(|i| [result.q4[i], result.q6[i], result.w6[i]]));
+            }
+            frame.box_lengths = l;
+            frame.pressure = pressure;
+            frame.stress = pressure_tensor;
+            frame.temperature = current_temperature;
+            frame.potential_energy = potential_energy;
+            frame.kinetic_energy = kinetic_energy;
+            units.convert_frame(&mut frame);
+            trajectory.write_frame(frame).expect("Failed to write trajectory frame");
+        }
+        if let Some(viz) = viz.as_ref().filter(|viz| viz.due(step)) {
+            positions.fill_rows(&mut position_rows);
+            velocities.fill_rows(&mut velocity_rows);
+            viz.offer(step, time, current_temperature, l, &position_rows, &velocity_rows);
+        }
+        if script_stop {
+            last_step = step + 1;
+            break;
+        }
+
+        // Paused from the live view
+        if let Some(viz) = &viz {
+            viz.hold(&interrupted);
+        }
+        let out_of_time = max_walltime.is_some_and(|limit| launched.elapsed() >= limit);
+        let stop = (interrupted.load(Ordering::Relaxed) || out_of_time) && step + 1 < steps;
+        let checkpoint_due = step + 1 < steps && match state.checkpoint_every {
+            CheckpointSchedule::Never => false,
+            CheckpointSchedule::Steps(every) => (step + 1) % every == 0,
+            CheckpointSchedule::WallSeconds(seconds) => last_checkpoint.elapsed().as_secs_f64() >= seconds,
+        };
+        if checkpoint_due || stop {
+            state.trajectory_bytes = trajectory.sync().expect("Failed to write trajectory");
+            if let Some(thermo) = &mut thermo {
+                state.thermo_bytes = thermo.sync().expect("Failed to write thermo log");
+            }
+            if let Some(heat_log) = &mut heat_log {
+                state.heat_current_bytes = heat_log.sync().expect("Failed to write heat current log");
+            }
+            if let Some(colvars_log) = &mut colvars_log {
+                state.colvars_bytes = colvars_log.sync().expect("Failed to write colvars log");
+            }
+            if let Some(hills_log) = &mut hills_log {
+                state.hills_bytes = hills_log.sync().expect("Failed to write hills log");
+            }
+            if let Some(script_log) = &mut script_log {
+                state.script_bytes = script_log.sync().expect("Failed to write script log");
+            }
+            state.step = step + 1;
+            positions.fill_rows(&mut state.positions);
+            positions_old.fill_rows(&mut state.positions_old);
+            velocities.fill_rows(&mut state.velocities);
+            state.temperature_sum = temperature_sum;
+            state.statistics_from = statistics_from;
+            state.final_temperature = final_temperature;
+            state.profile.clone_from(&profile);
+            state.wall_seconds = wall_before + start_time.elapsed().as_secs_f64();
+            match state.write(&state.checkpoint_path) {
+                Ok(()) => {
+                    if let Some(notifier) = &notifier {
+                        notifier.send(notify::Event::Checkpoint, &format!(
+                            "Simulation of {} atoms checkpointed at step {} of {} to {}",
+                            n, step + 1, steps, state.checkpoint_path
+                        ));
+                    }
+                }
+                Err(e) => eprintln!("Failed to write checkpoint {}: {}", state.checkpoint_path, e),
+            }
+            last_checkpoint = Instant::now();
+        }
+        if stop {
+            stopped_at = Some((step + 1, out_of_time));
+            break;
         }
 
         // Update progress bar with time left and speed
         let now = Instant::now();
         if now.duration_since(last_update) >= update_interval {
             let elapsed = now.duration_since(start_time);
-            let iterations_per_sec = step as f64 / elapsed.as_secs_f64();
-            let estimated_total = Duration::from_secs_f64(steps as f64 / iterations_per_sec);
+            let iterations_per_sec = (step - first_step) as f64 / elapsed.as_secs_f64();
+            let estimated_total = Duration::from_secs_f64((steps - first_step) as f64 / iterations_per_sec);
             let time_left = estimated_total.saturating_sub(elapsed);
+            velocities.fill_rows(&mut velocity_rows);
             
             pb.set_message(format!(
-                "Speed: {:.2} it/s | Time left: {}",
+                "t = {:.3} ps | Speed: {:.2} it/s ({:.2} ns/day) | Time left: {} | COM KE: {:.2}%",
+                units::picoseconds(step as f64 * dt),
                 iterations_per_sec,
-                HumanDuration(time_left)
+                units::picoseconds(iterations_per_sec * dt) * 86400.0 / 1000.0,
+                HumanDuration(time_left),
+                100.0 * simulation::com_kinetic_fraction(&velocity_rows)
             ));
             
+            write_status(&state, "running", step + 1, observables);
             last_update = now;
         }
     }
 
-    pb.finish_with_message("Simulation complete");
+    let loop_time = start_time.elapsed();
 
-    let simulation_data = SimulationData {
-        box_length: l,
-        num_atoms: n,
-        timestep: dt,
-        total_steps: steps,
-        snapshot_interval,
-        trajectory,
+    if let Some((step, out_of_time)) = stopped_at {
+        let reason = if out_of_time { "Reached --max-walltime" } else { "Interrupted" };
+        write_status(&state, if out_of_time { "walltime" } else { "interrupted" }, step, observables);
+        pb.abandon_with_message(reason);
+        print!("Time per phase of the main loop:\n{}", timer.table(loop_time));
+        trajectory.suspend().expect("Failed to write trajectory");
+        if let Some(thermo) = thermo {
+            thermo.finish().expect("Failed to write thermo log");
+        }
+        if let Some(heat_log) = heat_log {
+            heat_log.finish().expect("Failed to write heat current log");
+        }
+        if let Some(colvars_log) = colvars_log {
+            colvars_log.finish().expect("Failed to write colvars log");
+        }
+        if let Some(hills_log) = hills_log {
+            hills_log.finish().expect("Failed to write hills log");
+        }
+        if let Some(script_log) = script_log {
+            script_log.finish().expect("Failed to write script log");
+        }
+        write_free_energy(&state.metadynamics, &state.colvars, &state.fes_path, state.fes_bins);
+        write_profile(profile.as_ref(), &state.profile_path);
+        println!(
+            "{} after {} of {} steps ({:.3} ps), mean temperature {:.2} K",
+            reason,
+            step,
+            steps,
+            units::picoseconds(step as f64 * dt),
+            temperature_sum / (step - statistics_from).max(1) as f64
+        );
+        println!(
+            "Trajectory so far saved to {}. Continue with: sim resume {}",
+            output_path, state.checkpoint_path
+        );
+        if let Some(notifier) = &notifier {
+            notifier.send(notify::Event::Interrupted, &format!(
+                "Simulation of {} atoms stopped at step {} of {}: {}",
+                n, step, steps, reason
+            ));
+        }
+        // EX_TEMPFAIL tells a job script to resubmit with `sim resume`
+        std::process::exit(if out_of_time { 75 } else { 130 });
+    }
+
+    if last_step < steps {
+        pb.abandon_with_message("Stopped by the script");
+        println!("Stopped by the script after {} of {} steps", last_step, steps);
+    } else {
+        pb.finish_with_message("Simulation complete");
+    }
+    write_status(&state, "completed", last_step, observables);
+    if let Some(viz) = &viz {
+        viz.finish();
+    }
+    print!("Time per phase of the main loop:\n{}", timer.table(loop_time));
+    if state.monitor.is_some() {
+        println!("Monitor reported {} anomalies", state.anomalies.len());
+    }
+    match state.equilibration.as_ref().map(|detector| detector.equilibrated) {
+        Some(Some((start, _))) if statistics_from > 0 => println!(
+            "Equilibrated from step {}; mean temperature {:.2} K over the {} production steps from step {}",
+            start,
+            temperature_sum / (last_step - statistics_from).max(1) as f64,
+            last_step - statistics_from,
+            statistics_from
+        ),
+        Some(Some((start, _))) => println!("Equilibrated from step {}", start),
+        Some(None) => println!("No equilibration detected, the potential energy may still be drifting"),
+        None => {}
+    }
+    velocities.fill_rows(&mut velocity_rows);
+    println!(
+        "Centre-of-mass motion carries {:.2}% of the final kinetic energy",
+        100.0 * simulation::com_kinetic_fraction(&velocity_rows)
+    );
+
+    trajectory.finish().expect("Failed to write trajectory");
+    if let Some(thermo) = thermo {
+        thermo.finish().expect("Failed to write thermo log");
+    }
+    if let Some(heat_log) = heat_log {
+        heat_log.finish().expect("Failed to write heat current log");
+    }
+    if let Some(colvars_log) = colvars_log {
+        colvars_log.finish().expect("Failed to write colvars log");
+    }
+    if let Some(hills_log) = hills_log {
+        hills_log.finish().expect("Failed to write hills log");
+    }
+    if let Some(script_log) = script_log {
+        script_log.finish().expect("Failed to write script log");
+    }
+    write_free_energy(&state.metadynamics, &state.colvars, &state.fes_path, state.fes_bins);
+    write_profile(profile.as_ref(), &state.profile_path);
+    println!("Simulation completed: {:.3} ps in {} steps. Data saved to {}", units::picoseconds(last_step as f64 * dt), last_step, output_path);
+
+    if let Some(notifier) = &notifier {
+        notifier.send(notify::Event::Completed, &format!(
+            "Simulation of {} atoms completed {} steps ({:.3} ps) in {}",
+            n,
+            last_step,
+            units::picoseconds(last_step as f64 * dt),
+            HumanDuration(start_time.elapsed())
+        ));
+    }
+
+    if let Some(path) = &results_db_path {
+        let run = results_db::RunRecord {
+            command: env::args().collect::<Vec<_>>().join(" "),
+            started_at: state.started_at,
+            wall_seconds: wall_before + start_time.elapsed().as_secs_f64(),
+            parameters: vec![
+                ("box_length".to_string(), if l[0] == l[1] && l[1] == l[2] { l[0].to_string() } else { format!("{},{},{}", l[0], l[1], l[2]) }),
+                ("num_atoms".to_string(), n.to_string()),
+                ("timestep".to_string(), dt.to_string()),
+                ("total_steps".to_string(), steps.to_string()),
+                ("snapshot_interval".to_string(), snapshot_interval.to_string()),
+                ("target_temperature".to_string(), state.target_temperature.to_string()),
+                ("init_from".to_string(), state.init_from.clone().unwrap_or_default()),
+                ("seed".to_string(), state.seed.map(|seed| seed.to_string()).unwrap_or_default()),
+            ],
+            observables: vec![
+                ("mean_temperature".to_string(), temperature_sum / (last_step - statistics_from).max(1) as f64),
+                ("final_temperature".to_string(), final_temperature),
+                ("simulated_time_ps".to_string(), units::picoseconds(last_step as f64 * dt)),
+            ].into_iter().chain(
+                state.equilibration.as_ref().and_then(|detector| detector.equilibrated)
+                    .map(|(start, _)| ("equilibration_step".to_string(), start as f64))
+            ).collect(),
+        };
+        match results_db::record_run(path, &run) {
+            Ok(run_id) => println!("Run recorded as id {} in {}", run_id, path),
+            Err(e) => eprintln!("Failed to record run in {}: {}", path, e),
+        }
+    }
+}
+
+// Writes the profile accumulated so far, if the run samples one
+fn write_free_energy(metadynamics: &Option<Metadynamics>, colvars: &[Colvar], path: &str, bins: usize) {
+    let Some(metadynamics) = metadynamics else { return };
+    let names: Vec<&str> = metadynamics.colvars.iter().map(|&c| colvars[c].name.as_str()).collect();
+    match metadynamics.write_free_energy(path, &names, bins) {
+        Ok(()) => println!("Metadynamics free energy from {} hills saved to {}", metadynamics.hills.len(), path),
+        Err(e) => eprintln!("Failed to write free energy {}: {}", path, e),
+    }
+}
+
+fn write_profile(profile: Option<&Profile>, path: &str) {
+    let Some(profile) = profile else { return };
+    match profile.write(path) {
+        Ok(()) => println!("Density and temperature profile over {} samples saved to {}", profile.samples(), path),
+        Err(e) => eprintln!("Failed to write profile {}: {}", path, e),
+    }
+}
+
+// Replaces the status file, if the run has one. Failures are reported but never stop the run.
+fn write_status(state: &Checkpoint, name: &str, step: usize, observables: [f64; 4]) {
+    let Some(path) = &state.status_path else { return };
+    let status = Status {
+        state: name,
+        step,
+        total_steps: state.total_steps,
+        time_ps: units::picoseconds(step as f64 * state.timestep),
+        temperature: observables[0],
+        potential_energy: observables[1],
+        total_energy: observables[2],
+        pressure: observables[3],
+        warnings: &state.anomalies,
+    };
+    if let Err(e) = status.write(path) {
+        eprintln!("Failed to write status file {}: {}", path, e);
+    }
+}
+
+// Sums of the pair terms of each atom, over one block of the pair loop or all of them, the
+// forces in the precision of the kernel and the rest in f64. The tensors are only summed with
+// `with_tensors`.
+#[derive(Default)]
+struct PairSums<T: Real> {
+    forces: Vectors<T>,
+    energies: Vec<f64>,
+    virials: Vec<f64>,
+    tensors: Vec<[f64; 6]>,
+    with_tensors: bool,
+}
+
+impl<T: Real> PairSums<T> {
+    // Zero sums for `n` atoms in the memory of the last ones
+    fn reset(&mut self, n: usize, with_tensors: bool) {
+        self.forces.set_zeros(n);
+        for sums in [&mut self.energies, &mut self.virials] {
+            sums.clear();
+            sums.resize(n, 0.0);
+        }
+        if with_tensors {
+            self.tensors.clear();
+            self.tensors.resize(n, [0.0; 6]);
+        }
+        self.with_tensors = with_tensors;
+    }
+}
+
+// The buffers of `pair_forces`, kept from one step to the next so it allocates nothing once
+// they have grown to size. `positions` holds the positions in T when that is not f64.
+#[derive(Default)]
+struct PairKernel<T: Real> {
+    positions: Vectors<T>,
+    blocks: Vec<PairSums<T>>,
+}
+
+// Force of an atom in the kernel's precision with its energy, virial and virial tensor
+type RowTerms<T> = ([T; 3], f64, f64, [f64; 6]);
+
+// Pairs (i, j) of row i of the pair loop for j from `start` on: adds the terms of j to `sums`
+// and returns the sums of those of i, the tensor only when `sums` holds tensors. Distances and
+// forces are in T; every pair's energy and virial is added in f64.
+fn pair_row<T: Real>(positions: &Vectors<T>, i: usize, start: usize, l: [f64; 3], periodic: [bool; 3], sums: &mut PairSums<T>) -> RowTerms<T> {
+    let tensors = sums.with_tensors;
+    let position = positions.get(i);
+    let l = l.map(T::from_f64);
+    let (one, two, four, twenty_four) = (T::from_f64(1.0), T::from_f64(2.0), T::from_f64(4.0), T::from_f64(24.0));
+    let mut force = [T::default(); 3];
+    let (mut energy, mut virial, mut tensor) = (0.0, 0.0, [0.0; 6]);
+    for j in start..positions.len() {
+        let mut r_ij = [position[0] - positions.x[j], position[1] - positions.y[j], position[2] - positions.z[j]];
+        for k in 0..3 {
+            if periodic[k] {
+                r_ij[k] -= (r_ij[k] / l[k]).round() * l[k];
+            }
+        }
+        let r_sq = r_ij[0] * r_ij[0] + r_ij[1] * r_ij[1] + r_ij[2] * r_ij[2];
+        let s6 = one / (r_sq * r_sq * r_sq);
+        let potential = four * (s6 * s6 - s6);
+        // -dU/dr / r, so the pair force is this times r_ij, as in `forces::lj_force`
+        let force_magnitude = twenty_four * (two * s6 * s6 - s6) / r_sq;
+        let pair_force = [force_magnitude * r_ij[0], force_magnitude * r_ij[1], force_magnitude * r_ij[2]];
+        for k in 0..3 {
+            force[k] += pair_force[k];
+        }
+        sums.forces.x[j] -= pair_force[0];
+        sums.forces.y[j] -= pair_force[1];
+        sums.forces.z[j] -= pair_force[2];
+        let pair_energy = 0.5 * potential.to_f64();
+        let pair_virial = 0.5 * (force_magnitude * r_sq).to_f64();
+        energy += pair_energy;
+        virial += pair_virial;
+        sums.energies[j] += pair_energy;
+        sums.virials[j] += pair_virial;
+        if tensors {
+            let mut pair_tensor = [0.0; 6];
+            add_pair_virial(&mut pair_tensor, r_ij.map(T::to_f64), 0.5 * force_magnitude.to_f64());
+            for (t, p) in tensor.iter_mut().zip(pair_tensor) {
+                *t += p;
+            }
+            for (t, p) in sums.tensors[j].iter_mut().zip(pair_tensor) {
+                *t += p;
+            }
+        }
+    }
+    (force, energy, virial, tensor)
+}
+
+// Whether the CPU runs the AVX2 pair rows
+#[cfg(target_arch = "x86_64")]
+fn simd_forces() -> bool {
+    is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma")
+}
+
+// A whole row i of the pair loop in one precision, vectorized when the CPU allows
+trait PairRow: Real {
+    fn row(positions: &Vectors<Self>, i: usize, l: [f64; 3], periodic: [bool; 3], sums: &mut PairSums<Self>) -> RowTerms<Self>;
+
+    // `positions` in this precision, converted into `buffer` unless they already are
+    fn coordinates<'a>(positions: &'a Vectors<f64>, buffer: &'a mut Vectors<Self>) -> &'a Vectors<Self>;
+}
+
+impl PairRow for f64 {
+    fn coordinates<'a>(positions: &'a Vectors<f64>, _buffer: &'a mut Vectors<f64>) -> &'a Vectors<f64> {
+        positions
+    }
+
+    fn row(positions: &Vectors<f64>, i: usize, l: [f64; 3], periodic: [bool; 3], sums: &mut PairSums<f64>) -> RowTerms<f64> {
+        #[cfg(target_arch = "x86_64")]
+        if simd_forces() {
+            // SAFETY: the CPU has AVX2 and FMA
+            return unsafe { pair_row_avx2(positions, i, l, periodic, sums) };
+        }
+        pair_row(positions, i, i + 1, l, periodic, sums)
+    }
+}
+
+impl PairRow for f32 {
+    fn coordinates<'a>(positions: &'a Vectors<f64>, buffer: &'a mut Vectors<f32>) -> &'a Vectors<f32> {
+        buffer.set_from(positions);
+        buffer
+    }
+
+    fn row(positions: &Vectors<f32>, i: usize, l: [f64; 3], periodic: [bool; 3], sums: &mut PairSums<f32>) -> RowTerms<f32> {
+        #[cfg(target_arch = "x86_64")]
+        if simd_forces() {
+            // SAFETY: the CPU has AVX2 and FMA
+            return unsafe { pair_row_avx2_single(positions, i, l, periodic, sums) };
+        }
+        pair_row(positions, i, i + 1, l, periodic, sums)
+    }
+}
+
+// `pair_row` over all of row i, four neighbours j at a time in AVX2 registers, with the last
+// few left to `pair_row`. The four lanes of i's sums are added in a fixed order at the end.
+// The nearest image rounds halves to even, so results agree with the scalar row to rounding,
+// not bit for bit.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2,fma")]
+fn pair_row_avx2(positions: &Vectors<f64>, i: usize, l: [f64; 3], periodic: [bool; 3], sums: &mut PairSums<f64>) -> RowTerms<f64> {
+    use std::arch::x86_64::*;
+
+    let n = positions.len();
+    let tensors = sums.with_tensors;
+    let position = positions.get(i).map(|coord| _mm256_set1_pd(coord));
+    let lengths = l.map(|length| _mm256_set1_pd(length));
+    let (half, four, twenty_four) = (_mm256_set1_pd(0.5), _mm256_set1_pd(4.0), _mm256_set1_pd(24.0));
+    let coordinates = [&positions.x, &positions.y, &positions.z];
+    let mut force = [_mm256_setzero_pd(); 3];
+    let (mut energy, mut virial) = (_mm256_setzero_pd(), _mm256_setzero_pd());
+    let mut tensor = [_mm256_setzero_pd(); 6];
+    let mut j = i + 1;
+    while j + 4 <= n {
+        let mut r_ij = [_mm256_setzero_pd(); 3];
+        for k in 0..3 {
+            // SAFETY: j + 4 <= n, the length of every array loaded and stored here
+            r_ij[k] = _mm256_sub_pd(position[k], unsafe { _mm256_loadu_pd(coordinates[k].as_ptr().add(j)) });
+            if periodic[k] {
+                let images = _mm256_round_pd::<{ _MM_FROUND_TO_NEAREST_INT | _MM_FROUND_NO_EXC }>(_mm256_div_pd(r_ij[k], lengths[k]));
+                r_ij[k] = _mm256_fnmadd_pd(images, lengths[k], r_ij[k]);
+            }
+        }
+        let r_sq = _mm256_fmadd_pd(r_ij[0], r_ij[0], _mm256_fmadd_pd(r_ij[1], r_ij[1], _mm256_mul_pd(r_ij[2], r_ij[2])));
+        let inverse = _mm256_div_pd(_mm256_set1_pd(1.0), r_sq);
+        let s6 = _mm256_mul_pd(_mm256_mul_pd(inverse, inverse), inverse);
+        let potential = _mm256_mul_pd(four, _mm256_fmsub_pd(s6, s6, s6));
+        // 24 (2 s6^2 - s6) / r^2 as in `pair_row`
+        let force_magnitude = _mm256_mul_pd(_mm256_mul_pd(twenty_four, _mm256_fmsub_pd(_mm256_add_pd(s6, s6), s6, s6)), inverse);
+        let pair_energy = _mm256_mul_pd(half, potential);
+        let pair_virial = _mm256_mul_pd(_mm256_mul_pd(half, force_magnitude), r_sq);
+        let forces = [&mut sums.forces.x, &mut sums.forces.y, &mut sums.forces.z];
+        for (k, out) in forces.into_iter().enumerate() {
+            let pair_force = _mm256_mul_pd(force_magnitude, r_ij[k]);
+            force[k] = _mm256_add_pd(force[k], pair_force);
+            // SAFETY: as above
+            unsafe {
+                let p = out.as_mut_ptr().add(j);
+                _mm256_storeu_pd(p, _mm256_sub_pd(_mm256_loadu_pd(p), pair_force));
+            }
+        }
+        energy = _mm256_add_pd(energy, pair_energy);
+        virial = _mm256_add_pd(virial, pair_virial);
+        for (out, terms) in [(&mut sums.energies, pair_energy), (&mut sums.virials, pair_virial)] {
+            // SAFETY: as above
+            unsafe {
+                let p = out.as_mut_ptr().add(j);
+                _mm256_storeu_pd(p, _mm256_add_pd(_mm256_loadu_pd(p), terms));
+            }
+        }
+        if tensors {
+            let scale = _mm256_mul_pd(half, force_magnitude);
+            for (c, (a, b)) in [(0, 0), (1, 1), (2, 2), (0, 1), (0, 2), (1, 2)].into_iter().enumerate() {
+                let part = _mm256_mul_pd(scale, _mm256_mul_pd(r_ij[a], r_ij[b]));
+                tensor[c] = _mm256_add_pd(tensor[c], part);
+                for (q, lane) in lanes_f64(part).into_iter().enumerate() {
+                    sums.tensors[j + q][c] += lane;
+                }
+            }
+        }
+        j += 4;
+    }
+
+    let sum = |v| {
+        let lanes = lanes_f64(v);
+        (lanes[0] + lanes[1]) + (lanes[2] + lanes[3])
+    };
+    let (mut total_force, mut total_energy, mut total_virial, mut total_tensor) = pair_row(positions, i, j, l, periodic, sums);
+    for k in 0..3 {
+        total_force[k] += sum(force[k]);
+    }
+    total_energy += sum(energy);
+    total_virial += sum(virial);
+    if tensors {
+        for c in 0..6 {
+            total_tensor[c] += sum(tensor[c]);
+        }
+    }
+    (total_force, total_energy, total_virial, total_tensor)
+}
+
+// `pair_row` in single precision over all of row i, eight neighbours j at a time. Distances and
+// forces stay in f32 lanes; the energies, virials and tensors are widened to f64 four lanes at
+// a time before they are added up.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2,fma")]
+fn pair_row_avx2_single(positions: &Vectors<f32>, i: usize, l: [f64; 3], periodic: [bool; 3], sums: &mut PairSums<f32>) -> RowTerms<f32> {
+    use std::arch::x86_64::*;
+
+    let widen = |v: __m256| [_mm256_cvtps_pd(_mm256_castps256_ps128(v)), _mm256_cvtps_pd(_mm256_extractf128_ps::<1>(v))];
+    let n = positions.len();
+    let tensors = sums.with_tensors;
+    let position = positions.get(i).map(|coord| _mm256_set1_ps(coord));
+    let lengths = l.map(|length| _mm256_set1_ps(length as f32));
+    let (half, four, twenty_four) = (_mm256_set1_ps(0.5), _mm256_set1_ps(4.0), _mm256_set1_ps(24.0));
+    let coordinates = [&positions.x, &positions.y, &positions.z];
+    let mut force = [_mm256_setzero_ps(); 3];
+    let (mut energy, mut virial) = (_mm256_setzero_pd(), _mm256_setzero_pd());
+    let mut tensor = [_mm256_setzero_pd(); 6];
+    let mut j = i + 1;
+    while j + 8 <= n {
+        let mut r_ij = [_mm256_setzero_ps(); 3];
+        for k in 0..3 {
+            // SAFETY: j + 8 <= n, the length of every array loaded and stored here
+            r_ij[k] = _mm256_sub_ps(position[k], unsafe { _mm256_loadu_ps(coordinates[k].as_ptr().add(j)) });
+            if periodic[k] {
+                let images = _mm256_round_ps::<{ _MM_FROUND_TO_NEAREST_INT | _MM_FROUND_NO_EXC }>(_mm256_div_ps(r_ij[k], lengths[k]));
+                r_ij[k] = _mm256_fnmadd_ps(images, lengths[k], r_ij[k]);
+            }
+        }
+        let r_sq = _mm256_fmadd_ps(r_ij[0], r_ij[0], _mm256_fmadd_ps(r_ij[1], r_ij[1], _mm256_mul_ps(r_ij[2], r_ij[2])));
+        let inverse = _mm256_div_ps(_mm256_set1_ps(1.0), r_sq);
+        let s6 = _mm256_mul_ps(_mm256_mul_ps(inverse, inverse), inverse);
+        let potential = _mm256_mul_ps(four, _mm256_fmsub_ps(s6, s6, s6));
+        let force_magnitude = _mm256_mul_ps(_mm256_mul_ps(twenty_four, _mm256_fmsub_ps(_mm256_add_ps(s6, s6), s6, s6)), inverse);
+        let forces = [&mut sums.forces.x, &mut sums.forces.y, &mut sums.forces.z];
+        for (k, out) in forces.into_iter().enumerate() {
+            let pair_force = _mm256_mul_ps(force_magnitude, r_ij[k]);
+            force[k] = _mm256_add_ps(force[k], pair_force);
+            // SAFETY: as above
+            unsafe {
+                let p = out.as_mut_ptr().add(j);
+                _mm256_storeu_ps(p, _mm256_sub_ps(_mm256_loadu_ps(p), pair_force));
+            }
+        }
+        let pair_energy = _mm256_mul_ps(half, potential);
+        let pair_virial = _mm256_mul_ps(_mm256_mul_ps(half, force_magnitude), r_sq);
+        for (out, terms, total) in [(&mut sums.energies, pair_energy, &mut energy), (&mut sums.virials, pair_virial, &mut virial)] {
+            for (h, wide) in widen(terms).into_iter().enumerate() {
+                *total = _mm256_add_pd(*total, wide);
+                // SAFETY: as above
+                unsafe {
+                    let p = out.as_mut_ptr().add(j + 4 * h);
+                    _mm256_storeu_pd(p, _mm256_add_pd(_mm256_loadu_pd(p), wide));
+                }
+            }
+        }
+        if tensors {
+            let scale = _mm256_mul_ps(half, force_magnitude);
+            for (c, (a, b)) in [(0, 0), (1, 1), (2, 2), (0, 1), (0, 2), (1, 2)].into_iter().enumerate() {
+                let part = _mm256_mul_ps(scale, _mm256_mul_ps(r_ij[a], r_ij[b]));
+                for (h, wide) in widen(part).into_iter().enumerate() {
+                    tensor[c] = _mm256_add_pd(tensor[c], wide);
+                    for (q, lane) in lanes_f64(wide).into_iter().enumerate() {
+                        sums.tensors[j + 4 * h + q][c] += lane;
+                    }
+                }
+            }
+        }
+        j += 8;
+    }
+
+    let sum = |v| {
+        let lanes = lanes_f64(v);
+        (lanes[0] + lanes[1]) + (lanes[2] + lanes[3])
     };
+    let (mut total_force, mut total_energy, mut total_virial, mut total_tensor) = pair_row(positions, i, j, l, periodic, sums);
+    for k in 0..3 {
+        let [low, high] = widen(force[k]);
+        total_force[k] += (sum(low) + sum(high)) as f32;
+    }
+    total_energy += sum(energy);
+    total_virial += sum(virial);
+    if tensors {
+        for c in 0..6 {
+            total_tensor[c] += sum(tensor[c]);
+        }
+    }
+    (total_force, total_energy, total_virial, total_tensor)
+}
+
+// The four lanes of an AVX register
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx")]
+fn lanes_f64(v: std::arch::x86_64::__m256d) -> [f64; 4] {
+    let mut lanes = [0.0; 4];
+    // SAFETY: lanes holds four f64
+    unsafe { std::arch::x86_64::_mm256_storeu_pd(lanes.as_mut_ptr(), v) };
+    lanes
+}
+
+// Scales the velocities by `factor`, and the Verlet displacement with them, since the next step
+// takes its velocity from the current and previous positions rather than from `velocities`
+fn rescale_velocities(positions: &Vectors, positions_old: &mut Vectors, velocities: &mut Vectors, factor: f64) {
+    for ((pos, pos_old), vel) in positions.components().into_iter().zip(positions_old.components_mut()).zip(velocities.components_mut()) {
+        pos_old.par_iter_mut().zip(pos.par_iter()).zip(vel.par_iter_mut()).for_each(|((pos_old, pos), vel)| {
+            *pos_old = pos - factor * (pos - *pos_old);
+            *vel *= factor;
+        });
+    }
+}
+
+// Removes the centre-of-mass velocity of the atoms not marked in `frozen`, and the drift of
+// their Verlet displacement too or the next step restores it
+fn remove_drift(positions: &Vectors, positions_old: &mut Vectors, velocities: &mut Vectors, frozen: Option<&[bool]>) {
+    let mobile = |i: usize| !frozen.is_some_and(|f| f[i]);
+    let count = (0..positions.len()).filter(|&i| mobile(i)).count().max(1) as f64;
+    for ((pos, pos_old), vel) in positions.components().into_iter().zip(positions_old.components_mut()).zip(velocities.components_mut()) {
+        let (mut displacement, mut velocity) = (0.0, 0.0);
+        for i in (0..pos.len()).filter(|&i| mobile(i)) {
+            displacement += pos[i] - pos_old[i];
+            velocity += vel[i];
+        }
+        let (drift, com) = (displacement / count, velocity / count);
+        pos_old.par_iter_mut().zip(vel.par_iter_mut()).enumerate().filter(|(i, _)| mobile(*i)).for_each(|(_, (pos_old, vel))| {
+            *pos_old += drift;
+            *vel -= com;
+        });
+    }
+}
+
+// Kinetic energy of `velocities`, in a fixed summation order with `deterministic`
+fn kinetic_energy(velocities: &Vectors, mass: f64, deterministic: bool) -> f64 {
+    let squares: f64 = velocities.components().into_iter().map(|component| {
+        if deterministic {
+            simulation::ordered_sum(component, |v| [v * v])[0]
+        } else {
+            component.par_iter().map(|v| v * v).sum()
+        }
+    }).sum();
+    0.5 * mass * squares
+}
+
+// Position after the next step of position Verlet from the current and previous ones, and the
+// central-difference velocity at the current one
+fn verlet_step(pos: f64, pos_old: f64, acceleration: f64, dt: f64) -> (f64, f64) {
+    let pos_new = 2.0 * pos - pos_old + acceleration * dt * dt;
+    (pos_new, (pos_new - pos_old) / (2.0 * dt))
+}
+
+// Force, potential energy, scalar virial and virial tensor of every atom into `totals`, each
+// pair sharing its energy and virials equally between its two atoms. Every pair i < j is
+// computed once and its force added to i and subtracted from j. Row i of the pair triangle goes
+// with row n - 1 - i so all row pairs have the same work, and the row pairs are dealt round
+// PAIR_BLOCKS blocks, each accumulating into buffers of its own. The buffers are then summed
+// block by block, so the result does not depend on the number of threads. The positions are
+// read in place in f64 and converted once per call in f32, and the block buffers have the same
+// layout, so the inner loop over j streams through contiguous memory; with f32 twice as many
+// pairs fit in a vector register.
+fn pair_forces<T: PairRow>(
+    kernel: &mut PairKernel<T>,
+    positions: &Vectors<f64>,
+    l: [f64; 3],
+    periodic: [bool; 3],
+    atom_virials_needed: bool,
+    totals: &mut PairSums<f64>,
+) {
+    let n = positions.len();
+    let PairKernel { positions: buffer, blocks } = kernel;
+    let coordinates = T::coordinates(positions, buffer);
+    blocks.resize_with(PAIR_BLOCKS, PairSums::default);
+    blocks.par_iter_mut().enumerate().for_each(|(block, sums)| {
+        sums.reset(n, atom_virials_needed);
+        for row_pair in (block..n.div_ceil(2)).step_by(PAIR_BLOCKS) {
+            let mirror = n - 1 - row_pair;
+            for i in std::iter::once(row_pair).chain((mirror != row_pair).then_some(mirror)) {
+                let (force, energy, virial, tensor) = T::row(coordinates, i, l, periodic, sums);
+                sums.forces.add(i, force);
+                sums.energies[i] += energy;
+                sums.virials[i] += virial;
+                if atom_virials_needed {
+                    for (t, p) in sums.tensors[i].iter_mut().zip(tensor) {
+                        *t += p;
+                    }
+                }
+            }
+        }
+    });
+    totals.reset(n, atom_virials_needed);
+    let blocks: &[PairSums<T>] = blocks;
+    for (k, component) in totals.forces.components_mut().into_iter().enumerate() {
+        add_blocks(component, &blocks.iter().map(|sums| sums.forces.components()[k]).collect::<Vec<_>>());
+    }
+    add_blocks(&mut totals.energies, &blocks.iter().map(|sums| &sums.energies[..]).collect::<Vec<_>>());
+    add_blocks(&mut totals.virials, &blocks.iter().map(|sums| &sums.virials[..]).collect::<Vec<_>>());
+    if atom_virials_needed {
+        totals.tensors.par_iter_mut().enumerate().for_each(|(i, total)| {
+            for sums in blocks {
+                for (t, part) in total.iter_mut().zip(sums.tensors[i]) {
+                    *t += part;
+                }
+            }
+        });
+    }
+}
 
-    let json = serde_json::to_string(&simulation_data).unwrap();
-    let mut file = File::create("simulation_data.json").unwrap();
-    file.write_all(json.as_bytes()).unwrap();
+// Adds the blocks' parts to `total` in block order, a chunk of atoms at a time, so the sums do
+// not depend on the number of threads
+fn add_blocks<U: Real>(total: &mut [f64], parts: &[&[U]]) {
+    const CHUNK: usize = 256;
+    total.par_chunks_mut(CHUNK).enumerate().for_each(|(c, chunk)| {
+        let atoms = c * CHUNK..c * CHUNK + chunk.len();
+        for part in parts {
+            for (t, p) in chunk.iter_mut().zip(&part[atoms.clone()]) {
+                *t += p.to_f64();
+            }
+        }
+    });
+}
+
+// The per-atom terms of the GPU backend in the layout of `pair_forces`
+fn unpack_terms(terms: &[AtomTerms], totals: &mut PairSums<f64>) {
+    totals.reset(terms.len(), true);
+    for (i, (force, energy, virial, tensor)) in terms.iter().enumerate() {
+        totals.forces.add(i, *force);
+        totals.energies[i] = *energy;
+        totals.virials[i] = *virial;
+        totals.tensors[i] = *tensor;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    // A jittered simple cubic lattice of 6^3 atoms, periodic in x and y and open in z, so no
+    // pair is close enough for the forces to blow up
+    fn configuration() -> (Vec<[f64; 3]>, [f64; 3], [bool; 3]) {
+        let mut rng = StdRng::seed_from_u64(345);
+        let spacing = 1.12;
+        let mut positions = Vec::new();
+        for a in 0..6 {
+            for b in 0..6 {
+                for c in 0..6 {
+                    positions.push([a, b, c].map(|k| (k as f64 + 0.5) * spacing + rng.gen_range(-0.15..0.15)));
+                }
+            }
+        }
+        (positions, [6.0 * spacing; 3], [true, true, false])
+    }
+
+    // Each quantity in one list, as [forces, energies, virials, tensors]
+    type Quantities = [Vec<f64>; 4];
+
+    #[cfg(target_arch = "x86_64")]
+    fn push_terms<T: Real>(out: &mut Quantities, terms: &RowTerms<T>) {
+        out[0].extend(terms.0.map(T::to_f64));
+        out[1].push(terms.1);
+        out[2].push(terms.2);
+        out[3].extend(terms.3);
+    }
+
+    // Every row of the pair loop through `pair_row` and through `vector_row` into buffers of
+    // their own. What the rows return and what they add to the atoms j has to agree within
+    // `tolerance` relative to the largest value of the quantity, since a sum over a row can
+    // cancel to far less than its terms.
+    #[cfg(target_arch = "x86_64")]
+    fn compare_rows<T: Real>(vector_row: impl Fn(&Vectors<T>, usize, &mut PairSums<T>) -> RowTerms<T>, l: [f64; 3], periodic: [bool; 3], positions: &[[f64; 3]], tolerance: f64) {
+        let n = positions.len();
+        let coordinates = Vectors::<T>::from_rows(positions);
+        let (mut scalar, mut vector) = (PairSums::default(), PairSums::default());
+        scalar.reset(n, true);
+        vector.reset(n, true);
+        let (mut expected, mut actual) = (Quantities::default(), Quantities::default());
+        for i in 0..n {
+            push_terms(&mut expected, &pair_row(&coordinates, i, i + 1, l, periodic, &mut scalar));
+            push_terms(&mut actual, &vector_row(&coordinates, i, &mut vector));
+        }
+        for j in 0..n {
+            push_terms(&mut expected, &(scalar.forces.get(j), scalar.energies[j], scalar.virials[j], scalar.tensors[j]));
+            push_terms(&mut actual, &(vector.forces.get(j), vector.energies[j], vector.virials[j], vector.tensors[j]));
+        }
+        for (what, (expected, actual)) in ["force", "energy", "virial", "virial tensor"].into_iter().zip(expected.iter().zip(&actual)) {
+            let scale = expected.iter().fold(0.0f64, |m, x| m.max(x.abs()));
+            for (a, b) in actual.iter().zip(expected) {
+                assert!((a - b).abs() <= tolerance * scale, "{}: {} against {} (largest {})", what, a, b, scale);
+            }
+        }
+    }
+
+    // The GPU backend's terms for the lattice, split at `mixed_cutoff` if given, against the f64
+    // CPU kernel within `tolerance` relative to the largest value of each quantity. Without an
+    // adapter, or a build without the gpu feature, there is nothing to compare.
+    fn compare_gpu(mixed_cutoff: Option<f64>, tolerance: f64) {
+        let (rows, l, periodic) = configuration();
+        let mut gpu = match gpu::GpuForces::new(rows.len()) {
+            Ok(gpu) => gpu,
+            Err(e) => {
+                eprintln!("No GPU to compare against: {}", e);
+                return;
+            }
+        };
+        if let Some(cutoff) = mixed_cutoff {
+            gpu.split_at(cutoff);
+        }
+        let mut expected = PairSums::default();
+        pair_forces(&mut PairKernel::<f64>::default(), &Vectors::from_rows(&rows), l, periodic, true, &mut expected);
+        let (mut gpu_terms, mut actual) = (Vec::new(), PairSums::default());
+        gpu.compute(&rows, l, periodic, &mut gpu_terms);
+        unpack_terms(&gpu_terms, &mut actual);
+        let quantities = |sums: &PairSums<f64>| -> Quantities {
+            [
+                (0..rows.len()).flat_map(|i| sums.forces.get(i)).collect(),
+                sums.energies.clone(),
+                sums.virials.clone(),
+                sums.tensors.iter().flatten().copied().collect(),
+            ]
+        };
+        for (what, (expected, actual)) in ["force", "energy", "virial", "virial tensor"].into_iter().zip(quantities(&expected).iter().zip(&quantities(&actual))) {
+            let scale = expected.iter().fold(0.0f64, |m, x| m.max(x.abs()));
+            for (a, b) in actual.iter().zip(expected) {
+                assert!((a - b).abs() <= tolerance * scale, "{} on {}: {} against {} (largest {})", what, gpu.adapter, a, b, scale);
+            }
+        }
+        let total = |sums: &PairSums<f64>| sums.energies.iter().sum::<f64>();
+        assert!((total(&actual) - total(&expected)).abs() <= tolerance * total(&expected).abs(), "potential energy on {}: {} against {}", gpu.adapter, total(&actual), total(&expected));
+        eprintln!("{} agrees with the CPU kernel", gpu.adapter);
+    }
 
-    println!("Simulation completed. Data saved to simulation_data.json");
-}
\ No newline at end of file
+    #[test]
+    fn verlet_step_moves_with_force_over_mass() {
+        // Under a constant force the positions are exact: x = v t + F t^2 / (2 m)
+        let (force, velocity, dt) = (3.0, 0.25, 0.01);
+        let acceleration = force / simulation::MASS_ARGON;
+        let (mut pos, mut pos_old) = (0.0, -velocity * dt + 0.5 * acceleration * dt * dt);
+        for step in 1..=100 {
+            let (pos_new, vel) = verlet_step(pos, pos_old, acceleration, dt);
+            let t = step as f64 * dt;
+            assert!((pos_new - (velocity * t + 0.5 * acceleration * t * t)).abs() < 1e-12);
+            assert!((vel - (velocity + acceleration * (t - dt))).abs() < 1e-12);
+            (pos_old, pos) = (pos, pos_new);
+        }
+    }
+
+    // Kinetic and potential energy of every step of the main run's integration of the lattice,
+    // with the pair kernel in T and, given a target temperature and tau, the Berendsen thermostat
+    fn lattice_run<T: PairRow>(steps: usize, thermostat: Option<(f64, f64)>) -> Vec<(f64, f64)> {
+        let (rows, l, _) = configuration();
+        let periodic = [true; 3];
+        let mass = simulation::MASS_ARGON;
+        let dt = 0.002;
+        let mut rng = StdRng::seed_from_u64(348);
+        let mut velocity_rows: Vec<[f64; 3]> = (0..rows.len()).map(|_| std::array::from_fn(|_| rng.gen_range(-0.3..0.3))).collect();
+        simulation::remove_com_velocity(&mut velocity_rows);
+        let old_rows: Vec<[f64; 3]> = rows.iter().zip(&velocity_rows).map(|(pos, vel)| std::array::from_fn(|k| pos[k] - vel[k] * dt)).collect();
+        let (mut positions, mut positions_old) = (Vectors::from_rows(&rows), Vectors::from_rows(&old_rows));
+        let mut velocities = Vectors::from_rows(&velocity_rows);
+
+        let mut kernel = PairKernel::<T>::default();
+        let mut terms = PairSums::default();
+        let mut energies = Vec::new();
+        for _ in 0..steps {
+            pair_forces(&mut kernel, &positions, l, periodic, false, &mut terms);
+            let components = positions.components_mut().into_iter().zip(positions_old.components_mut()).zip(velocities.components_mut()).zip(terms.forces.components());
+            for (((pos, pos_old), vel), force) in components {
+                for i in 0..pos.len() {
+                    let (pos_new, vel_new) = verlet_step(pos[i], pos_old[i], force[i] / mass, dt);
+                    (pos_old[i], pos[i], vel[i]) = (pos[i], pos_new, vel_new);
+                }
+            }
+            // The velocities are those of the positions the forces were computed at, as in the run
+            let potential: f64 = terms.energies.iter().sum();
+            let kinetic = kinetic_energy(&velocities, mass, true);
+            energies.push((kinetic, potential));
+            if let Some((target, tau)) = thermostat {
+                let temperature = 2.0 * kinetic / (3.0 * positions.len() as f64 * simulation::KB);
+                let factor = (1.0 + dt / tau * (target / temperature - 1.0)).sqrt();
+                rescale_velocities(&positions, &mut positions_old, &mut velocities, factor);
+            }
+        }
+        energies
+    }
+
+    // Largest deviation of the total energy from its start, relative to the kinetic energy, over
+    // `steps` steps without thermostat
+    fn nve_drift<T: PairRow>(steps: usize) -> f64 {
+        let energies = lattice_run::<T>(steps, None);
+        let (kinetic_start, potential_start) = energies[0];
+        let start = kinetic_start + potential_start;
+        energies.iter().map(|(kinetic, potential)| (kinetic + potential - start).abs() / kinetic_start).fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn berendsen_thermostat_relaxes_the_temperature() {
+        let temperature = |kinetic: f64| 2.0 * kinetic / (3.0 * 216.0 * simulation::KB);
+        let mean_temperature = |energies: &[(f64, f64)]| energies.iter().map(|(kinetic, _)| temperature(*kinetic)).sum::<f64>() / energies.len() as f64;
+        let free = lattice_run::<f64>(600, None);
+        let start = temperature(free[0].0);
+        let free_end = mean_temperature(&free[450..]);
+        // Far from the target both without the thermostat and at the start
+        let target = 40.0;
+        assert!(free_end > 1.5 * target, "{} K without thermostat", free_end);
+        // After ten times tau the temperature is where the target holds it
+        let thermostatted = lattice_run::<f64>(600, Some((target, 0.1)));
+        let end = mean_temperature(&thermostatted[450..]);
+        assert!((end - target).abs() < 0.1 * target, "{} K against a target of {} K, from {} K", end, target, start);
+    }
+
+    #[test]
+    fn single_precision_drift_stays_close_to_double() {
+        let steps = 600;
+        let double = nve_drift::<f64>(steps);
+        let single = nve_drift::<f32>(steps);
+        assert!(double < 1e-4, "double precision drift {}", double);
+        // The f32 rounding of every force adds to the integration error of double precision,
+        // which dominates over a run this short, so the penalty has to stay well below it
+        assert!(single < 2.0 * double, "single precision drift {} against {} in double", single, double);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn avx2_rows_match_the_scalar_row() {
+        if !simd_forces() {
+            return;
+        }
+        let (positions, l, periodic) = configuration();
+        // SAFETY: the CPU has AVX2 and FMA
+        compare_rows::<f64>(|coordinates, i, sums| unsafe { pair_row_avx2(coordinates, i, l, periodic, sums) }, l, periodic, &positions, 1e-12);
+        compare_rows::<f32>(|coordinates, i, sums| unsafe { pair_row_avx2_single(coordinates, i, l, periodic, sums) }, l, periodic, &positions, 1e-5);
+    }
+
+    #[test]
+    fn gpu_forces_match_the_cpu_kernel() {
+        // The shader sums in f32
+        compare_gpu(None, 1e-4);
+    }
+
+    #[test]
+    fn mixed_forces_match_the_cpu_kernel() {
+        // Only the pairs beyond the cutoff are summed in f32
+        compare_gpu(Some(2.5), 1e-5);
+    }
+}
@@ -0,0 +1,284 @@
+use std::fs::File;
+use std::io::BufWriter;
+
+use crate::cli::{self, Args};
+use crate::forces::{compute_forces, LennardJones, PairPotential};
+use crate::glass::progress_bar;
+use crate::input;
+use crate::neighbors::CellList;
+use crate::random::Philox;
+use crate::simulation::KB;
+use crate::table::TableWriter;
+use crate::xyz;
+
+const USAGE: &str = "Usage: sim mc <input.xyz|input.data|input.pdb> [--box L] [--temperature 87.3] \
+[--cutoff 2.5 | --cutoff-accuracy 1e-2] [--equilibration 1000] [--sweeps 10000] [--acceptance 0.5] \
+[--max-displacement 0.1] [--adapt-every 10] [--log-every 10] [--log mc.csv] [--snapshot-interval 0] \
+[--trajectory mc.extxyz] [--seed S] [--output mc.xyz]";
+
+// Metropolis Monte Carlo in the NVT ensemble with single-atom displacements. A sweep tries to
+// move every atom once, in order, by a uniform random displacement of at most
+// `max_displacement` along each axis, accepted with probability min(1, exp(-dU / kT)). Only
+// the interactions of the moved atom are evaluated, through a cell list rebuilt every sweep.
+pub struct MonteCarlo {
+    pub positions: Vec<[f64; 3]>,
+    pub box_length: f64,
+    pub cutoff: f64,
+    pub temperature: f64,
+    pub max_displacement: f64,
+    // Potential energy inside the cutoff, kept up to date move by move
+    pub energy: f64,
+    pub attempted: u64,
+    pub accepted: u64,
+    potential: LennardJones,
+    rng: Philox,
+    sweeps: u64,
+}
+
+impl MonteCarlo {
+    // The cutoff is cut back to half the box, as in the MD engine
+    pub fn new(positions: Vec<[f64; 3]>, box_length: f64, cutoff: f64, temperature: f64, max_displacement: f64, seed: u64) -> Self {
+        let positions: Vec<[f64; 3]> = positions.iter().map(|pos| pos.map(|coord| coord.rem_euclid(box_length))).collect();
+        let cutoff = cutoff.min(0.5 * box_length);
+        let mut mc = MonteCarlo {
+            positions,
+            box_length,
+            cutoff,
+            temperature,
+            max_displacement,
+            energy: 0.0,
+            attempted: 0,
+            accepted: 0,
+            potential: LennardJones::default(),
+            rng: Philox::new(seed),
+            sweeps: 0,
+        };
+        mc.energy = mc.measure().0;
+        mc
+    }
+
+    pub fn num_atoms(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn density(&self) -> f64 {
+        self.num_atoms() as f64 / self.box_length.powi(3)
+    }
+
+    // Energy of atom `i` with all the others inside the cutoff, using `cells` built from
+    // positions no further than its search range minus the cutoff away from the current ones
+    fn atom_energy(&self, cells: &CellList, i: usize) -> f64 {
+        cells.neighbours(&self.positions, i).iter()
+            .map(|(_, delta)| (delta[0] * delta[0] + delta[1] * delta[1] + delta[2] * delta[2]).sqrt())
+            .filter(|&r| r < self.cutoff)
+            .map(|r| self.potential.energy(r))
+            .sum()
+    }
+
+    // One displacement attempt per atom. Returns the number accepted.
+    pub fn sweep(&mut self) -> usize {
+        // An atom and its trial position stay within sqrt(3) max_displacement of where they
+        // were when the cell list was built, so widening the search by that finds every pair
+        let search = self.cutoff + 3f64.sqrt() * self.max_displacement;
+        let cells = CellList::new(&self.positions, self.box_length, search);
+        let kt = KB * self.temperature;
+        let mut accepted = 0;
+        for i in 0..self.num_atoms() {
+            let u = self.rng.uniforms(self.sweeps, i as u32, 0);
+            let old = self.positions[i];
+            let before = self.atom_energy(&cells, i);
+            self.positions[i] = std::array::from_fn(|k| {
+                (old[k] + self.max_displacement * (2.0 * u[k] - 1.0)).rem_euclid(self.box_length)
+            });
+            let delta = self.atom_energy(&cells, i) - before;
+            if delta <= 0.0 || u[3] < (-delta / kt).exp() {
+                self.energy += delta;
+                accepted += 1;
+            } else {
+                self.positions[i] = old;
+            }
+        }
+        self.sweeps += 1;
+        self.attempted += self.num_atoms() as u64;
+        self.accepted += accepted as u64;
+        accepted
+    }
+
+    // Scales the maximum displacement towards an acceptance ratio of `target`, given the ratio
+    // `acceptance` seen since the last adjustment. Never beyond half the box.
+    pub fn adapt(&mut self, acceptance: f64, target: f64) {
+        let factor = (acceptance / target).clamp(0.5, 1.5);
+        self.max_displacement = (self.max_displacement * factor).min(0.5 * self.box_length);
+    }
+
+    // Potential energy and virial from a full evaluation, which also resets the running energy
+    // so rounding errors do not accumulate
+    pub fn measure(&mut self) -> (f64, f64) {
+        let output = compute_forces(&self.positions, self.box_length, self.cutoff);
+        self.energy = output.potential_energy;
+        (output.potential_energy, output.virial)
+    }
+
+    // Pressure inside the cutoff from the ideal gas term at the set temperature and `virial`
+    pub fn pressure(&self, virial: f64) -> f64 {
+        (3.0 * self.num_atoms() as f64 * KB * self.temperature + virial) / (3.0 * self.box_length.powi(3))
+    }
+
+    // Potential energy per atom and pressure the cutoff leaves out
+    pub fn tail_corrections(&self) -> (f64, f64) {
+        let density = self.density();
+        (self.potential.tail_energy(self.cutoff, density), self.potential.tail_pressure(self.cutoff, density))
+    }
+}
+
+// Samples a configuration read from file by Metropolis Monte Carlo: the maximum displacement
+// adapts to the target acceptance during equilibration and stays fixed in production, where
+// the energy and pressure are logged and averaged
+pub fn run(args: &[String]) {
+    let args = Args::parse(args);
+    if args.positional.len() != 1 {
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
+    }
+    let path = &args.positional[0];
+    let temperature: f64 = args.get("temperature", 87.3);
+    let equilibration: usize = args.get("equilibration", 1000);
+    let sweeps: usize = args.get("sweeps", 10000);
+    let target: f64 = args.get("acceptance", 0.5);
+    let max_displacement: f64 = args.get("max-displacement", 0.1);
+    let adapt_every: usize = args.get("adapt-every", 10);
+    let log_every: usize = args.get("log-every", 10);
+    let log_path: String = args.get("log", "mc.csv".to_string());
+    let snapshot_interval: usize = args.get("snapshot-interval", 0);
+    let trajectory_path: String = args.get("trajectory", "mc.extxyz".to_string());
+    let seed: u64 = args.get("seed", rand::random());
+    let output: String = args.get("output", "mc.xyz".to_string());
+    if temperature <= 0.0 {
+        eprintln!("--temperature must be positive");
+        std::process::exit(1);
+    }
+    if !(target > 0.0 && target < 1.0) {
+        eprintln!("--acceptance must be between 0 and 1");
+        std::process::exit(1);
+    }
+    if max_displacement <= 0.0 {
+        eprintln!("--max-displacement must be positive");
+        std::process::exit(1);
+    }
+    if adapt_every == 0 || log_every == 0 {
+        eprintln!("--adapt-every and --log-every must be at least 1");
+        std::process::exit(1);
+    }
+
+    let frame = input::read_configuration(path).unwrap_or_else(|e| {
+        eprintln!("Failed to read {}: {}", path, e);
+        std::process::exit(1);
+    });
+    let box_length = input::cubic_box_length(path, frame.box_lengths, args.get_opt("box")).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let cutoff = cli::cutoff_from_args(&args, 2.5, frame.positions.len(), box_length);
+    let mut mc = MonteCarlo::new(frame.positions, box_length, cutoff, temperature, max_displacement, seed);
+    let n = mc.num_atoms() as f64;
+    let (tail_energy, tail_pressure) = mc.tail_corrections();
+    println!(
+        "Monte Carlo: {} atoms at {} K, density {:.4}, cutoff {:.3}, seed {}",
+        mc.num_atoms(),
+        temperature,
+        mc.density(),
+        mc.cutoff,
+        seed
+    );
+
+    let pb = progress_bar(equilibration);
+    pb.set_message("equilibration");
+    let mut window = 0;
+    for sweep in 1..=equilibration {
+        window += mc.sweep();
+        if sweep.is_multiple_of(adapt_every) {
+            mc.adapt(window as f64 / (adapt_every as f64 * n), target);
+            window = 0;
+        }
+        pb.inc(1);
+    }
+    pb.finish_and_clear();
+    if equilibration > 0 {
+        println!("Equilibrated for {} sweeps, maximum displacement {:.4}", equilibration, mc.max_displacement);
+    }
+
+    let columns = [
+        "sweep",
+        "acceptance",
+        "max_displacement",
+        "potential_energy_per_atom",
+        "potential_energy_per_atom_corrected",
+        "pressure",
+        "pressure_corrected",
+    ];
+    let mut log = TableWriter::create(&log_path, &columns).unwrap_or_else(|e| {
+        eprintln!("Failed to create {}: {}", log_path, e);
+        std::process::exit(1);
+    });
+    let mut trajectory = (snapshot_interval > 0).then(|| {
+        BufWriter::new(File::create(&trajectory_path).unwrap_or_else(|e| {
+            eprintln!("Failed to create {}: {}", trajectory_path, e);
+            std::process::exit(1);
+        }))
+    });
+
+    let (attempted, accepted) = (mc.attempted, mc.accepted);
+    let (mut energy_sum, mut pressure_sum, mut samples) = (0.0, 0.0, 0);
+    let mut window = 0;
+    let pb = progress_bar(sweeps);
+    pb.set_message("production");
+    for sweep in 1..=sweeps {
+        window += mc.sweep();
+        if sweep.is_multiple_of(log_every) {
+            let (energy, virial) = mc.measure();
+            let pressure = mc.pressure(virial);
+            log.write_row(&[
+                sweep as f64,
+                window as f64 / (log_every as f64 * n),
+                mc.max_displacement,
+                energy / n,
+                energy / n + tail_energy,
+                pressure,
+                pressure + tail_pressure,
+            ]).expect("Failed to write Monte Carlo log");
+            energy_sum += energy / n;
+            pressure_sum += pressure;
+            samples += 1;
+            window = 0;
+        }
+        if let Some(file) = trajectory.as_mut().filter(|_| sweep.is_multiple_of(snapshot_interval)) {
+            let comment = format!("Temperature={} Sweep={}", temperature, sweep);
+            xyz::write_frame(file, &mc.positions, None, box_length, &comment).expect("Failed to write trajectory");
+        }
+        pb.inc(1);
+    }
+    pb.finish_and_clear();
+    log.finish().expect("Failed to write Monte Carlo log");
+
+    let attempts = mc.attempted - attempted;
+    if attempts > 0 {
+        println!("Production: {} sweeps, acceptance {:.3}", sweeps, (mc.accepted - accepted) as f64 / attempts as f64);
+    }
+    if samples > 0 {
+        let (energy, pressure) = (energy_sum / samples as f64, pressure_sum / samples as f64);
+        println!(
+            "Mean potential energy per atom {:.6} ({:.6} corrected), mean pressure {:.6} ({:.6} corrected) over {} samples",
+            energy,
+            energy + tail_energy,
+            pressure,
+            pressure + tail_pressure,
+            samples
+        );
+    }
+
+    let energy = mc.measure().0;
+    let mut file = BufWriter::new(File::create(&output).expect("Failed to create output file"));
+    let comment = format!("Temperature={} PotentialEnergy={}", temperature, energy);
+    xyz::write_frame(&mut file, &mc.positions, None, box_length, &comment).unwrap();
+    println!("Final configuration saved to {}, log in {}", output, log_path);
+}
@@ -0,0 +1,100 @@
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+
+use crate::simulation::KB;
+use crate::table::TableWriter;
+
+// Gaussian hill of the bias, height h * exp(-sum (s - centre)^2 / 2 width^2)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Hill {
+    pub centre: Vec<f64>,
+    pub height: f64,
+}
+
+// Well-tempered metadynamics on one or two collective variables. Every `stride` steps a hill
+// is dropped where the system is, its height scaled by exp(-V(s) / kB dT) with
+// dT = (gamma - 1) T, so the bias converges to -(1 - 1/gamma) F(s) rather than overfilling.
+// An infinite bias factor gives plain metadynamics with hills of constant height.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Metadynamics {
+    // Indices of the biased colvars in the colvars file
+    pub colvars: Vec<usize>,
+    pub height: f64,
+    pub widths: Vec<f64>,
+    pub bias_factor: f64,
+    pub temperature: f64,
+    pub stride: usize,
+    pub hills: Vec<Hill>,
+}
+
+impl Metadynamics {
+    // Bias V(s) and its derivatives dV/ds
+    pub fn bias(&self, s: &[f64]) -> (f64, Vec<f64>) {
+        let mut energy = 0.0;
+        let mut slope = vec![0.0; s.len()];
+        for hill in &self.hills {
+            // At most two colvars, scaled by the widths
+            let mut offsets = [0.0; 2];
+            for (((u, x), c), w) in offsets.iter_mut().zip(s).zip(&hill.centre).zip(&self.widths) {
+                *u = (x - c) / w;
+            }
+            let value = hill.height * (-0.5 * offsets.iter().map(|u| u * u).sum::<f64>()).exp();
+            energy += value;
+            for ((ds, u), w) in slope.iter_mut().zip(&offsets).zip(&self.widths) {
+                *ds -= value * u / w;
+            }
+        }
+        (energy, slope)
+    }
+
+    // Drops a hill at `s` and returns it
+    pub fn deposit(&mut self, s: &[f64]) -> &Hill {
+        let scale = if self.bias_factor.is_finite() {
+            (-self.bias(s).0 / (KB * (self.bias_factor - 1.0) * self.temperature)).exp()
+        } else {
+            1.0
+        };
+        self.hills.push(Hill { centre: s.to_vec(), height: self.height * scale });
+        self.hills.last().unwrap()
+    }
+
+    // Free energy estimate from the bias, F(s) = -gamma / (gamma - 1) V(s)
+    pub fn free_energy(&self, bias: f64) -> f64 {
+        if self.bias_factor.is_finite() {
+            -self.bias_factor / (self.bias_factor - 1.0) * bias
+        } else {
+            -bias
+        }
+    }
+
+    // Writes the bias and the free energy, shifted to a minimum of 0, on a grid of `bins`
+    // points per colvar spanning the hills and three widths beyond them
+    pub fn write_free_energy<P: AsRef<Path>>(&self, path: P, names: &[&str], bins: usize) -> io::Result<()> {
+        let dims = self.colvars.len();
+        let axes: Vec<Vec<f64>> = (0..dims).map(|d| {
+            let (lo, hi) = self.hills.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), hill| {
+                (lo.min(hill.centre[d]), hi.max(hill.centre[d]))
+            });
+            let (lo, hi) = (lo - 3.0 * self.widths[d], hi + 3.0 * self.widths[d]);
+            (0..bins).map(|b| lo + (hi - lo) * b as f64 / (bins - 1) as f64).collect()
+        }).collect();
+        let points: Vec<Vec<f64>> = if dims == 1 {
+            axes[0].iter().map(|&x| vec![x]).collect()
+        } else {
+            axes[0].iter().flat_map(|&x| axes[1].iter().map(move |&y| vec![x, y])).collect()
+        };
+        let biases: Vec<f64> = points.iter().map(|point| self.bias(point).0).collect();
+        let lowest = biases.iter().map(|&v| self.free_energy(v)).fold(f64::INFINITY, f64::min);
+
+        let mut columns = names.to_vec();
+        columns.extend(["bias", "free_energy"]);
+        let mut table = TableWriter::create(path, &columns)?;
+        for (point, &bias) in points.iter().zip(&biases) {
+            let mut row = point.clone();
+            row.extend([bias, self.free_energy(bias) - lowest]);
+            table.write_row(&row)?;
+        }
+        table.finish()
+    }
+}
@@ -0,0 +1,144 @@
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::cli::Args;
+
+// Prometheus metrics of the main run, behind `--metrics host:port` and the `metrics` feature.
+// GET /metrics answers in the text exposition format with the progress of the run, its speed
+// over the last few seconds, the current temperature, pressure and total energy, the drift of
+// the total energy per atom since the run was launched or resumed, and the resident memory of
+// the process (Linux only). Any other path is a 404.
+
+// Seconds over which the step rate is measured
+const RATE_WINDOW: f64 = 5.0;
+
+// What the run last reported
+struct Values {
+    steps: usize,
+    temperature: f64,
+    pressure: f64,
+    total_energy: f64,
+    // Total energy at the first step of this launch
+    initial_energy: Option<f64>,
+    steps_per_second: f64,
+    // Start of the current rate window
+    window_start: Instant,
+    window_steps: usize,
+}
+
+pub struct Metrics {
+    total_steps: usize,
+    num_atoms: usize,
+    values: Mutex<Values>,
+}
+
+pub fn available() -> bool {
+    cfg!(feature = "metrics")
+}
+
+// `--metrics` of the main run and resume; starts serving straight away, so a taken port is
+// reported before the run starts
+pub fn from_args(args: &Args, first_step: usize, total_steps: usize, num_atoms: usize) -> Option<Arc<Metrics>> {
+    let listen: String = args.get_opt("metrics")?;
+    if !available() {
+        eprintln!("--metrics requires a build with --features metrics");
+        std::process::exit(1);
+    }
+    let metrics = Arc::new(Metrics {
+        total_steps,
+        num_atoms,
+        values: Mutex::new(Values {
+            steps: first_step,
+            temperature: 0.0,
+            pressure: 0.0,
+            total_energy: 0.0,
+            initial_energy: None,
+            steps_per_second: 0.0,
+            window_start: Instant::now(),
+            window_steps: first_step,
+        }),
+    });
+    if let Err(e) = serve(&listen, Arc::clone(&metrics)) {
+        eprintln!("Failed to listen on {}: {}", listen, e);
+        std::process::exit(1);
+    }
+    println!("Serving metrics on http://{}/metrics", listen);
+    Some(metrics)
+}
+
+impl Metrics {
+    // `steps` done so far, with the temperature, potential energy, total energy and pressure
+    // after the last of them
+    pub fn update(&self, steps: usize, observables: &[f64; 4]) {
+        let [temperature, _, total_energy, pressure] = *observables;
+        let mut values = self.values.lock().unwrap();
+        values.steps = steps;
+        values.temperature = temperature;
+        values.pressure = pressure;
+        values.total_energy = total_energy;
+        values.initial_energy.get_or_insert(total_energy);
+        let elapsed = values.window_start.elapsed().as_secs_f64();
+        if elapsed >= RATE_WINDOW {
+            values.steps_per_second = (steps - values.window_steps) as f64 / elapsed;
+            values.window_start = Instant::now();
+            values.window_steps = steps;
+        }
+    }
+
+    // The metrics in the Prometheus text format
+    pub fn render(&self) -> String {
+        let values = self.values.lock().unwrap();
+        let drift = values.initial_energy.map_or(0.0, |initial| (values.total_energy - initial) / self.num_atoms.max(1) as f64);
+        let mut metrics = vec![
+            ("sim_steps_completed_total", "counter", "Steps of the run done so far", values.steps as f64),
+            ("sim_steps_planned", "gauge", "Steps the run is set to take", self.total_steps as f64),
+            ("sim_steps_per_second", "gauge", "Steps per second over the last few seconds", values.steps_per_second),
+            ("sim_atoms", "gauge", "Number of atoms", self.num_atoms as f64),
+            ("sim_temperature_kelvin", "gauge", "Temperature after the last step", values.temperature),
+            ("sim_pressure", "gauge", "Virial pressure after the last step", values.pressure),
+            ("sim_total_energy", "gauge", "Total energy after the last step", values.total_energy),
+            ("sim_energy_drift_per_atom", "gauge", "Change of the total energy per atom since the run was launched or resumed", drift),
+        ];
+        if let Some(bytes) = resident_memory() {
+            metrics.push(("process_resident_memory_bytes", "gauge", "Resident memory size in bytes", bytes as f64));
+        }
+        let mut text = String::new();
+        for (name, kind, help, value) in metrics {
+            let _ = write!(text, "# HELP {} {}\n# TYPE {} {}\n{} {}\n", name, help, name, kind, name, value);
+        }
+        text
+    }
+}
+
+#[cfg(feature = "metrics")]
+fn serve(listen: &str, metrics: Arc<Metrics>) -> std::io::Result<()> {
+    use tiny_http::{Header, Response, Server};
+
+    let server = Server::http(listen).map_err(std::io::Error::other)?;
+    std::thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = if request.url().split('?').next() == Some("/metrics") {
+                let content_type = Header::from_bytes("Content-Type", "text/plain; version=0.0.4").unwrap();
+                Response::from_string(metrics.render()).with_header(content_type)
+            } else {
+                Response::from_string("not found\n").with_status_code(404)
+            };
+            let _ = request.respond(response);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(feature = "metrics"))]
+fn serve(_listen: &str, _metrics: Arc<Metrics>) -> std::io::Result<()> {
+    unreachable!("--metrics is rejected without the metrics feature")
+}
+
+// VmRSS of /proc/self/status
+fn resident_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kilobytes * 1024)
+}
@@ -0,0 +1,374 @@
+use rayon::prelude::*;
+use std::fs::File;
+use std::io::BufWriter;
+
+use crate::cli::{self, Args};
+use crate::forces::{compute_forces, ForceOutput};
+use crate::input;
+use crate::simulation::ordered_sum;
+use crate::xyz;
+
+pub const METHODS: [&str; 3] = ["sd", "fire", "cg"];
+
+const USAGE: &str = "Usage: sim minimize <input.xyz|input.data|input.pdb> [--method fire|sd|cg] [--box L] \
+[--cutoff L/2 | --cutoff-accuracy 1e-2] [--tolerance 1e-3] [--max-iterations 100000] [--output minimized.xyz]";
+
+pub struct MinimizeResult {
+    pub iterations: usize,
+    pub potential_energy: f64,
+    // Largest force on any atom at the final configuration
+    pub max_force: f64,
+    pub converged: bool,
+}
+
+// Minimizes a configuration read from file and writes the result as extended XYZ
+pub fn run(args: &[String]) {
+    let args = Args::parse(args);
+    if args.positional.len() != 1 {
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
+    }
+    let path = &args.positional[0];
+    let method: String = args.get("method", "fire".to_string());
+    let tolerance: f64 = args.get("tolerance", 1e-3);
+    let max_iterations: usize = args.get("max-iterations", 100000);
+    let output: String = args.get("output", "minimized.xyz".to_string());
+    if !METHODS.contains(&method.as_str()) {
+        eprintln!("--method must be one of {}", METHODS.join(", "));
+        std::process::exit(1);
+    }
+
+    let frame = input::read_configuration(path).unwrap_or_else(|e| {
+        eprintln!("Failed to read {}: {}", path, e);
+        std::process::exit(1);
+    });
+    let box_length = input::cubic_box_length(path, frame.box_lengths, args.get_opt("box")).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let cutoff = cli::cutoff_from_args(&args, 0.5 * box_length, frame.positions.len(), box_length);
+
+    let mut positions: Vec<[f64; 3]> = frame.positions.iter()
+        .map(|pos| pos.map(|coord| coord.rem_euclid(box_length)))
+        .collect();
+    let result = minimize(&method, &mut positions, box_length, cutoff, tolerance, max_iterations);
+    println!(
+        "Minimization ({}) {} after {} iterations: potential energy {:.6}, max force {:.4e}",
+        method,
+        if result.converged { "converged" } else { "stopped" },
+        result.iterations,
+        result.potential_energy,
+        result.max_force
+    );
+
+    let mut file = BufWriter::new(File::create(&output).expect("Failed to create output file"));
+    let comment = format!("PotentialEnergy={} MaxForce={}", result.potential_energy, result.max_force);
+    xyz::write_frame(&mut file, &positions, None, box_length, &comment).unwrap();
+    println!("Minimized configuration saved to {}", output);
+}
+
+// Runs the minimizer named in `METHODS`
+pub fn minimize(
+    method: &str,
+    positions: &mut Vec<[f64; 3]>,
+    box_length: f64,
+    cutoff: f64,
+    force_tolerance: f64,
+    max_iterations: usize,
+) -> MinimizeResult {
+    match method {
+        "fire" => fire(positions, box_length, cutoff, force_tolerance, max_iterations),
+        "cg" => conjugate_gradient(positions, box_length, cutoff, force_tolerance, max_iterations),
+        _ => steepest_descent(positions, box_length, cutoff, force_tolerance, max_iterations),
+    }
+}
+
+fn max_force(forces: &[[f64; 3]]) -> f64 {
+    forces.par_iter()
+        .map(|f| (f[0].powi(2) + f[1].powi(2) + f[2].powi(2)).sqrt())
+        .reduce(|| 0.0, f64::max)
+}
+
+// Steepest descent with an adaptive step: every step moves the atom with the largest force by
+// `step_size` along its force and the others proportionally. Accepted steps grow the step size,
+// rejected ones (energy went up) shrink it. Stops once the largest atomic force drops below
+// `force_tolerance`. Positions are kept wrapped into the periodic box.
+pub fn steepest_descent(
+    positions: &mut Vec<[f64; 3]>,
+    box_length: f64,
+    cutoff: f64,
+    force_tolerance: f64,
+    max_iterations: usize,
+) -> MinimizeResult {
+    let mut step_size = 0.01 * box_length.min(1.0);
+    let mut current = compute_forces(positions, box_length, cutoff);
+    let mut current_max_force = max_force(&current.forces);
+
+    let mut iterations = 0;
+    while iterations < max_iterations && current_max_force > force_tolerance {
+        iterations += 1;
+
+        let scale = step_size / current_max_force;
+        let trial: Vec<[f64; 3]> = positions.par_iter().zip(current.forces.par_iter()).map(|(pos, force)| {
+            let mut new_pos = [0.0; 3];
+            for k in 0..3 {
+                new_pos[k] = (pos[k] + scale * force[k]).rem_euclid(box_length);
+            }
+            new_pos
+        }).collect();
+
+        let trial_output = compute_forces(&trial, box_length, cutoff);
+        if trial_output.potential_energy < current.potential_energy {
+            *positions = trial;
+            current = trial_output;
+            current_max_force = max_force(&current.forces);
+            step_size *= 1.2;
+        } else {
+            step_size *= 0.2;
+            // The step can no longer change the energy at double precision
+            if step_size < 1e-12 * box_length {
+                break;
+            }
+        }
+    }
+
+    MinimizeResult {
+        iterations,
+        potential_energy: current.potential_energy,
+        max_force: current_max_force,
+        converged: current_max_force <= force_tolerance,
+    }
+}
+
+// FIRE (Bitzek et al., PRL 97, 170201): damped MD with unit masses that mixes the velocity
+// towards the force direction while power P = F.v stays positive, grows the timestep after
+// `N_MIN` good steps and resets everything as soon as the system goes uphill.
+pub fn fire(
+    positions: &mut [[f64; 3]],
+    box_length: f64,
+    cutoff: f64,
+    force_tolerance: f64,
+    max_iterations: usize,
+) -> MinimizeResult {
+    const N_MIN: usize = 5;
+    const F_INC: f64 = 1.1;
+    const F_DEC: f64 = 0.5;
+    const ALPHA_START: f64 = 0.1;
+    const F_ALPHA: f64 = 0.99;
+    const DT_MAX: f64 = 1.0;
+    // Largest total displacement of one step, in units of sigma
+    const MAX_STEP: f64 = 0.2;
+
+    let mut dt = 0.1;
+    let mut alpha = ALPHA_START;
+    let mut steps_since_reset = 0;
+    let mut velocities = vec![[0.0; 3]; positions.len()];
+
+    let mut current = compute_forces(positions, box_length, cutoff);
+    let mut current_max_force = max_force(&current.forces);
+
+    let mut iterations = 0;
+    while iterations < max_iterations && current_max_force > force_tolerance {
+        iterations += 1;
+        let forces = &current.forces;
+
+        let power: f64 = dot(forces, &velocities);
+        if power > 0.0 {
+            let v_norm = dot(&velocities, &velocities).sqrt();
+            let f_norm = dot(forces, forces).sqrt();
+            velocities.par_iter_mut().zip(forces.par_iter()).for_each(|(vel, force)| {
+                for k in 0..3 {
+                    vel[k] = (1.0 - alpha) * vel[k] + alpha * force[k] / f_norm * v_norm;
+                }
+            });
+            if steps_since_reset > N_MIN {
+                dt = (dt * F_INC).min(DT_MAX);
+                alpha *= F_ALPHA;
+            }
+            steps_since_reset += 1;
+        } else {
+            velocities.iter_mut().for_each(|vel| *vel = [0.0; 3]);
+            dt *= F_DEC;
+            alpha = ALPHA_START;
+            steps_since_reset = 0;
+        }
+
+        velocities.par_iter_mut().zip(forces.par_iter()).for_each(|(vel, force)| {
+            for k in 0..3 {
+                vel[k] += dt * force[k];
+            }
+        });
+        let step_norm = dt * dot(&velocities, &velocities).sqrt();
+        let step_scale = if step_norm > MAX_STEP { MAX_STEP / step_norm } else { 1.0 };
+        positions.par_iter_mut().zip(velocities.par_iter()).for_each(|(pos, vel)| {
+            for k in 0..3 {
+                pos[k] = (pos[k] + step_scale * dt * vel[k]).rem_euclid(box_length);
+            }
+        });
+
+        current = compute_forces(positions, box_length, cutoff);
+        current_max_force = max_force(&current.forces);
+    }
+
+    MinimizeResult {
+        iterations,
+        potential_energy: current.potential_energy,
+        max_force: current_max_force,
+        converged: current_max_force <= force_tolerance,
+    }
+}
+
+// Polak-Ribiere conjugate gradient (with the PR+ restart, beta >= 0) and a line search that
+// brackets the minimum along the search direction and refines it until the Armijo and strong
+// curvature conditions hold. Each iteration counts one line search.
+pub fn conjugate_gradient(
+    positions: &mut Vec<[f64; 3]>,
+    box_length: f64,
+    cutoff: f64,
+    force_tolerance: f64,
+    max_iterations: usize,
+) -> MinimizeResult {
+    let mut current = compute_forces(positions, box_length, cutoff);
+    let mut current_max_force = max_force(&current.forces);
+    let mut direction = current.forces.clone();
+    // Largest displacement of any atom on the first trial step, in units of sigma
+    let mut trial_displacement = 0.01;
+
+    let mut iterations = 0;
+    while iterations < max_iterations && current_max_force > force_tolerance {
+        iterations += 1;
+
+        let alpha_init = trial_displacement / max_force(&direction);
+        let accepted = line_search(positions, &direction, &current, alpha_init, box_length, cutoff);
+        let (alpha, new_positions, new_output) = match accepted {
+            Some(result) => result,
+            // No decrease along a conjugate direction: restart from steepest descent once
+            None if direction != current.forces => {
+                direction = current.forces.clone();
+                continue;
+            }
+            None => break,
+        };
+        trial_displacement = (alpha * max_force(&direction)).clamp(1e-6, 0.2);
+
+        let old_forces = std::mem::replace(&mut current, new_output).forces;
+        *positions = new_positions;
+        current_max_force = max_force(&current.forces);
+
+        let old_norm = dot(&old_forces, &old_forces);
+        let atoms: Vec<usize> = (0..old_forces.len()).collect();
+        let [overlap] = ordered_sum(&atoms, |&i| {
+            let (f, g) = (current.forces[i], old_forces[i]);
+            [f[0] * (f[0] - g[0]) + f[1] * (f[1] - g[1]) + f[2] * (f[2] - g[2])]
+        });
+        let beta = (overlap / old_norm).max(0.0);
+        direction.par_iter_mut().zip(current.forces.par_iter()).for_each(|(d, f)| {
+            for k in 0..3 {
+                d[k] = f[k] + beta * d[k];
+            }
+        });
+        // Not a descent direction any more
+        if dot(&direction, &current.forces) <= 0.0 {
+            direction = current.forces.clone();
+        }
+    }
+
+    MinimizeResult {
+        iterations,
+        potential_energy: current.potential_energy,
+        max_force: current_max_force,
+        converged: current_max_force <= force_tolerance,
+    }
+}
+
+type LineSearchPoint = (f64, Vec<[f64; 3]>, ForceOutput);
+
+// Finds a step along `direction` with sufficient decrease (Armijo) and a small directional
+// derivative (strong curvature), first expanding the step until the minimum is bracketed and
+// then shrinking the bracket by secant/bisection. Returns the best point that lowered the energy.
+fn line_search(
+    positions: &[[f64; 3]],
+    direction: &[[f64; 3]],
+    start: &ForceOutput,
+    alpha_init: f64,
+    box_length: f64,
+    cutoff: f64,
+) -> Option<LineSearchPoint> {
+    const ARMIJO: f64 = 1e-4;
+    const CURVATURE: f64 = 0.1;
+
+    let e0 = start.potential_energy;
+    // Close to the minimum the energy change drops below round-off, so within this margin the
+    // step is judged on the directional derivative alone
+    let noise = 1e-12 * e0.abs();
+    let slope0 = -dot(&start.forces, direction);
+    if slope0 >= 0.0 {
+        return None;
+    }
+
+    let evaluate = |alpha: f64| {
+        let trial: Vec<[f64; 3]> = positions.par_iter().zip(direction.par_iter()).map(|(pos, d)| {
+            let mut new_pos = [0.0; 3];
+            for k in 0..3 {
+                new_pos[k] = (pos[k] + alpha * d[k]).rem_euclid(box_length);
+            }
+            new_pos
+        }).collect();
+        let output = compute_forces(&trial, box_length, cutoff);
+        let slope = -dot(&output.forces, direction);
+        (trial, output, slope)
+    };
+
+    let mut best: Option<LineSearchPoint> = None;
+    let mut consider = |alpha: f64, trial: Vec<[f64; 3]>, output: ForceOutput| {
+        if output.potential_energy < best.as_ref().map_or(e0, |b| b.2.potential_energy) {
+            best = Some((alpha, trial, output));
+        }
+    };
+
+    let (mut lo, mut slope_lo) = (0.0, slope0);
+    let (mut hi, mut slope_hi) = (f64::INFINITY, 0.0);
+    let mut alpha = alpha_init;
+
+    for _ in 0..60 {
+        let (trial, output, slope) = evaluate(alpha);
+        let sufficient = output.potential_energy <= e0 + ARMIJO * alpha * slope0 + noise;
+        if sufficient && slope.abs() <= CURVATURE * slope0.abs() {
+            return Some((alpha, trial, output));
+        }
+        if sufficient {
+            consider(alpha, trial, output);
+        }
+        if !sufficient || slope > 0.0 {
+            hi = alpha;
+            slope_hi = slope;
+        } else {
+            lo = alpha;
+            slope_lo = slope;
+        }
+
+        alpha = if hi.is_infinite() {
+            2.0 * alpha
+        } else {
+            // Secant on the directional derivative when it brackets a sign change, else bisect
+            let secant = lo - slope_lo * (hi - lo) / (slope_hi - slope_lo);
+            let margin = 0.1 * (hi - lo);
+            if slope_hi > 0.0 && secant > lo + margin && secant < hi - margin {
+                secant
+            } else {
+                0.5 * (lo + hi)
+            }
+        };
+        if hi - lo <= 1e-12 * hi {
+            break;
+        }
+    }
+
+    best
+}
+
+// In fixed-order chunks, so a minimization ends on the same configuration on any number of threads
+fn dot(a: &[[f64; 3]], b: &[[f64; 3]]) -> f64 {
+    let atoms: Vec<usize> = (0..a.len()).collect();
+    ordered_sum(&atoms, |&i| [a[i][0] * b[i][0] + a[i][1] * b[i][1] + a[i][2] * b[i][2]])[0]
+}
@@ -0,0 +1,175 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::cli::Args;
+
+// Thresholds of the anomaly monitor
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct MonitorSettings {
+    // Steps the rolling statistics average over; the first window is warm-up and sets the baseline
+    pub window: usize,
+    // A value further than this many rolling standard deviations from the rolling mean is a jump
+    pub jump_sigmas: f64,
+    // Relative change of the rolling mean from the baseline that counts as drift
+    pub drift: f64,
+    // Steps between samples, for runs where watching every step costs too much
+    pub every: usize,
+}
+
+impl MonitorSettings {
+    // From `--monitor` with `--monitor-window 1000`, `--monitor-jump 8`, `--monitor-drift 0.2`
+    // and `--monitor-every 1`
+    pub fn from_args(args: &Args) -> Option<Self> {
+        if !args.has("monitor") {
+            return None;
+        }
+        let settings = MonitorSettings {
+            window: args.get("monitor-window", 1000),
+            jump_sigmas: args.get("monitor-jump", 8.0),
+            drift: args.get("monitor-drift", 0.2),
+            every: args.get("monitor-every", 1),
+        };
+        if settings.every == 0 || settings.window < 2 * settings.every || settings.jump_sigmas <= 0.0 || settings.drift <= 0.0 {
+            eprintln!("--monitor-every must be at least 1, --monitor-window at least twice that, --monitor-jump and --monitor-drift positive");
+            std::process::exit(1);
+        }
+        Some(settings)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Anomaly {
+    pub step: usize,
+    pub observable: String,
+    pub message: String,
+}
+
+// Exponentially weighted rolling mean and variance of one observable
+struct Tracker {
+    name: &'static str,
+    samples: usize,
+    mean: f64,
+    variance: f64,
+    baseline: Option<f64>,
+    // Conditions already reported, so a lasting problem is reported once
+    diverged: bool,
+    jumped: bool,
+    drifted: bool,
+}
+
+// Watches observables step by step for the signs of a run going wrong: values that are no
+// longer finite, sudden jumps against the rolling statistics, and slow drift of the rolling
+// mean away from where it settled after the first window.
+pub struct Monitor {
+    settings: MonitorSettings,
+    // Samples in one window
+    window: usize,
+    alpha: f64,
+    trackers: Vec<Tracker>,
+}
+
+impl Monitor {
+    pub fn new(settings: MonitorSettings, names: &[&'static str]) -> Self {
+        let trackers = names.iter().map(|&name| Tracker {
+            name,
+            samples: 0,
+            mean: 0.0,
+            variance: 0.0,
+            baseline: None,
+            diverged: false,
+            jumped: false,
+            drifted: false,
+        }).collect();
+        let window = settings.window / settings.every;
+        Monitor { settings, window, alpha: 2.0 / (window as f64 + 1.0), trackers }
+    }
+
+    // Whether `step` is one the monitor samples
+    pub fn due(&self, step: usize) -> bool {
+        step.is_multiple_of(self.settings.every)
+    }
+
+    // Takes one value per observable, in the order given to `new`
+    pub fn observe(&mut self, step: usize, values: &[f64]) -> Vec<Anomaly> {
+        let settings = self.settings;
+        let window = self.window;
+        let alpha = self.alpha;
+        let mut anomalies = Vec::new();
+        for (tracker, &value) in self.trackers.iter_mut().zip(values) {
+            let name = tracker.name;
+            let mut report = |message: String| anomalies.push(Anomaly { step, observable: name.to_string(), message });
+            if !value.is_finite() {
+                if !tracker.diverged {
+                    report(format!("{} is {}, the run has diverged", name, value));
+                    tracker.diverged = true;
+                }
+                continue;
+            }
+            tracker.samples += 1;
+            if tracker.samples == 1 {
+                tracker.mean = value;
+                continue;
+            }
+
+            if let Some(baseline) = tracker.baseline {
+                // A floor on the spread keeps a nearly constant observable from flagging rounding noise
+                let sigma = tracker.variance.sqrt().max(1e-6 * tracker.mean.abs()).max(f64::MIN_POSITIVE);
+                let jump = (value - tracker.mean).abs() > settings.jump_sigmas * sigma;
+                if jump && !tracker.jumped {
+                    report(format!(
+                        "{} jumped to {:.6e}, rolling mean {:.6e} +- {:.3e}",
+                        name, value, tracker.mean, sigma
+                    ));
+                }
+                tracker.jumped = jump;
+
+                let change = (tracker.mean - baseline).abs() / baseline.abs().max(sigma);
+                if change > settings.drift && !tracker.drifted {
+                    report(format!(
+                        "{} drifted to {:.6e} from {:.6e} ({:.0}%)",
+                        name, tracker.mean, baseline, 100.0 * change
+                    ));
+                    tracker.drifted = true;
+                } else if change < 0.5 * settings.drift {
+                    tracker.drifted = false;
+                }
+            }
+
+            let delta = value - tracker.mean;
+            tracker.mean += alpha * delta;
+            tracker.variance = (1.0 - alpha) * (tracker.variance + alpha * delta * delta);
+            if tracker.baseline.is_none() && tracker.samples >= window {
+                tracker.baseline = Some(tracker.mean);
+            }
+        }
+        anomalies
+    }
+}
+
+// Snapshot of a running main run for scripts and dashboards, replaced in place as the run goes
+#[derive(Serialize)]
+pub struct Status<'a> {
+    // running, completed, interrupted or walltime
+    pub state: &'a str,
+    pub step: usize,
+    pub total_steps: usize,
+    pub time_ps: f64,
+    pub temperature: f64,
+    pub potential_energy: f64,
+    pub total_energy: f64,
+    pub pressure: f64,
+    pub warnings: &'a [Anomaly],
+}
+
+impl Status<'_> {
+    // Written next to `path` and renamed over it, so readers never see a partial file
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        fs::write(&temporary, serde_json::to_vec_pretty(self).map_err(io::Error::other)?)?;
+        fs::rename(&temporary, path)
+    }
+}
@@ -0,0 +1,61 @@
+// Atoms sorted into a cubic grid of cells at least `cutoff` wide, so the neighbours of an atom
+// are found among its own and the 26 surrounding cells instead of all atoms. Boxes too small
+// for three cells per side get a single cell.
+pub struct CellList {
+    box_length: f64,
+    cutoff: f64,
+    cells: usize,
+    members: Vec<Vec<usize>>,
+}
+
+impl CellList {
+    pub fn new(positions: &[[f64; 3]], box_length: f64, cutoff: f64) -> Self {
+        let cells = ((box_length / cutoff).floor() as usize).max(1);
+        let cells = if cells < 3 { 1 } else { cells };
+        let mut members = vec![Vec::new(); cells.pow(3)];
+        for (i, pos) in positions.iter().enumerate() {
+            members[cell_index(pos, box_length, cells)].push(i);
+        }
+        CellList { box_length, cutoff, cells, members }
+    }
+
+    // Indices of the atoms within the cutoff of atom `i` and their minimum-image separations
+    // from it (neighbour minus atom)
+    pub fn neighbours(&self, positions: &[[f64; 3]], i: usize) -> Vec<(usize, [f64; 3])> {
+        let l = self.box_length;
+        let c = self.cells;
+        let home = cell_coordinates(&positions[i], l, c);
+        let span: &[usize] = if c == 1 { &[0] } else { &[c - 1, 0, 1] };
+        let mut found = Vec::new();
+        for &dx in span {
+            for &dy in span {
+                for &dz in span {
+                    let cell = ((home[0] + dx) % c * c + (home[1] + dy) % c) * c + (home[2] + dz) % c;
+                    for &j in &self.members[cell] {
+                        if j == i {
+                            continue;
+                        }
+                        let mut delta = [0.0; 3];
+                        for (k, d) in delta.iter_mut().enumerate() {
+                            *d = positions[j][k] - positions[i][k];
+                            *d -= (*d / l).round() * l;
+                        }
+                        if delta.iter().map(|d| d * d).sum::<f64>() <= self.cutoff * self.cutoff {
+                            found.push((j, delta));
+                        }
+                    }
+                }
+            }
+        }
+        found
+    }
+}
+
+fn cell_coordinates(pos: &[f64; 3], box_length: f64, cells: usize) -> [usize; 3] {
+    pos.map(|coord| ((coord.rem_euclid(box_length) / box_length * cells as f64) as usize).min(cells - 1))
+}
+
+fn cell_index(pos: &[f64; 3], box_length: f64, cells: usize) -> usize {
+    let [x, y, z] = cell_coordinates(pos, box_length, cells);
+    (x * cells + y) * cells + z
+}
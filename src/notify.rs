@@ -0,0 +1,146 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+#[cfg(feature = "webhooks")]
+use std::time::Duration;
+
+use crate::cli::Args;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Event {
+    Completed,
+    Failed,
+    // Stopped by a signal or --max-walltime, to be continued with `sim resume`
+    Interrupted,
+    // A checkpoint was written
+    Checkpoint,
+    // Reported by the anomaly monitor while the run goes on
+    Anomaly,
+}
+
+impl Event {
+    fn name(self) -> &'static str {
+        match self {
+            Event::Completed => "completed",
+            Event::Failed => "failed",
+            Event::Interrupted => "interrupted",
+            Event::Checkpoint => "checkpoint",
+            Event::Anomaly => "anomaly",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    event: &'a str,
+    command: &'a str,
+    // Human readable summary; Slack and Matrix incoming webhooks display this field
+    text: String,
+}
+
+// Fires a generic JSON POST (`event`, `command`, `text`) at a webhook URL when a run finishes,
+// fails, is interrupted or writes a checkpoint, so long runs can report to chat instead of being
+// polled.
+#[derive(Clone)]
+pub struct Notifier {
+    url: String,
+    events: Vec<Event>,
+    command: String,
+}
+
+pub fn available() -> bool {
+    cfg!(feature = "webhooks")
+}
+
+impl Notifier {
+    // Built from `--notify-url <url>` and `--notify-on completed,failed,interrupted` (those three
+    // by default)
+    pub fn from_args(args: &Args) -> Option<Notifier> {
+        let url: String = args.get_opt("notify-url")?;
+        if !available() {
+            eprintln!("--notify-url requires a build with --features webhooks");
+            std::process::exit(1);
+        }
+        let names: Vec<String> = args.get_list("notify-on", ["completed", "failed", "interrupted"].map(String::from).to_vec());
+        let events = names.iter().map(|name| match name.as_str() {
+            "completed" => Event::Completed,
+            "failed" => Event::Failed,
+            "interrupted" => Event::Interrupted,
+            "checkpoint" => Event::Checkpoint,
+            "anomaly" => Event::Anomaly,
+            _ => {
+                eprintln!("Unknown --notify-on event {}, expected completed, failed, interrupted, checkpoint or anomaly", name);
+                std::process::exit(1);
+            }
+        }).collect();
+        let command = std::env::args().collect::<Vec<_>>().join(" ");
+        Some(Notifier { url, events, command })
+    }
+
+    // Makes this the notifier that `fail` reports to, and reports panics as failures too
+    pub fn install(&self) {
+        let _ = ACTIVE.set(self.clone());
+        self.install_panic_hook();
+    }
+
+    // Reports panics as failures before the default hook prints them. Only the first panic is
+    // sent, since a failure inside the rayon pool can panic on several threads at once.
+    fn install_panic_hook(&self) {
+        let notifier = self.clone();
+        let reported = AtomicBool::new(false);
+        let default_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            if !reported.swap(true, Ordering::SeqCst) {
+                notifier.send(Event::Failed, &format!("Simulation failed: {}", info));
+            }
+            default_hook(info);
+        }));
+    }
+
+    // Delivery problems are reported but never abort the run
+    pub fn send(&self, event: Event, message: &str) {
+        if !self.events.contains(&event) {
+            return;
+        }
+        let payload = Payload {
+            event: event.name(),
+            command: &self.command,
+            text: format!("{} ({})", message, self.command),
+        };
+        let body = serde_json::to_string(&payload).unwrap();
+        if let Err(e) = post(&self.url, &body) {
+            eprintln!("Failed to send {} notification to {}: {}", event.name(), self.url, e);
+        }
+    }
+}
+
+// The notifier of the process, once installed
+static ACTIVE: OnceLock<Notifier> = OnceLock::new();
+
+// Ends the process on a fatal error: prints the message, sends it as a failure if a notifier is
+// installed and exits with status 1
+pub fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    if let Some(notifier) = ACTIVE.get() {
+        notifier.send(Event::Failed, &format!("Simulation failed: {}", message));
+    }
+    std::process::exit(1);
+}
+
+#[cfg(feature = "webhooks")]
+fn post(url: &str, body: &str) -> Result<(), String> {
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .timeout_global(Some(Duration::from_secs(10)))
+        .build()
+        .into();
+    agent.post(url)
+        .content_type("application/json")
+        .send(body)
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "webhooks"))]
+fn post(_url: &str, _body: &str) -> Result<(), String> {
+    Err("built without webhook support".to_string())
+}
@@ -0,0 +1,273 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::analysis;
+use crate::lammps::DumpColumns;
+use crate::simulation::Simulation;
+use crate::table::TableWriter;
+use crate::trajectory::{Frame, SimulationData, TrajectoryWriter};
+use crate::units::Units;
+
+// Something that looks at a running simulation: output, on-the-fly analysis, a stop check.
+// `on_step` is called after every step a `Pipeline` was asked to call it at, with the step count
+// of the simulation; `finish` once after the last step, to write results and close files.
+pub trait Observer {
+    fn on_step(&mut self, step: u64, sim: &Simulation) -> io::Result<()>;
+
+    fn finish(&mut self, _sim: &Simulation) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Lets a pipeline borrow an observer whose results are read after the run
+impl<O: Observer + ?Sized> Observer for &mut O {
+    fn on_step(&mut self, step: u64, sim: &Simulation) -> io::Result<()> {
+        (**self).on_step(step, sim)
+    }
+
+    fn finish(&mut self, sim: &Simulation) -> io::Result<()> {
+        (**self).finish(sim)
+    }
+}
+
+// Observer from a closure, for one-off callbacks
+pub struct FnObserver<F>(pub F);
+
+impl<F: FnMut(u64, &Simulation) -> io::Result<()>> Observer for FnObserver<F> {
+    fn on_step(&mut self, step: u64, sim: &Simulation) -> io::Result<()> {
+        (self.0)(step, sim)
+    }
+}
+
+// Observers of a run, each with the stride of steps it is called at. A simulation stepped by
+// `run` calls them in the order they were added after every step whose count is a multiple of
+// their stride; a driver with its own loop calls `observe` after each step instead.
+#[derive(Default)]
+pub struct Pipeline<'a> {
+    observers: Vec<(u64, Box<dyn Observer + 'a>)>,
+}
+
+impl<'a> Pipeline<'a> {
+    pub fn new() -> Self {
+        Pipeline { observers: Vec::new() }
+    }
+
+    pub fn add(&mut self, stride: u64, observer: impl Observer + 'a) -> &mut Self {
+        assert!(stride > 0, "the stride of an observer must be at least 1");
+        self.observers.push((stride, Box::new(observer)));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.observers.is_empty()
+    }
+
+    // Calls the observers due at the current step of `sim`
+    pub fn observe(&mut self, sim: &Simulation) -> io::Result<()> {
+        let step = sim.step_count();
+        for (stride, observer) in &mut self.observers {
+            if step.is_multiple_of(*stride) {
+                observer.on_step(step, sim)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Advances `sim` by `steps` steps, observing after each
+    pub fn run(&mut self, sim: &mut Simulation, steps: u64) -> io::Result<()> {
+        for _ in 0..steps {
+            sim.step();
+            self.observe(sim)?;
+        }
+        Ok(())
+    }
+
+    // Finishes every observer, even after one fails, and returns the first error
+    pub fn finish(&mut self, sim: &Simulation) -> io::Result<()> {
+        let mut result = Ok(());
+        for (_, observer) in &mut self.observers {
+            let finished = observer.finish(sim);
+            if result.is_ok() {
+                result = finished;
+            }
+        }
+        result
+    }
+}
+
+// Thermodynamic log of a run as a table, CSV, TSV or Parquet by the file extension
+pub struct ThermoLogger {
+    table: Option<TableWriter>,
+}
+
+impl ThermoLogger {
+    pub const COLUMNS: [&'static str; 7] =
+        ["step", "time", "temperature", "kinetic_energy", "potential_energy", "total_energy", "pressure"];
+
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(ThermoLogger { table: Some(TableWriter::create(path, &Self::COLUMNS)?) })
+    }
+}
+
+impl Observer for ThermoLogger {
+    fn on_step(&mut self, step: u64, sim: &Simulation) -> io::Result<()> {
+        let table = self.table.as_mut().ok_or_else(|| io::Error::other("thermo log already finished"))?;
+        let kinetic_energy = sim.kinetic_energy();
+        let potential_energy = sim.potential_energy();
+        table.write_row(&[
+            step as f64,
+            sim.time(),
+            sim.temperature(),
+            kinetic_energy,
+            potential_energy,
+            kinetic_energy + potential_energy,
+            sim.pressure(),
+        ])
+    }
+
+    fn finish(&mut self, _sim: &Simulation) -> io::Result<()> {
+        self.table.take().map_or(Ok(()), TableWriter::finish)
+    }
+}
+
+// Trajectory in any of the formats of the main run, through its `TrajectoryWriter`
+pub struct TrajectoryObserver {
+    writer: Option<TrajectoryWriter>,
+    columns: DumpColumns,
+    // Reused for every frame
+    frame: Frame,
+}
+
+impl TrajectoryObserver {
+    // `interval` is the stride the observer is added with, which JSON and DCD record. Of the
+    // optional columns, velocities, forces and per-atom energies are available; order
+    // parameters are not.
+    pub fn create(format: &str, path: &str, sim: &Simulation, interval: u64, columns: DumpColumns) -> io::Result<Self> {
+        if columns.order {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "order parameters cannot be written by an observer"));
+        }
+        let header = SimulationData {
+            box_length: sim.box_length(),
+            cell: Some(sim.cell().vectors()),
+            num_atoms: sim.num_atoms(),
+            timestep: sim.dt,
+            // Not known to an open-ended pipeline
+            total_steps: 0,
+            snapshot_interval: interval as usize,
+            seed: None,
+            units: Units::Native,
+            trajectory: Vec::new(),
+            pressure: Vec::new(),
+            stress: None,
+            velocities: None,
+        };
+        let frame = Frame {
+            step: 0,
+            time: 0.0,
+            positions: Vec::new(),
+            velocities: Vec::new(),
+            forces: Vec::new(),
+            energies: Vec::new(),
+            order: Vec::new(),
+            box_lengths: sim.box_lengths(),
+            pressure: 0.0,
+            stress: None,
+            temperature: 0.0,
+            potential_energy: 0.0,
+            kinetic_energy: 0.0,
+        };
+        Ok(TrajectoryObserver { writer: Some(TrajectoryWriter::create(format, path, header, columns)?), columns, frame })
+    }
+}
+
+impl Observer for TrajectoryObserver {
+    fn on_step(&mut self, step: u64, sim: &Simulation) -> io::Result<()> {
+        let writer = self.writer.as_mut().ok_or_else(|| io::Error::other("trajectory already finished"))?;
+        let frame = &mut self.frame;
+        frame.step = step as usize;
+        frame.time = sim.time();
+        frame.positions.clear();
+        frame.positions.extend_from_slice(sim.positions());
+        frame.velocities.clear();
+        frame.velocities.extend_from_slice(sim.velocities());
+        frame.forces.clear();
+        if self.columns.forces {
+            frame.forces.extend_from_slice(sim.forces());
+        }
+        frame.energies.clear();
+        if self.columns.energy {
+            frame.energies.extend_from_slice(sim.atom_energies());
+        }
+        frame.box_lengths = sim.box_lengths();
+        frame.pressure = sim.pressure();
+        frame.temperature = sim.temperature();
+        frame.potential_energy = sim.potential_energy();
+        frame.kinetic_energy = sim.kinetic_energy();
+        writer.write_frame(frame)
+    }
+
+    fn finish(&mut self, _sim: &Simulation) -> io::Result<()> {
+        self.writer.take().map_or(Ok(()), TrajectoryWriter::finish)
+    }
+}
+
+// Radial distribution function averaged over the configurations observed, with `bins` shells
+// up to `r_max` (at most half the box). Written as r and g(r) columns on `finish` when given
+// an output file.
+pub struct RdfAccumulator {
+    bins: usize,
+    r_max: f64,
+    centres: Vec<f64>,
+    sum: Vec<f64>,
+    samples: usize,
+    output: Option<PathBuf>,
+}
+
+impl RdfAccumulator {
+    pub fn new(bins: usize, r_max: f64) -> Self {
+        RdfAccumulator { bins, r_max, centres: Vec::new(), sum: vec![0.0; bins], samples: 0, output: None }
+    }
+
+    pub fn with_output<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.output = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    // Shell centres and the mean g(r) so far
+    pub fn rdf(&self) -> (Vec<f64>, Vec<f64>) {
+        let samples = self.samples.max(1) as f64;
+        (self.centres.clone(), self.sum.iter().map(|g| g / samples).collect())
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let (centres, g) = self.rdf();
+        let mut table = TableWriter::create(path, &["r", "g"])?;
+        for (r, g) in centres.into_iter().zip(g) {
+            table.write_row(&[r, g])?;
+        }
+        table.finish()
+    }
+}
+
+impl Observer for RdfAccumulator {
+    fn on_step(&mut self, _step: u64, sim: &Simulation) -> io::Result<()> {
+        let (centres, g) = analysis::rdf(&[sim.positions().to_vec()], sim.box_length(), self.bins, self.r_max);
+        self.centres = centres;
+        for (sum, g) in self.sum.iter_mut().zip(g) {
+            *sum += g;
+        }
+        self.samples += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self, _sim: &Simulation) -> io::Result<()> {
+        match &self.output {
+            Some(path) => self.write(path),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,290 @@
+use rayon::prelude::*;
+use std::f64::consts::PI;
+
+use crate::neighbors::CellList;
+
+// Neighbour distance for order parameters written with trajectory frames, near the first
+// minimum of g(r) of the Lennard-Jones liquid and solid
+pub const NEIGHBOUR_CUTOFF: f64 = 1.5;
+// ten Wolde, Ruiz-Montero and Frenkel (J. Chem. Phys. 104, 9932): two neighbours are
+// connected when their normalised q6 vectors overlap by more than this, and an atom with at
+// least SOLID_BONDS connections is solid-like
+pub const CONNECTION_THRESHOLD: f64 = 0.7;
+pub const SOLID_BONDS: usize = 7;
+
+// Steinhardt bond-orientational order of every atom (Steinhardt, Nelson and Ronchetti, PRB
+// 28, 784) over the neighbours within the cutoff. Perfect crystals give q4 = 0.191, q6 = 0.575,
+// w6 = -0.013 (fcc), q6 = 0.485, w6 = -0.012 (hcp) and q6 = 0.511, w6 = 0.013 (bcc, both
+// neighbour shells), while the liquid has q6 around 0.35 and w6 near zero.
+pub struct BondOrder {
+    pub q4: Vec<f64>,
+    pub q6: Vec<f64>,
+    // Normalised third-order invariant
+    pub w6: Vec<f64>,
+    pub neighbours: Vec<usize>,
+    // Neighbours connected in the ten Wolde sense
+    pub solid_bonds: Vec<usize>,
+}
+
+impl BondOrder {
+    // Fraction of atoms with at least SOLID_BONDS connections
+    pub fn solid_fraction(&self) -> f64 {
+        let solid = self.solid_bonds.iter().filter(|&&b| b >= SOLID_BONDS).count();
+        solid as f64 / self.solid_bonds.len().max(1) as f64
+    }
+}
+
+// Neighbours of one atom and its q_lm for m = 0..=l as [re, im]; negative m follow from
+// q_l,-m = (-1)^m conj(q_lm)
+struct Harmonics {
+    neighbours: Vec<usize>,
+    q4: Vec<[f64; 2]>,
+    q6: Vec<[f64; 2]>,
+}
+
+pub fn bond_order(positions: &[[f64; 3]], box_length: f64, cutoff: f64) -> BondOrder {
+    let cells = CellList::new(positions, box_length, cutoff);
+    let atoms: Vec<Harmonics> = (0..positions.len()).into_par_iter().map(|i| {
+        let bonds = cells.neighbours(positions, i);
+        let mut q4 = vec![[0.0; 2]; 5];
+        let mut q6 = vec![[0.0; 2]; 7];
+        for (_, delta) in &bonds {
+            for (l, q) in [(4, &mut q4), (6, &mut q6)] {
+                for (m, value) in spherical_harmonics(l, delta).into_iter().enumerate() {
+                    q[m][0] += value[0];
+                    q[m][1] += value[1];
+                }
+            }
+        }
+        let count = bonds.len().max(1) as f64;
+        for value in q4.iter_mut().chain(q6.iter_mut()) {
+            value[0] /= count;
+            value[1] /= count;
+        }
+        Harmonics { neighbours: bonds.into_iter().map(|(j, _)| j).collect(), q4, q6 }
+    }).collect();
+
+    let magnitude = |q: &[[f64; 2]]| -> f64 {
+        q.iter().enumerate().map(|(m, v)| if m == 0 { 1.0 } else { 2.0 } * (v[0] * v[0] + v[1] * v[1])).sum()
+    };
+    let invariant = |l: usize, q: &[[f64; 2]]| (4.0 * PI / (2 * l + 1) as f64 * magnitude(q)).sqrt();
+    let q4 = atoms.iter().map(|atom| invariant(4, &atom.q4)).collect();
+    let q6: Vec<f64> = atoms.iter().map(|atom| invariant(6, &atom.q6)).collect();
+    let symbols = wigner_symbols(6);
+    let w6 = atoms.par_iter().map(|atom| {
+        let q = &atom.q6;
+        let norm = magnitude(q);
+        if norm == 0.0 {
+            return 0.0;
+        }
+        let signed = |m: i64| -> [f64; 2] {
+            let v = q[m.unsigned_abs() as usize];
+            if m >= 0 {
+                v
+            } else if m % 2 == 0 {
+                [v[0], -v[1]]
+            } else {
+                [-v[0], v[1]]
+            }
+        };
+        symbols.iter().map(|&(m1, m2, symbol)| {
+            let (a, b, c) = (signed(m1), signed(m2), signed(-m1 - m2));
+            let ab = [a[0] * b[0] - a[1] * b[1], a[0] * b[1] + a[1] * b[0]];
+            symbol * (ab[0] * c[0] - ab[1] * c[1])
+        }).sum::<f64>() / norm.powf(1.5)
+    }).collect();
+
+    let solid_bonds = atoms.par_iter().map(|atom| {
+        let qi = &atom.q6;
+        atom.neighbours.iter().filter(|&&j| {
+            let qj = &atoms[j].q6;
+            let overlap: f64 = qi.iter().zip(qj).enumerate()
+                .map(|(m, (a, b))| if m == 0 { 1.0 } else { 2.0 } * (a[0] * b[0] + a[1] * b[1]))
+                .sum();
+            let norm = (magnitude(qi) * magnitude(qj)).sqrt();
+            norm > 0.0 && overlap / norm > CONNECTION_THRESHOLD
+        }).count()
+    }).collect();
+
+    BondOrder { q4, q6, w6, neighbours: atoms.iter().map(|atom| atom.neighbours.len()).collect(), solid_bonds }
+}
+
+// Y_lm of the direction of `r` for m = 0..=l as [re, im], with the Condon-Shortley phase
+fn spherical_harmonics(l: usize, r: &[f64; 3]) -> Vec<[f64; 2]> {
+    let length = (r[0] * r[0] + r[1] * r[1] + r[2] * r[2]).sqrt();
+    let x = r[2] / length;
+    let rho = (r[0] * r[0] + r[1] * r[1]).sqrt();
+    let (cos_phi, sin_phi) = if rho > 0.0 { (r[0] / rho, r[1] / rho) } else { (1.0, 0.0) };
+    let sin_theta = (1.0 - x * x).max(0.0).sqrt();
+    let mut values = Vec::with_capacity(l + 1);
+    // e^(i m phi), built up one m at a time
+    let mut phase = [1.0, 0.0];
+    for m in 0..=l {
+        // P_m^m, then upwards in degree to P_l^m
+        let mut p_mm = 1.0;
+        for k in 0..m {
+            p_mm *= -((2 * k + 1) as f64) * sin_theta;
+        }
+        let legendre = if l == m {
+            p_mm
+        } else {
+            let (mut previous, mut current) = (p_mm, x * (2 * m + 1) as f64 * p_mm);
+            for degree in m + 2..=l {
+                let next = ((2 * degree - 1) as f64 * x * current - (degree + m - 1) as f64 * previous) / (degree - m) as f64;
+                previous = current;
+                current = next;
+            }
+            current
+        };
+        let norm = ((2 * l + 1) as f64 / (4.0 * PI) * factorial(l - m) / factorial(l + m)).sqrt();
+        values.push([norm * legendre * phase[0], norm * legendre * phase[1]]);
+        phase = [phase[0] * cos_phi - phase[1] * sin_phi, phase[0] * sin_phi + phase[1] * cos_phi];
+    }
+    values
+}
+
+// Non-zero Wigner 3j symbols (l l l; m1 m2 -m1-m2) with their m1 and m2
+fn wigner_symbols(l: usize) -> Vec<(i64, i64, f64)> {
+    let l = l as i64;
+    let mut symbols = Vec::new();
+    for m1 in -l..=l {
+        for m2 in -l..=l {
+            let m3 = -m1 - m2;
+            if m3.abs() > l {
+                continue;
+            }
+            let symbol = wigner_3j(l, m1, m2, m3);
+            if symbol != 0.0 {
+                symbols.push((m1, m2, symbol));
+            }
+        }
+    }
+    symbols
+}
+
+// Racah's formula for (l l l; m1 m2 m3) with m1 + m2 + m3 = 0
+fn wigner_3j(l: i64, m1: i64, m2: i64, m3: i64) -> f64 {
+    let f = |n: i64| factorial(n as usize);
+    let triangle = f(l).powi(3) / f(3 * l + 1);
+    let prefactor = (triangle * f(l + m1) * f(l - m1) * f(l + m2) * f(l - m2) * f(l + m3) * f(l - m3)).sqrt();
+    let sum: f64 = (0..=3 * l).filter_map(|k| {
+        let arguments = [k, l - k, l - m1 - k, l + m2 - k, m1 + k, -m2 + k];
+        if arguments.iter().any(|&a| a < 0) {
+            return None;
+        }
+        let sign = if k % 2 == 0 { 1.0 } else { -1.0 };
+        Some(sign / arguments.iter().map(|&a| f(a)).product::<f64>())
+    }).sum();
+    let sign = if (-m3).rem_euclid(2) == 0 { 1.0 } else { -1.0 };
+    sign * prefactor * sum
+}
+
+fn factorial(n: usize) -> f64 {
+    (1..=n).map(|k| k as f64).product()
+}
+
+// Local crystal structure of an atom, numbered as in OVITO's common neighbour analysis
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Structure {
+    Other = 0,
+    Fcc = 1,
+    Hcp = 2,
+    Bcc = 3,
+    Icosahedral = 4,
+}
+
+impl Structure {
+    pub const ALL: [Structure; 5] = [Structure::Other, Structure::Fcc, Structure::Hcp, Structure::Bcc, Structure::Icosahedral];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Structure::Other => "Other",
+            Structure::Fcc => "FCC",
+            Structure::Hcp => "HCP",
+            Structure::Bcc => "BCC",
+            Structure::Icosahedral => "ICO",
+        }
+    }
+}
+
+// Adaptive common neighbour analysis (Stukowski, Modelling Simul. Mater. Sci. Eng. 20, 045021).
+// Instead of one global cutoff, every atom gets its own from its 12 nearest neighbours for
+// the close-packed and icosahedral signatures, and from its 14 nearest for bcc, so the
+// classification follows thermal expansion and strain without tuning.
+pub fn adaptive_cna(positions: &[[f64; 3]], box_length: f64) -> Vec<Structure> {
+    // Generous enough to hold the 14 nearest neighbours at any liquid or solid density
+    let search = (2.0 * (box_length.powi(3) / positions.len().max(1) as f64).cbrt()).min(0.5 * box_length);
+    let cells = CellList::new(positions, box_length, search);
+    let length = |d: &[f64; 3]| (d[0] * d[0] + d[1] * d[1] + d[2] * d[2]).sqrt();
+    (0..positions.len()).into_par_iter().map(|i| {
+        let mut neighbours: Vec<[f64; 3]> = cells.neighbours(positions, i).into_iter().map(|(_, d)| d).collect();
+        neighbours.sort_by(|a, b| length(a).total_cmp(&length(b)));
+        let mean = |shell: &[[f64; 3]]| shell.iter().map(length).sum::<f64>() / shell.len() as f64;
+        let factor = 0.5 * (1.0 + 2f64.sqrt());
+
+        if neighbours.len() >= 12 {
+            let local = &neighbours[..12];
+            let signatures = signatures(local, factor * mean(local));
+            let count = |signature| signatures.iter().filter(|&&s| s == signature).count();
+            if count((4, 2, 1)) == 12 {
+                return Structure::Fcc;
+            }
+            if count((4, 2, 1)) == 6 && count((4, 2, 2)) == 6 {
+                return Structure::Hcp;
+            }
+            if count((5, 5, 5)) == 12 {
+                return Structure::Icosahedral;
+            }
+        }
+        if neighbours.len() >= 14 {
+            let local = &neighbours[..14];
+            // The first shell scaled to the distance of the second
+            let cutoff = factor * 0.5 * (2.0 / 3f64.sqrt() * mean(&local[..8]) + mean(&local[8..]));
+            let signatures = signatures(local, cutoff);
+            let count = |signature| signatures.iter().filter(|&&s| s == signature).count();
+            if count((4, 4, 4)) == 6 && count((6, 6, 6)) == 8 {
+                return Structure::Bcc;
+            }
+        }
+        Structure::Other
+    }).collect()
+}
+
+// CNA signature of the bond to each of the `local` neighbours (given as separations from the
+// central atom): the number of neighbours common to both ends, the bonds among those, and the
+// bonds in the longest chain they form
+fn signatures(local: &[[f64; 3]], cutoff: f64) -> Vec<(usize, usize, usize)> {
+    let bonded = |a: usize, b: usize| {
+        let d = [local[a][0] - local[b][0], local[a][1] - local[b][1], local[a][2] - local[b][2]];
+        d[0] * d[0] + d[1] * d[1] + d[2] * d[2] < cutoff * cutoff
+    };
+    (0..local.len()).map(|j| {
+        let common: Vec<usize> = (0..local.len()).filter(|&k| k != j && bonded(j, k)).collect();
+        let mut bonds = Vec::new();
+        for (a, &k) in common.iter().enumerate() {
+            for &m in &common[a + 1..] {
+                if bonded(k, m) {
+                    bonds.push((k, m));
+                }
+            }
+        }
+        let count = bonds.len();
+        (common.len(), count, longest_chain(bonds))
+    }).collect()
+}
+
+// Largest number of bonds connected through shared atoms
+fn longest_chain(mut bonds: Vec<(usize, usize)>) -> usize {
+    let mut longest = 0;
+    while let Some(first) = bonds.pop() {
+        let mut atoms = vec![first.0, first.1];
+        let mut size = 1;
+        while let Some(index) = bonds.iter().position(|(a, b)| atoms.contains(a) || atoms.contains(b)) {
+            let (a, b) = bonds.swap_remove(index);
+            atoms.extend([a, b]);
+            size += 1;
+        }
+        longest = longest.max(size);
+    }
+    longest
+}
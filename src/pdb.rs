@@ -0,0 +1,105 @@
+use std::io::{self, BufRead};
+use std::path::Path;
+
+use crate::compress;
+
+pub struct PdbStructure {
+    pub positions: Vec<[f64; 3]>,
+    pub elements: Vec<String>,
+    // Edge lengths from the CRYST1 record, when present
+    pub box_lengths: Option<[f64; 3]>,
+}
+
+// Reads ATOM/HETATM records of the first model in a PDB file. Elements come from columns 77-78
+// and fall back to the atom name when those are blank, as in many hand-made files.
+pub fn read_pdb<P: AsRef<Path>>(path: P) -> io::Result<PdbStructure> {
+    let reader = compress::open(path)?;
+    let mut positions = Vec::new();
+    let mut elements = Vec::new();
+    let mut box_lengths = None;
+
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let record = field(&line, 0, 6);
+        match record {
+            "CRYST1" => {
+                let cell: Vec<f64> = [(6, 15), (15, 24), (24, 33), (33, 40), (40, 47), (47, 54)].iter()
+                    .map(|&(start, end)| parse(&line, start, end, number))
+                    .collect::<io::Result<_>>()?;
+                if cell[3..].iter().any(|angle| (angle - 90.0).abs() > 1e-3) {
+                    return Err(invalid(&format!("line {}: only orthorhombic CRYST1 cells are supported", number + 1)));
+                }
+                box_lengths = Some([cell[0], cell[1], cell[2]]);
+            }
+            "ATOM" | "HETATM" => {
+                positions.push([
+                    parse(&line, 30, 38, number)?,
+                    parse(&line, 38, 46, number)?,
+                    parse(&line, 46, 54, number)?,
+                ]);
+                let element = match field(&line, 76, 78) {
+                    "" => field(&line, 12, 16).trim_matches(|c: char| !c.is_ascii_alphabetic()).chars().take(2).collect(),
+                    element => element.to_string(),
+                };
+                elements.push(normalize_element(&element));
+            }
+            "ENDMDL" | "END" => break,
+            _ => {}
+        }
+    }
+
+    if positions.is_empty() {
+        return Err(invalid("no ATOM or HETATM records"));
+    }
+    Ok(PdbStructure { positions, elements, box_lengths })
+}
+
+// "AR" and "ar" both become "Ar"
+fn normalize_element(element: &str) -> String {
+    let mut chars = element.chars();
+    match chars.next() {
+        Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str().to_ascii_lowercase().as_str(),
+        None => String::new(),
+    }
+}
+
+// Fixed-width column slice, tolerating short lines
+fn field(line: &str, start: usize, end: usize) -> &str {
+    line.get(start.min(line.len())..end.min(line.len())).unwrap_or("").trim()
+}
+
+fn parse(line: &str, start: usize, end: usize, number: usize) -> io::Result<f64> {
+    field(line, start, end).parse()
+        .map_err(|_| invalid(&format!("line {}: cannot parse columns {}-{}", number + 1, start + 1, end)))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn structure_reads_back() {
+        let atoms = [("Ar", [1.5, -2.25, 10.0]), ("Kr", [12.125, 0.0, -3.5]), ("AR", [0.001, 99.999, -100.5])];
+        let mut text = format!("CRYST1{:9.3}{:9.3}{:9.3}{:7.2}{:7.2}{:7.2} P 1           1\n", 20.0, 21.5, 22.25, 90.0, 90.0, 90.0);
+        for (i, (element, pos)) in atoms.iter().enumerate() {
+            text += &format!(
+                "HETATM{:>5} {:<4} {:>3} A{:>4}    {:8.3}{:8.3}{:8.3}{:6.2}{:6.2}          {:>2}\n",
+                i + 1, element, element, i + 1, pos[0], pos[1], pos[2], 1.0, 0.0, element
+            );
+        }
+        // Only the first model is read
+        text += "ENDMDL\nMODEL        2\nATOM      1  AR   AR  A   1       0.000   0.000   0.000  1.00  0.00          AR\n";
+        let path = std::env::temp_dir().join(format!("sim-pdb-{}.pdb", std::process::id()));
+        std::fs::write(&path, text).unwrap();
+
+        let structure = read_pdb(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(structure.positions, atoms.map(|(_, pos)| pos));
+        assert_eq!(structure.elements, ["Ar", "Kr", "Ar"]);
+        assert_eq!(structure.box_lengths, Some([20.0, 21.5, 22.25]));
+    }
+}
@@ -0,0 +1,111 @@
+use plotters::coord::Shift;
+use plotters::prelude::*;
+use std::path::Path;
+
+pub struct Series {
+    pub label: String,
+    pub points: Vec<(f64, f64)>,
+}
+
+// One chart in a figure; charts are stacked vertically and share the x axis label
+pub struct Panel<'a> {
+    pub y_label: &'a str,
+    pub series: Vec<&'a Series>,
+}
+
+const WIDTH: u32 = 720;
+const PANEL_HEIGHT: u32 = 480;
+
+// Renders overlaid line series to an SVG document
+pub fn line_plot_svg(title: &str, x_label: &str, y_label: &str, series: &[&Series]) -> Result<String, String> {
+    let mut svg = String::new();
+    {
+        let root = SVGBackend::with_string(&mut svg, (WIDTH, PANEL_HEIGHT)).into_drawing_area();
+        let panel = Panel { y_label, series: series.to_vec() };
+        draw(&root, title, x_label, &[panel])?;
+        root.present().map_err(|e| e.to_string())?;
+    }
+    Ok(svg)
+}
+
+// Writes a figure to `path`: SVG, or PNG when the extension is .png
+pub fn save_plot<P: AsRef<Path>>(path: P, title: &str, x_label: &str, panels: &[Panel]) -> Result<(), String> {
+    let path = path.as_ref();
+    // Stacked panels get less height each so tall figures stay readable
+    let height = if panels.len() > 1 { 200 * panels.len() as u32 + 60 } else { PANEL_HEIGHT };
+    if path.extension().is_some_and(|ext| ext == "png") {
+        return save_png(path, (WIDTH, height), title, x_label, panels);
+    }
+    let root = SVGBackend::new(path, (WIDTH, height)).into_drawing_area();
+    draw(&root, title, x_label, panels)?;
+    root.present().map_err(|e| e.to_string())
+}
+
+#[cfg(feature = "png")]
+fn save_png(path: &Path, size: (u32, u32), title: &str, x_label: &str, panels: &[Panel]) -> Result<(), String> {
+    let root = BitMapBackend::new(path, size).into_drawing_area();
+    draw(&root, title, x_label, panels)?;
+    root.present().map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "png"))]
+fn save_png(_path: &Path, _size: (u32, u32), _title: &str, _x_label: &str, _panels: &[Panel]) -> Result<(), String> {
+    Err("built without PNG support (rebuild with --features png, or plot to .svg)".to_string())
+}
+
+fn draw<DB: DrawingBackend>(root: &DrawingArea<DB, Shift>, title: &str, x_label: &str, panels: &[Panel]) -> Result<(), String> {
+    root.fill(&WHITE).map_err(|e| e.to_string())?;
+    let root = root.titled(title, ("sans-serif", 20)).map_err(|e| e.to_string())?;
+
+    for (area, panel) in root.split_evenly((panels.len(), 1)).iter().zip(panels) {
+        let (x_range, y_range) = ranges(&panel.series);
+        let mut chart = ChartBuilder::on(area)
+            .margin(15)
+            .x_label_area_size(40)
+            .y_label_area_size(70)
+            .build_cartesian_2d(x_range, y_range)
+            .map_err(|e| e.to_string())?;
+        chart.configure_mesh()
+            .x_desc(x_label)
+            .y_desc(panel.y_label)
+            .draw()
+            .map_err(|e| e.to_string())?;
+
+        for (i, s) in panel.series.iter().enumerate() {
+            let color = Palette99::pick(i).to_rgba();
+            chart.draw_series(LineSeries::new(s.points.iter().copied(), color.stroke_width(2)))
+                .map_err(|e| e.to_string())?
+                .label(s.label.clone())
+                .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], color.stroke_width(2)));
+        }
+        // A lone series is already named by the axis label
+        if panel.series.len() > 1 {
+            chart.configure_series_labels()
+                .background_style(WHITE.mix(0.8))
+                .border_style(BLACK)
+                .draw()
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+// Axis ranges covering every finite point, with a little headroom on y
+fn ranges(series: &[&Series]) -> (std::ops::Range<f64>, std::ops::Range<f64>) {
+    let (mut x_min, mut x_max, mut y_min, mut y_max) = (f64::MAX, f64::MIN, f64::MAX, f64::MIN);
+    let points = series.iter().flat_map(|s| s.points.iter());
+    for &(x, y) in points.filter(|(x, y)| x.is_finite() && y.is_finite()) {
+        x_min = x_min.min(x);
+        x_max = x_max.max(x);
+        y_min = y_min.min(y);
+        y_max = y_max.max(y);
+    }
+    if x_min > x_max {
+        (x_min, x_max, y_min, y_max) = (0.0, 1.0, 0.0, 1.0);
+    }
+    if x_max == x_min {
+        x_max = x_min + 1.0;
+    }
+    let margin = 0.05 * (y_max - y_min).max(1e-12);
+    (x_min..x_max, (y_min - margin)..(y_max + margin))
+}
@@ -0,0 +1,91 @@
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+
+use crate::simulation::KB;
+use crate::table::TableWriter;
+
+pub const AXES: [&str; 3] = ["x", "y", "z"];
+
+// Number density and kinetic temperature in slabs along one axis of the box, accumulated over
+// samples. Atoms are binned by their fractional coordinate, so the bins follow a box that
+// changes size. The temperature of a slab is m <v^2> / 3 kB over the atoms that were in it,
+// with no correction for a streaming velocity.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Profile {
+    pub axis: usize,
+    pub bins: usize,
+    samples: usize,
+    counts: Vec<f64>,
+    // Sum of m v^2 over the atoms in each bin
+    kinetic: Vec<f64>,
+    box_length_sum: f64,
+    // Sum of the bin volume over samples
+    volume_sum: f64,
+}
+
+impl Profile {
+    pub fn new(axis: usize, bins: usize) -> Self {
+        assert!(axis < 3 && bins > 0, "axis must be 0, 1 or 2 and bins at least 1");
+        Profile {
+            axis,
+            bins,
+            samples: 0,
+            counts: vec![0.0; bins],
+            kinetic: vec![0.0; bins],
+            box_length_sum: 0.0,
+            volume_sum: 0.0,
+        }
+    }
+
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    // Adds one configuration in a box with edges `box_lengths` along x, y and z. Atoms marked
+    // in `frozen` are left out.
+    pub fn sample(&mut self, positions: &[[f64; 3]], velocities: &[[f64; 3]], box_lengths: [f64; 3], mass: f64, frozen: Option<&[bool]>) {
+        let box_length = box_lengths[self.axis];
+        for (i, (pos, vel)) in positions.iter().zip(velocities).enumerate() {
+            if frozen.is_some_and(|f| f[i]) {
+                continue;
+            }
+            let fraction = (pos[self.axis] / box_length).rem_euclid(1.0);
+            let bin = ((fraction * self.bins as f64) as usize).min(self.bins - 1);
+            self.counts[bin] += 1.0;
+            self.kinetic[bin] += mass * (vel[0].powi(2) + vel[1].powi(2) + vel[2].powi(2));
+        }
+        self.samples += 1;
+        self.box_length_sum += box_length;
+        self.volume_sum += box_lengths[0] * box_lengths[1] * box_lengths[2] / self.bins as f64;
+    }
+
+    // Bin centres along the axis (at the mean box length), number densities, temperatures and
+    // mean atom counts. Bins no atom visited have a NaN temperature.
+    pub fn results(&self) -> (Vec<f64>, Vec<f64>, Vec<f64>, Vec<f64>) {
+        let width = self.box_length_sum / self.samples.max(1) as f64 / self.bins as f64;
+        let centres = (0..self.bins).map(|b| (b as f64 + 0.5) * width).collect();
+        let densities = self.counts.iter().map(|c| c / self.volume_sum).collect();
+        let temperatures = self.kinetic.iter().zip(&self.counts).map(|(k, c)| {
+            if *c > 0.0 { k / (3.0 * KB * c) } else { f64::NAN }
+        }).collect();
+        let atoms = self.counts.iter().map(|c| c / self.samples.max(1) as f64).collect();
+        (centres, densities, temperatures, atoms)
+    }
+
+    // Writes `position density temperature atoms` per bin as a table, in any format
+    // TableWriter takes
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let (centres, densities, temperatures, atoms) = self.results();
+        let mut table = TableWriter::create(path, &["position", "density", "temperature", "atoms"])?;
+        for b in 0..self.bins {
+            table.write_row(&[centres[b], densities[b], temperatures[b], atoms[b]])?;
+        }
+        table.finish()
+    }
+}
+
+// Axis index from `x`, `y` or `z`
+pub fn parse_axis(name: &str) -> Option<usize> {
+    AXES.iter().position(|&axis| axis == name)
+}
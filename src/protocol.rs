@@ -0,0 +1,478 @@
+use serde::Deserialize;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::analysis::{self, block_average};
+use crate::cli::Args;
+use crate::compress::CompressedWriter;
+use crate::geometry::Cell;
+use crate::glass::progress_bar;
+use crate::input;
+use crate::minimize;
+use crate::random::Philox;
+use crate::simulation::{
+    initial_state, maxwell_boltzmann_velocities, remove_com_velocity, BerendsenBarostat, BerendsenThermostat, LangevinThermostat,
+    Simulation, MASS_ARGON,
+};
+use crate::table::TableWriter;
+use crate::xyz;
+
+const USAGE: &str = "Usage: sim protocol <protocol.toml> [--output-dir DIR] [--dry-run]";
+
+pub const ENSEMBLES: [&str; 4] = ["minimize", "nve", "nvt", "npt"];
+
+pub const SCHEDULES: [&str; 3] = ["linear", "staircase", "exponential"];
+
+// Blocks for the error of the viscosity of a sheared stage
+const SHEAR_BLOCKS: usize = 10;
+
+// The system every stage continues from: `atoms` on a lattice at `density`, or a configuration
+// read with `init_from` (box from the file unless `box` is given), with velocities at `temperature`.
+// `cell` gives the edge vectors a, b and c of a triclinic box instead; the lattice then fills
+// it whatever the density, and a configuration is taken to be in it.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct System {
+    atoms: Option<usize>,
+    density: f64,
+    init_from: Option<String>,
+    #[serde(rename = "box")]
+    box_length: Option<f64>,
+    cell: Option<[[f64; 3]; 3]>,
+    temperature: f64,
+    timestep: f64,
+    cutoff: f64,
+    seed: Option<u64>,
+    output_dir: String,
+}
+
+// One stage of the protocol. Settings a stage leaves out take the defaults in `Default`, not
+// the values of the stage before, so every stage reads on its own.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Stage {
+    name: Option<String>,
+    ensemble: String,
+    steps: usize,
+    // Falls back to the timestep of `[system]`
+    timestep: Option<f64>,
+    // Thermostat target, taken to `final_temperature` over the stage along `schedule` when
+    // given: a linear ramp, `levels` equal plateaus, or exponential (geometric) cooling
+    temperature: Option<f64>,
+    final_temperature: Option<f64>,
+    schedule: String,
+    levels: usize,
+    thermostat: String,
+    tau: f64,
+    friction: f64,
+    pressure: f64,
+    barostat_tau: f64,
+    compressibility: f64,
+    // Planar shear flow u_x = shear_rate * y (1/ps) with SLLOD dynamics and Lees-Edwards
+    // boundaries, as in `sim shear`
+    shear_rate: f64,
+    thermo_every: usize,
+    snapshot_every: usize,
+    // Minimization settings, as for `sim minimize`
+    method: String,
+    tolerance: f64,
+    max_iterations: usize,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Protocol {
+    system: System,
+    #[serde(default)]
+    stage: Vec<Stage>,
+}
+
+impl Default for System {
+    fn default() -> Self {
+        System {
+            atoms: None,
+            density: 0.8,
+            init_from: None,
+            box_length: None,
+            cell: None,
+            temperature: 87.3,
+            timestep: 0.005,
+            cutoff: 2.5,
+            seed: None,
+            output_dir: "protocol".to_string(),
+        }
+    }
+}
+
+impl Default for Stage {
+    fn default() -> Self {
+        Stage {
+            name: None,
+            ensemble: String::new(),
+            steps: 0,
+            timestep: None,
+            temperature: None,
+            final_temperature: None,
+            schedule: "linear".to_string(),
+            levels: 5,
+            thermostat: "berendsen".to_string(),
+            tau: 0.1,
+            friction: 1.0,
+            pressure: 0.0,
+            barostat_tau: 2.0,
+            compressibility: 0.1,
+            shear_rate: 0.0,
+            thermo_every: 100,
+            snapshot_every: 0,
+            method: "fire".to_string(),
+            tolerance: 10.0,
+            max_iterations: 10000,
+        }
+    }
+}
+
+// `sim protocol protocol.toml`: runs a chain of stages (minimization, NVE, NVT or NPT with an
+// optional temperature ramp) on one system in a single invocation, each continuing from the
+// state the previous one left. Every stage writes its own thermo log, optional trajectory and
+// final configuration to `<output_dir>/<index>_<name>`.
+pub fn run(args: &[String]) {
+    let args = Args::parse(args);
+    if args.positional.len() != 1 {
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
+    }
+    let path = &args.positional[0];
+    let text = fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("Failed to read {}: {}", path, e);
+        std::process::exit(1);
+    });
+    let mut protocol: Protocol = toml::from_str(&text).unwrap_or_else(|e| {
+        eprintln!("Invalid protocol {}: {}", path, e);
+        std::process::exit(1);
+    });
+    if let Some(output_dir) = args.get_opt("output-dir") {
+        protocol.system.output_dir = output_dir;
+    }
+    if let Err(e) = validate(&protocol) {
+        eprintln!("Invalid protocol {}: {}", path, e);
+        std::process::exit(1);
+    }
+
+    let system = &protocol.system;
+    let seed = system.seed.unwrap_or_else(rand::random);
+    let names: Vec<String> = protocol.stage.iter().enumerate().map(|(i, stage)| {
+        format!("{:02}_{}", i + 1, stage.name.clone().unwrap_or_else(|| stage.ensemble.clone()))
+    }).collect();
+    for (stage, name) in protocol.stage.iter().zip(&names) {
+        println!("{}: {}", name, describe(stage, system.timestep));
+    }
+    if args.has("dry-run") {
+        return;
+    }
+
+    let mut sim = initial_system(system, seed);
+    let cell = *sim.cell();
+    // The timestep can change between stages, so the time is summed here
+    let mut time = 0.0;
+    if cell.is_orthorhombic() {
+        println!("{} atoms in a box of {:.4}, seed {}", sim.num_atoms(), sim.box_length(), seed);
+    } else {
+        println!("{} atoms in a cell of edges {:.4?} and angles {:.2?}, seed {}", sim.num_atoms(), cell.lengths(), cell.angles(), seed);
+    }
+    for (index, (stage, name)) in protocol.stage.iter().zip(&names).enumerate() {
+        let dir = Path::new(&system.output_dir).join(name);
+        fs::create_dir_all(&dir).unwrap_or_else(|e| {
+            eprintln!("Failed to create {}: {}", dir.display(), e);
+            std::process::exit(1);
+        });
+        // Every stage gets its own noise stream
+        time = run_stage(&mut sim, stage, &dir, system.timestep, seed.wrapping_add(index as u64 + 1), time);
+        let final_path = dir.join("final.xyz");
+        let mut file = BufWriter::new(File::create(&final_path).expect("Failed to create configuration file"));
+        let comment = format!("Time={} Stage={}", time, name);
+        xyz::write_cell_frame(&mut file, sim.positions(), Some(sim.velocities()), sim.cell(), &comment).unwrap();
+        println!(
+            "{} done at {:.3} ps: T = {:.2} K, P = {:.4}, density {:.4}, potential energy per atom {:.4}",
+            name,
+            time,
+            sim.temperature(),
+            sim.pressure(),
+            sim.density(),
+            sim.potential_energy() / sim.num_atoms() as f64
+        );
+    }
+    println!("Protocol of {} stages completed. Output saved to {}", names.len(), system.output_dir);
+}
+
+fn validate(protocol: &Protocol) -> Result<(), String> {
+    let system = &protocol.system;
+    match (system.atoms, &system.init_from) {
+        (Some(_), Some(_)) => return Err("[system] takes either atoms or init_from, not both".to_string()),
+        (None, None) => return Err("[system] needs atoms or init_from".to_string()),
+        (Some(0), None) => return Err("[system] atoms must be at least 1".to_string()),
+        _ => {}
+    }
+    if system.density <= 0.0 || system.temperature < 0.0 || system.timestep <= 0.0 || system.cutoff <= 0.0 {
+        return Err("[system] density, timestep and cutoff must be positive and temperature not negative".to_string());
+    }
+    if let Some(vectors) = system.cell {
+        if system.box_length.is_some() {
+            return Err("[system] takes either box or cell, not both".to_string());
+        }
+        let Some(cell) = Cell::triclinic(vectors) else {
+            return Err("[system] cell vectors must be right-handed and not coplanar".to_string());
+        };
+        if system.cutoff > 0.5 * cell.widths().into_iter().fold(f64::INFINITY, f64::min) {
+            return Err("[system] cutoff must be at most half the narrowest width of the cell".to_string());
+        }
+    }
+    let cell = system.cell.and_then(Cell::triclinic);
+    let triclinic = cell.is_some_and(|cell| !cell.is_orthorhombic());
+    let cubic = cell.is_none_or(|cell| !triclinic && cell.widths().iter().all(|&w| w == cell.widths()[0]));
+    if protocol.stage.is_empty() {
+        return Err("no [[stage]] given".to_string());
+    }
+    for (i, stage) in protocol.stage.iter().enumerate() {
+        let context = format!("stage {}", i + 1);
+        if !ENSEMBLES.contains(&stage.ensemble.as_str()) {
+            return Err(format!("{}: ensemble must be one of {}", context, ENSEMBLES.join(", ")));
+        }
+        if stage.ensemble == "minimize" {
+            if !cubic {
+                return Err(format!("{}: the minimizers need a cubic box", context));
+            }
+            // The minimizers know nothing of the shifted Lees-Edwards images
+            if protocol.stage[..i].iter().any(|earlier| earlier.shear_rate != 0.0) {
+                return Err(format!("{}: cannot minimize after a sheared stage", context));
+            }
+            if !minimize::METHODS.contains(&stage.method.as_str()) {
+                return Err(format!("{}: method must be one of {}", context, minimize::METHODS.join(", ")));
+            }
+            continue;
+        }
+        if stage.steps == 0 {
+            return Err(format!("{}: steps must be at least 1", context));
+        }
+        if stage.timestep.is_some_and(|dt| dt <= 0.0) {
+            return Err(format!("{}: timestep must be positive", context));
+        }
+        let thermostatted = stage.ensemble != "nve";
+        if thermostatted && stage.temperature.is_none() {
+            return Err(format!("{}: {} needs a temperature", context, stage.ensemble));
+        }
+        if !thermostatted && (stage.temperature.is_some() || stage.final_temperature.is_some()) {
+            return Err(format!("{}: nve has no thermostat, remove temperature", context));
+        }
+        if stage.temperature.into_iter().chain(stage.final_temperature).any(|t| t <= 0.0) {
+            return Err(format!("{}: temperatures must be positive", context));
+        }
+        if !SCHEDULES.contains(&stage.schedule.as_str()) {
+            return Err(format!("{}: schedule must be one of {}", context, SCHEDULES.join(", ")));
+        }
+        if stage.schedule != "linear" && stage.final_temperature.is_none() {
+            return Err(format!("{}: a {} schedule needs a final_temperature", context, stage.schedule));
+        }
+        if stage.schedule == "staircase" && stage.levels < 2 {
+            return Err(format!("{}: a staircase needs at least 2 levels", context));
+        }
+        if stage.thermostat != "berendsen" && stage.thermostat != "langevin" {
+            return Err(format!("{}: thermostat must be berendsen or langevin", context));
+        }
+        if stage.shear_rate != 0.0 && !thermostatted {
+            return Err(format!("{}: a sheared stage needs a thermostat to remove the viscous heat, use nvt or npt", context));
+        }
+        if stage.shear_rate != 0.0 && triclinic {
+            return Err(format!("{}: Lees-Edwards shear needs an orthogonal box", context));
+        }
+        if stage.tau <= 0.0 || stage.friction <= 0.0 || stage.barostat_tau <= 0.0 || stage.compressibility <= 0.0 {
+            return Err(format!("{}: tau, friction, barostat_tau and compressibility must be positive", context));
+        }
+    }
+    Ok(())
+}
+
+// Thermostat target after `step` steps of a stage
+fn scheduled_temperature(stage: &Stage, step: usize) -> f64 {
+    let fraction = step as f64 / stage.steps as f64;
+    match (stage.temperature, stage.final_temperature) {
+        (Some(start), Some(end)) => match stage.schedule.as_str() {
+            "staircase" => {
+                let level = ((fraction * stage.levels as f64) as usize).min(stage.levels - 1);
+                start + (end - start) * level as f64 / (stage.levels - 1) as f64
+            }
+            "exponential" => start * (end / start).powf(fraction),
+            _ => start + (end - start) * fraction,
+        },
+        (start, _) => start.unwrap_or(f64::NAN),
+    }
+}
+
+// One-line summary printed before the run
+fn describe(stage: &Stage, default_timestep: f64) -> String {
+    if stage.ensemble == "minimize" {
+        return format!("minimize ({}, force tolerance {})", stage.method, stage.tolerance);
+    }
+    let mut text = format!("{} for {} steps of {} ps", stage.ensemble, stage.steps, stage.timestep.unwrap_or(default_timestep));
+    if let Some(t) = stage.temperature {
+        text += &format!(" at {} K", t);
+        if let Some(t_final) = stage.final_temperature {
+            text += &match stage.schedule.as_str() {
+                "staircase" => format!(" stepped to {} K in {} levels", t_final, stage.levels),
+                "exponential" => format!(" cooled exponentially to {} K", t_final),
+                _ => format!(" ramped to {} K", t_final),
+            };
+        }
+        text += &format!(" ({})", stage.thermostat);
+    }
+    if stage.ensemble == "npt" {
+        text += &format!(", pressure {}", stage.pressure);
+    }
+    if stage.shear_rate != 0.0 {
+        text += &format!(", shear rate {} 1/ps", stage.shear_rate);
+    }
+    text
+}
+
+fn initial_system(system: &System, seed: u64) -> Simulation {
+    let cell = system.cell.and_then(Cell::triclinic);
+    let Some(path) = &system.init_from else {
+        let n = system.atoms.unwrap();
+        let Some(cell) = cell else {
+            return initial_state(n, system.density, system.temperature, system.timestep, system.cutoff, seed);
+        };
+        // The simple cubic lattice in fractional coordinates, sheared along with the cell
+        let lattice = initial_state(n, 1.0, system.temperature, system.timestep, system.cutoff, seed);
+        let scale = lattice.box_length();
+        let positions = lattice.positions().iter().map(|pos| cell.to_cartesian(&pos.map(|x| x / scale))).collect();
+        return Simulation::with_cell(positions, lattice.velocities().to_vec(), cell, system.timestep, system.cutoff);
+    };
+    let frame = input::read_configuration(path).unwrap_or_else(|e| {
+        eprintln!("Failed to read {}: {}", path, e);
+        std::process::exit(1);
+    });
+    let velocities = frame.velocities.clone().unwrap_or_else(|| {
+        let mut velocities = maxwell_boltzmann_velocities(frame.positions.len(), system.temperature, MASS_ARGON, &Philox::new(seed));
+        remove_com_velocity(&mut velocities);
+        velocities
+    });
+    if let Some(cell) = cell {
+        let positions = frame.positions.iter().map(|pos| cell.wrap(pos)).collect();
+        return Simulation::with_cell(positions, velocities, cell, system.timestep, system.cutoff);
+    }
+    let box_length = input::cubic_box_length(path, frame.box_lengths, system.box_length).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let positions = frame.positions.iter().map(|pos| pos.map(|coord| coord.rem_euclid(box_length))).collect();
+    Simulation::new(positions, velocities, box_length, system.timestep, system.cutoff)
+}
+
+// Runs one stage from `start_time` (ps) and returns the time at its end
+fn run_stage(sim: &mut Simulation, stage: &Stage, dir: &Path, default_timestep: f64, seed: u64, start_time: f64) -> f64 {
+    if stage.ensemble == "minimize" {
+        let mut positions = sim.positions().to_vec();
+        let result = minimize::minimize(&stage.method, &mut positions, sim.box_length(), sim.cutoff(), stage.tolerance, stage.max_iterations);
+        sim.set_positions(&positions);
+        if !result.converged {
+            eprintln!("Warning: minimization stopped after {} iterations with a largest force of {:.4}", result.iterations, result.max_force);
+        }
+        return start_time;
+    }
+
+    sim.dt = stage.timestep.unwrap_or(default_timestep);
+    sim.thermostat = None;
+    sim.langevin = None;
+    sim.barostat = None;
+    sim.shear_rate = stage.shear_rate;
+    if let Some(temperature) = stage.temperature {
+        if stage.thermostat == "langevin" {
+            sim.langevin = Some(LangevinThermostat { target_temperature: temperature, friction: stage.friction, seed });
+        } else {
+            sim.thermostat = Some(BerendsenThermostat { target_temperature: temperature, tau: stage.tau });
+        }
+    }
+    if stage.ensemble == "npt" {
+        sim.barostat = Some(BerendsenBarostat {
+            target_pressure: stage.pressure,
+            tau: stage.barostat_tau,
+            compressibility: stage.compressibility,
+        });
+    }
+
+    let sheared = stage.shear_rate != 0.0;
+    let mut columns = vec!["time", "target_temperature", "temperature", "pressure", "density", "potential_energy", "total_energy"];
+    if sheared {
+        columns.push("pxy");
+    }
+    let mut log = (stage.thermo_every > 0).then(|| {
+        let path = dir.join("thermo.csv");
+        TableWriter::create(&path, &columns).unwrap_or_else(|e| {
+            eprintln!("Failed to create {}: {}", path.display(), e);
+            std::process::exit(1);
+        })
+    });
+    let mut trajectory = (stage.snapshot_every > 0).then(|| {
+        let path = dir.join("trajectory.extxyz");
+        CompressedWriter::create(&path).unwrap_or_else(|e| {
+            eprintln!("Failed to create {}: {}", path.display(), e);
+            std::process::exit(1);
+        })
+    });
+
+    // Shear stress of every step of a sheared stage, for its viscosity
+    let mut pxy = Vec::new();
+    let pb = progress_bar(stage.steps);
+    for step in 1..=stage.steps {
+        let target_temperature = scheduled_temperature(stage, step);
+        sim.set_target_temperature(target_temperature);
+        sim.step();
+        pb.inc(1);
+        let time = start_time + step as f64 * sim.dt;
+        if sheared {
+            pxy.push(sim.pressure_tensor()[3]);
+        }
+
+        if let Some(log) = log.as_mut().filter(|_| step.is_multiple_of(stage.thermo_every)) {
+            let potential_energy = sim.potential_energy();
+            let mut row = vec![
+                time,
+                target_temperature,
+                sim.temperature(),
+                sim.pressure(),
+                sim.density(),
+                potential_energy,
+                potential_energy + sim.kinetic_energy(),
+            ];
+            row.extend(pxy.last());
+            log.write_row(&row).expect("Failed to write thermo log");
+        }
+        if let Some(trajectory) = trajectory.as_mut().filter(|_| step.is_multiple_of(stage.snapshot_every)) {
+            let comment = format!("Time={}", time);
+            xyz::write_cell_frame(trajectory, sim.positions(), Some(sim.velocities()), sim.cell(), &comment)
+                .expect("Failed to write trajectory");
+        }
+    }
+    pb.finish_with_message(format!("T = {:.2} K, density {:.4}", sim.temperature(), sim.density()));
+    if let Some(log) = log {
+        log.finish().expect("Failed to write thermo log");
+    }
+    if let Some(trajectory) = trajectory {
+        trajectory.finish().expect("Failed to write trajectory");
+    }
+    if sheared {
+        // Over the whole stage, so a stage measuring the viscosity should follow one that sets
+        // up the flow
+        let (mean, error) = block_average(&pxy, SHEAR_BLOCKS);
+        println!(
+            "Shear stress P_xy {:.6e} +- {:.2e}, viscosity {:.6e} +- {:.2e} energy ps/length^3, reduced {:.4} +- {:.4}",
+            mean,
+            error,
+            -mean / stage.shear_rate,
+            error / stage.shear_rate.abs(),
+            analysis::reduced_viscosity(-mean / stage.shear_rate),
+            analysis::reduced_viscosity(error / stage.shear_rate.abs())
+        );
+    }
+    start_time + stage.steps as f64 * sim.dt
+}
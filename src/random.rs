@@ -0,0 +1,246 @@
+use rand::RngCore;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+// Start of a recording, followed by the atom count of the run as a little-endian u64
+const RECORDING_MAGIC: &[u8; 8] = b"SIMRAND1";
+const RECORDING_HEADER: u64 = 16;
+
+// Passes random numbers through from `inner` and appends their raw bytes to a file, so the run
+// can later be repeated bit-exactly with `ReplayRng`
+pub struct RecordingRng<R: RngCore> {
+    inner: R,
+    writer: BufWriter<File>,
+}
+
+impl<R: RngCore> RecordingRng<R> {
+    pub fn create<P: AsRef<Path>>(path: P, inner: R, atoms: usize) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(RECORDING_MAGIC)?;
+        writer.write_all(&(atoms as u64).to_le_bytes())?;
+        Ok(RecordingRng { inner, writer })
+    }
+
+    fn record(&mut self, bytes: &[u8]) {
+        self.writer.write_all(bytes).expect("Failed to record random numbers");
+    }
+}
+
+impl<R: RngCore> RngCore for RecordingRng<R> {
+    fn next_u32(&mut self) -> u32 {
+        let value = self.inner.next_u32();
+        self.record(&value.to_le_bytes());
+        value
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let value = self.inner.next_u64();
+        self.record(&value.to_le_bytes());
+        value
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.inner.fill_bytes(dest);
+        self.record(dest);
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl<R: RngCore> Drop for RecordingRng<R> {
+    fn drop(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+// Serves the random numbers saved by `RecordingRng` in the order they were drawn. The replayed
+// run has to consume them the same way, so opening checks that the recording is for `atoms`
+// atoms and holds exactly the `length` bytes the run will draw.
+pub struct ReplayRng {
+    reader: BufReader<File>,
+}
+
+impl ReplayRng {
+    pub fn open<P: AsRef<Path>>(path: P, atoms: usize, length: u64) -> io::Result<Self> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+        let mut header = [0u8; RECORDING_HEADER as usize];
+        if size < RECORDING_HEADER || reader.read_exact(&mut header).is_err() || &header[..8] != RECORDING_MAGIC {
+            return Err(invalid("not a random number recording".to_string()));
+        }
+        let recorded_atoms = u64::from_le_bytes(header[8..].try_into().unwrap());
+        if recorded_atoms != atoms as u64 {
+            return Err(invalid(format!("recorded for {} atoms, this run has {}", recorded_atoms, atoms)));
+        }
+        if size - RECORDING_HEADER != length {
+            return Err(invalid(format!("holds {} bytes of random numbers, this run draws {}", size - RECORDING_HEADER, length)));
+        }
+        Ok(ReplayRng { reader })
+    }
+}
+
+impl RngCore for ReplayRng {
+    fn next_u32(&mut self) -> u32 {
+        let mut bytes = [0u8; 4];
+        self.fill_bytes(&mut bytes);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut bytes = [0u8; 8];
+        self.fill_bytes(&mut bytes);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.reader.read_exact(dest).expect("Recorded random stream exhausted or unreadable");
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.reader.read_exact(dest).map_err(rand::Error::new)
+    }
+}
+
+const PHILOX_M0: u32 = 0xD251_1F53;
+const PHILOX_M1: u32 = 0xCD9E_8D57;
+const PHILOX_W0: u32 = 0x9E37_79B9;
+const PHILOX_W1: u32 = 0xBB67_AE85;
+
+// Philox4x32-10 block function (Salmon et al., SC'11): maps a 128-bit counter and a 64-bit key
+// to four pseudo-random words. Equal inputs give equal outputs on any thread in any order.
+pub fn philox4x32(counter: [u32; 4], key: [u32; 2]) -> [u32; 4] {
+    let mut c = counter;
+    let mut k = key;
+    for round in 0..10 {
+        if round > 0 {
+            k = [k[0].wrapping_add(PHILOX_W0), k[1].wrapping_add(PHILOX_W1)];
+        }
+        let product0 = PHILOX_M0 as u64 * c[0] as u64;
+        let product1 = PHILOX_M1 as u64 * c[2] as u64;
+        c = [
+            (product1 >> 32) as u32 ^ c[1] ^ k[0],
+            product1 as u32,
+            (product0 >> 32) as u32 ^ c[3] ^ k[1],
+            product0 as u32,
+        ];
+    }
+    c
+}
+
+// Counter-based generator keyed on the seed. Every (step, particle) pair owns its own random
+// numbers, so parallel loops draw the same values whatever the thread count or schedule, and
+// reproducing a run only takes the seed.
+#[derive(Clone, Copy)]
+pub struct Philox {
+    key: [u32; 2],
+}
+
+impl Philox {
+    pub fn new(seed: u64) -> Self {
+        Philox { key: [seed as u32, (seed >> 32) as u32] }
+    }
+
+    // Four uniform numbers in (0, 1) for `particle` at `step`; `block` selects further
+    // independent sets for the same pair
+    pub fn uniforms(&self, step: u64, particle: u32, block: u32) -> [f64; 4] {
+        let words = philox4x32([step as u32, (step >> 32) as u32, particle, block], self.key);
+        words.map(|w| (w as f64 + 0.5) / 4_294_967_296.0)
+    }
+
+    // Four standard normal numbers for `particle` at `step` (Box-Muller)
+    pub fn normals(&self, step: u64, particle: u32, block: u32) -> [f64; 4] {
+        let u = self.uniforms(step, particle, block);
+        let mut normals = [0.0; 4];
+        for pair in 0..2 {
+            let radius = (-2.0 * u[2 * pair].ln()).sqrt();
+            let angle = 2.0 * std::f64::consts::PI * u[2 * pair + 1];
+            normals[2 * pair] = radius * angle.cos();
+            normals[2 * pair + 1] = radius * angle.sin();
+        }
+        normals
+    }
+}
+
+// Philox run as a sequential generator, for code that draws from an `RngCore` one number after
+// another: consecutive counters give consecutive blocks of four words. Unlike the generators
+// of `rand` the sequence is fixed by the seed alone, whatever the version of the crate.
+pub struct PhiloxRng {
+    key: [u32; 2],
+    counter: u64,
+    words: [u32; 4],
+    used: usize,
+}
+
+impl PhiloxRng {
+    pub fn new(seed: u64) -> Self {
+        PhiloxRng { key: Philox::new(seed).key, counter: 0, words: [0; 4], used: 4 }
+    }
+}
+
+impl RngCore for PhiloxRng {
+    fn next_u32(&mut self) -> u32 {
+        if self.used == 4 {
+            self.words = philox4x32([self.counter as u32, (self.counter >> 32) as u32, 0, 0], self.key);
+            self.counter += 1;
+            self.used = 0;
+        }
+        self.used += 1;
+        self.words[self.used - 1]
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let low = self.next_u32() as u64;
+        low | (self.next_u32() as u64) << 32
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(4) {
+            chunk.copy_from_slice(&self.next_u32().to_le_bytes()[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Known-answer vectors of Random123 (kat_vectors, philox4x32 with 10 rounds)
+    #[test]
+    fn philox_matches_the_published_vectors() {
+        assert_eq!(philox4x32([0; 4], [0; 2]), [0x6627e8d5, 0xe169c58d, 0xbc57ac4c, 0x9b00dbd8]);
+        assert_eq!(philox4x32([u32::MAX; 4], [u32::MAX; 2]), [0x408f276d, 0x41c83b0e, 0xa20bc7c6, 0x6d5451fd]);
+        assert_eq!(
+            philox4x32([0x243f6a88, 0x85a308d3, 0x13198a2e, 0x03707344], [0xa4093822, 0x299f31d0]),
+            [0xd16cfe09, 0x94fdcceb, 0x5001e420, 0x24126ea1]
+        );
+    }
+
+    #[test]
+    fn replay_checks_the_recording() {
+        let path = std::env::temp_dir().join(format!("sim-rng-{}.bin", std::process::id()));
+        let mut recording = RecordingRng::create(&path, PhiloxRng::new(11), 5).unwrap();
+        let drawn: Vec<u64> = (0..16).map(|_| recording.next_u64()).collect();
+        drop(recording);
+
+        let mut replay = ReplayRng::open(&path, 5, 16 * 8).unwrap();
+        assert_eq!((0..16).map(|_| replay.next_u64()).collect::<Vec<_>>(), drawn);
+        let atoms = ReplayRng::open(&path, 6, 16 * 8).err().unwrap();
+        assert_eq!(atoms.to_string(), "recorded for 5 atoms, this run has 6");
+        let length = ReplayRng::open(&path, 5, 17 * 8).err().unwrap();
+        assert_eq!(length.to_string(), "holds 128 bytes of random numbers, this run draws 136");
+        std::fs::write(&path, [0u8; 8]).unwrap();
+        assert_eq!(ReplayRng::open(&path, 5, 0).err().unwrap().kind(), io::ErrorKind::InvalidData);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,157 @@
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use crate::profile::AXES;
+
+// Geometric region whose surface pushes atoms out of it (an indenter) or keeps them in it (a
+// container). An atom that has crossed the surface by a depth d feels E = K d^3, the
+// LAMMPS `fix indent` form, so the push starts softly and stiffens quickly.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum Shape {
+    Sphere { centre: [f64; 3], radius: f64 },
+    // Infinite along `axis`; the component of `centre` along it is ignored
+    Cylinder { axis: usize, centre: [f64; 3], radius: f64 },
+    // Everything between the planes at `lo` and `hi` along `axis`
+    Slab { axis: usize, lo: f64, hi: f64 },
+}
+
+impl FromStr for Shape {
+    type Err = String;
+
+    // `sphere:x,y,z:radius`, `cylinder:axis:a,b:radius` with a and b the coordinates of the
+    // cylinder axis along the other two axes in order, or `slab:axis:lo:hi`
+    fn from_str(text: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = text.split(':').collect();
+        let number = |field: &str| field.parse::<f64>().ok().filter(|x| x.is_finite());
+        let numbers = |field: &str| field.split(',').map(number).collect::<Option<Vec<f64>>>();
+        let axis = |field: &str| AXES.iter().position(|&a| a == field);
+        let shape = match fields[..] {
+            ["sphere", centre, radius] => match (numbers(centre).as_deref(), number(radius)) {
+                (Some(&[x, y, z]), Some(radius)) => Some(Shape::Sphere { centre: [x, y, z], radius }),
+                _ => None,
+            },
+            ["cylinder", axis_name, centre, radius] => match (axis(axis_name), numbers(centre).as_deref(), number(radius)) {
+                (Some(axis), Some(&[a, b]), Some(radius)) => {
+                    let mut position = [0.0; 3];
+                    let others: Vec<usize> = (0..3).filter(|&k| k != axis).collect();
+                    position[others[0]] = a;
+                    position[others[1]] = b;
+                    Some(Shape::Cylinder { axis, centre: position, radius })
+                }
+                _ => None,
+            },
+            ["slab", axis_name, lo, hi] => match (axis(axis_name), number(lo), number(hi)) {
+                (Some(axis), Some(lo), Some(hi)) if lo < hi => Some(Shape::Slab { axis, lo, hi }),
+                _ => None,
+            },
+            _ => None,
+        };
+        match shape {
+            Some(Shape::Sphere { radius, .. } | Shape::Cylinder { radius, .. }) if radius <= 0.0 => {
+                Err(format!("{}: the radius must be positive", text))
+            }
+            Some(shape) => Ok(shape),
+            None => Err(format!("{}: expected sphere:x,y,z:radius, cylinder:axis:a,b:radius or slab:axis:lo:hi", text)),
+        }
+    }
+}
+
+impl Shape {
+    // The same shape moved by `offset`
+    pub fn translated(&self, offset: [f64; 3]) -> Self {
+        let shift = |centre: [f64; 3]| std::array::from_fn(|k| centre[k] + offset[k]);
+        match *self {
+            Shape::Sphere { centre, radius } => Shape::Sphere { centre: shift(centre), radius },
+            Shape::Cylinder { axis, centre, radius } => Shape::Cylinder { axis, centre: shift(centre), radius },
+            Shape::Slab { axis, lo, hi } => Shape::Slab { axis, lo: lo + offset[axis], hi: hi + offset[axis] },
+        }
+    }
+
+    // Signed distance of `pos` from the surface, positive outside, and the outward normal of
+    // the surface nearest to it. Separations from the centre of a sphere or cylinder are taken
+    // to the nearest image along the `periodic` axes.
+    fn surface_distance(&self, pos: &[f64; 3], box_lengths: [f64; 3], periodic: [bool; 3]) -> (f64, [f64; 3]) {
+        let radial = |centre: &[f64; 3], radius: f64, skip: Option<usize>| {
+            let mut delta = [0.0; 3];
+            for k in (0..3).filter(|&k| Some(k) != skip) {
+                delta[k] = pos[k] - centre[k];
+                if periodic[k] {
+                    delta[k] -= (delta[k] / box_lengths[k]).round() * box_lengths[k];
+                }
+            }
+            let r = (delta[0].powi(2) + delta[1].powi(2) + delta[2].powi(2)).sqrt();
+            // No direction at the very centre, where the push vanishes by symmetry
+            let normal = if r > 0.0 { delta.map(|d| d / r) } else { [0.0; 3] };
+            (r - radius, normal)
+        };
+        match self {
+            Shape::Sphere { centre, radius } => radial(centre, *radius, None),
+            Shape::Cylinder { axis, centre, radius } => radial(centre, *radius, Some(*axis)),
+            Shape::Slab { axis, lo, hi } => {
+                let x = pos[*axis];
+                let mut normal = [0.0; 3];
+                let distance = if x - lo < hi - x {
+                    normal[*axis] = -1.0;
+                    lo - x
+                } else {
+                    normal[*axis] = 1.0;
+                    x - hi
+                };
+                (distance, normal)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Region {
+    pub shape: Shape,
+    // Keeps atoms inside rather than outside
+    pub container: bool,
+    // K in E = K d^3 (energy / length^3)
+    pub stiffness: f64,
+    // Constant velocity at which the region moves from where it starts at time 0
+    pub velocity: [f64; 3],
+}
+
+impl Region {
+    pub fn shape_at(&self, time: f64) -> Shape {
+        self.shape.translated(self.velocity.map(|v| v * time))
+    }
+
+    // Adds the forces of the region at `time` to `forces`, and their energies to `atom_energies`
+    // unless that is empty. Returns the total energy and the force the atoms exert on the
+    // region, the load on an indenter.
+    pub fn add_forces(
+        &self,
+        time: f64,
+        positions: &[[f64; 3]],
+        box_lengths: [f64; 3],
+        periodic: [bool; 3],
+        forces: &mut [[f64; 3]],
+        atom_energies: &mut [f64],
+    ) -> (f64, [f64; 3]) {
+        let shape = self.shape_at(time);
+        let mut total = 0.0;
+        let mut load = [0.0; 3];
+        for (i, pos) in positions.iter().enumerate() {
+            let (distance, normal) = shape.surface_distance(pos, box_lengths, periodic);
+            // Depth past the surface on the forbidden side, and the way back out
+            let (depth, sign) = if self.container { (distance, -1.0) } else { (-distance, 1.0) };
+            if depth <= 0.0 {
+                continue;
+            }
+            let magnitude = 3.0 * self.stiffness * depth * depth;
+            for k in 0..3 {
+                forces[i][k] += sign * magnitude * normal[k];
+                load[k] -= sign * magnitude * normal[k];
+            }
+            let energy = self.stiffness * depth.powi(3);
+            if let Some(e) = atom_energies.get_mut(i) {
+                *e += energy;
+            }
+            total += energy;
+        }
+        (total, load)
+    }
+}
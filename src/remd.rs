@@ -0,0 +1,179 @@
+use rayon::prelude::*;
+use std::fs::{self, File};
+use std::io::BufWriter;
+
+use crate::cli::{self, Args};
+use crate::glass::progress_bar;
+use crate::ladder::read_ladder;
+use crate::random::Philox;
+use crate::simulation::{initial_state, LangevinThermostat, KB};
+use crate::table::TableWriter;
+use crate::xyz;
+
+const USAGE: &str = "Usage: sim remd <ladder.txt> [--num-atoms 256] [--density 0.8] [--timestep 0.005] \
+[--cutoff 2.5 | --cutoff-accuracy 1e-2] [--friction 1.0] [--equilibration 5000] [--steps 50000] \
+[--exchange-every 100] [--snapshot-interval 0] [--seed S] [--output-dir remd]";
+
+// Replica exchange MD (parallel tempering): one Langevin NVT replica per temperature of a
+// ladder, all stepped in parallel. Every `--exchange-every` steps neighbouring temperatures
+// try to swap replicas with the Metropolis probability min(1, exp[(b_i - b_j)(U_i - U_j)]),
+// alternating between the even and the odd pairs. A swap moves the temperatures rather than
+// the configurations, with the velocities rescaled to the new one, so every replica's
+// trajectory is continuous and its output is already demultiplexed.
+pub fn run(args: &[String]) {
+    let args = Args::parse(args);
+    if args.positional.len() != 1 {
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
+    }
+    let path = &args.positional[0];
+    let temperatures = read_ladder(path).unwrap_or_else(|e| {
+        eprintln!("Failed to read {}: {}", path, e);
+        std::process::exit(1);
+    });
+    if temperatures.len() < 2 || temperatures.windows(2).any(|pair| pair[1] <= pair[0]) || temperatures[0] <= 0.0 {
+        eprintln!("{} must hold at least two positive temperatures in increasing order", path);
+        std::process::exit(1);
+    }
+    let n: usize = args.get("num-atoms", 256);
+    let density: f64 = args.get("density", 0.8);
+    let dt: f64 = args.get("timestep", 0.005);
+    let cutoff = cli::cutoff_from_args(&args, 2.5, n, (n as f64 / density).cbrt());
+    let friction: f64 = args.get("friction", 1.0);
+    let equilibration: usize = args.get("equilibration", 5000);
+    let steps: usize = args.get("steps", 50000);
+    let exchange_every: usize = args.get("exchange-every", 100);
+    let snapshot_interval: usize = args.get("snapshot-interval", 0);
+    let seed: u64 = args.get("seed", rand::random());
+    let output_dir: String = args.get("output-dir", "remd".to_string());
+    if exchange_every == 0 || steps < exchange_every {
+        eprintln!("--exchange-every must be at least 1 and no more than --steps");
+        std::process::exit(1);
+    }
+    if snapshot_interval > 0 && !snapshot_interval.is_multiple_of(exchange_every) {
+        eprintln!("--snapshot-interval must be a multiple of --exchange-every");
+        std::process::exit(1);
+    }
+    fs::create_dir_all(&output_dir).unwrap_or_else(|e| {
+        eprintln!("Failed to create {}: {}", output_dir, e);
+        std::process::exit(1);
+    });
+
+    let m = temperatures.len();
+    println!(
+        "Replica exchange: {} replicas of {} atoms from {} K to {} K, exchanges every {} steps, seed {}",
+        m,
+        n,
+        temperatures[0],
+        temperatures[m - 1],
+        exchange_every,
+        seed
+    );
+    let mut replicas: Vec<_> = temperatures.iter().enumerate().map(|(i, &t)| {
+        let seed = seed.wrapping_add(i as u64);
+        let mut sim = initial_state(n, density, t, dt, cutoff, seed);
+        sim.langevin = Some(LangevinThermostat { target_temperature: t, friction, seed });
+        sim
+    }).collect();
+
+    let pb = progress_bar(equilibration);
+    pb.set_message("equilibration");
+    replicas.par_iter_mut().for_each(|sim| {
+        for _ in 0..equilibration {
+            sim.step();
+            pb.inc(1);
+        }
+    });
+    pb.finish_and_clear();
+
+    // Replica at every temperature, and the temperature of every replica
+    let mut at_temperature: Vec<usize> = (0..m).collect();
+    let mut temperature_of: Vec<usize> = (0..m).collect();
+    let mut attempts = vec![0usize; m - 1];
+    let mut accepted = vec![0usize; m - 1];
+    let rng = Philox::new(seed);
+
+    let index_path = format!("{}/replica_index.csv", output_dir);
+    let mut columns = vec!["step".to_string(), "time".to_string()];
+    columns.extend((0..m).map(|k| format!("t_{}", k)));
+    let columns: Vec<&str> = columns.iter().map(String::as_str).collect();
+    let mut index_log = TableWriter::create(&index_path, &columns).unwrap_or_else(|e| {
+        eprintln!("Failed to create {}: {}", index_path, e);
+        std::process::exit(1);
+    });
+    let mut trajectories: Vec<BufWriter<File>> = if snapshot_interval > 0 {
+        (0..m).map(|i| {
+            let path = format!("{}/replica_{}.extxyz", output_dir, i);
+            BufWriter::new(File::create(&path).unwrap_or_else(|e| {
+                eprintln!("Failed to create {}: {}", path, e);
+                std::process::exit(1);
+            }))
+        }).collect()
+    } else {
+        Vec::new()
+    };
+
+    let rounds = steps / exchange_every;
+    let pb = progress_bar(rounds * exchange_every);
+    pb.set_message("replica exchange");
+    for round in 0..rounds {
+        replicas.par_iter_mut().for_each(|sim| {
+            for _ in 0..exchange_every {
+                sim.step();
+            }
+        });
+        pb.inc(exchange_every as u64);
+        let step = (round + 1) * exchange_every;
+
+        for k in (round % 2..m - 1).step_by(2) {
+            let (a, b) = (at_temperature[k], at_temperature[k + 1]);
+            let (t_low, t_high) = (temperatures[k], temperatures[k + 1]);
+            let exponent = (1.0 / (KB * t_low) - 1.0 / (KB * t_high)) * (replicas[a].potential_energy() - replicas[b].potential_energy());
+            attempts[k] += 1;
+            if exponent >= 0.0 || rng.uniforms(round as u64, k as u32, 0)[0] < exponent.exp() {
+                accepted[k] += 1;
+                at_temperature.swap(k, k + 1);
+                temperature_of[a] = k + 1;
+                temperature_of[b] = k;
+                for (replica, from, to) in [(a, t_low, t_high), (b, t_high, t_low)] {
+                    let scale = (to / from).sqrt();
+                    replicas[replica].velocities_mut().iter_mut().flatten().for_each(|v| *v *= scale);
+                    replicas[replica].set_target_temperature(to);
+                }
+            }
+        }
+
+        let mut row = vec![step as f64, step as f64 * dt];
+        row.extend(at_temperature.iter().map(|&i| i as f64));
+        index_log.write_row(&row).expect("Failed to write replica index");
+        if snapshot_interval > 0 && step.is_multiple_of(snapshot_interval) {
+            for (i, (sim, file)) in replicas.iter().zip(&mut trajectories).enumerate() {
+                let comment = format!("Temperature={} Time={}", temperatures[temperature_of[i]], step as f64 * dt);
+                xyz::write_frame(file, sim.positions(), Some(sim.velocities()), sim.box_length(), &comment).expect("Failed to write trajectory");
+            }
+        }
+    }
+    pb.finish_and_clear();
+    index_log.finish().expect("Failed to write replica index");
+
+    let acceptance_path = format!("{}/acceptance.csv", output_dir);
+    let mut table = TableWriter::create(&acceptance_path, &["t_low", "t_high", "attempts", "accepted", "acceptance"]).unwrap_or_else(|e| {
+        eprintln!("Failed to create {}: {}", acceptance_path, e);
+        std::process::exit(1);
+    });
+    println!("{:>12} {:>12} {:>10} {:>12}", "T_low (K)", "T_high (K)", "attempts", "acceptance");
+    for k in 0..m - 1 {
+        let acceptance = if attempts[k] > 0 { accepted[k] as f64 / attempts[k] as f64 } else { f64::NAN };
+        println!("{:>12.4} {:>12.4} {:>10} {:>12.3}", temperatures[k], temperatures[k + 1], attempts[k], acceptance);
+        table.write_row(&[temperatures[k], temperatures[k + 1], attempts[k] as f64, accepted[k] as f64, acceptance]).unwrap();
+    }
+    table.finish().unwrap();
+    println!(
+        "{} exchange rounds of {} steps done. Replica at each temperature in {}, acceptance in {}{}",
+        rounds,
+        exchange_every,
+        index_path,
+        acceptance_path,
+        if snapshot_interval > 0 { format!(", replica trajectories in {}/replica_<i>.extxyz", output_dir) } else { String::new() }
+    );
+}
@@ -0,0 +1,187 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::analysis;
+use crate::cli::Args;
+use crate::forces::compute_forces;
+use crate::plot::{line_plot_svg, Series};
+use crate::trajectory::{self, SimulationData};
+
+const USAGE: &str = "Usage: sim report <run> [<run> ...] [--output report.html|report.md] [--bins 100] [--skip 0]
+  <run> is a simulation_data.json file or a directory containing one";
+
+struct RunSummary {
+    label: String,
+    num_atoms: usize,
+    box_length: f64,
+    frames: usize,
+    time_span: f64,
+    potential_energy: (f64, f64),
+    rdf_peak: (f64, f64),
+    diffusion: f64,
+    rdf: Series,
+    msd: Series,
+}
+
+// Compares thermodynamic averages, g(r) and MSD of several runs in one HTML or Markdown report
+pub fn run(args: &[String]) {
+    let args = Args::parse(args);
+    if args.positional.is_empty() {
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
+    }
+    let output: String = args.get("output", "report.html".to_string());
+    let bins: usize = args.get("bins", 100);
+    let skip: usize = args.get("skip", 0);
+
+    let summaries: Vec<RunSummary> = args.positional.iter().map(|run| {
+        let path = resolve_run(run);
+        let data = trajectory::load_json(&path).unwrap_or_else(|e| {
+            eprintln!("Failed to read {}: {}", path.display(), e);
+            std::process::exit(1);
+        });
+        if data.trajectory.len() <= skip {
+            eprintln!("{} has {} frames, cannot skip {}", path.display(), data.trajectory.len(), skip);
+            std::process::exit(1);
+        }
+        if let Err(e) = data.cubic_box_length() {
+            eprintln!("{}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+        println!("Analyzing {} ({} frames)", run, data.trajectory.len() - skip);
+        summarize(run, &data, skip, bins)
+    }).collect();
+
+    let output_path = Path::new(&output);
+    let markdown = output_path.extension().is_some_and(|ext| ext == "md");
+    let report = if markdown {
+        markdown_report(&summaries, output_path)
+    } else {
+        html_report(&summaries)
+    };
+    fs::write(output_path, report).expect("Failed to write report");
+    println!("Report saved to {}", output);
+}
+
+fn resolve_run(run: &str) -> PathBuf {
+    let path = PathBuf::from(run);
+    if path.is_dir() {
+        path.join("simulation_data.json")
+    } else {
+        path
+    }
+}
+
+fn summarize(label: &str, data: &SimulationData, skip: usize, bins: usize) -> RunSummary {
+    let frames = &data.trajectory[skip..];
+    let l = data.box_length;
+    let n = data.num_atoms;
+    let frame_interval = data.frame_interval();
+
+    let energies: Vec<f64> = frames.iter()
+        .map(|positions| compute_forces(positions, l, f64::INFINITY).potential_energy / n as f64)
+        .collect();
+
+    let (r, g) = analysis::rdf(frames, l, bins, 0.5 * l);
+    let peak = r.iter().zip(g.iter())
+        .max_by(|a, b| a.1.partial_cmp(b.1).unwrap())
+        .map_or((0.0, 0.0), |(&r, &g)| (r, g));
+
+    let msd = analysis::msd(frames, l);
+    let times: Vec<f64> = (0..msd.len()).map(|lag| lag as f64 * frame_interval).collect();
+
+    RunSummary {
+        label: label.to_string(),
+        num_atoms: n,
+        box_length: l,
+        frames: frames.len(),
+        time_span: (frames.len() - 1) as f64 * frame_interval,
+        potential_energy: analysis::mean_std(&energies),
+        rdf_peak: peak,
+        diffusion: diffusion_coefficient(&times, &msd),
+        rdf: Series { label: label.to_string(), points: r.into_iter().zip(g).collect() },
+        msd: Series { label: label.to_string(), points: times.into_iter().zip(msd).collect() },
+    }
+}
+
+// Einstein relation D = slope / 6, fitted between 10% and 50% of the longest lag where the
+// ballistic regime is over and enough time origins remain
+fn diffusion_coefficient(times: &[f64], msd: &[f64]) -> f64 {
+    let start = times.len() / 10;
+    let end = (times.len() / 2).max(start + 2).min(times.len());
+    if end - start < 2 {
+        return f64::NAN;
+    }
+    analysis::linear_slope(&times[start..end], &msd[start..end]) / 6.0
+}
+
+fn summary_rows(summaries: &[RunSummary]) -> Vec<[String; 8]> {
+    summaries.iter().map(|s| [
+        s.label.clone(),
+        s.num_atoms.to_string(),
+        format!("{:.4}", s.num_atoms as f64 / s.box_length.powi(3)),
+        s.frames.to_string(),
+        format!("{:.4}", s.time_span),
+        format!("{:.4} ± {:.4}", s.potential_energy.0, s.potential_energy.1),
+        format!("{:.3} ({:.3})", s.rdf_peak.1, s.rdf_peak.0),
+        format!("{:.4e}", s.diffusion),
+    ]).collect()
+}
+
+const HEADERS: [&str; 8] = [
+    "Run", "Atoms", "Density", "Frames", "Time span (ps)", "Potential energy / atom", "g(r) peak (r)", "D (MSD)",
+];
+
+fn plots(summaries: &[RunSummary]) -> (String, String) {
+    let rdf: Vec<&Series> = summaries.iter().map(|s| &s.rdf).collect();
+    let msd: Vec<&Series> = summaries.iter().map(|s| &s.msd).collect();
+    let rdf_svg = line_plot_svg("Radial distribution function", "r", "g(r)", &rdf).expect("Failed to plot g(r)");
+    let msd_svg = line_plot_svg("Mean squared displacement", "time (ps)", "MSD", &msd).expect("Failed to plot MSD");
+    (rdf_svg, msd_svg)
+}
+
+fn html_report(summaries: &[RunSummary]) -> String {
+    let (rdf_svg, msd_svg) = plots(summaries);
+    let mut html = String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Simulation comparison</title>\n\
+<style>body { font-family: sans-serif; margin: 2em; } table { border-collapse: collapse; } \
+th, td { border: 1px solid #999; padding: 0.3em 0.6em; text-align: right; } td:first-child { text-align: left; }</style>\n\
+</head>\n<body>\n<h1>Simulation comparison</h1>\n<h2>Thermodynamic averages</h2>\n<table>\n<tr>");
+    for header in HEADERS {
+        write!(html, "<th>{}</th>", header).unwrap();
+    }
+    html.push_str("</tr>\n");
+    for row in summary_rows(summaries) {
+        html.push_str("<tr>");
+        for cell in row {
+            write!(html, "<td>{}</td>", escape_html(&cell)).unwrap();
+        }
+        html.push_str("</tr>\n");
+    }
+    write!(html, "</table>\n<h2>Radial distribution function</h2>\n{}\n<h2>Mean squared displacement</h2>\n{}\n</body>\n</html>\n", rdf_svg, msd_svg).unwrap();
+    html
+}
+
+// Markdown cannot inline SVG portably, so the plots are written next to the report
+fn markdown_report(summaries: &[RunSummary], output_path: &Path) -> String {
+    let (rdf_svg, msd_svg) = plots(summaries);
+    let stem = output_path.file_stem().and_then(|s| s.to_str()).unwrap_or("report");
+    let rdf_name = format!("{}_rdf.svg", stem);
+    let msd_name = format!("{}_msd.svg", stem);
+    let directory = output_path.parent().unwrap_or(Path::new(""));
+    fs::write(directory.join(&rdf_name), rdf_svg).expect("Failed to write g(r) plot");
+    fs::write(directory.join(&msd_name), msd_svg).expect("Failed to write MSD plot");
+
+    let mut markdown = String::from("# Simulation comparison\n\n## Thermodynamic averages\n\n");
+    writeln!(markdown, "| {} |", HEADERS.join(" | ")).unwrap();
+    writeln!(markdown, "|{}", "---|".repeat(HEADERS.len())).unwrap();
+    for row in summary_rows(summaries) {
+        writeln!(markdown, "| {} |", row.join(" | ")).unwrap();
+    }
+    write!(markdown, "\n## Radial distribution function\n\n![g(r)]({})\n\n## Mean squared displacement\n\n![MSD]({})\n", rdf_name, msd_name).unwrap();
+    markdown
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
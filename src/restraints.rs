@@ -0,0 +1,55 @@
+use serde::{Deserialize, Serialize};
+
+use crate::cli;
+
+// Harmonic tether E = k/2 |r - r0|^2 of one atom to a fixed reference position, for holding
+// a substrate in place without freezing it or restraining a solute during equilibration
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Restraint {
+    pub atom: usize,
+    pub spring: f64,
+    pub reference: [f64; 3],
+}
+
+// `--restrain 0-99:50,150:10`: atom indices or ranges, each with the spring constant of its
+// group (energy / length^2). Returns (atom, spring) pairs, or None when malformed.
+pub fn parse_groups(text: &str) -> Option<Vec<(usize, f64)>> {
+    let mut atoms = Vec::new();
+    for group in text.split(',') {
+        let (indices, spring) = group.split_once(':')?;
+        let spring: f64 = spring.trim().parse().ok().filter(|&k: &f64| k > 0.0 && k.is_finite())?;
+        atoms.extend(cli::parse_indices(indices)?.into_iter().map(|atom| (atom, spring)));
+    }
+    Some(atoms)
+}
+
+// Adds the restraint forces to `forces`, and their energies to `atom_energies` unless that is
+// empty, and returns the total restraint energy. Displacements along the `periodic` axes are
+// taken to the nearest image of the reference.
+pub fn add_restraint_forces(
+    restraints: &[Restraint],
+    positions: &[[f64; 3]],
+    box_lengths: [f64; 3],
+    periodic: [bool; 3],
+    forces: &mut [[f64; 3]],
+    atom_energies: &mut [f64],
+) -> f64 {
+    let mut total = 0.0;
+    for restraint in restraints {
+        let i = restraint.atom;
+        let mut energy = 0.0;
+        for k in 0..3 {
+            let mut delta = positions[i][k] - restraint.reference[k];
+            if periodic[k] {
+                delta -= (delta / box_lengths[k]).round() * box_lengths[k];
+            }
+            forces[i][k] -= restraint.spring * delta;
+            energy += 0.5 * restraint.spring * delta * delta;
+        }
+        if let Some(e) = atom_energies.get_mut(i) {
+            *e += energy;
+        }
+        total += energy;
+    }
+    total
+}
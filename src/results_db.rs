@@ -0,0 +1,82 @@
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+#[cfg(feature = "sqlite")]
+use std::time::UNIX_EPOCH;
+
+#[cfg(feature = "sqlite")]
+use rusqlite::{params, Connection};
+
+// One finished run: what was asked for and what came out
+#[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+pub struct RunRecord {
+    pub command: String,
+    pub started_at: SystemTime,
+    pub wall_seconds: f64,
+    pub parameters: Vec<(String, String)>,
+    pub observables: Vec<(String, f64)>,
+}
+
+// Lets callers refuse `--results-db` up front instead of failing after a long run
+pub fn available() -> bool {
+    cfg!(feature = "sqlite")
+}
+
+#[cfg(feature = "sqlite")]
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    command TEXT NOT NULL,
+    started_at REAL NOT NULL,
+    wall_seconds REAL NOT NULL
+);
+CREATE TABLE IF NOT EXISTS parameters (
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    name TEXT NOT NULL,
+    value TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS observables (
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    name TEXT NOT NULL,
+    value REAL NOT NULL
+);
+";
+
+// Appends a run to a SQLite results database (one file per project), creating the tables on
+// first use. Parameters and observables are stored as name/value rows so that sweeps can be
+// queried with joins, e.g. temperature against timestep across all runs.
+#[cfg(feature = "sqlite")]
+pub fn record_run<P: AsRef<Path>>(path: P, run: &RunRecord) -> io::Result<i64> {
+    let mut connection = Connection::open(path).map_err(io::Error::other)?;
+    connection.execute_batch(SCHEMA).map_err(io::Error::other)?;
+
+    let transaction = connection.transaction().map_err(io::Error::other)?;
+    let started_at = run.started_at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+    transaction.execute(
+        "INSERT INTO runs (command, started_at, wall_seconds) VALUES (?1, ?2, ?3)",
+        params![run.command, started_at, run.wall_seconds],
+    ).map_err(io::Error::other)?;
+    let run_id = transaction.last_insert_rowid();
+    for (name, value) in &run.parameters {
+        transaction.execute(
+            "INSERT INTO parameters (run_id, name, value) VALUES (?1, ?2, ?3)",
+            params![run_id, name, value],
+        ).map_err(io::Error::other)?;
+    }
+    for (name, value) in &run.observables {
+        transaction.execute(
+            "INSERT INTO observables (run_id, name, value) VALUES (?1, ?2, ?3)",
+            params![run_id, name, value],
+        ).map_err(io::Error::other)?;
+    }
+    transaction.commit().map_err(io::Error::other)?;
+    Ok(run_id)
+}
+
+#[cfg(not(feature = "sqlite"))]
+pub fn record_run<P: AsRef<Path>>(_path: P, _run: &RunRecord) -> io::Result<i64> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "built without SQLite support (rebuild with --features sqlite)",
+    ))
+}
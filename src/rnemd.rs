@@ -0,0 +1,202 @@
+use std::fs;
+
+use crate::analysis;
+use crate::cli::Args;
+use crate::glass::progress_bar;
+use crate::profile::{self, Profile};
+use crate::simulation::{initial_state, BerendsenThermostat, Simulation};
+use crate::table::TableWriter;
+
+const USAGE: &str = "Usage: sim rnemd [--atoms 1000] [--density 0.8] [--temperature 120] [--timestep 0.005] [--cutoff 2.5] \
+[--equilibration 5000] [--steps 50000] [--transient steps/5] [--slabs 20] [--axis z] [--swap-every 50] [--swaps 1] \
+[--profile-every 10] [--blocks 5] [--log-interval 1000] [--seed S] [--output-dir rnemd]";
+
+// Müller-Plathe reverse non-equilibrium MD (J. Chem. Phys. 106, 6082). The box is cut into
+// slabs along one axis; every `--swap-every` steps the velocities of the hottest atoms of the
+// cold slab (the first) and the coldest of the hot slab (the middle one) are exchanged. The
+// swaps carry a known heat flux from the cold to the hot slab, which flows back through the
+// liquid in both directions, and the temperature gradient it sets up gives the conductivity
+// k = -J / (dT/dz). Energy and momentum are conserved, so the run is NVE after equilibration.
+pub fn run(args: &[String]) {
+    let args = Args::parse(args);
+    if !args.positional.is_empty() {
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
+    }
+    let n: usize = args.get("atoms", 1000);
+    let density: f64 = args.get("density", 0.8);
+    let temperature: f64 = args.get("temperature", 120.0);
+    let dt: f64 = args.get("timestep", 0.005);
+    let cutoff: f64 = args.get("cutoff", 2.5);
+    let equilibration: usize = args.get("equilibration", 5000);
+    let steps: usize = args.get("steps", 50000);
+    let transient: usize = args.get("transient", steps / 5);
+    let slabs: usize = args.get("slabs", 20);
+    let axis: String = args.get("axis", "z".to_string());
+    let swap_every: usize = args.get("swap-every", 50);
+    let swaps: usize = args.get("swaps", 1);
+    let profile_every: usize = args.get("profile-every", 10);
+    let blocks: usize = args.get("blocks", 5);
+    let log_interval: usize = args.get("log-interval", 1000);
+    let seed: u64 = args.get("seed", rand::random());
+    let output_dir: String = args.get("output-dir", "rnemd".to_string());
+
+    let Some(axis) = profile::parse_axis(&axis) else {
+        eprintln!("--axis must be one of {}", profile::AXES.join(", "));
+        std::process::exit(1);
+    };
+    // Each half between the exchange slabs needs slabs left over for the fit once the two next
+    // to either exchange slab are dropped
+    if slabs < 12 || !slabs.is_multiple_of(2) {
+        eprintln!("--slabs must be even and at least 12");
+        std::process::exit(1);
+    }
+    if swap_every == 0 || swaps == 0 || profile_every == 0 || blocks == 0 || log_interval == 0 {
+        eprintln!("--swap-every, --swaps, --profile-every, --blocks and --log-interval must be at least 1");
+        std::process::exit(1);
+    }
+    if transient >= steps || (steps - transient) / profile_every < blocks {
+        eprintln!("--steps must leave at least one profile sample per block after the --transient");
+        std::process::exit(1);
+    }
+
+    let mut sim = initial_state(n, density, temperature, dt, cutoff, seed);
+    let l = sim.box_length();
+    let area = l * l;
+    fs::create_dir_all(&output_dir).expect("Failed to create output directory");
+    println!(
+        "{} atoms at density {} in a box of {:.3}, {} slabs along {}, swapping {} pair(s) every {} steps, seed {}",
+        n, density, l, slabs, profile::AXES[axis], swaps, swap_every, seed
+    );
+
+    sim.thermostat = Some(BerendsenThermostat { target_temperature: temperature, tau: 0.1 });
+    let pb = progress_bar(equilibration);
+    pb.set_message("equilibrating");
+    for _ in 0..equilibration {
+        sim.step();
+        pb.inc(1);
+    }
+    pb.finish();
+    sim.thermostat = None;
+
+    let log_path = format!("{}/rnemd.csv", output_dir);
+    let mut log = TableWriter::create(&log_path, &["time", "swaps", "exchanged_energy", "temperature", "total_energy"]).unwrap_or_else(|e| {
+        eprintln!("Failed to create {}: {}", log_path, e);
+        std::process::exit(1);
+    });
+    let block_steps = (steps - transient).div_ceil(blocks);
+    let mut profile = Profile::new(axis, slabs);
+    let mut block_profiles = vec![Profile::new(axis, slabs); blocks];
+    // Energy carried from the cold to the hot slab, in total and per block
+    let mut exchanged = 0.0;
+    let mut block_exchanged = vec![0.0; blocks];
+    let mut swapped = 0;
+    let pb = progress_bar(steps);
+    for step in 1..=steps {
+        sim.step();
+        pb.inc(1);
+        let measuring = step > transient;
+        let block = (step - 1).saturating_sub(transient) / block_steps;
+
+        if step.is_multiple_of(swap_every) {
+            let (count, energy) = swap_velocities(&mut sim, axis, slabs, swaps);
+            swapped += count;
+            exchanged += energy;
+            if measuring {
+                block_exchanged[block] += energy;
+            }
+        }
+        if measuring && step.is_multiple_of(profile_every) {
+            profile.sample(sim.positions(), sim.velocities(), sim.box_lengths(), sim.mass, None);
+            block_profiles[block].sample(sim.positions(), sim.velocities(), sim.box_lengths(), sim.mass, None);
+        }
+        if step.is_multiple_of(log_interval) {
+            let total_energy = sim.kinetic_energy() + sim.potential_energy();
+            log.write_row(&[step as f64 * dt, swapped as f64, exchanged, sim.temperature(), total_energy])
+                .expect("Failed to write RNEMD log");
+            pb.set_message(format!("T = {:.2} K, {} swaps", sim.temperature(), swapped));
+        }
+    }
+    pb.finish();
+    log.finish().expect("Failed to write RNEMD log");
+    let profile_path = format!("{}/profile.csv", output_dir);
+    profile.write(&profile_path).expect("Failed to write profile");
+
+    // The heat flows from the hot slab to the cold one through both halves of the box
+    let flux = |energy: f64, steps: usize| energy / (2.0 * area * steps as f64 * dt);
+    let measured_steps = steps - transient;
+    let (gradient, rising, falling) = temperature_gradient(&profile);
+    let conductivity = flux(exchanged_in(&block_exchanged), measured_steps) / gradient;
+    let per_block: Vec<f64> = block_profiles.iter().zip(&block_exchanged).enumerate().map(|(b, (p, &energy))| {
+        let length = block_steps.min(measured_steps - b * block_steps);
+        flux(energy, length) / temperature_gradient(p).0
+    }).collect();
+    let error = if blocks > 1 { analysis::mean_std(&per_block).1 / (blocks as f64 - 1.0).sqrt() } else { f64::NAN };
+
+    println!(
+        "{} swaps carried {:.4e} energy units over {:.3} time units; heat flux {:.4e} energy/(length^2 time)",
+        swapped,
+        exchanged_in(&block_exchanged),
+        measured_steps as f64 * dt,
+        flux(exchanged_in(&block_exchanged), measured_steps)
+    );
+    println!("Temperature gradient {:.4} K/length (rising half {:.4}, falling half {:.4})", gradient, rising, -falling);
+    println!(
+        "Thermal conductivity {:.6e} +- {:.2e} energy/(length time K), reduced {:.4} +- {:.4}",
+        conductivity,
+        error,
+        analysis::reduced_conductivity(conductivity),
+        analysis::reduced_conductivity(error)
+    );
+    println!("Temperature profile saved to {}, swap log to {}", profile_path, log_path);
+}
+
+fn exchanged_in(block_exchanged: &[f64]) -> f64 {
+    block_exchanged.iter().sum()
+}
+
+// Exchanges the velocities of the `swaps` fastest atoms of the cold slab with those of the
+// slowest atoms of the hot slab, pair by pair while the cold one is the more energetic.
+// Returns the number of swaps and the kinetic energy moved into the hot slab.
+fn swap_velocities(sim: &mut Simulation, axis: usize, slabs: usize, swaps: usize) -> (usize, f64) {
+    let l = sim.box_length();
+    let mass = sim.mass;
+    let slab_of = |pos: &[f64; 3]| (((pos[axis] / l).rem_euclid(1.0) * slabs as f64) as usize).min(slabs - 1);
+    let speed_sq = |v: &[f64; 3]| v[0] * v[0] + v[1] * v[1] + v[2] * v[2];
+    let mut cold = Vec::new();
+    let mut hot = Vec::new();
+    for (i, (pos, vel)) in sim.positions().iter().zip(sim.velocities()).enumerate() {
+        match slab_of(pos) {
+            0 => cold.push((speed_sq(vel), i)),
+            s if s == slabs / 2 => hot.push((speed_sq(vel), i)),
+            _ => {}
+        }
+    }
+    cold.sort_by(|a, b| b.0.total_cmp(&a.0));
+    hot.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let velocities = sim.velocities_mut();
+    let mut count = 0;
+    let mut energy = 0.0;
+    for (&(cold_sq, i), &(hot_sq, j)) in cold.iter().zip(&hot).take(swaps) {
+        if cold_sq <= hot_sq {
+            break;
+        }
+        velocities.swap(i, j);
+        energy += 0.5 * mass * (cold_sq - hot_sq);
+        count += 1;
+    }
+    (count, energy)
+}
+
+// Magnitude of the temperature gradient, the mean of linear fits over the two halves between
+// the exchange slabs, leaving out the two slabs next to each exchange slab where the profile
+// bends. Also returns the slopes of the rising and falling halves.
+fn temperature_gradient(profile: &Profile) -> (f64, f64, f64) {
+    let (centres, _, temperatures, _) = profile.results();
+    let half = profile.bins / 2;
+    let fit = |range: std::ops::Range<usize>| analysis::linear_slope(&centres[range.clone()], &temperatures[range]);
+    let rising = fit(3..half - 2);
+    let falling = fit(half + 3..profile.bins - 2);
+    (0.5 * (rising - falling), rising, falling)
+}
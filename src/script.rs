@@ -0,0 +1,157 @@
+// Rhai hooks for the main run, behind the `scripting` feature. The script defines any of three
+// functions, each taking a map of the current state (step, time, temperature, kinetic_energy,
+// potential_energy, total_energy, pressure, volume, box, num_atoms, target_temperature, tau,
+// body_force, positions and velocities):
+//
+//   fn observe(s)    -> map of custom observables by name, logged as table columns
+//   fn stop(s)       -> true to end the run at this step
+//   fn parameters(s) -> map with any of target_temperature, tau and body_force to change them
+//
+// They are called every `--script-every` steps. Top-level statements run before every call, so
+// constants can be defined there.
+#[cfg(feature = "scripting")]
+use rhai::{Array, Dynamic, Engine, Map, Scope, AST};
+
+// State of the run a script sees
+pub struct ScriptState<'a> {
+    pub step: usize,
+    pub time: f64,
+    pub temperature: f64,
+    pub kinetic_energy: f64,
+    pub potential_energy: f64,
+    pub pressure: f64,
+    pub box_lengths: [f64; 3],
+    pub target_temperature: f64,
+    pub tau: f64,
+    pub body_force: [f64; 3],
+    pub positions: &'a [[f64; 3]],
+    pub velocities: &'a [[f64; 3]],
+}
+
+// What the hooks asked for at one step; parameters not set stay as they are
+#[derive(Default)]
+pub struct Outcome {
+    pub observables: Vec<(String, f64)>,
+    pub stop: bool,
+    pub target_temperature: Option<f64>,
+    pub tau: Option<f64>,
+    pub body_force: Option<[f64; 3]>,
+}
+
+#[cfg(feature = "scripting")]
+pub struct Hooks {
+    engine: Engine,
+    ast: AST,
+    observe: bool,
+    stop: bool,
+    parameters: bool,
+}
+
+#[cfg(not(feature = "scripting"))]
+pub struct Hooks;
+
+pub fn available() -> bool {
+    cfg!(feature = "scripting")
+}
+
+#[cfg(feature = "scripting")]
+impl Hooks {
+    // Compiles the script at `path`, which must define at least one of the hooks
+    pub fn load(path: &str) -> Result<Self, String> {
+        let engine = Engine::new();
+        let ast = engine.compile_file(path.into()).map_err(|e| e.to_string())?;
+        let defines = |name: &str| ast.iter_functions().any(|f| f.name == name && f.params.len() == 1);
+        let (observe, stop, parameters) = (defines("observe"), defines("stop"), defines("parameters"));
+        if !(observe || stop || parameters) {
+            return Err("defines none of observe(s), stop(s) and parameters(s)".to_string());
+        }
+        Ok(Hooks { engine, ast, observe, stop, parameters })
+    }
+
+    pub fn evaluate(&self, state: &ScriptState) -> Result<Outcome, String> {
+        let s = state_map(state);
+        let call = |name: &str| {
+            self.engine.call_fn::<Dynamic>(&mut Scope::new(), &self.ast, name, (s.clone(),)).map_err(|e| e.to_string())
+        };
+        let mut outcome = Outcome::default();
+        if self.observe {
+            let values = call("observe")?.try_cast::<Map>().ok_or("observe must return a map")?;
+            for (name, value) in values {
+                let value = number(&value).ok_or_else(|| format!("observable {} is not a number", name))?;
+                outcome.observables.push((name.to_string(), value));
+            }
+        }
+        if self.stop {
+            outcome.stop = call("stop")?.as_bool().map_err(|_| "stop must return true or false")?;
+        }
+        if self.parameters {
+            let values = call("parameters")?.try_cast::<Map>().ok_or("parameters must return a map")?;
+            for (name, value) in values {
+                match name.as_str() {
+                    "target_temperature" | "tau" => {
+                        let value = number(&value).filter(|&v| v > 0.0)
+                            .ok_or_else(|| format!("{} must be a positive number", name))?;
+                        if name == "tau" {
+                            outcome.tau = Some(value);
+                        } else {
+                            outcome.target_temperature = Some(value);
+                        }
+                    }
+                    "body_force" => outcome.body_force = Some(vector(&value).ok_or("body_force must be an array of 3 numbers")?),
+                    other => return Err(format!("unknown parameter {}", other)),
+                }
+            }
+        }
+        Ok(outcome)
+    }
+}
+
+#[cfg(not(feature = "scripting"))]
+impl Hooks {
+    pub fn load(_path: &str) -> Result<Self, String> {
+        Err("built without scripting support (rebuild with --features scripting)".to_string())
+    }
+
+    pub fn evaluate(&self, _state: &ScriptState) -> Result<Outcome, String> {
+        unreachable!("Hooks cannot be loaded without the scripting feature")
+    }
+}
+
+#[cfg(feature = "scripting")]
+fn state_map(state: &ScriptState) -> Map {
+    let array = |v: &[f64; 3]| -> Dynamic { v.iter().map(|&x| Dynamic::from(x)).collect::<Array>().into() };
+    let rows = |rows: &[[f64; 3]]| -> Dynamic { rows.iter().map(array).collect::<Array>().into() };
+    let [lx, ly, lz] = state.box_lengths;
+    let mut map = Map::new();
+    map.insert("step".into(), (state.step as i64).into());
+    map.insert("time".into(), state.time.into());
+    map.insert("temperature".into(), state.temperature.into());
+    map.insert("kinetic_energy".into(), state.kinetic_energy.into());
+    map.insert("potential_energy".into(), state.potential_energy.into());
+    map.insert("total_energy".into(), (state.kinetic_energy + state.potential_energy).into());
+    map.insert("pressure".into(), state.pressure.into());
+    map.insert("volume".into(), (lx * ly * lz).into());
+    map.insert("box".into(), array(&state.box_lengths));
+    map.insert("num_atoms".into(), (state.positions.len() as i64).into());
+    map.insert("target_temperature".into(), state.target_temperature.into());
+    map.insert("tau".into(), state.tau.into());
+    map.insert("body_force".into(), array(&state.body_force));
+    map.insert("positions".into(), rows(state.positions));
+    map.insert("velocities".into(), rows(state.velocities));
+    map
+}
+
+// Integers are accepted where a real number is expected
+#[cfg(feature = "scripting")]
+fn number(value: &Dynamic) -> Option<f64> {
+    value.as_float().ok().or_else(|| value.as_int().ok().map(|i| i as f64))
+}
+
+#[cfg(feature = "scripting")]
+fn vector(value: &Dynamic) -> Option<[f64; 3]> {
+    let array = value.clone().try_cast::<Array>()?;
+    match array.as_slice() {
+        [x, y, z] => Some([number(x)?, number(y)?, number(z)?]),
+        _ => None,
+    }
+}
@@ -0,0 +1,386 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::cli::Args;
+
+const USAGE: &str = "Usage: sim serve [--listen 127.0.0.1:8080] [--jobs-dir jobs]";
+
+// How often a followed log is checked for new rows
+const POLL: Duration = Duration::from_millis(200);
+
+// A main run submitted over HTTP
+#[derive(Deserialize)]
+struct Submission {
+    // Arguments of the run, as they follow `sim` on the command line
+    args: Vec<String>,
+    // Input files by name (configurations, colvars, scripts), written to the job directory
+    // before the run starts
+    #[serde(default)]
+    files: BTreeMap<String, String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum JobState {
+    Running,
+    // Asked to stop at the next step with a checkpoint
+    Pausing,
+    Paused,
+    Completed,
+    Failed,
+}
+
+// One main run in its own directory, running as a child `sim` process while it is not paused
+struct Job {
+    id: usize,
+    dir: PathBuf,
+    args: Vec<String>,
+    state: JobState,
+    child: Option<Child>,
+}
+
+impl Job {
+    // Catches up with the process having exited
+    fn refresh(&mut self) {
+        let Some(child) = &mut self.child else { return };
+        let Ok(Some(status)) = child.try_wait() else { return };
+        self.state = match status.code() {
+            Some(0) => JobState::Completed,
+            // Interrupted or out of time, with a checkpoint to resume from
+            Some(130) | Some(75) => JobState::Paused,
+            _ => JobState::Failed,
+        };
+        self.child = None;
+    }
+
+    fn is_active(&self) -> bool {
+        matches!(self.state, JobState::Running | JobState::Pausing)
+    }
+
+    // The value of `--name` in the arguments of the run, or `default`
+    fn option(&self, name: &str, default: &str) -> String {
+        let flag = format!("--{}", name);
+        self.args.iter().position(|arg| *arg == flag)
+            .and_then(|i| self.args.get(i + 1))
+            .map_or_else(|| default.to_string(), |value| value.clone())
+    }
+
+    fn summary(&mut self) -> Value {
+        self.refresh();
+        json!({ "id": self.id, "state": self.state, "args": self.args })
+    }
+}
+
+type Jobs = Arc<Mutex<Vec<Job>>>;
+
+// What a request gets back
+enum Reply {
+    Json(u16, Value),
+    File(File),
+    // A log sent as it grows, until its job stops
+    Follow(Follow),
+}
+
+fn error(status: u16, message: impl std::fmt::Display) -> Reply {
+    Reply::Json(status, json!({ "error": message.to_string() }))
+}
+
+// `sim serve`: an HTTP control server for main runs. Every submitted run is a child `sim`
+// process in its own directory under `--jobs-dir`; pausing interrupts it with a checkpoint and
+// resuming continues it with `sim resume`. Endpoints, all JSON apart from the logs and files:
+//
+//   POST /jobs                      submit {"args": [...], "files": {"name": "contents"}}
+//   GET  /jobs                      list the jobs and their states
+//   GET  /jobs/<id>                 state and the latest status file of the run
+//   GET  /jobs/<id>/thermo          thermo log, followed until the run stops
+//   POST /jobs/<id>/pause           stop at the next step with a checkpoint
+//   POST /jobs/<id>/resume          continue a paused run
+//   GET  /jobs/<id>/files           names and sizes of the files of the run
+//   GET  /jobs/<id>/files/<name>    download one of them
+pub fn run(args: &[String]) {
+    let args = Args::parse(args);
+    if !args.positional.is_empty() {
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
+    }
+    let listen: String = args.get("listen", "127.0.0.1:8080".to_string());
+    let jobs_dir = PathBuf::from(args.get("jobs-dir", "jobs".to_string()));
+    fs::create_dir_all(&jobs_dir).unwrap_or_else(|e| {
+        eprintln!("Failed to create {}: {}", jobs_dir.display(), e);
+        std::process::exit(1);
+    });
+    let server = Server::http(&listen).unwrap_or_else(|e| {
+        eprintln!("Failed to listen on {}: {}", listen, e);
+        std::process::exit(1);
+    });
+    let exe = Arc::new(std::env::current_exe().expect("Failed to locate the sim executable"));
+    let jobs: Jobs = Arc::default();
+    // Numbering carries on after the jobs of earlier sessions, whose directories are kept
+    let first_id = fs::read_dir(&jobs_dir).map(|entries| {
+        entries.filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<usize>().ok()).max().map_or(0, |id| id + 1)
+    }).unwrap_or(0);
+    let next_id = Arc::new(Mutex::new(first_id));
+    println!("Serving on http://{} with jobs in {}", listen, jobs_dir.display());
+
+    for request in server.incoming_requests() {
+        let (jobs, jobs_dir, exe, next_id) = (jobs.clone(), jobs_dir.clone(), exe.clone(), next_id.clone());
+        // Followed logs keep their request open, so every request gets a thread
+        thread::spawn(move || {
+            let mut request = request;
+            let reply = handle(&mut request, &jobs, &jobs_dir, &exe, &next_id);
+            if let Err(e) = respond(request, reply) {
+                eprintln!("Failed to send a response: {}", e);
+            }
+        });
+    }
+}
+
+fn handle(request: &mut Request, jobs: &Jobs, jobs_dir: &Path, exe: &Path, next_id: &Mutex<usize>) -> Reply {
+    let path = request.url().split('?').next().unwrap_or("").to_string();
+    let parts: Vec<&str> = path.split('/').filter(|part| !part.is_empty()).collect();
+    let method = request.method().clone();
+    if let ["jobs", id, ..] = parts.as_slice() {
+        let known = id.parse::<usize>().ok().filter(|&id| jobs.lock().unwrap().iter().any(|job| job.id == id));
+        let Some(id) = known else {
+            return error(404, format!("no job {}", id));
+        };
+        return match (method, &parts[2..]) {
+            (Method::Get, []) => status(jobs, id),
+            (Method::Get, ["thermo"]) => follow_thermo(jobs, id),
+            (Method::Post, ["pause"]) => pause(jobs, id),
+            (Method::Post, ["resume"]) => resume(jobs, id, exe),
+            (Method::Get, ["files"]) => list_files(jobs, id),
+            (Method::Get, ["files", name]) => download(jobs, id, name),
+            _ => error(404, format!("no endpoint {}", path)),
+        };
+    }
+    match (method, parts.as_slice()) {
+        (Method::Get, ["jobs"]) => {
+            let mut jobs = jobs.lock().unwrap();
+            Reply::Json(200, Value::Array(jobs.iter_mut().map(Job::summary).collect()))
+        }
+        (Method::Post, ["jobs"]) => {
+            let mut body = String::new();
+            if let Err(e) = request.as_reader().read_to_string(&mut body) {
+                return error(400, e);
+            }
+            match serde_json::from_str::<Submission>(&body) {
+                Ok(submission) => submit(submission, jobs, jobs_dir, exe, next_id),
+                Err(e) => error(400, format!("invalid submission: {}", e)),
+            }
+        }
+        _ => error(404, format!("no endpoint {}", path)),
+    }
+}
+
+// A path given to a run that stays inside its directory
+fn is_contained(path: &str) -> bool {
+    let path = Path::new(path);
+    !path.has_root() && path.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+fn submit(submission: Submission, jobs: &Jobs, jobs_dir: &Path, exe: &Path, next_id: &Mutex<usize>) -> Reply {
+    let Submission { mut args, files } = submission;
+    // Only main runs, whose first argument is the box, and nothing outside the job directory
+    if !args.first().is_some_and(|first| first.starts_with(|c: char| c.is_ascii_digit() || c == '.')) {
+        return error(400, "args must be those of a main run, starting with the box length");
+    }
+    if let Some(arg) = args.iter().find(|arg| !arg.contains("://") && !is_contained(arg)) {
+        return error(400, format!("{} leaves the job directory", arg));
+    }
+    if let Some(name) = files.keys().find(|name| Path::new(name).file_name().and_then(|n| n.to_str()) != Some(name.as_str())) {
+        return error(400, format!("{} is not a plain file name", name));
+    }
+    if !args.iter().any(|arg| arg == "--status-file") {
+        args.extend(["--status-file".to_string(), "status.json".to_string()]);
+    }
+
+    let id = {
+        let mut next_id = next_id.lock().unwrap();
+        *next_id += 1;
+        *next_id - 1
+    };
+    let dir = jobs_dir.join(id.to_string());
+    let started = fs::create_dir_all(&dir)
+        .and_then(|_| files.iter().try_for_each(|(name, contents)| fs::write(dir.join(name), contents)))
+        .and_then(|_| spawn(exe, &dir, &args));
+    let mut job = Job { id, dir, args, state: JobState::Running, child: None };
+    match started {
+        Ok(child) => job.child = Some(child),
+        Err(e) => {
+            job.state = JobState::Failed;
+            eprintln!("Failed to start job {}: {}", id, e);
+        }
+    }
+    let summary = job.summary();
+    jobs.lock().unwrap().push(job);
+    Reply::Json(201, summary)
+}
+
+// Starts `sim` with `args` in `dir`, its output appended to log.txt there
+fn spawn(exe: &Path, dir: &Path, args: &[String]) -> io::Result<Child> {
+    let log = OpenOptions::new().create(true).append(true).open(dir.join("log.txt"))?;
+    Command::new(exe)
+        .args(args)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::from(log.try_clone()?))
+        .stderr(Stdio::from(log))
+        .spawn()
+}
+
+// Runs `f` on job `id`, which `handle` has checked exists
+fn with_job<T>(jobs: &Jobs, id: usize, f: impl FnOnce(&mut Job) -> T) -> T {
+    let mut jobs = jobs.lock().unwrap();
+    let job = jobs.iter_mut().find(|job| job.id == id).unwrap();
+    job.refresh();
+    f(job)
+}
+
+fn status(jobs: &Jobs, id: usize) -> Reply {
+    with_job(jobs, id, |job| {
+        let mut summary = job.summary();
+        // Written by the run itself; missing until its first update
+        let status_path = job.dir.join(job.option("status-file", "status.json"));
+        summary["status"] = fs::read(status_path).ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or(Value::Null);
+        Reply::Json(200, summary)
+    })
+}
+
+fn pause(jobs: &Jobs, id: usize) -> Reply {
+    with_job(jobs, id, |job| {
+        let Some(child) = job.child.as_ref().filter(|_| job.state == JobState::Running) else {
+            return error(409, "the job is not running");
+        };
+        // The run's own SIGINT handler finishes the step and writes a checkpoint
+        if unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGINT) } != 0 {
+            return error(500, io::Error::last_os_error());
+        }
+        job.state = JobState::Pausing;
+        Reply::Json(202, job.summary())
+    })
+}
+
+fn resume(jobs: &Jobs, id: usize, exe: &Path) -> Reply {
+    with_job(jobs, id, |job| {
+        if job.state != JobState::Paused {
+            return error(409, "the job is not paused");
+        }
+        let checkpoint = job.option("checkpoint", "checkpoint.bin");
+        match spawn(exe, &job.dir, &["resume".to_string(), checkpoint]) {
+            Ok(child) => {
+                job.child = Some(child);
+                job.state = JobState::Running;
+                Reply::Json(202, job.summary())
+            }
+            Err(e) => error(500, format!("failed to resume: {}", e)),
+        }
+    })
+}
+
+fn list_files(jobs: &Jobs, id: usize) -> Reply {
+    let dir = with_job(jobs, id, |job| job.dir.clone());
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) => return error(500, e),
+    };
+    let mut files: Vec<(String, u64)> = entries.filter_map(|entry| {
+        let entry = entry.ok()?;
+        let metadata = entry.metadata().ok().filter(|metadata| metadata.is_file())?;
+        Some((entry.file_name().into_string().ok()?, metadata.len()))
+    }).collect();
+    files.sort();
+    Reply::Json(200, Value::Array(files.into_iter().map(|(name, size)| json!({ "name": name, "size": size })).collect()))
+}
+
+fn download(jobs: &Jobs, id: usize, name: &str) -> Reply {
+    let dir = with_job(jobs, id, |job| job.dir.clone());
+    if Path::new(name).file_name().and_then(|n| n.to_str()) != Some(name) {
+        return error(400, format!("{} is not a plain file name", name));
+    }
+    match File::open(dir.join(name)) {
+        Ok(file) => Reply::File(file),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => error(404, format!("no file {}", name)),
+        Err(e) => error(500, e),
+    }
+}
+
+fn follow_thermo(jobs: &Jobs, id: usize) -> Reply {
+    let path = with_job(jobs, id, |job| job.dir.join(job.option("thermo", "thermo.csv")));
+    Reply::Follow(Follow { path, file: None, jobs: jobs.clone(), id })
+}
+
+// Reads a log as its job writes it, ending once the job has stopped and everything written is read
+struct Follow {
+    path: PathBuf,
+    // Opened once the run has created it
+    file: Option<File>,
+    jobs: Jobs,
+    id: usize,
+}
+
+impl Read for Follow {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            // Checked before reading, so the rows written just before the job stopped are read
+            let active = with_job(&self.jobs, self.id, |job| job.is_active());
+            if self.file.is_none() {
+                self.file = File::open(&self.path).ok();
+            }
+            if let Some(file) = &mut self.file {
+                let read = file.read(buf)?;
+                if read > 0 {
+                    return Ok(read);
+                }
+            }
+            if !active {
+                return Ok(0);
+            }
+            thread::sleep(POLL);
+        }
+    }
+}
+
+fn respond(request: Request, reply: Reply) -> io::Result<()> {
+    let header = |name: &str, value: &str| Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap();
+    match reply {
+        Reply::Json(status, value) => request.respond(
+            Response::from_string(value.to_string())
+                .with_status_code(status)
+                .with_header(header("Content-Type", "application/json")),
+        ),
+        Reply::File(file) => request.respond(
+            Response::from_file(file).with_header(header("Content-Type", "application/octet-stream")),
+        ),
+        // Sent chunk by chunk as the rows arrive, which the buffered responses of tiny_http
+        // would hold back
+        Reply::Follow(mut follow) => {
+            let mut writer = request.into_writer();
+            writer.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/csv\r\nTransfer-Encoding: chunked\r\n\r\n")?;
+            writer.flush()?;
+            let mut buffer = vec![0; 64 * 1024];
+            loop {
+                let read = follow.read(&mut buffer)?;
+                write!(writer, "{:x}\r\n", read)?;
+                writer.write_all(&buffer[..read])?;
+                writer.write_all(b"\r\n")?;
+                writer.flush()?;
+                if read == 0 {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
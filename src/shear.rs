@@ -0,0 +1,164 @@
+use std::fs;
+use std::path::Path;
+
+use crate::analysis::block_average;
+use crate::cli::{self, Args};
+use crate::glass::progress_bar;
+use crate::simulation::{initial_state, BerendsenThermostat, LangevinThermostat, Simulation};
+use crate::table::TableWriter;
+
+const USAGE: &str = "Usage: sim shear <rate_min> <rate_max> [--count 8] [--num-atoms 500] [--density 0.8442] \
+[--temperature 87.3] [--timestep 0.01] [--cutoff 2.5 | --cutoff-accuracy 1e-2] [--equilibration 5000] [--transient 5000] \
+[--steps 20000] [--blocks 10] [--thermostat berendsen|langevin] [--friction 1.0] [--seed S] [--log-interval 100] \
+[--output-dir shear]";
+
+// Flow curve by non-equilibrium MD: one equilibrated liquid is sheared at each of `count` rates
+// spaced logarithmically from `rate_min` to `rate_max` (per time unit) with SLLOD dynamics and
+// Lees-Edwards boundaries. After a transient, the shear stress is averaged and the viscosity
+// eta = -<P_xy> / rate gets its error bar from block averages.
+pub fn run(args: &[String]) {
+    let args = Args::parse(args);
+    if args.positional.len() != 2 {
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
+    }
+    let rate_min: f64 = args.positional[0].parse().expect("Invalid minimum shear rate");
+    let rate_max: f64 = args.positional[1].parse().expect("Invalid maximum shear rate");
+    let count: usize = args.get("count", 8);
+    let n: usize = args.get("num-atoms", 500);
+    let density: f64 = args.get("density", 0.8442);
+    let temperature: f64 = args.get("temperature", 87.3);
+    let dt: f64 = args.get("timestep", 0.01);
+    let cutoff = cli::cutoff_from_args(&args, 2.5, n, (n as f64 / density).cbrt());
+    let equilibration: usize = args.get("equilibration", 5000);
+    let transient: usize = args.get("transient", 5000);
+    let steps: usize = args.get("steps", 20000);
+    let blocks: usize = args.get("blocks", 10);
+    let thermostat: String = args.get("thermostat", "berendsen".to_string());
+    let friction: f64 = args.get("friction", 1.0);
+    let seed: u64 = args.get("seed", rand::random());
+    let log_interval: usize = args.get("log-interval", 100);
+    let output_dir: String = args.get("output-dir", "shear".to_string());
+
+    if rate_min <= 0.0 || rate_max < rate_min {
+        eprintln!("Shear rates must satisfy 0 < rate_min <= rate_max");
+        std::process::exit(1);
+    }
+    if count == 0 || (count == 1 && rate_max > rate_min) {
+        eprintln!("--count must be at least 1, and 2 for a range of rates");
+        std::process::exit(1);
+    }
+    if blocks < 2 || steps < blocks {
+        eprintln!("--blocks must be at least 2 and --steps at least --blocks");
+        std::process::exit(1);
+    }
+    if thermostat != "berendsen" && thermostat != "langevin" {
+        eprintln!("--thermostat must be berendsen or langevin");
+        std::process::exit(1);
+    }
+    if friction <= 0.0 || log_interval == 0 {
+        eprintln!("--friction and --log-interval must be positive");
+        std::process::exit(1);
+    }
+
+    let rates: Vec<f64> = (0..count).map(|i| {
+        let fraction = if count > 1 { i as f64 / (count - 1) as f64 } else { 0.0 };
+        rate_min * (rate_max / rate_min).powf(fraction)
+    }).collect();
+    fs::create_dir_all(&output_dir).expect("Failed to create output directory");
+
+    // The thermostat acts on velocities relative to the flow, so it removes the viscous heat
+    // without fighting the velocity profile
+    let mut liquid = initial_state(n, density, temperature, dt, cutoff, seed);
+    if thermostat == "langevin" {
+        liquid.langevin = Some(LangevinThermostat { target_temperature: temperature, friction, seed });
+    } else {
+        liquid.thermostat = Some(BerendsenThermostat { target_temperature: temperature, tau: 0.1 });
+    }
+    println!("Seed: {}", seed);
+    println!("Equilibrating {} atoms at {} K for {} steps ({:.3} ps)", n, temperature, equilibration, equilibration as f64 * dt);
+    let pb = progress_bar(equilibration);
+    for _ in 0..equilibration {
+        liquid.step();
+        pb.inc(1);
+    }
+    pb.finish_with_message(format!("T = {:.2} K", liquid.temperature()));
+
+    let curve_path = Path::new(&output_dir).join("flow_curve.csv");
+    let columns = [
+        "shear_rate",
+        "viscosity",
+        "viscosity_error",
+        "pxy",
+        "pxy_error",
+        "temperature",
+        "pressure",
+        "potential_energy_per_atom",
+    ];
+    let mut curve = TableWriter::create(&curve_path, &columns).unwrap_or_else(|e| {
+        eprintln!("Failed to create {}: {}", curve_path.display(), e);
+        std::process::exit(1);
+    });
+    let settings = Sweep { transient, steps, blocks, log_interval, output_dir: &output_dir };
+    println!("{:>12} {:>14} {:>14} {:>10}", "rate (1/time)", "viscosity", "error", "T (K)");
+    for &rate in &rates {
+        let mut sim = liquid.clone();
+        sim.shear_rate = rate;
+        let point = shear(sim, rate, &settings);
+        println!("{:>12.4e} {:>14.6e} {:>14.3e} {:>10.2}", rate, point[1], point[2], point[5]);
+        curve.write_row(&point).unwrap();
+    }
+    curve.finish().unwrap();
+    println!("Flow curve saved to {}", curve_path.display());
+}
+
+struct Sweep<'a> {
+    transient: usize,
+    steps: usize,
+    blocks: usize,
+    log_interval: usize,
+    output_dir: &'a str,
+}
+
+// Runs one shear rate and returns its row of the flow curve
+fn shear(mut sim: Simulation, rate: f64, settings: &Sweep) -> [f64; 8] {
+    let Sweep { transient, steps, blocks, log_interval, output_dir } = *settings;
+    let log_path = Path::new(output_dir).join(format!("shear_rate_{:.4e}.csv", rate));
+    let mut log = TableWriter::create(&log_path, &["time", "temperature", "pressure", "pxy"]).unwrap_or_else(|e| {
+        eprintln!("Failed to create {}: {}", log_path.display(), e);
+        std::process::exit(1);
+    });
+
+    let pb = progress_bar(transient + steps);
+    let mut pxy = Vec::with_capacity(steps);
+    let (mut temperature, mut pressure, mut potential_energy) = (0.0, 0.0, 0.0);
+    for step in 1..=transient + steps {
+        sim.step();
+        let tensor = sim.pressure_tensor();
+        if step > transient {
+            pxy.push(tensor[3]);
+            temperature += sim.temperature();
+            pressure += sim.pressure();
+            potential_energy += sim.potential_energy();
+        }
+        if step % log_interval == 0 {
+            log.write_row(&[step as f64 * sim.dt, sim.temperature(), sim.pressure(), tensor[3]]).unwrap();
+        }
+        pb.inc(1);
+    }
+    pb.finish_and_clear();
+    log.finish().unwrap();
+
+    let (mean, error) = block_average(&pxy, blocks);
+    let samples = steps as f64;
+    [
+        rate,
+        -mean / rate,
+        error / rate,
+        mean,
+        error,
+        temperature / samples,
+        pressure / samples,
+        potential_energy / samples / sim.num_atoms() as f64,
+    ]
+}
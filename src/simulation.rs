@@ -0,0 +1,798 @@
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use crate::forces::{compute_forces_sheared, switched_energy, LennardJones, PairPotential};
+use crate::geometry::Cell;
+use crate::random::Philox;
+
+pub const MASS_ARGON: f64 = 39.95;
+pub const KB: f64 = 0.0083144621;
+
+// Atoms per chunk of `ordered_sum`
+const SUM_CHUNK: usize = 1024;
+
+// Sum of `term` over `items`, bitwise the same whatever the number of threads: chunks of a
+// fixed size are summed in parallel, each in order, and the chunk sums then in order. A plain
+// parallel sum splits the work as the threads happen to steal it, so its rounding varies.
+pub fn ordered_sum<T: Sync, const K: usize>(items: &[T], term: impl Fn(&T) -> [f64; K] + Sync) -> [f64; K] {
+    let add = |a: [f64; K], b: [f64; K]| std::array::from_fn(|c| a[c] + b[c]);
+    let chunks: Vec<[f64; K]> = items.par_chunks(SUM_CHUNK).map(|chunk| chunk.iter().map(&term).fold([0.0; K], add)).collect();
+    chunks.into_iter().fold([0.0; K], add)
+}
+
+// Velocities drawn from the Maxwell-Boltzmann distribution: every component is Gaussian with
+// variance k_B T / m. The sample is then rescaled so its kinetic temperature is exactly `temperature`.
+// Each atom's numbers come from `rng` at step 0 and its own index, so the result does not depend
+// on the number of threads.
+pub fn maxwell_boltzmann_velocities(n: usize, temperature: f64, mass: f64, rng: &Philox) -> Vec<[f64; 3]> {
+    let sigma = (KB * temperature / mass).sqrt();
+    let mut velocities: Vec<[f64; 3]> = (0..n).into_par_iter().map(|i| {
+        let normals = rng.normals(0, i as u32, 0);
+        [sigma * normals[0], sigma * normals[1], sigma * normals[2]]
+    }).collect();
+
+    let [kinetic_energy] = ordered_sum(&velocities, |vel| [0.5 * mass * (vel[0].powi(2) + vel[1].powi(2) + vel[2].powi(2))]);
+    if kinetic_energy > 0.0 {
+        let scale = (3.0 * n as f64 * KB * temperature / (2.0 * kinetic_energy)).sqrt();
+        for vel in velocities.iter_mut() {
+            for coord in vel.iter_mut() {
+                *coord *= scale;
+            }
+        }
+    }
+    velocities
+}
+
+// Sum of m v v over all atoms as [xx, yy, zz, xy, xz, yz], the kinetic part of the pressure
+// tensor times the volume
+pub fn kinetic_tensor(velocities: &[[f64; 3]], mass: f64) -> [f64; 6] {
+    ordered_sum(velocities, |v| {
+        [v[0] * v[0], v[1] * v[1], v[2] * v[2], v[0] * v[1], v[0] * v[2], v[1] * v[2]].map(|c| mass * c)
+    })
+}
+
+// Microscopic heat current J = sum_i e_i v_i + sum_i W_i v_i, with e_i the kinetic plus potential
+// energy of atom i and W_i its share of the pair virial tensor ([xx, yy, zz, xy, xz, yz]). The
+// heat flux is J over the volume.
+pub fn heat_current(velocities: &[[f64; 3]], mass: f64, atom_energies: &[f64], atom_virials: &[[f64; 6]]) -> [f64; 3] {
+    let atoms: Vec<usize> = (0..velocities.len()).collect();
+    ordered_sum(&atoms, |&i| {
+        let (v, potential, w) = (velocities[i], atom_energies[i], atom_virials[i]);
+        let energy = 0.5 * mass * (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]) + potential;
+        [
+            energy * v[0] + w[0] * v[0] + w[3] * v[1] + w[4] * v[2],
+            energy * v[1] + w[3] * v[0] + w[1] * v[1] + w[5] * v[2],
+            energy * v[2] + w[4] * v[0] + w[5] * v[1] + w[2] * v[2],
+        ]
+    })
+}
+
+// Centre-of-mass velocity of equal-mass atoms
+pub fn com_velocity(velocities: &[[f64; 3]]) -> [f64; 3] {
+    let mut com = [0.0; 3];
+    for vel in velocities {
+        for k in 0..3 {
+            com[k] += vel[k];
+        }
+    }
+    com.map(|c| c / velocities.len().max(1) as f64)
+}
+
+// Subtracts the centre-of-mass velocity and returns what was removed
+pub fn remove_com_velocity(velocities: &mut [[f64; 3]]) -> [f64; 3] {
+    let com = com_velocity(velocities);
+    velocities.par_iter_mut().for_each(|vel| {
+        for k in 0..3 {
+            vel[k] -= com[k];
+        }
+    });
+    com
+}
+
+// Same as `remove_com_velocity` for the atoms not marked in `frozen`, which keep zero velocity
+pub fn remove_mobile_com_velocity(velocities: &mut [[f64; 3]], frozen: &[bool]) -> [f64; 3] {
+    let mobile: Vec<[f64; 3]> = velocities.iter().zip(frozen).filter(|(_, &f)| !f).map(|(vel, _)| *vel).collect();
+    let com = com_velocity(&mobile);
+    velocities.par_iter_mut().zip(frozen).filter(|(_, &f)| !f).for_each(|(vel, _)| {
+        for k in 0..3 {
+            vel[k] -= com[k];
+        }
+    });
+    com
+}
+
+// Share of the kinetic energy carried by centre-of-mass motion. A value that keeps growing is
+// the "flying ice cube" artifact of velocity-rescaling thermostats.
+pub fn com_kinetic_fraction(velocities: &[[f64; 3]]) -> f64 {
+    let com = com_velocity(velocities);
+    let com_sq = com[0].powi(2) + com[1].powi(2) + com[2].powi(2);
+    let [total_sq] = ordered_sum(velocities, |vel| [vel[0].powi(2) + vel[1].powi(2) + vel[2].powi(2)]);
+    if total_sq > 0.0 {
+        velocities.len() as f64 * com_sq / total_sq
+    } else {
+        0.0
+    }
+}
+
+// Atoms on a simple cubic lattice (random placement overlaps badly at liquid densities),
+// with Maxwell-Boltzmann velocities at the melt temperature and no net momentum.
+pub fn initial_state(n: usize, density: f64, temperature: f64, dt: f64, cutoff: f64, seed: u64) -> Simulation {
+    let l = (n as f64 / density).cbrt();
+    let cells = (n as f64).cbrt().ceil() as usize;
+    let spacing = l / cells as f64;
+    let positions = (0..n).map(|i| {
+        let (x, y, z) = (i % cells, (i / cells) % cells, i / (cells * cells));
+        [(x as f64 + 0.5) * spacing, (y as f64 + 0.5) * spacing, (z as f64 + 0.5) * spacing]
+    }).collect::<Vec<_>>();
+
+    let mut velocities = maxwell_boltzmann_velocities(n, temperature, MASS_ARGON, &Philox::new(seed));
+    remove_com_velocity(&mut velocities);
+    Simulation::new(positions, velocities, l, dt, cutoff)
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BerendsenThermostat {
+    pub target_temperature: f64,
+    pub tau: f64,
+}
+
+// Friction plus random kicks, which samples the canonical ensemble. The noise for every atom
+// and step comes from a counter-based generator keyed on `seed`: the same seed repeats a run
+// exactly, and a different seed branches the same state into an independent trajectory.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LangevinThermostat {
+    pub target_temperature: f64,
+    // Collision frequency, per time unit
+    pub friction: f64,
+    pub seed: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BerendsenBarostat {
+    pub target_pressure: f64,
+    pub tau: f64,
+    pub compressibility: f64,
+}
+
+// Full state of a `Simulation`, saved and loaded by `env::Environment`, the C interface and the
+// tests. The Berendsen couplings have no internal variables beyond their parameters, and the
+// Langevin noise is keyed on the seed and step. Drivers keep their running sums (averages and
+// the like) in `accumulators` so they survive a restart too. The main run has its own engine
+// and checkpoint format, `checkpoint::Checkpoint`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SaveState {
+    pub box_length: f64,
+    // Edge vectors of a cell that is not cubic; `box_length` is then the length of the first
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cell: Option<[[f64; 3]; 3]>,
+    pub mass: f64,
+    pub dt: f64,
+    pub cutoff: f64,
+    #[serde(default)]
+    pub switch_fraction: Option<f64>,
+    #[serde(default)]
+    pub potential: LennardJones,
+    pub step_count: u64,
+    pub positions: Vec<[f64; 3]>,
+    pub velocities: Vec<[f64; 3]>,
+    pub thermostat: Option<BerendsenThermostat>,
+    #[serde(default)]
+    pub langevin: Option<LangevinThermostat>,
+    pub barostat: Option<BerendsenBarostat>,
+    pub external_forces: Option<Vec<[f64; 3]>>,
+    #[serde(default)]
+    pub shear_rate: f64,
+    #[serde(default)]
+    pub image_offset: f64,
+    #[serde(default)]
+    pub frozen: Option<Vec<bool>>,
+    #[serde(default)]
+    pub atom_epsilons: Option<Vec<f64>>,
+    #[serde(default)]
+    pub accumulators: BTreeMap<String, f64>,
+}
+
+impl SaveState {
+    pub fn write_json<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(&mut writer, self).map_err(io::Error::other)?;
+        writer.flush()
+    }
+
+    pub fn read_json<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        serde_json::from_reader(reader).map_err(io::Error::other)
+    }
+}
+
+// Velocity Verlet integration of Lennard-Jones atoms in a periodic cell, cubic unless built
+// with `with_cell`, with optional Berendsen temperature and pressure coupling.
+//
+// Parameters that can change freely between steps are public fields. The configuration goes
+// through accessors, because forces and energies derived from it have to stay consistent.
+#[derive(Clone)]
+pub struct Simulation {
+    pub mass: f64,
+    pub dt: f64,
+    pub thermostat: Option<BerendsenThermostat>,
+    // Applied after the Berendsen scaling when both are set
+    pub langevin: Option<LangevinThermostat>,
+    pub barostat: Option<BerendsenBarostat>,
+    // Extra force on every atom, added to the pair forces in each half kick
+    pub external_forces: Option<Vec<[f64; 3]>>,
+    // Planar shear flow u_x = shear_rate * y (per time unit) with SLLOD dynamics and Lees-Edwards
+    // boundaries; velocities are then relative to the flow. Zero for equilibrium runs.
+    pub shear_rate: f64,
+    // Atoms marked true keep their positions and zero velocity, like the atoms of a rigid wall.
+    // They still exert and feel pair forces but count for nothing in the temperature.
+    pub frozen: Option<Vec<bool>>,
+    potential: LennardJones,
+    // Well depth of each atom relative to the potential's, mixed as sqrt(eps_i eps_j)
+    atom_epsilons: Option<Vec<f64>>,
+    cell: Cell,
+    // x displacement of the periodic images one box up in y, accumulated by the shear flow
+    image_offset: f64,
+    cutoff: f64,
+    // Switching starts at this fraction of the cutoff; None truncates the potential
+    switch_fraction: Option<f64>,
+    positions: Vec<[f64; 3]>,
+    velocities: Vec<[f64; 3]>,
+    forces: Vec<[f64; 3]>,
+    potential_energy: f64,
+    virial: f64,
+    virial_tensor: [f64; 6],
+    atom_energies: Vec<f64>,
+    atom_virials: Vec<[f64; 6]>,
+    step_count: u64,
+}
+
+impl Simulation {
+    pub fn new(positions: Vec<[f64; 3]>, velocities: Vec<[f64; 3]>, box_length: f64, dt: f64, cutoff: f64) -> Self {
+        Simulation::with_cell(positions, velocities, Cell::cubic(box_length), dt, cutoff)
+    }
+
+    // A simulation in any periodic cell, orthorhombic or triclinic. The cutoff is cut back to
+    // half the smallest cell width (see `effective_cutoff`).
+    pub fn with_cell(positions: Vec<[f64; 3]>, velocities: Vec<[f64; 3]>, cell: Cell, dt: f64, cutoff: f64) -> Self {
+        assert_eq!(positions.len(), velocities.len(), "positions and velocities differ in length");
+        let mut sim = Simulation {
+            mass: MASS_ARGON,
+            dt,
+            thermostat: None,
+            langevin: None,
+            barostat: None,
+            external_forces: None,
+            shear_rate: 0.0,
+            frozen: None,
+            potential: LennardJones::default(),
+            atom_epsilons: None,
+            cell,
+            image_offset: 0.0,
+            cutoff,
+            switch_fraction: None,
+            positions,
+            velocities,
+            forces: Vec::new(),
+            potential_energy: 0.0,
+            virial: 0.0,
+            virial_tensor: [0.0; 6],
+            atom_energies: Vec::new(),
+            atom_virials: Vec::new(),
+            step_count: 0,
+        };
+        sim.update_forces();
+        sim
+    }
+
+    pub fn positions(&self) -> &[[f64; 3]] {
+        &self.positions
+    }
+
+    // Replaces the configuration in place, wrapping it into the box and recomputing the forces.
+    // Panics if the number of atoms differs.
+    pub fn set_positions(&mut self, positions: &[[f64; 3]]) {
+        self.positions.copy_from_slice(positions);
+        let cell = self.cell;
+        self.positions.par_iter_mut().for_each(|pos| *pos = cell.wrap(pos));
+        self.update_forces();
+    }
+
+    pub fn velocities(&self) -> &[[f64; 3]] {
+        &self.velocities
+    }
+
+    // Nothing is derived from the velocities, so they can be edited directly
+    pub fn velocities_mut(&mut self) -> &mut [[f64; 3]] {
+        &mut self.velocities
+    }
+
+    // Pair forces at the current positions, without `external_forces`
+    pub fn forces(&self) -> &[[f64; 3]] {
+        &self.forces
+    }
+
+    pub fn potential_energy(&self) -> f64 {
+        self.potential_energy
+    }
+
+    // Sum of r_ij . f_ij over all pairs at the current positions
+    pub fn virial(&self) -> f64 {
+        self.virial
+    }
+
+    // Sum of the outer products r_ij f_ij over all pairs, as [xx, yy, zz, xy, xz, yz]
+    pub fn virial_tensor(&self) -> [f64; 6] {
+        self.virial_tensor
+    }
+
+    // Edge of a cubic box; the length of the first edge vector of any other cell
+    pub fn box_length(&self) -> f64 {
+        if self.cell.is_orthorhombic() {
+            self.cell.vectors()[0][0]
+        } else {
+            self.cell.lengths()[0]
+        }
+    }
+
+    // Edges of an orthorhombic box along x, y and z; for a tilted cell the widths between
+    // opposite faces
+    pub fn box_lengths(&self) -> [f64; 3] {
+        self.cell.widths()
+    }
+
+    pub fn cell(&self) -> &Cell {
+        &self.cell
+    }
+
+    fn is_cubic(&self) -> bool {
+        let [a, b, c] = self.cell.widths();
+        self.cell.is_orthorhombic() && a == b && b == c
+    }
+
+    // Resizes the box, or the cell keeping its shape, scaling the positions with it, and
+    // recomputes the forces
+    pub fn set_box_length(&mut self, box_length: f64) {
+        let scale = box_length / self.box_length();
+        self.cell = self.cell.scaled(scale);
+        self.image_offset *= scale;
+        self.positions.par_iter_mut().for_each(|pos| {
+            for coord in pos.iter_mut() {
+                *coord *= scale;
+            }
+        });
+        self.update_forces();
+    }
+
+    // Lees-Edwards image offset in [0, box_length), zero unless the box has been sheared
+    pub fn image_offset(&self) -> f64 {
+        self.image_offset
+    }
+
+    pub fn cutoff(&self) -> f64 {
+        self.cutoff
+    }
+
+    pub fn set_cutoff(&mut self, cutoff: f64) {
+        self.cutoff = cutoff;
+        self.update_forces();
+    }
+
+    pub fn atom_epsilons(&self) -> Option<&[f64]> {
+        self.atom_epsilons.as_deref()
+    }
+
+    // Gives atoms their own well depths, for example a weakly attracting wall, and recomputes
+    // the forces. Panics if the number of atoms differs.
+    pub fn set_atom_epsilons(&mut self, epsilons: Option<Vec<f64>>) {
+        if let Some(epsilons) = &epsilons {
+            assert_eq!(epsilons.len(), self.num_atoms(), "one epsilon per atom");
+        }
+        self.atom_epsilons = epsilons;
+        self.update_forces();
+    }
+
+    // Deletes the atoms at `indices` along with their frozen flags, well depths and external
+    // forces, and recomputes the forces. The remaining atoms keep their order.
+    pub fn remove_atoms(&mut self, indices: &[usize]) {
+        let mut keep = vec![true; self.num_atoms()];
+        for &i in indices {
+            keep[i] = false;
+        }
+        fn retain<T>(values: &mut Vec<T>, keep: &[bool]) {
+            let mut i = 0;
+            values.retain(|_| {
+                i += 1;
+                keep[i - 1]
+            });
+        }
+        retain(&mut self.positions, &keep);
+        retain(&mut self.velocities, &keep);
+        if let Some(forces) = &mut self.external_forces {
+            retain(forces, &keep);
+        }
+        if let Some(frozen) = &mut self.frozen {
+            retain(frozen, &keep);
+        }
+        if let Some(epsilons) = &mut self.atom_epsilons {
+            retain(epsilons, &keep);
+        }
+        self.update_forces();
+    }
+
+    // Appends mobile atoms with the potential's own well depth and no external force, wrapped
+    // into the box, and recomputes the forces
+    pub fn insert_atoms(&mut self, positions: &[[f64; 3]], velocities: &[[f64; 3]]) {
+        assert_eq!(positions.len(), velocities.len(), "positions and velocities differ in length");
+        let cell = self.cell;
+        self.positions.extend(positions.iter().map(|pos| cell.wrap(pos)));
+        self.velocities.extend_from_slice(velocities);
+        let n = self.num_atoms();
+        if let Some(forces) = &mut self.external_forces {
+            forces.resize(n, [0.0; 3]);
+        }
+        if let Some(frozen) = &mut self.frozen {
+            frozen.resize(n, false);
+        }
+        if let Some(epsilons) = &mut self.atom_epsilons {
+            epsilons.resize(n, 1.0);
+        }
+        self.update_forces();
+    }
+
+    pub fn switch_fraction(&self) -> Option<f64> {
+        self.switch_fraction
+    }
+
+    // Switches the potential smoothly to zero from `fraction` of the cutoff (e.g. 0.9) instead
+    // of truncating it, or back to truncation with None
+    pub fn set_switch_fraction(&mut self, fraction: Option<f64>) {
+        self.switch_fraction = fraction;
+        self.update_forces();
+    }
+
+    pub fn potential(&self) -> &LennardJones {
+        &self.potential
+    }
+
+    // Replaces the pair potential, for example to change the well depth of every atom at once
+    pub fn set_potential(&mut self, potential: LennardJones) {
+        self.potential = potential;
+        self.update_forces();
+    }
+
+    // Energy of one pair at distance `r` as the forces count it, with the cutoff and switching
+    // of the simulation; per-atom well depths are left to the caller
+    pub fn pair_energy(&self, r: f64) -> f64 {
+        let cutoff = self.effective_cutoff();
+        let switch_start = self.switch_fraction.map_or(f64::INFINITY, |fraction| fraction * cutoff);
+        switched_energy(&self.potential, r, cutoff, switch_start)
+    }
+
+    // Number of steps taken, or the value it was last set to
+    pub fn step_count(&self) -> u64 {
+        self.step_count
+    }
+
+    pub fn set_step_count(&mut self, step_count: u64) {
+        self.step_count = step_count;
+    }
+
+    // Simulated time since step 0 at the current timestep
+    pub fn time(&self) -> f64 {
+        self.step_count as f64 * self.dt
+    }
+
+    pub fn num_atoms(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn volume(&self) -> f64 {
+        if self.cell.is_orthorhombic() {
+            let [lx, ly, lz] = self.cell.widths();
+            lx * ly * lz
+        } else {
+            self.cell.volume()
+        }
+    }
+
+    pub fn density(&self) -> f64 {
+        self.num_atoms() as f64 / self.volume()
+    }
+
+    pub fn kinetic_energy(&self) -> f64 {
+        ordered_sum(&self.velocities, |vel| [0.5 * self.mass * (vel[0].powi(2) + vel[1].powi(2) + vel[2].powi(2))])[0]
+    }
+
+    pub fn temperature(&self) -> f64 {
+        (2.0 * self.kinetic_energy()) / (3.0 * self.mobile_atoms() as f64 * KB)
+    }
+
+    // Atoms that are not frozen
+    pub fn mobile_atoms(&self) -> usize {
+        self.frozen.as_ref().map_or(self.num_atoms(), |frozen| frozen.iter().filter(|&&f| !f).count())
+    }
+
+    // Pressure from the pair interactions inside the cutoff only
+    pub fn pressure(&self) -> f64 {
+        (2.0 * self.kinetic_energy() + self.virial) / (3.0 * self.volume())
+    }
+
+    // Pressure tensor as [xx, yy, zz, xy, xz, yz], kinetic plus pair virial inside the cutoff.
+    // Its trace over 3 is `pressure()`.
+    pub fn pressure_tensor(&self) -> [f64; 6] {
+        let kinetic = kinetic_tensor(&self.velocities, self.mass);
+        let volume = self.volume();
+        std::array::from_fn(|c| (kinetic[c] + self.virial_tensor[c]) / volume)
+    }
+
+    // Potential energy of each atom, half of every pair it is in
+    pub fn atom_energies(&self) -> &[f64] {
+        &self.atom_energies
+    }
+
+    // Heat current of the current positions and velocities, see `heat_current`
+    pub fn heat_current(&self) -> [f64; 3] {
+        heat_current(&self.velocities, self.mass, &self.atom_energies, &self.atom_virials)
+    }
+
+    // Interaction range actually used: never further than half the narrowest width of the
+    // cell, otherwise the minimum image is ambiguous
+    pub fn effective_cutoff(&self) -> f64 {
+        let [a, b, c] = self.cell.widths();
+        self.cutoff.min(0.5 * a.min(b).min(c))
+    }
+
+    // Potential energy the cutoff leaves out, for all atoms. Taken from the cutoff as if the
+    // potential were truncated there; with switching the true deficit is somewhat larger.
+    pub fn tail_energy(&self) -> f64 {
+        self.num_atoms() as f64 * self.potential.tail_energy(self.effective_cutoff(), self.density())
+    }
+
+    pub fn tail_pressure(&self) -> f64 {
+        self.potential.tail_pressure(self.effective_cutoff(), self.density())
+    }
+
+    pub fn corrected_potential_energy(&self) -> f64 {
+        self.potential_energy + self.tail_energy()
+    }
+
+    // Pressure including the tail correction; this is what the barostat controls
+    pub fn corrected_pressure(&self) -> f64 {
+        self.pressure() + self.tail_pressure()
+    }
+
+    // Moves the target of whichever thermostats are set
+    pub fn set_target_temperature(&mut self, target_temperature: f64) {
+        if let Some(thermostat) = &mut self.thermostat {
+            thermostat.target_temperature = target_temperature;
+        }
+        if let Some(langevin) = &mut self.langevin {
+            langevin.target_temperature = target_temperature;
+        }
+    }
+
+    // Snapshot of everything `from_state` needs to continue bit-exactly
+    pub fn save_state(&self) -> SaveState {
+        SaveState {
+            box_length: self.box_length(),
+            cell: (!self.is_cubic()).then(|| self.cell.vectors()),
+            mass: self.mass,
+            dt: self.dt,
+            cutoff: self.cutoff,
+            switch_fraction: self.switch_fraction,
+            potential: self.potential,
+            step_count: self.step_count,
+            positions: self.positions.clone(),
+            velocities: self.velocities.clone(),
+            thermostat: self.thermostat.clone(),
+            langevin: self.langevin.clone(),
+            barostat: self.barostat.clone(),
+            external_forces: self.external_forces.clone(),
+            shear_rate: self.shear_rate,
+            image_offset: self.image_offset,
+            frozen: self.frozen.clone(),
+            atom_epsilons: self.atom_epsilons.clone(),
+            accumulators: BTreeMap::new(),
+        }
+    }
+
+    // Forces are not stored; recomputing them from the same positions gives the same values
+    pub fn from_state(state: SaveState) -> Self {
+        let cell = match state.cell {
+            Some(vectors) => Cell::triclinic(vectors).expect("saved cell is not a valid periodic cell"),
+            None => Cell::cubic(state.box_length),
+        };
+        let mut sim = Simulation::with_cell(state.positions, state.velocities, cell, state.dt, state.cutoff);
+        sim.mass = state.mass;
+        sim.thermostat = state.thermostat;
+        sim.langevin = state.langevin;
+        sim.barostat = state.barostat;
+        sim.external_forces = state.external_forces;
+        sim.shear_rate = state.shear_rate;
+        sim.frozen = state.frozen;
+        sim.step_count = state.step_count;
+        if state.switch_fraction.is_some() || state.image_offset != 0.0 || state.atom_epsilons.is_some() || state.potential != LennardJones::default() {
+            sim.potential = state.potential;
+            sim.image_offset = state.image_offset;
+            sim.switch_fraction = state.switch_fraction;
+            sim.atom_epsilons = state.atom_epsilons;
+            sim.update_forces();
+        }
+        sim
+    }
+
+    fn update_forces(&mut self) {
+        let cutoff = self.effective_cutoff();
+        let switch_start = self.switch_fraction.map_or(f64::INFINITY, |fraction| fraction * cutoff);
+        let output = compute_forces_sheared(
+            &self.potential,
+            &self.positions,
+            &self.cell,
+            cutoff,
+            switch_start,
+            self.image_offset,
+            self.atom_epsilons.as_deref(),
+        );
+        self.forces = output.forces;
+        self.potential_energy = output.potential_energy;
+        self.virial = output.virial;
+        self.virial_tensor = output.virial_tensor;
+        self.atom_energies = output.atom_energies;
+        self.atom_virials = output.atom_virials;
+    }
+
+    fn half_kick(&mut self) {
+        let factor = 0.5 * self.dt / self.mass;
+        // SLLOD: the flow gradient feeds y momentum into x momentum relative to the flow
+        let shear = 0.5 * self.dt * self.shear_rate;
+        let external = self.external_forces.as_deref();
+        let frozen = self.frozen.as_deref();
+        self.velocities.par_iter_mut().zip(self.forces.par_iter()).enumerate().for_each(|(i, (vel, force))| {
+            if frozen.is_some_and(|f| f[i]) {
+                *vel = [0.0; 3];
+                return;
+            }
+            for k in 0..3 {
+                let extra = external.map_or(0.0, |f| f[i][k]);
+                vel[k] += factor * (force[k] + extra);
+            }
+            vel[0] -= shear * vel[1];
+        });
+    }
+
+    pub fn step(&mut self) {
+        let dt = self.dt;
+        let cell = self.cell;
+        let orthorhombic = cell.is_orthorhombic();
+        assert!(orthorhombic || self.shear_rate == 0.0, "Lees-Edwards shear needs an orthorhombic cell");
+        let l = cell.widths();
+        let pressure = self.corrected_pressure();
+
+        // First half kick and drift, wrapping atoms back into the box. Under shear the atoms
+        // also stream with the flow, and one leaving through a y face comes back through the
+        // other shifted by the image offset; its velocity relative to the flow is unchanged.
+        self.half_kick();
+        let shear_rate = self.shear_rate;
+        self.image_offset = (self.image_offset + shear_rate * l[1] * dt).rem_euclid(l[0]);
+        let offset = self.image_offset;
+        let frozen = self.frozen.as_deref();
+        self.positions.par_iter_mut().zip(self.velocities.par_iter()).enumerate()
+            .for_each(|(i, (pos, vel))| {
+                if frozen.is_some_and(|f| f[i]) {
+                    return;
+                }
+                pos[0] += dt * shear_rate * pos[1];
+                for k in 0..3 {
+                    pos[k] += dt * vel[k];
+                }
+                if !orthorhombic {
+                    *pos = cell.wrap(pos);
+                    return;
+                }
+                pos[0] -= (pos[1] / l[1]).floor() * offset;
+                for (coord, l) in pos.iter_mut().zip(l) {
+                    *coord -= (*coord / l).floor() * l;
+                }
+            });
+
+        // Rescale the box with the pressure from the start of the step, so forces are only evaluated once
+        if let Some(barostat) = &self.barostat {
+            let mu = (1.0 - barostat.compressibility * dt / barostat.tau * (barostat.target_pressure - pressure)).cbrt();
+            // Keep a single step from collapsing or exploding the box
+            let mu = mu.clamp(0.99, 1.01);
+            self.cell = self.cell.scaled(mu);
+            self.image_offset *= mu;
+            self.positions.par_iter_mut().for_each(|pos| {
+                for coord in pos.iter_mut() {
+                    *coord *= mu;
+                }
+            });
+        }
+
+        self.update_forces();
+
+        self.half_kick();
+
+        if let Some(thermostat) = &self.thermostat {
+            let current_temperature = self.temperature();
+            if current_temperature > 0.0 {
+                let scaling_factor = (1.0 + dt / thermostat.tau * (thermostat.target_temperature / current_temperature - 1.0)).max(0.0).sqrt();
+                self.velocities.par_iter_mut().for_each(|vel| {
+                    for coord in vel.iter_mut() {
+                        *coord *= scaling_factor;
+                    }
+                });
+            }
+        }
+
+        // Exact Ornstein-Uhlenbeck update of the velocities over the whole step
+        if let Some(langevin) = &self.langevin {
+            let decay = (-langevin.friction * dt).exp();
+            let kick = ((1.0 - decay * decay) * KB * langevin.target_temperature / self.mass).sqrt();
+            let rng = Philox::new(langevin.seed);
+            let step = self.step_count;
+            let frozen = self.frozen.as_deref();
+            self.velocities.par_iter_mut().enumerate().for_each(|(i, vel)| {
+                if frozen.is_some_and(|f| f[i]) {
+                    return;
+                }
+                let noise = rng.normals(step, i as u32, 0);
+                for k in 0..3 {
+                    vel[k] = decay * vel[k] + kick * noise[k];
+                }
+            });
+        }
+
+        self.step_count += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ordered_sum_is_the_same_on_any_number_of_threads() {
+        let rng = Philox::new(7);
+        let velocities: Vec<[f64; 3]> = (0..10_000).map(|i| {
+            let normals = rng.normals(0, i, 0);
+            [normals[0], normals[1], normals[2]]
+        }).collect();
+        let sums: Vec<[f64; 6]> = [1, 2, 5, 16]
+            .into_iter()
+            .map(|threads| {
+                let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+                pool.install(|| kinetic_tensor(&velocities, MASS_ARGON))
+            })
+            .collect();
+        assert!(sums.iter().all(|sum| sum.map(f64::to_bits) == sums[0].map(f64::to_bits)));
+    }
+
+    #[test]
+    fn save_state_round_trip_continues_exactly() {
+        let mut sim = initial_state(64, 0.8, 120.0, 0.002, 2.5, 3);
+        sim.langevin = Some(LangevinThermostat { target_temperature: 120.0, friction: 5.0, seed: 11 });
+        sim.frozen = Some((0..64).map(|i| i % 16 == 0).collect());
+        for _ in 0..10 {
+            sim.step();
+        }
+
+        let path = std::env::temp_dir().join(format!("sim-save-state-{}.json", std::process::id()));
+        sim.save_state().write_json(&path).unwrap();
+        let mut restored = Simulation::from_state(SaveState::read_json(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(restored.forces(), sim.forces());
+
+        for _ in 0..20 {
+            sim.step();
+            restored.step();
+        }
+        assert_eq!(restored.step_count(), sim.step_count());
+        assert_eq!(restored.positions(), sim.positions());
+        assert_eq!(restored.velocities(), sim.velocities());
+    }
+}
@@ -0,0 +1,138 @@
+use std::fmt::Debug;
+use std::ops::{Add, AddAssign, Div, Mul, Sub, SubAssign};
+
+// Floating-point type of the per-atom arrays, f64 normally and f32 for single-precision force
+// kernels. Values come in and go out as f64.
+pub trait Real:
+    Copy + Default + Debug + PartialEq + Send + Sync
+    + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> + Div<Output = Self>
+    + AddAssign + SubAssign
+{
+    fn from_f64(x: f64) -> Self;
+    fn to_f64(self) -> f64;
+    fn sqrt(self) -> Self;
+    fn round(self) -> Self;
+}
+
+impl Real for f64 {
+    fn from_f64(x: f64) -> Self {
+        x
+    }
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+
+    fn sqrt(self) -> Self {
+        f64::sqrt(self)
+    }
+
+    fn round(self) -> Self {
+        f64::round(self)
+    }
+}
+
+impl Real for f32 {
+    fn from_f64(x: f64) -> Self {
+        x as f32
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn sqrt(self) -> Self {
+        f32::sqrt(self)
+    }
+
+    fn round(self) -> Self {
+        f32::round(self)
+    }
+}
+
+// Per-atom vectors with every component in an array of its own, so loops over the atoms read
+// dense streams of floats that the compiler can vectorize. The main run keeps its positions,
+// velocities and forces this way: the pair kernel reads the positions in place, and the
+// integration and thermostat go through one component at a time. Code that takes `[f64; 3]`
+// rows (external forces, analyses, output, checkpoints and `Simulation`) gets them from
+// `fill_rows` on the steps it runs.
+#[derive(Clone, Default, Debug, PartialEq)]
+pub struct Vectors<T: Real = f64> {
+    pub x: Vec<T>,
+    pub y: Vec<T>,
+    pub z: Vec<T>,
+}
+
+impl<T: Real> Vectors<T> {
+    pub fn zeros(n: usize) -> Self {
+        Vectors { x: vec![T::default(); n], y: vec![T::default(); n], z: vec![T::default(); n] }
+    }
+
+    pub fn from_rows(rows: &[[f64; 3]]) -> Self {
+        Vectors {
+            x: rows.iter().map(|row| T::from_f64(row[0])).collect(),
+            y: rows.iter().map(|row| T::from_f64(row[1])).collect(),
+            z: rows.iter().map(|row| T::from_f64(row[2])).collect(),
+        }
+    }
+
+    // Takes over `rows`, reusing the arrays' memory
+    pub fn set_rows(&mut self, rows: &[[f64; 3]]) {
+        for (k, component) in [&mut self.x, &mut self.y, &mut self.z].into_iter().enumerate() {
+            component.clear();
+            component.extend(rows.iter().map(|row| T::from_f64(row[k])));
+        }
+    }
+
+    // Takes over `other` in this precision, reusing the arrays' memory
+    pub fn set_from<U: Real>(&mut self, other: &Vectors<U>) {
+        for (component, from) in [&mut self.x, &mut self.y, &mut self.z].into_iter().zip(other.components()) {
+            component.clear();
+            component.extend(from.iter().map(|&value| T::from_f64(value.to_f64())));
+        }
+    }
+
+    // The vectors as rows in `rows`, reusing its memory
+    pub fn fill_rows(&self, rows: &mut Vec<[f64; 3]>) {
+        rows.clear();
+        rows.extend((0..self.len()).map(|i| self.get(i).map(T::to_f64)));
+    }
+
+    pub fn components(&self) -> [&[T]; 3] {
+        [&self.x, &self.y, &self.z]
+    }
+
+    pub fn components_mut(&mut self) -> [&mut [T]; 3] {
+        [&mut self.x, &mut self.y, &mut self.z]
+    }
+
+    // `n` zero vectors, reusing the arrays' memory
+    pub fn set_zeros(&mut self, n: usize) {
+        for component in [&mut self.x, &mut self.y, &mut self.z] {
+            component.clear();
+            component.resize(n, T::default());
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.x.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.x.is_empty()
+    }
+
+    pub fn get(&self, i: usize) -> [T; 3] {
+        [self.x[i], self.y[i], self.z[i]]
+    }
+
+    pub fn add(&mut self, i: usize, v: [T; 3]) {
+        self.x[i] += v[0];
+        self.y[i] += v[1];
+        self.z[i] += v[2];
+    }
+
+    pub fn to_rows(&self) -> Vec<[f64; 3]> {
+        (0..self.len()).map(|i| self.get(i).map(T::to_f64)).collect()
+    }
+}
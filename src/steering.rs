@@ -0,0 +1,122 @@
+use serde::{Deserialize, Serialize};
+
+// How the centre of mass of a group is pulled: by a spring E = k/2 |anchor - com|^2 whose
+// anchor moves at constant velocity (energy / length^2, length / time), or by a constant force
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum Pulling {
+    Velocity { velocity: [f64; 3], spring: f64 },
+    Force([f64; 3]),
+}
+
+// Steered MD on one group of atoms. The pulling force acts on the centre of mass and is
+// shared equally among the atoms, which all have the same mass. The centre of mass is
+// followed across periodic boundaries so displacements and work keep accumulating.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Pull {
+    pub atoms: Vec<usize>,
+    pub pulling: Pulling,
+    // Centre of mass at time 0, where the spring anchor starts
+    pub origin: [f64; 3],
+    // Latest unwrapped centre of mass
+    pub com: [f64; 3],
+    // Work done on the group so far, up to `time`
+    pub work: f64,
+    pub time: f64,
+}
+
+impl Pull {
+    pub fn new(atoms: Vec<usize>, pulling: Pulling, positions: &[[f64; 3]], box_lengths: [f64; 3], periodic: [bool; 3]) -> Self {
+        let com = group_com(&atoms, positions, box_lengths, periodic);
+        Pull { atoms, pulling, origin: com, com, work: 0.0, time: 0.0 }
+    }
+
+    // The same pull restarted at `time`: the anchor and the origin are where they have got
+    // to, and no work has been done
+    pub fn restarted(&self, time: f64) -> Self {
+        let mut origin = self.com;
+        if let Pulling::Velocity { velocity, .. } = self.pulling {
+            origin = std::array::from_fn(|k| self.origin[k] + velocity[k] * time);
+        }
+        Pull { origin, work: 0.0, time: 0.0, ..self.clone() }
+    }
+
+    // Unit vector along which displacements and forces are reported
+    pub fn direction(&self) -> [f64; 3] {
+        let v = match self.pulling {
+            Pulling::Velocity { velocity, .. } => velocity,
+            Pulling::Force(force) => force,
+        };
+        let norm = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+        if norm > 0.0 { v.map(|x| x / norm) } else { [0.0; 3] }
+    }
+
+    // Displacement of the centre of mass from the origin along the pulling direction
+    pub fn displacement(&self) -> f64 {
+        let direction = self.direction();
+        (0..3).map(|k| (self.com[k] - self.origin[k]) * direction[k]).sum()
+    }
+
+    // Follows the centre of mass to `positions` at `time`, adds the pulling force to `forces`
+    // and the spring energy, split equally, to `atom_energies` unless that is empty, and
+    // accumulates the work since the previous call. Returns the spring energy and the pulling
+    // force along the pulling direction.
+    pub fn apply(
+        &mut self,
+        time: f64,
+        positions: &[[f64; 3]],
+        box_lengths: [f64; 3],
+        periodic: [bool; 3],
+        forces: &mut [[f64; 3]],
+        atom_energies: &mut [f64],
+    ) -> (f64, f64) {
+        let wrapped = group_com(&self.atoms, positions, box_lengths, periodic);
+        for k in 0..3 {
+            let mut step = wrapped[k] - self.com[k];
+            if periodic[k] {
+                step -= (step / box_lengths[k]).round() * box_lengths[k];
+            }
+            self.com[k] += step;
+        }
+        let (force, energy) = match self.pulling {
+            Pulling::Velocity { velocity, spring } => {
+                let stretch: [f64; 3] = std::array::from_fn(|k| self.origin[k] + velocity[k] * time - self.com[k]);
+                let force = stretch.map(|s| spring * s);
+                // Power the moving anchor puts in
+                self.work += (0..3).map(|k| force[k] * velocity[k]).sum::<f64>() * (time - self.time);
+                (force, 0.5 * spring * (0..3).map(|k| stretch[k] * stretch[k]).sum::<f64>())
+            }
+            Pulling::Force(force) => {
+                self.work = (0..3).map(|k| force[k] * (self.com[k] - self.origin[k])).sum();
+                (force, 0.0)
+            }
+        };
+        self.time = time;
+        let share = 1.0 / self.atoms.len() as f64;
+        for &i in &self.atoms {
+            for k in 0..3 {
+                forces[i][k] += force[k] * share;
+            }
+            if let Some(e) = atom_energies.get_mut(i) {
+                *e += energy * share;
+            }
+        }
+        let direction = self.direction();
+        (energy, (0..3).map(|k| force[k] * direction[k]).sum())
+    }
+}
+
+// Centre of mass of `atoms`, with every atom taken to its image nearest the first one
+pub fn group_com(atoms: &[usize], positions: &[[f64; 3]], box_lengths: [f64; 3], periodic: [bool; 3]) -> [f64; 3] {
+    let first = positions[atoms[0]];
+    let mut sum = [0.0; 3];
+    for &i in atoms {
+        for k in 0..3 {
+            let mut delta = positions[i][k] - first[k];
+            if periodic[k] {
+                delta -= (delta / box_lengths[k]).round() * box_lengths[k];
+            }
+            sum[k] += delta;
+        }
+    }
+    std::array::from_fn(|k| first[k] + sum[k] / atoms.len() as f64)
+}
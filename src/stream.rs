@@ -0,0 +1,229 @@
+#[cfg(feature = "stream")]
+use std::net::{TcpListener, TcpStream};
+#[cfg(feature = "stream")]
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "stream")]
+use std::thread;
+
+#[cfg(feature = "stream")]
+use crossbeam_channel::{Sender, TrySendError};
+#[cfg(feature = "stream")]
+use serde::Serialize;
+#[cfg(feature = "stream")]
+use tungstenite::Message;
+
+use crate::cli::Args;
+
+// Live stream of the main run to WebSocket clients, behind `--stream ws://host:port` and the
+// `stream` feature. Every client gets a `hello` message on connecting, then `thermo` messages
+// every `--stream-every` steps and `frame` messages with the positions (single precision) of
+// at most `--stream-atoms` evenly spaced atoms every `--stream-frames-every` steps. Messages are
+// JSON text, or MessagePack binary with `--stream-format msgpack`, both maps with a `type` key.
+// Each client has its own queue and thread; a client that cannot keep up misses messages
+// rather than slowing down the run.
+
+// How the run is described to a client that connects
+pub struct RunInfo {
+    pub num_atoms: usize,
+    pub box_lengths: [f64; 3],
+    pub timestep: f64,
+    pub total_steps: usize,
+}
+
+#[cfg(feature = "stream")]
+pub struct Streamer {
+    every: usize,
+    frames_every: usize,
+    msgpack: bool,
+    // Atoms whose positions go into frames
+    atoms: Vec<usize>,
+    clients: Arc<Mutex<Vec<Sender<Message>>>>,
+}
+
+#[cfg(not(feature = "stream"))]
+pub struct Streamer;
+
+// Messages queued per client before it starts missing them
+#[cfg(feature = "stream")]
+const QUEUE: usize = 64;
+
+#[cfg(feature = "stream")]
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Payload<'a> {
+    Hello {
+        num_atoms: usize,
+        box_lengths: [f64; 3],
+        timestep: f64,
+        total_steps: usize,
+        thermo_every: usize,
+        frames_every: usize,
+        // Indices of the atoms in every frame
+        atoms: &'a [usize],
+    },
+    Thermo {
+        step: usize,
+        time: f64,
+        temperature: f64,
+        kinetic_energy: f64,
+        potential_energy: f64,
+        total_energy: f64,
+        pressure: f64,
+    },
+    Frame {
+        step: usize,
+        time: f64,
+        box_lengths: [f64; 3],
+        positions: Vec<[f32; 3]>,
+    },
+}
+
+pub fn available() -> bool {
+    cfg!(feature = "stream")
+}
+
+// `--stream` and its options of the main run and resume; binds the address straight away so a
+// taken port is reported before the run starts
+pub fn from_args(args: &Args, info: RunInfo) -> Option<Streamer> {
+    let url: String = args.get_opt("stream")?;
+    if !available() {
+        eprintln!("--stream requires a build with --features stream");
+        std::process::exit(1);
+    }
+    let Some(address) = url.strip_prefix("ws://").map(|rest| rest.trim_end_matches('/')) else {
+        eprintln!("--stream takes a ws://host:port address, got {}", url);
+        std::process::exit(1);
+    };
+    let every: usize = args.get("stream-every", 10);
+    let frames_every: usize = args.get("stream-frames-every", 100);
+    let max_atoms: usize = args.get("stream-atoms", 1000);
+    if every == 0 || frames_every == 0 || max_atoms == 0 {
+        eprintln!("--stream-every, --stream-frames-every and --stream-atoms must be at least 1");
+        std::process::exit(1);
+    }
+    let format: String = args.get("stream-format", "json".to_string());
+    let msgpack = match format.as_str() {
+        "json" => false,
+        "msgpack" => true,
+        _ => {
+            eprintln!("Unknown --stream-format {}, expected json or msgpack", format);
+            std::process::exit(1);
+        }
+    };
+    let atoms: Vec<usize> = (0..info.num_atoms).step_by(info.num_atoms.div_ceil(max_atoms).max(1)).collect();
+    let streamer = Streamer::bind(address, every, frames_every, msgpack, atoms, info).unwrap_or_else(|e| {
+        eprintln!("Failed to listen on {}: {}", address, e);
+        std::process::exit(1);
+    });
+    println!("Streaming to WebSocket clients on ws://{}", address);
+    Some(streamer)
+}
+
+#[cfg(feature = "stream")]
+impl Streamer {
+    fn bind(address: &str, every: usize, frames_every: usize, msgpack: bool, atoms: Vec<usize>, info: RunInfo) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let clients = Arc::new(Mutex::new(Vec::new()));
+        let hello = encode(msgpack, &Payload::Hello {
+            num_atoms: info.num_atoms,
+            box_lengths: info.box_lengths,
+            timestep: info.timestep,
+            total_steps: info.total_steps,
+            thermo_every: every,
+            frames_every,
+            atoms: &atoms,
+        });
+        let accepted = Arc::clone(&clients);
+        thread::spawn(move || {
+            for connection in listener.incoming().flatten() {
+                let clients = Arc::clone(&accepted);
+                let hello = hello.clone();
+                thread::spawn(move || serve_client(connection, hello, &clients));
+            }
+        });
+        Ok(Streamer { every, frames_every, msgpack, atoms, clients })
+    }
+
+    pub fn thermo_due(&self, step: usize) -> bool {
+        step.is_multiple_of(self.every) && self.connected()
+    }
+
+    pub fn frame_due(&self, step: usize) -> bool {
+        step.is_multiple_of(self.frames_every) && self.connected()
+    }
+
+    pub fn thermo(&self, step: usize, time: f64, temperature: f64, kinetic_energy: f64, potential_energy: f64, pressure: f64) {
+        self.broadcast(&Payload::Thermo {
+            step,
+            time,
+            temperature,
+            kinetic_energy,
+            potential_energy,
+            total_energy: kinetic_energy + potential_energy,
+            pressure,
+        });
+    }
+
+    pub fn frame(&self, step: usize, time: f64, box_lengths: [f64; 3], positions: &[[f64; 3]]) {
+        let positions = self.atoms.iter().map(|&i| positions[i].map(|x| x as f32)).collect();
+        self.broadcast(&Payload::Frame { step, time, box_lengths, positions });
+    }
+
+    fn connected(&self) -> bool {
+        !self.clients.lock().unwrap().is_empty()
+    }
+
+    // Queues the message for every client, dropping those that have gone
+    fn broadcast(&self, payload: &Payload) {
+        let message = encode(self.msgpack, payload);
+        self.clients.lock().unwrap().retain(|client| match client.try_send(message.clone()) {
+            Ok(()) | Err(TrySendError::Full(_)) => true,
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+}
+
+#[cfg(not(feature = "stream"))]
+impl Streamer {
+    fn bind(_address: &str, _every: usize, _frames_every: usize, _msgpack: bool, _atoms: Vec<usize>, _info: RunInfo) -> std::io::Result<Self> {
+        unreachable!("--stream is rejected without the stream feature")
+    }
+
+    pub fn thermo_due(&self, _step: usize) -> bool {
+        false
+    }
+
+    pub fn frame_due(&self, _step: usize) -> bool {
+        false
+    }
+
+    pub fn thermo(&self, _step: usize, _time: f64, _temperature: f64, _kinetic_energy: f64, _potential_energy: f64, _pressure: f64) {}
+
+    pub fn frame(&self, _step: usize, _time: f64, _box_lengths: [f64; 3], _positions: &[[f64; 3]]) {}
+}
+
+#[cfg(feature = "stream")]
+fn encode(msgpack: bool, payload: &Payload) -> Message {
+    if msgpack {
+        Message::binary(rmp_serde::to_vec_named(payload).expect("Failed to encode stream message"))
+    } else {
+        Message::text(serde_json::to_string(payload).expect("Failed to encode stream message"))
+    }
+}
+
+// Completes the WebSocket handshake and sends the client its messages until it goes away
+#[cfg(feature = "stream")]
+fn serve_client(connection: TcpStream, hello: Message, clients: &Mutex<Vec<Sender<Message>>>) {
+    let _ = connection.set_nodelay(true);
+    let Ok(mut socket) = tungstenite::accept(connection) else { return };
+    if socket.send(hello).is_err() {
+        return;
+    }
+    let (sender, receiver) = crossbeam_channel::bounded(QUEUE);
+    clients.lock().unwrap().push(sender);
+    for message in receiver {
+        if socket.send(message).is_err() {
+            break;
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::compress::{self, CompressedWriter};
+
+#[cfg(feature = "parquet")]
+use std::fs::File;
+#[cfg(feature = "parquet")]
+use std::sync::Arc;
+#[cfg(feature = "parquet")]
+use arrow_array::{ArrayRef, Float64Array, RecordBatch};
+#[cfg(feature = "parquet")]
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+#[cfg(feature = "parquet")]
+use parquet::arrow::ArrowWriter;
+
+// Rows buffered per Parquet record batch
+#[cfg(feature = "parquet")]
+const BATCH_ROWS: usize = 4096;
+
+// Row-oriented writer for numeric tables (thermodynamic logs, analysis results).
+// The format follows the file extension: `.parquet` writes Apache Parquet, `.tsv` tab separated
+// text and anything else CSV. Text tables are compressed when the name ends in `.gz` or `.zst`.
+pub enum TableWriter {
+    Text { writer: CompressedWriter, separator: &'static str },
+    #[cfg(feature = "parquet")]
+    Parquet(Box<ParquetTable>),
+}
+
+impl TableWriter {
+    pub fn create<P: AsRef<Path>>(path: P, columns: &[&str]) -> io::Result<Self> {
+        let path = path.as_ref();
+        if path.extension().is_some_and(|ext| ext == "parquet") {
+            return Self::create_parquet(path, columns);
+        }
+        let separator = separator(path);
+        let mut writer = CompressedWriter::create(path)?;
+        writeln!(writer, "{}", columns.join(separator))?;
+        Ok(TableWriter::Text { writer, separator })
+    }
+
+    // Continues a text table at byte `offset`, as returned by `sync`
+    pub fn append<P: AsRef<Path>>(path: P, offset: u64) -> io::Result<Self> {
+        let path = path.as_ref();
+        if path.extension().is_some_and(|ext| ext == "parquet") {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "Parquet tables cannot be continued"));
+        }
+        Ok(TableWriter::Text { writer: CompressedWriter::append(path, offset)?, separator: separator(path) })
+    }
+
+    // Puts the rows so far on disk and returns the length of the file, for `append`
+    pub fn sync(&mut self) -> io::Result<u64> {
+        match self {
+            TableWriter::Text { writer, .. } => writer.sync(),
+            #[cfg(feature = "parquet")]
+            TableWriter::Parquet(_) => Err(io::Error::new(io::ErrorKind::Unsupported, "Parquet tables cannot be continued")),
+        }
+    }
+
+    #[cfg(feature = "parquet")]
+    fn create_parquet(path: &Path, columns: &[&str]) -> io::Result<Self> {
+        let fields: Vec<Field> = columns.iter().map(|name| Field::new(*name, DataType::Float64, false)).collect();
+        let schema = Arc::new(Schema::new(fields));
+        let writer = ArrowWriter::try_new(File::create(path)?, schema.clone(), None).map_err(io::Error::other)?;
+        Ok(TableWriter::Parquet(Box::new(ParquetTable {
+            schema,
+            writer,
+            buffers: vec![Vec::with_capacity(BATCH_ROWS); columns.len()],
+        })))
+    }
+
+    #[cfg(not(feature = "parquet"))]
+    fn create_parquet(_path: &Path, _columns: &[&str]) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "built without Parquet support (rebuild with --features parquet)",
+        ))
+    }
+
+    pub fn write_row(&mut self, values: &[f64]) -> io::Result<()> {
+        match self {
+            TableWriter::Text { writer, separator } => {
+                let row: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+                writeln!(writer, "{}", row.join(separator))
+            }
+            #[cfg(feature = "parquet")]
+            TableWriter::Parquet(table) => table.write_row(values),
+        }
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            TableWriter::Text { writer, .. } => writer.finish(),
+            #[cfg(feature = "parquet")]
+            TableWriter::Parquet(table) => table.finish(),
+        }
+    }
+}
+
+fn separator(path: &Path) -> &'static str {
+    if compress::inner_extension(&path.to_string_lossy()) == "tsv" {
+        "\t"
+    } else {
+        ","
+    }
+}
+
+#[cfg(feature = "parquet")]
+pub struct ParquetTable {
+    schema: SchemaRef,
+    writer: ArrowWriter<File>,
+    buffers: Vec<Vec<f64>>,
+}
+
+#[cfg(feature = "parquet")]
+impl ParquetTable {
+    fn write_row(&mut self, values: &[f64]) -> io::Result<()> {
+        for (buffer, &value) in self.buffers.iter_mut().zip(values) {
+            buffer.push(value);
+        }
+        if self.buffers[0].len() >= BATCH_ROWS {
+            self.flush_batch()?;
+        }
+        Ok(())
+    }
+
+    fn flush_batch(&mut self) -> io::Result<()> {
+        if self.buffers.first().is_none_or(|buffer| buffer.is_empty()) {
+            return Ok(());
+        }
+        let arrays: Vec<ArrayRef> = self.buffers.iter_mut()
+            .map(|buffer| Arc::new(Float64Array::from(std::mem::take(buffer))) as ArrayRef)
+            .collect();
+        let batch = RecordBatch::try_new(self.schema.clone(), arrays).map_err(io::Error::other)?;
+        self.writer.write(&batch).map_err(io::Error::other)
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        self.flush_batch()?;
+        self.writer.close().map_err(io::Error::other)?;
+        Ok(())
+    }
+}
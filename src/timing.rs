@@ -0,0 +1,61 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing::span::Id;
+use tracing::Subscriber;
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+// Wall time per phase of a run, from `tracing` spans: every span is a phase named after it, and
+// the time from entering to leaving it is added to that phase each time. A loop creates its
+// spans once and enters them every step, so a phase entered twice in a step counts twice.
+#[derive(Clone, Default)]
+pub struct PhaseTimer {
+    // Name and total time of each phase, in the order they were first left
+    phases: Arc<Mutex<Vec<(&'static str, Duration)>>>,
+}
+
+impl PhaseTimer {
+    // Name and time of every phase so far
+    pub fn phases(&self) -> Vec<(&'static str, Duration)> {
+        self.phases.lock().unwrap().clone()
+    }
+
+    // Table of the phases with their share of `total`, and the rest of it as "other"
+    pub fn table(&self, total: Duration) -> String {
+        let phases = self.phases();
+        let timed: Duration = phases.iter().map(|(_, time)| *time).sum();
+        let total_seconds = total.as_secs_f64().max(f64::MIN_POSITIVE);
+        let mut table = format!("{:<12} {:>10} {:>7}\n", "phase", "time (s)", "share");
+        let rows = phases.into_iter().chain([("other", total.saturating_sub(timed)), ("total", total)]);
+        for (name, time) in rows {
+            let seconds = time.as_secs_f64();
+            table += &format!("{:<12} {:>10.3} {:>6.1}%\n", name, seconds, 100.0 * seconds / total_seconds);
+        }
+        table
+    }
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for PhaseTimer {
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().replace(Instant::now());
+        }
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let Some(entered) = span.extensions_mut().remove::<Instant>() else {
+            return;
+        };
+        let elapsed = entered.elapsed();
+        let name = span.name();
+        let mut phases = self.phases.lock().unwrap();
+        match phases.iter_mut().find(|(phase, _)| *phase == name) {
+            Some((_, time)) => *time += elapsed,
+            None => phases.push((name, elapsed)),
+        }
+    }
+}
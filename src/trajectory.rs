@@ -0,0 +1,493 @@
+use crossbeam_channel::{Receiver, Sender};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+use std::thread::{self, JoinHandle};
+
+use crate::compress::{self, CompressedWriter, Compression};
+use crate::dcd::DcdWriter;
+use crate::geometry::Cell;
+use crate::h5md::H5mdWriter;
+use crate::lammps::{self, DumpAtoms, DumpColumns};
+use crate::units::{self, Units};
+use crate::xtc::XtcWriter;
+use crate::xyz;
+
+pub const FORMATS: [&str; 7] = ["json", "xyz", "extxyz", "dcd", "xtc", "dump", "h5md"];
+
+#[derive(Serialize, Deserialize)]
+pub struct SimulationData {
+    pub box_length: f64,
+    // Edge vectors of the periodic cell as rows; files from before it was recorded only have
+    // the cubic `box_length`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cell: Option<[[f64; 3]; 3]>,
+    pub num_atoms: usize,
+    pub timestep: f64,
+    pub total_steps: usize,
+    pub snapshot_interval: usize,
+    // Seed the run started from, when it has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    // Units of every length, time, energy and pressure in the file; files without it are native
+    #[serde(default, skip_serializing_if = "Units::is_native")]
+    pub units: Units,
+    pub trajectory: Vec<Vec<[f64; 3]>>,
+    // Virial pressure of every frame; empty in files from before it was recorded
+    #[serde(default)]
+    pub pressure: Vec<f64>,
+    // Pressure tensor of every frame as [xx, yy, zz, xy, xz, yz], when the run computed it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stress: Option<Vec<[f64; 6]>>,
+    // Velocities of every frame, when the run stored them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub velocities: Option<Vec<Vec<[f64; 3]>>>,
+}
+
+impl SimulationData {
+    // Time between two stored frames
+    pub fn frame_interval(&self) -> f64 {
+        self.timestep * self.snapshot_interval as f64
+    }
+
+    // The periodic cell, cubic for files without one
+    pub fn cell(&self) -> Cell {
+        self.cell.and_then(Cell::triclinic).unwrap_or_else(|| Cell::cubic(self.box_length))
+    }
+
+    // Edge of the box for the analyses that only handle cubic boxes
+    pub fn cubic_box_length(&self) -> Result<f64, String> {
+        let cell = self.cell();
+        let [a, b, c] = cell.widths();
+        if cell.is_orthorhombic() && a == b && b == c {
+            Ok(a)
+        } else {
+            Err(format!("the box is not cubic ({:?}), which this analysis does not handle", cell.lengths()))
+        }
+    }
+}
+
+// Reads a JSON trajectory, gzip or Zstandard compressed if the name ends in `.gz` or `.zst`.
+// A file cut short by a crashed run is read up to its last complete frame.
+pub fn load_json<P: AsRef<Path>>(path: P) -> io::Result<SimulationData> {
+    let mut bytes = Vec::new();
+    match compress::open(&path)?.read_to_end(&mut bytes) {
+        // A compressed stream that ends early still yields what was decompressed so far
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {}
+        result => {
+            result?;
+        }
+    }
+    let contents = String::from_utf8(bytes).map_err(io::Error::other)?;
+    match serde_json::from_str(&contents) {
+        Err(e) if e.is_eof() => {
+            let repaired = close_truncated(&contents).ok_or_else(|| io::Error::other(e))?;
+            let data: SimulationData = serde_json::from_str(&repaired).map_err(io::Error::other)?;
+            eprintln!(
+                "Warning: {} is incomplete, read {} frames",
+                path.as_ref().display(),
+                data.trajectory.len()
+            );
+            Ok(data)
+        }
+        result => result.map_err(io::Error::other),
+    }
+}
+
+// Closes the trajectory array after the last complete frame (`...]]`) of a truncated document
+fn close_truncated(contents: &str) -> Option<String> {
+    let start = contents.find("\"trajectory\":[")? + "\"trajectory\":[".len();
+    let end = contents[start..].rfind("]]").map_or(start, |i| start + i + 2);
+    Some(format!("{}]}}", &contents[..end]))
+}
+
+// Trajectory output of the main run. Every format appends each frame to the file as it is
+// produced; JSON writes the run parameters first and closes the document in `finish`.
+// The text formats are compressed when the file name ends in `.gz` or `.zst`.
+pub enum TrajectoryWriter {
+    // `simulation_data.json`, with whether a frame has been written yet. The per-frame pressures
+    // follow the frames, so they are kept until `finish`. Velocities, if stored, are as big as
+    // the frames and go to a file of their own that `finish` copies in.
+    Json {
+        writer: CompressedWriter,
+        started: bool,
+        pressure: Vec<f64>,
+        stress: Option<Vec<[f64; 6]>>,
+        velocities: Option<(Box<CompressedWriter>, String)>,
+    },
+    // Plain `Ar x y z` frames with the step and time in the comment line
+    Xyz(CompressedWriter),
+    // Extended XYZ with the box and velocities, and per-atom energies and order parameters if
+    // asked for
+    ExtXyz(CompressedWriter, DumpColumns),
+    // Binary CHARMM/NAMD DCD with the box of every frame
+    Dcd(DcdWriter),
+    // GROMACS XTC, lossy with a resolution of 0.001 length units
+    Xtc(XtcWriter),
+    // LAMMPS text dump, with velocity and force columns on request
+    Dump(CompressedWriter, DumpColumns),
+    // H5MD, with velocities, the box and the thermodynamic time series of every frame
+    H5md(H5mdWriter),
+}
+
+impl TrajectoryWriter {
+    // `header` supplies the run parameters, its trajectory and pressures are ignored.
+    // `dump_columns` only applies to the LAMMPS dump format, apart from the energies and order
+    // parameters that extended XYZ also takes and the velocities that JSON also takes.
+    pub fn create(format: &str, path: &str, header: SimulationData, dump_columns: DumpColumns) -> io::Result<Self> {
+        check_compression(format, path)?;
+        match format {
+            "json" => {
+                let mut writer = CompressedWriter::create(path)?;
+                // Same layout serde gives SimulationData, with the frames left open
+                let stress = header.stress.as_ref().map(|_| Vec::new());
+                let json = serde_json::to_string(&SimulationData {
+                    trajectory: Vec::new(),
+                    pressure: Vec::new(),
+                    stress: None,
+                    velocities: None,
+                    ..header
+                }).map_err(io::Error::other)?;
+                let open = json.find("\"trajectory\":[]").ok_or_else(|| io::Error::other("unexpected JSON layout"))?;
+                writer.write_all(&json.as_bytes()[..open + "\"trajectory\":[".len()])?;
+                let velocities = if dump_columns.velocities {
+                    let path = velocities_path(path);
+                    Some((Box::new(CompressedWriter::create(&path)?), path))
+                } else {
+                    None
+                };
+                Ok(TrajectoryWriter::Json { writer, started: false, pressure: Vec::new(), stress, velocities })
+            }
+            "xyz" => Ok(TrajectoryWriter::Xyz(CompressedWriter::create(path)?)),
+            "extxyz" => Ok(TrajectoryWriter::ExtXyz(CompressedWriter::create(path)?, dump_columns)),
+            "dcd" => Ok(TrajectoryWriter::Dcd(DcdWriter::create(
+                path,
+                header.num_atoms,
+                0,
+                header.snapshot_interval,
+                units::picoseconds(header.timestep / header.units.time()),
+            )?)),
+            "xtc" => Ok(TrajectoryWriter::Xtc(XtcWriter::create(path, 1000.0)?)),
+            "dump" => Ok(TrajectoryWriter::Dump(CompressedWriter::create(path)?, dump_columns)),
+            "h5md" => Ok(TrajectoryWriter::H5md(H5mdWriter::create(path, &header, dump_columns.forces)?)),
+            other => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown trajectory format {}", other))),
+        }
+    }
+
+    // Continues the output of an interrupted run, from the `offset` that `sync` returned after
+    // `frames` frames. For JSON `header` carries the pressures of those frames.
+    pub fn append(
+        format: &str,
+        path: &str,
+        offset: u64,
+        frames: usize,
+        header: SimulationData,
+        dump_columns: DumpColumns,
+    ) -> io::Result<Self> {
+        check_compression(format, path)?;
+        match format {
+            "json" => {
+                let velocities = if dump_columns.velocities {
+                    let path = velocities_path(path);
+                    let offset = frames_end(&path, frames)?;
+                    Some((Box::new(CompressedWriter::append(&path, offset)?), path))
+                } else {
+                    None
+                };
+                Ok(TrajectoryWriter::Json {
+                    writer: CompressedWriter::append(path, offset)?,
+                    started: frames > 0,
+                    pressure: header.pressure,
+                    stress: header.stress,
+                    velocities,
+                })
+            }
+            "xyz" => Ok(TrajectoryWriter::Xyz(CompressedWriter::append(path, offset)?)),
+            "extxyz" => Ok(TrajectoryWriter::ExtXyz(CompressedWriter::append(path, offset)?, dump_columns)),
+            "dcd" => Ok(TrajectoryWriter::Dcd(DcdWriter::append(path, header.num_atoms, frames as u32, offset)?)),
+            "xtc" => Ok(TrajectoryWriter::Xtc(XtcWriter::append(path, 1000.0, offset)?)),
+            "dump" => Ok(TrajectoryWriter::Dump(CompressedWriter::append(path, offset)?, dump_columns)),
+            "h5md" => Ok(TrajectoryWriter::H5md(H5mdWriter::append(path, frames, dump_columns.forces, header.stress.is_some())?)),
+            other => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown trajectory format {}", other))),
+        }
+    }
+
+    // Default file name of each format
+    pub fn path_for(format: &str) -> String {
+        match format {
+            "json" => "simulation_data.json".to_string(),
+            "dump" => "trajectory.lammpstrj".to_string(),
+            "h5md" => "trajectory.h5".to_string(),
+            _ => format!("trajectory.{}", format),
+        }
+    }
+
+    pub fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        let Frame { step, time, ref positions, ref velocities, ref forces, ref energies, ref order, box_lengths, .. } = *frame;
+        match self {
+            TrajectoryWriter::Json { writer, started, pressure, stress, velocities: sidecar } => {
+                if *started {
+                    writer.write_all(b",")?;
+                }
+                pressure.push(frame.pressure);
+                if let (Some(stress), Some(tensor)) = (stress, frame.stress) {
+                    stress.push(tensor);
+                }
+                if let Some((sidecar, _)) = sidecar {
+                    if *started {
+                        sidecar.write_all(b",")?;
+                    }
+                    serde_json::to_writer(sidecar.as_mut(), velocities).map_err(io::Error::other)?;
+                }
+                *started = true;
+                serde_json::to_writer(writer, positions).map_err(io::Error::other)
+            }
+            TrajectoryWriter::Xyz(writer) => {
+                xyz::write_plain_frame(writer, positions, &format!("step {} time {}", step, time))
+            }
+            TrajectoryWriter::ExtXyz(writer, columns) => {
+                let comment = format!("Step={} Time={}", step, time);
+                let mut extra: Vec<(&str, &[f64])> = Vec::new();
+                if columns.energy {
+                    extra.push(("energy", energies));
+                }
+                let order: Vec<Vec<f64>> = (0..3).map(|c| order.iter().map(|q| q[c]).collect()).collect();
+                if columns.order {
+                    extra.extend([("q4", order[0].as_slice()), ("q6", &order[1]), ("w6", &order[2])]);
+                }
+                xyz::write_frame_with_columns(writer, positions, None, Some(velocities.as_slice()), &extra, box_lengths, &comment)
+            }
+            TrajectoryWriter::Dcd(writer) => writer.write_frame(positions, box_lengths),
+            TrajectoryWriter::Xtc(writer) => writer.write_frame(step, time, positions, box_lengths),
+            TrajectoryWriter::Dump(writer, columns) => {
+                let atoms = DumpAtoms {
+                    positions,
+                    velocities: columns.velocities.then_some(velocities.as_slice()),
+                    forces: columns.forces.then_some(forces.as_slice()),
+                    energies: columns.energy.then_some(energies.as_slice()),
+                    order: columns.order.then_some(order.as_slice()),
+                };
+                lammps::write_dump_frame(writer, step, time, &atoms, box_lengths)
+            }
+            TrajectoryWriter::H5md(writer) => writer.write_frame(frame),
+        }
+    }
+
+    // Puts every frame written so far on disk and returns the file length, which `append`
+    // continues from; H5MD returns the frame count instead. A JSON file is left open and only
+    // readable through its recovery.
+    pub fn sync(&mut self) -> io::Result<u64> {
+        match self {
+            TrajectoryWriter::Json { writer, velocities, .. } => {
+                if let Some((sidecar, _)) = velocities {
+                    sidecar.sync()?;
+                }
+                writer.sync()
+            }
+            TrajectoryWriter::Xyz(writer)
+            | TrajectoryWriter::ExtXyz(writer, _)
+            | TrajectoryWriter::Dump(writer, _) => writer.sync(),
+            TrajectoryWriter::Dcd(writer) => writer.sync(),
+            TrajectoryWriter::Xtc(writer) => writer.sync(),
+            TrajectoryWriter::H5md(writer) => writer.sync(),
+        }
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        self.close(false)
+    }
+
+    // Like `finish`, for a run that stops early and will `append` later: the file is complete
+    // and readable, and what `append` needs to continue it is kept
+    pub fn suspend(self) -> io::Result<()> {
+        self.close(true)
+    }
+
+    fn close(self, resumable: bool) -> io::Result<()> {
+        match self {
+            TrajectoryWriter::Json { mut writer, pressure, stress, velocities, .. } => {
+                writer.write_all(b"],\"pressure\":")?;
+                serde_json::to_writer(&mut writer, &pressure).map_err(io::Error::other)?;
+                if let Some(stress) = stress {
+                    writer.write_all(b",\"stress\":")?;
+                    serde_json::to_writer(&mut writer, &stress).map_err(io::Error::other)?;
+                }
+                if let Some((sidecar, path)) = velocities {
+                    sidecar.finish()?;
+                    writer.write_all(b",\"velocities\":[")?;
+                    io::copy(&mut File::open(&path)?, &mut writer)?;
+                    writer.write_all(b"]")?;
+                    if !resumable {
+                        fs::remove_file(&path)?;
+                    }
+                }
+                writer.write_all(b"}")?;
+                writer.finish()
+            }
+            TrajectoryWriter::Xyz(writer) | TrajectoryWriter::ExtXyz(writer, _) | TrajectoryWriter::Dump(writer, _) => {
+                writer.finish()
+            }
+            TrajectoryWriter::Dcd(writer) => writer.finish(),
+            TrajectoryWriter::Xtc(writer) => writer.finish(),
+            TrajectoryWriter::H5md(writer) => writer.finish(),
+        }
+    }
+}
+
+// Where a JSON run keeps its velocities until it finishes
+fn velocities_path(path: &str) -> String {
+    format!("{}.velocities", path)
+}
+
+// Length of the first `frames` frames of a velocities file. Every frame ends in `]]`, which
+// nothing inside a frame contains.
+fn frames_end(path: &str, frames: usize) -> io::Result<u64> {
+    if frames == 0 {
+        return Ok(0);
+    }
+    let mut found = 0;
+    let mut previous = 0u8;
+    for (offset, byte) in BufReader::new(File::open(path)?).bytes().enumerate() {
+        let byte = byte?;
+        if previous == b']' && byte == b']' {
+            found += 1;
+            if found == frames {
+                return Ok(offset as u64 + 1);
+            }
+        }
+        previous = byte;
+    }
+    Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("{} holds fewer than {} frames", path, frames)))
+}
+
+fn check_compression(format: &str, path: &str) -> io::Result<()> {
+    if Compression::from_path(path) != Compression::None && matches!(format, "dcd" | "xtc" | "h5md") {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} output cannot be compressed", format)));
+    }
+    Ok(())
+}
+
+enum Message {
+    Frame(Box<Frame>),
+    // Asks for `TrajectoryWriter::sync` once the frames queued before are written
+    Sync(Sender<io::Result<u64>>),
+    // Closes the writer with `TrajectoryWriter::suspend` instead of `finish`
+    Suspend,
+}
+
+// One stored frame, owned so it can cross to the writer thread
+#[derive(Default)]
+pub struct Frame {
+    pub step: usize,
+    pub time: f64,
+    pub positions: Vec<[f64; 3]>,
+    pub velocities: Vec<[f64; 3]>,
+    pub forces: Vec<[f64; 3]>,
+    // Potential energy of each atom, empty unless the format writes it
+    pub energies: Vec<f64>,
+    // Steinhardt q4, q6 and w6 of each atom, empty unless the format writes them
+    pub order: Vec<[f64; 3]>,
+    pub box_lengths: [f64; 3],
+    // Virial pressure, and the pressure tensor as [xx, yy, zz, xy, xz, yz] if computed
+    pub pressure: f64,
+    pub stress: Option<[f64; 6]>,
+    // Thermodynamic state of the frame, for the formats that keep time series
+    pub temperature: f64,
+    pub potential_energy: f64,
+    pub kinetic_energy: f64,
+}
+
+// Runs a `TrajectoryWriter` on its own thread so serialization and disk writes overlap the
+// force computation. At most `capacity` frames wait in the queue; only when the disk falls that
+// far behind does `write_frame` block the simulation. Written frames come back through
+// `spare_frame`, so their buffers can be filled again instead of allocated for every frame.
+pub struct BackgroundWriter {
+    sender: Option<Sender<Message>>,
+    handle: Option<JoinHandle<io::Result<()>>>,
+    spares: Receiver<Box<Frame>>,
+}
+
+impl BackgroundWriter {
+    pub fn spawn(writer: TrajectoryWriter, capacity: usize) -> Self {
+        let (sender, receiver) = crossbeam_channel::bounded::<Message>(capacity);
+        let (spare_sender, spares) = crossbeam_channel::bounded::<Box<Frame>>(capacity);
+        let handle = thread::Builder::new()
+            .name("trajectory-writer".to_string())
+            .spawn(move || {
+                let mut writer = writer;
+                let mut resumable = false;
+                for message in receiver {
+                    match message {
+                        Message::Frame(frame) => {
+                            writer.write_frame(&frame)?;
+                            let _ = spare_sender.try_send(frame);
+                        }
+                        Message::Sync(reply) => {
+                            let _ = reply.send(writer.sync());
+                        }
+                        Message::Suspend => resumable = true,
+                    }
+                }
+                writer.close(resumable)
+            })
+            .expect("Failed to start trajectory writer thread");
+        BackgroundWriter { sender: Some(sender), handle: Some(handle), spares }
+    }
+
+    // A frame already written, with whatever it held, or a new empty one if none has come back
+    pub fn spare_frame(&self) -> Box<Frame> {
+        self.spares.try_recv().unwrap_or_default()
+    }
+
+    // Queues a frame. A write that failed on the writer thread is reported here, by the first
+    // frame queued after it.
+    pub fn write_frame(&mut self, frame: Box<Frame>) -> io::Result<()> {
+        self.send(Message::Frame(frame))
+    }
+
+    // Waits until the queued frames are on disk and returns the file length (see
+    // `TrajectoryWriter::sync`)
+    pub fn sync(&mut self) -> io::Result<u64> {
+        let (reply, response) = crossbeam_channel::bounded(1);
+        self.send(Message::Sync(reply))?;
+        match response.recv() {
+            Ok(result) => result,
+            Err(_) => {
+                self.sender = None;
+                self.join()?;
+                Err(io::Error::other("trajectory writer already stopped"))
+            }
+        }
+    }
+
+    fn send(&mut self, message: Message) -> io::Result<()> {
+        let sent = self.sender.as_ref().map(|sender| sender.send(message).is_ok());
+        if sent == Some(true) {
+            return Ok(());
+        }
+        // The thread only drops the receiver when it stops on an error
+        self.sender = None;
+        self.join()?;
+        Err(io::Error::other("trajectory writer already stopped"))
+    }
+
+    // Closes the file like `finish` for a run that will be resumed, see `TrajectoryWriter::suspend`
+    pub fn suspend(mut self) -> io::Result<()> {
+        self.send(Message::Suspend)?;
+        self.finish()
+    }
+
+    // Waits for the queued frames to reach the disk and closes the file
+    pub fn finish(mut self) -> io::Result<()> {
+        self.sender = None;
+        self.join()
+    }
+
+    fn join(&mut self) -> io::Result<()> {
+        match self.handle.take() {
+            Some(handle) => handle.join().unwrap_or_else(|_| Err(io::Error::other("trajectory writer thread panicked"))),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::cli::Args;
+use crate::colvars;
+use crate::trajectory::TrajectoryWriter;
+
+const USAGE: &str = "Usage: sim umbrella <colvars.toml> --colvar NAME --centres start:end:count|c1,c2,... --bias-k K \
+[--jobs 1] [--output-dir umbrella] -- <main run arguments>";
+
+// Main-run outputs that every window writes into its own directory, with their default names
+const OUTPUTS: [(&str, &str); 5] = [
+    ("thermo", "thermo.csv"),
+    ("checkpoint", "checkpoint.bin"),
+    ("heat-current", "heat_current.csv"),
+    ("profile", "profile.csv"),
+    ("status-file", "status.json"),
+];
+
+// One umbrella window as listed in a metadata file: the colvars log of its run, the colvar
+// and the harmonic bias it was sampled under
+pub struct Window {
+    pub path: PathBuf,
+    pub colvar: String,
+    pub centre: f64,
+    pub spring: f64,
+}
+
+// `sim umbrella colvars.toml --colvar gap --centres 2:5:13 --bias-k 50 -- 10 500 0.001 100000 1000 ...`:
+// runs the main run once per window with the colvar biased towards the window centre, each in
+// `<output-dir>/window_<i>`, and writes `<output-dir>/metadata.txt` for `sim analyze wham`
+pub fn run(args: &[String]) {
+    let Some(split) = args.iter().position(|arg| arg == "--") else {
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
+    };
+    let main_args = &args[split + 1..];
+    let args = Args::parse(&args[..split]);
+    if args.positional.len() != 1 || main_args.is_empty() {
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
+    }
+    let path = &args.positional[0];
+    let jobs: usize = args.get("jobs", 1);
+    let output_dir: String = args.get("output-dir", "umbrella".to_string());
+    let spring: f64 = args.get("bias-k", 0.0);
+    let (Some(name), Some(centres)) = (args.get_opt::<String>("colvar"), args.get_opt::<String>("centres")) else {
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
+    };
+    let Some(centres) = parse_centres(&centres) else {
+        eprintln!("Invalid value for --centres: {} (expected start:end:count or a list of centres)", centres);
+        std::process::exit(1);
+    };
+    if spring <= 0.0 || jobs == 0 {
+        eprintln!("--bias-k must be positive and --jobs at least 1");
+        std::process::exit(1);
+    }
+    let defined = colvars::read_colvars(path).unwrap_or_else(|e| {
+        eprintln!("Invalid collective variables {}: {}", path, e);
+        std::process::exit(1);
+    });
+    if !defined.iter().any(|colvar| colvar.name == name) {
+        eprintln!("{} defines no colvar {}", path, name);
+        std::process::exit(1);
+    }
+    let text = fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("Failed to read {}: {}", path, e);
+        std::process::exit(1);
+    });
+
+    let main_options = Args::parse(main_args);
+    let output_format: String = main_options.get("output-format", "json".to_string());
+    let width = (centres.len() - 1).to_string().len();
+    let mut metadata = String::from("# window colvar centre spring\n");
+    let windows: Vec<(PathBuf, Vec<String>)> = centres.iter().enumerate().map(|(i, &centre)| {
+        let name_dir = format!("window_{:0width$}", i, width = width);
+        let dir = Path::new(&output_dir).join(&name_dir);
+        fs::create_dir_all(&dir).unwrap_or_else(|e| {
+            eprintln!("Failed to create {}: {}", dir.display(), e);
+            std::process::exit(1);
+        });
+        let colvars_path = dir.join("colvars.toml");
+        if let Err(e) = fs::write(&colvars_path, biased(&text, &name, centre, spring)) {
+            eprintln!("Failed to write {}: {}", colvars_path.display(), e);
+            std::process::exit(1);
+        }
+        let in_dir = |file: &str| dir.join(Path::new(file).file_name().unwrap()).to_string_lossy().into_owned();
+        // Later options override earlier ones, so appending redirects the outputs
+        let mut window_args = main_args.to_vec();
+        let output: String = main_options.get("output", TrajectoryWriter::path_for(&output_format));
+        window_args.extend(["--output".to_string(), in_dir(&output)]);
+        window_args.extend(["--colvars".to_string(), colvars_path.to_string_lossy().into_owned()]);
+        window_args.extend(["--colvars-log".to_string(), in_dir("colvars.csv")]);
+        for (option, default) in OUTPUTS {
+            if main_options.has(option) || option == "thermo" || option == "checkpoint" {
+                let file: String = main_options.get(option, default.to_string());
+                window_args.extend([format!("--{}", option), in_dir(&file)]);
+            }
+        }
+        metadata.push_str(&format!("{}/colvars.csv {} {} {}\n", name_dir, name, centre, spring));
+        (dir, window_args)
+    }).collect();
+    let metadata_path = Path::new(&output_dir).join("metadata.txt");
+    if let Err(e) = fs::write(&metadata_path, metadata) {
+        eprintln!("Failed to write {}: {}", metadata_path.display(), e);
+        std::process::exit(1);
+    }
+    println!("Running {} umbrella windows on {} from {} to {}, {} at a time", windows.len(), name, centres[0], centres[centres.len() - 1], jobs);
+
+    // Windows run as separate main-run processes, `jobs` at a time, splitting the threads between them
+    let exe = env::current_exe().expect("Failed to locate the sim executable");
+    let threads = rayon::current_num_threads();
+    let threads_per_job = (threads / jobs).max(1);
+    let next = AtomicUsize::new(0);
+    let failed = Mutex::new(Vec::new());
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.min(windows.len()) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some((dir, window_args)) = windows.get(i) else { break };
+                let log = dir.join("log.txt");
+                let status = fs::File::create(&log).and_then(|file| {
+                    Command::new(&exe)
+                        .args(window_args)
+                        .env("RAYON_NUM_THREADS", threads_per_job.to_string())
+                        .stdout(Stdio::from(file.try_clone()?))
+                        .stderr(Stdio::from(file))
+                        .status()
+                });
+                match status {
+                    Ok(status) if status.success() => println!("Window {} (centre {}) done, log in {}", i, centres[i], log.display()),
+                    Ok(status) => {
+                        eprintln!("Window {} failed ({}), see {}", i, status, log.display());
+                        failed.lock().unwrap().push(i);
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to start window {}: {}", i, e);
+                        failed.lock().unwrap().push(i);
+                    }
+                }
+            });
+        }
+    });
+
+    let failed = failed.into_inner().unwrap();
+    if !failed.is_empty() {
+        eprintln!("{} of {} windows failed", failed.len(), windows.len());
+        std::process::exit(1);
+    }
+    println!("All {} windows completed. Free energy profile: sim analyze wham {}", windows.len(), metadata_path.display());
+}
+
+// `start:end:count` with both ends included, or a comma-separated list
+fn parse_centres(text: &str) -> Option<Vec<f64>> {
+    let centres: Vec<f64> = match text.split(':').collect::<Vec<_>>()[..] {
+        [start, end, count] => {
+            let (start, end): (f64, f64) = (start.parse().ok()?, end.parse().ok()?);
+            let count: usize = count.parse().ok().filter(|&n| n >= 2)?;
+            (0..count).map(|i| start + (end - start) * i as f64 / (count - 1) as f64).collect()
+        }
+        [list] => list.split(',').map(|c| c.trim().parse().ok()).collect::<Option<_>>()?,
+        _ => return None,
+    };
+    (!centres.is_empty() && centres.iter().all(|c| c.is_finite())).then_some(centres)
+}
+
+// The colvars file with the bias of colvar `name` set to `centre` and `spring`
+fn biased(text: &str, name: &str, centre: f64, spring: f64) -> String {
+    let mut table: toml::Table = text.parse().expect("colvars file already read");
+    if let Some(toml::Value::Array(colvars)) = table.get_mut("colvar") {
+        for colvar in colvars.iter_mut().filter_map(toml::Value::as_table_mut) {
+            if colvar.get("name").and_then(toml::Value::as_str) == Some(name) {
+                colvar.insert("bias_centre".to_string(), toml::Value::Float(centre));
+                colvar.insert("bias_k".to_string(), toml::Value::Float(spring));
+            }
+        }
+    }
+    table.to_string()
+}
+
+// Reads a metadata file of `path colvar centre spring` lines, paths relative to the file
+pub fn read_metadata<P: AsRef<Path>>(path: P) -> Result<Vec<Window>, String> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let base = path.parent().unwrap_or(Path::new(""));
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| match line.split_whitespace().collect::<Vec<_>>()[..] {
+            [file, colvar, centre, spring] => match (centre.parse(), spring.parse()) {
+                (Ok(centre), Ok(spring)) => Ok(Window { path: base.join(file), colvar: colvar.to_string(), centre, spring }),
+                _ => Err(format!("invalid centre or spring in {:?}", line)),
+            },
+            _ => Err(format!("expected path colvar centre spring, got {:?}", line)),
+        })
+        .collect()
+}
+
+pub struct Pmf {
+    // Bin centres
+    pub colvar: Vec<f64>,
+    // Free energy relative to its minimum, NaN in bins no window sampled
+    pub free_energy: Vec<f64>,
+    pub probability: Vec<f64>,
+    // Free energy offsets f_i of the windows, relative to the first
+    pub window_offsets: Vec<f64>,
+    pub iterations: usize,
+    pub converged: bool,
+}
+
+// Weighted histogram analysis method: unbiases and combines the histograms of the `samples`
+// of every window, biased by E = k/2 (s - centre)^2 with the (centre, spring) in `biases`, by
+// iterating the self-consistent equations for the window free energies until they change by
+// less than `tolerance` (in kT). Sums are taken in log space to keep the weights in range.
+pub fn wham(samples: &[Vec<f64>], biases: &[(f64, f64)], range: (f64, f64), bins: usize, kt: f64, tolerance: f64, max_iterations: usize) -> Pmf {
+    let (lo, hi) = range;
+    let width = (hi - lo) / bins as f64;
+    let colvar: Vec<f64> = (0..bins).map(|b| lo + (b as f64 + 0.5) * width).collect();
+    let mut counts = vec![0.0f64; bins];
+    let mut ln_n = Vec::with_capacity(samples.len());
+    for window in samples {
+        let mut n = 0.0;
+        for &s in window {
+            if s >= lo && s < hi {
+                counts[((s - lo) / width) as usize] += 1.0;
+                n += 1.0;
+            }
+        }
+        ln_n.push(f64::ln(n));
+    }
+    // Reduced bias of window i in bin b
+    let bias: Vec<Vec<f64>> = biases.iter().map(|&(centre, spring)| {
+        colvar.iter().map(|&s| 0.5 * spring * (s - centre).powi(2) / kt).collect()
+    }).collect();
+
+    let mut offsets = vec![0.0; samples.len()];
+    let mut ln_p = vec![f64::NEG_INFINITY; bins];
+    let mut iterations = 0;
+    let mut converged = false;
+    while iterations < max_iterations && !converged {
+        iterations += 1;
+        for b in 0..bins {
+            ln_p[b] = if counts[b] > 0.0 {
+                counts[b].ln() - log_sum_exp((0..samples.len()).map(|i| ln_n[i] - bias[i][b] + offsets[i]))
+            } else {
+                f64::NEG_INFINITY
+            };
+        }
+        let mut updated: Vec<f64> = bias.iter().map(|bias| -log_sum_exp((0..bins).map(|b| ln_p[b] - bias[b]))).collect();
+        let first = updated[0];
+        updated.iter_mut().for_each(|f| *f -= first);
+        converged = updated.iter().zip(&offsets).all(|(a, b)| (a - b).abs() < tolerance);
+        offsets = updated;
+    }
+
+    let ln_total = log_sum_exp(ln_p.iter().copied());
+    let probability: Vec<f64> = ln_p.iter().map(|&p| (p - ln_total).exp()).collect();
+    let lowest = ln_p.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let free_energy = ln_p.iter().map(|&p| if p.is_finite() { kt * (lowest - p) } else { f64::NAN }).collect();
+    Pmf { colvar, free_energy, probability, window_offsets: offsets.iter().map(|f| f * kt).collect(), iterations, converged }
+}
+
+fn log_sum_exp(terms: impl Iterator<Item = f64> + Clone) -> f64 {
+    let max = terms.clone().fold(f64::NEG_INFINITY, f64::max);
+    if max == f64::NEG_INFINITY {
+        return max;
+    }
+    max + terms.map(|t| (t - max).exp()).sum::<f64>().ln()
+}
@@ -0,0 +1,227 @@
+// Units the main run reads its box, timestep and starting configuration in and writes its
+// trajectory and thermo log in, chosen with `--units`.
+//
+// The engine itself always works in one system: lengths in sigma, energies in epsilon taken as
+// 1 kJ/mol, masses in amu (argon, 39.95), temperatures in K through k_B in kJ/mol/K, and time
+// in sigma sqrt(amu / (kJ/mol)). That is `native`, the default, and what every other command
+// reads and writes. The other systems follow LAMMPS and map sigma onto argon's 3.405 Å:
+// - `reduced`: LJ units with sigma = epsilon = k_B = 1 and the argon mass as the unit of mass
+// - `real`: Å, fs, kcal/mol, K, Å/fs, kcal/mol/Å and atm
+// - `metal`: Å, ps, eV, K, Å/ps, eV/Å and bar
+
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use crate::simulation::{KB, MASS_ARGON};
+use crate::trajectory::Frame;
+
+// Sigma of argon in Å
+const SIGMA_ARGON: f64 = 3.405;
+const AVOGADRO: f64 = 6.02214076e23;
+// Joules per eV
+const ELECTRON_VOLT: f64 = 1.602176634e-19;
+// kJ per kcal
+const KILOCALORIE: f64 = 4.184;
+// Pascals per atm and per bar
+const ATMOSPHERE: f64 = 101325.0;
+const BAR: f64 = 1e5;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Units {
+    #[default]
+    Native,
+    Reduced,
+    Real,
+    Metal,
+}
+
+impl FromStr for Units {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String> {
+        match text {
+            "native" => Ok(Units::Native),
+            "reduced" | "lj" => Ok(Units::Reduced),
+            "real" => Ok(Units::Real),
+            "metal" => Ok(Units::Metal),
+            _ => Err(format!("unknown units {}, expected native, reduced, real or metal", text)),
+        }
+    }
+}
+
+// Engine time in ps, with sigma taken as argon's 3.405 Å. The run summaries, status file and
+// results database report simulated time in ps whatever `--units` says.
+pub fn picoseconds(time: f64) -> f64 {
+    time * Units::Metal.time()
+}
+
+// Each factor below is one engine unit of the quantity expressed in the chosen units: output
+// multiplies by it and input divides by it
+impl Units {
+    pub fn is_native(&self) -> bool {
+        *self == Units::Native
+    }
+
+    // The system and its units, for the start of the run
+    pub fn describe(self) -> &'static str {
+        match self {
+            Units::Native => "native (sigma, kJ/mol, K, sigma sqrt(amu mol/kJ))",
+            Units::Reduced => "reduced (sigma, epsilon, epsilon/k_B, sigma sqrt(m/epsilon))",
+            Units::Real => "real (Å, kcal/mol, K, fs, atm)",
+            Units::Metal => "metal (Å, eV, K, ps, bar)",
+        }
+    }
+
+    pub fn length(self) -> f64 {
+        match self {
+            Units::Native | Units::Reduced => 1.0,
+            Units::Real | Units::Metal => SIGMA_ARGON,
+        }
+    }
+
+    pub fn time(self) -> f64 {
+        match self {
+            Units::Native => 1.0,
+            // The LJ time sigma sqrt(m / epsilon) is sqrt(39.95) engine units
+            Units::Reduced => 1.0 / MASS_ARGON.sqrt(),
+            // sqrt(amu / (kJ/mol)) is 1e-3 s/m, times sigma in m
+            Units::Real => SIGMA_ARGON * 100.0,
+            Units::Metal => SIGMA_ARGON * 0.1,
+        }
+    }
+
+    pub fn energy(self) -> f64 {
+        match self {
+            Units::Native | Units::Reduced => 1.0,
+            Units::Real => 1.0 / KILOCALORIE,
+            Units::Metal => 1000.0 / (AVOGADRO * ELECTRON_VOLT),
+        }
+    }
+
+    pub fn temperature(self) -> f64 {
+        match self {
+            Units::Reduced => KB,
+            Units::Native | Units::Real | Units::Metal => 1.0,
+        }
+    }
+
+    pub fn velocity(self) -> f64 {
+        self.length() / self.time()
+    }
+
+    pub fn force(self) -> f64 {
+        self.energy() / self.length()
+    }
+
+    pub fn pressure(self) -> f64 {
+        // epsilon / sigma^3 of one atom in Pa
+        let pascals = 1000.0 / AVOGADRO / (SIGMA_ARGON * 1e-10).powi(3);
+        match self {
+            Units::Native | Units::Reduced => 1.0,
+            Units::Real => pascals / ATMOSPHERE,
+            Units::Metal => pascals / BAR,
+        }
+    }
+
+    // Converts a trajectory frame from engine units; order parameters have none
+    pub fn convert_frame(self, frame: &mut Frame) {
+        if self.is_native() {
+            return;
+        }
+        let scale = |vectors: &mut Vec<[f64; 3]>, factor: f64| {
+            vectors.iter_mut().flatten().for_each(|x| *x *= factor);
+        };
+        scale(&mut frame.positions, self.length());
+        scale(&mut frame.velocities, self.velocity());
+        scale(&mut frame.forces, self.force());
+        frame.energies.iter_mut().for_each(|e| *e *= self.energy());
+        frame.box_lengths = frame.box_lengths.map(|l| l * self.length());
+        frame.time *= self.time();
+        frame.pressure *= self.pressure();
+        frame.stress = frame.stress.map(|tensor| tensor.map(|p| p * self.pressure()));
+        frame.temperature *= self.temperature();
+        frame.potential_energy *= self.energy();
+        frame.kinetic_energy *= self.energy();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Each system's k_B, mv^2-to-energy factor and (energy / volume)-to-pressure factor as
+    // LAMMPS gives them, with masses in amu except in reduced units
+    const SYSTEMS: [(Units, f64, f64, f64, f64); 4] = [
+        (Units::Native, MASS_ARGON, KB, 1.0, 1.0),
+        (Units::Reduced, 1.0, 1.0, 1.0, 1.0),
+        (Units::Real, MASS_ARGON, 0.0019872067, 48.88821291 * 48.88821291, 68568.415),
+        (Units::Metal, MASS_ARGON, 8.617343e-5, 1.0364269e-4, 1.6021765e6),
+    ];
+
+    fn assert_relative(a: f64, b: f64, tolerance: f64) {
+        assert!((a - b).abs() <= tolerance * b.abs(), "{} against {}", a, b);
+    }
+
+    #[test]
+    fn kinetic_energy_and_forces_convert_with_the_mass() {
+        let (velocity, force) = (0.37, 2.5);
+        let kinetic_energy = 0.5 * MASS_ARGON * velocity * velocity;
+        let acceleration = force / MASS_ARGON;
+        for (units, mass, _, mvv2e, _) in SYSTEMS {
+            let v = velocity * units.velocity();
+            assert_relative(0.5 * mass * v * v * mvv2e, kinetic_energy * units.energy(), 1e-6);
+            // a = F / m, with LAMMPS' ftm2v = 1 / mvv2e turning force / mass into acceleration
+            let a = acceleration * units.length() / (units.time() * units.time());
+            assert_relative(force * units.force() / mass / mvv2e, a, 1e-6);
+        }
+    }
+
+    #[test]
+    fn temperature_and_pressure_match_the_energy() {
+        let (temperature, pressure) = (94.4, 0.12);
+        for (units, _, kb, _, nktv2p) in SYSTEMS {
+            // KB is an older CODATA value than LAMMPS', a few parts per million apart
+            assert_relative(kb * temperature * units.temperature(), KB * temperature * units.energy(), 1e-5);
+            let volume = units.length().powi(3);
+            assert_relative(pressure * units.pressure(), pressure * units.energy() / volume * nktv2p, 1e-6);
+        }
+    }
+
+    #[test]
+    fn reduced_time_is_the_lennard_jones_time() {
+        // tau = sigma sqrt(m / epsilon) with m the argon mass, about 2.15 ps for argon
+        assert_relative(1.0 / Units::Reduced.time(), MASS_ARGON.sqrt(), 1e-15);
+        assert_relative(Units::Metal.time() / Units::Reduced.time(), 2.152, 1e-3);
+        assert_relative(Units::Real.time() / Units::Metal.time(), 1000.0, 1e-15);
+        // One engine time unit is 3.405 Å sqrt(amu / (kJ/mol)) = 0.3405 ps
+        assert_relative(picoseconds(1.0), 0.3405, 1e-6);
+        assert_eq!("lj".parse::<Units>(), Ok(Units::Reduced));
+        assert!("si".parse::<Units>().is_err());
+    }
+
+    #[test]
+    fn native_frames_are_left_alone() {
+        let frame = || Frame {
+            time: 2.0,
+            positions: vec![[1.0, 2.0, 3.0]],
+            velocities: vec![[0.5, -0.5, 0.25]],
+            box_lengths: [10.0; 3],
+            pressure: 0.1,
+            temperature: 90.0,
+            ..Frame::default()
+        };
+        let mut native = frame();
+        Units::Native.convert_frame(&mut native);
+        assert_eq!(native.positions, frame().positions);
+        assert_eq!(native.time, 2.0);
+
+        let mut metal = frame();
+        Units::Metal.convert_frame(&mut metal);
+        assert_relative(metal.positions[0][2], 3.0 * SIGMA_ARGON, 1e-15);
+        assert_relative(metal.velocities[0][0], 0.5 * Units::Metal.velocity(), 1e-15);
+        assert_relative(metal.box_lengths[0], 10.0 * SIGMA_ARGON, 1e-15);
+        assert_relative(metal.time, 2.0 * Units::Metal.time(), 1e-15);
+        assert_eq!(metal.temperature, 90.0);
+    }
+}
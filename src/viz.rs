@@ -0,0 +1,224 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::cli::Args;
+
+// Live 3D view of the main run, behind `--viz`. The run steps on a worker thread while a kiss3d
+// window owns the main thread, as windowing systems want. Every `--viz-every` steps the run
+// offers the window the current configuration, which is dropped if the window has not drawn the
+// last one yet, so a slow window never holds the run back. Space pauses and resumes the run and
+// C switches the coloring between speed and atom type (mobile or frozen); the mouse orbits, pans
+// and zooms the camera. Closing the window lets the run carry on without it.
+
+// Configuration of the run at one step
+pub struct Frame {
+    pub step: usize,
+    pub time: f64,
+    pub temperature: f64,
+    pub box_lengths: [f64; 3],
+    pub positions: Vec<[f64; 3]>,
+    pub speeds: Vec<f64>,
+}
+
+// What the run and the window share
+pub struct Link {
+    // Steps between frames offered to the window
+    pub every: usize,
+    frame: Mutex<Option<Frame>>,
+    paused: AtomicBool,
+    closed: AtomicBool,
+    finished: AtomicBool,
+}
+
+pub fn available() -> bool {
+    cfg!(feature = "viz")
+}
+
+// `--viz` and `--viz-every` of the main run and resume
+pub fn from_args(args: &Args) -> Option<Arc<Link>> {
+    if !args.has("viz") {
+        return None;
+    }
+    if !available() {
+        eprintln!("--viz requires a build with --features viz");
+        std::process::exit(1);
+    }
+    // The window would fail only after the run has set up; X11 and Wayland say so up front
+    let headless = ["DISPLAY", "WAYLAND_DISPLAY", "WAYLAND_SOCKET"].iter().all(|name| std::env::var_os(name).is_none());
+    if cfg!(all(unix, not(target_os = "macos"))) && headless {
+        eprintln!("--viz needs a display, but neither DISPLAY nor WAYLAND_DISPLAY is set");
+        std::process::exit(1);
+    }
+    let every: usize = args.get("viz-every", 10);
+    if every == 0 {
+        eprintln!("--viz-every must be at least 1");
+        std::process::exit(1);
+    }
+    Some(Arc::new(Link {
+        every,
+        frame: Mutex::new(None),
+        paused: AtomicBool::new(false),
+        closed: AtomicBool::new(false),
+        finished: AtomicBool::new(false),
+    }))
+}
+
+impl Link {
+    pub fn due(&self, step: usize) -> bool {
+        step.is_multiple_of(self.every) && !self.closed.load(Ordering::Relaxed)
+    }
+
+    // Hands the window the configuration at `step`, unless it still has one to draw
+    pub fn offer(&self, step: usize, time: f64, temperature: f64, box_lengths: [f64; 3], positions: &[[f64; 3]], velocities: &[[f64; 3]]) {
+        let mut slot = self.frame.lock().unwrap();
+        if slot.is_none() {
+            *slot = Some(Frame {
+                step,
+                time,
+                temperature,
+                box_lengths,
+                positions: positions.to_vec(),
+                speeds: velocities.iter().map(|v| (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt()).collect(),
+            });
+        }
+    }
+
+    // Holds the run while it is paused, until it is resumed, the window is closed or the run is
+    // interrupted
+    pub fn hold(&self, interrupted: &AtomicBool) {
+        while self.paused.load(Ordering::Relaxed) && !self.closed.load(Ordering::Relaxed) && !interrupted.load(Ordering::Relaxed) {
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    // Called by the run after its last step; the window stays open on the last frame
+    pub fn finish(&self) {
+        self.finished.store(true, Ordering::Relaxed);
+    }
+
+    #[cfg(feature = "viz")]
+    fn take(&self) -> Option<Frame> {
+        self.frame.lock().unwrap().take()
+    }
+}
+
+// Opens the window and draws the frames of the run until the window is closed. `frozen` marks
+// the atoms held in place, for the type coloring.
+#[cfg(feature = "viz")]
+pub fn show(link: &Link, frozen: Option<&[bool]>) {
+    kiss3d::pollster::block_on(view(link, frozen));
+    link.closed.store(true, Ordering::Relaxed);
+}
+
+#[cfg(not(feature = "viz"))]
+pub fn show(_link: &Link, _frozen: Option<&[bool]>) {
+    unreachable!("--viz is rejected without the viz feature")
+}
+
+#[cfg(feature = "viz")]
+async fn view(link: &Link, frozen: Option<&[bool]>) {
+    use kiss3d::prelude::*;
+
+    let mut window = Window::new("sim").await;
+    window.set_background_color(Color::new(0.07, 0.07, 0.07, 1.0));
+    let font = Font::default();
+    let mut scene = SceneNode3d::empty();
+    let mut light = scene.add_light(Light::point(1000.0));
+    // Sigma across
+    let mut atoms = scene.add_sphere(0.5);
+    let mut camera: Option<OrbitCamera3d> = None;
+    let mut frame: Option<Frame> = None;
+    let mut by_type = false;
+    let mut recolor = false;
+    let mut instances = Vec::new();
+
+    loop {
+        for mut event in window.events().iter() {
+            if let WindowEvent::Key(key, Action::Press, _) = event.value {
+                match key {
+                    Key::Space => {
+                        link.paused.fetch_xor(true, Ordering::Relaxed);
+                        event.inhibited = true;
+                    }
+                    Key::C => {
+                        by_type = !by_type;
+                        recolor = true;
+                        event.inhibited = true;
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        if let Some(next) = link.take() {
+            frame = Some(next);
+            recolor = true;
+        }
+        if let Some(frame) = frame.as_ref().filter(|_| recolor) {
+            recolor = false;
+            let [lx, ly, lz] = frame.box_lengths.map(|l| l as f32);
+            let centre = Vec3::new(lx, ly, lz) / 2.0;
+            if camera.is_none() {
+                let size = lx.max(ly).max(lz);
+                camera = Some(OrbitCamera3d::new(Vec3::new(0.6, 0.5, 1.2) * 1.6 * size, Vec3::ZERO));
+                light.set_position(Vec3::new(1.0, 2.0, 1.5) * size);
+            }
+            // Blue for slow atoms through red for fast ones, relative to twice the RMS speed
+            let n = frame.speeds.len().max(1) as f64;
+            let scale = 2.0 * (frame.speeds.iter().map(|v| v * v).sum::<f64>() / n).sqrt();
+            instances.clear();
+            instances.extend(frame.positions.iter().zip(&frame.speeds).enumerate().map(|(i, (pos, &speed))| {
+                let color = if by_type {
+                    match frozen.is_some_and(|f| f[i]) {
+                        true => Color::new(0.55, 0.55, 0.55, 1.0),
+                        false => Color::new(0.35, 0.75, 0.95, 1.0),
+                    }
+                } else {
+                    let t = (speed / scale).min(1.0) as f32;
+                    Color::new(t, (80.0 + 100.0 * (1.0 - (2.0 * t - 1.0).abs())) / 255.0, 1.0 - t, 1.0)
+                };
+                InstanceData3d {
+                    position: Vec3::new(pos[0] as f32, pos[1] as f32, pos[2] as f32) - centre,
+                    color,
+                    ..Default::default()
+                }
+            }));
+            atoms.set_instances(&instances);
+        }
+
+        if let Some(frame) = &frame {
+            // Box edges
+            let half = Vec3::new(frame.box_lengths[0] as f32, frame.box_lengths[1] as f32, frame.box_lengths[2] as f32) / 2.0;
+            let corner = |i: usize| half * Vec3::new([-1.0, 1.0][i & 1], [-1.0, 1.0][(i >> 1) & 1], [-1.0, 1.0][(i >> 2) & 1]);
+            for (a, b) in [(0, 1), (1, 3), (3, 2), (2, 0), (4, 5), (5, 7), (7, 6), (6, 4), (0, 4), (1, 5), (2, 6), (3, 7)] {
+                window.draw_line(corner(a), corner(b), Color::new(0.4, 0.4, 0.4, 1.0), 1.5, true);
+            }
+            let status = if link.finished.load(Ordering::Relaxed) {
+                "finished"
+            } else if link.paused.load(Ordering::Relaxed) {
+                "paused"
+            } else {
+                "running"
+            };
+            let text = format!(
+                "step {}   t = {:.3}   T = {:.1} K   {}   (space: pause, c: color by {})",
+                frame.step,
+                frame.time,
+                frame.temperature,
+                status,
+                if by_type { "speed" } else { "type" }
+            );
+            window.draw_text(&text, Vec2::new(10.0, 10.0), 36.0, &font, Color::new(0.85, 0.85, 0.85, 1.0));
+        }
+
+        let open = match camera.as_mut() {
+            Some(camera) => window.render_3d(&mut scene, camera).await,
+            // Nothing to look at before the first frame
+            None => window.render_3d(&mut scene, &mut OrbitCamera3d::default()).await,
+        };
+        if !open {
+            break;
+        }
+    }
+}
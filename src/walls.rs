@@ -0,0 +1,131 @@
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+use crate::profile::AXES;
+
+// Structureless walls on the faces of the box. A wall acts on every atom through its
+// distance r from the face alone:
+//   lj93      E = eps [2/15 (sigma/r)^9 - (sigma/r)^3], a half-space of LJ atoms integrated out
+//   lj104     E = 2 pi eps [2/5 (sigma/r)^10 - (sigma/r)^4], a single integrated-out LJ plane
+//   harmonic  E = eps (r - sigma)^2 for r < sigma, a soft purely repulsive boundary
+// The LJ walls are cut at `cutoff` and shifted to zero there; a cutoff at the minimum of the
+// potential leaves only its repulsive part.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum WallKind {
+    Lj93,
+    Lj104,
+    Harmonic,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Wall {
+    pub axis: usize,
+    // The face at the box edge rather than at 0
+    pub high: bool,
+    pub kind: WallKind,
+    pub epsilon: f64,
+    pub sigma: f64,
+    pub cutoff: f64,
+}
+
+impl FromStr for Wall {
+    type Err = String;
+
+    // `face:kind:epsilon:sigma[:cutoff]` with the face `xlo` to `zhi`, e.g. `zlo:lj93:1.0:1.0:2.5`.
+    // The cutoff defaults to 2.5 sigma and is sigma for harmonic walls.
+    fn from_str(text: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = text.split(':').collect();
+        if fields.len() != 4 && fields.len() != 5 {
+            return Err(format!("{}: expected face:kind:epsilon:sigma[:cutoff]", text));
+        }
+        let face = fields[0];
+        let axis = AXES.iter().position(|&a| face.len() == 3 && face.starts_with(a));
+        let (Some(axis), Some(side)) = (axis, face.get(1..)) else {
+            return Err(format!("{}: face must be one of xlo, xhi, ylo, yhi, zlo, zhi", text));
+        };
+        let high = match side {
+            "lo" => false,
+            "hi" => true,
+            _ => return Err(format!("{}: face must be one of xlo, xhi, ylo, yhi, zlo, zhi", text)),
+        };
+        let kind = match fields[1] {
+            "lj93" => WallKind::Lj93,
+            "lj104" => WallKind::Lj104,
+            "harmonic" => WallKind::Harmonic,
+            _ => return Err(format!("{}: kind must be lj93, lj104 or harmonic", text)),
+        };
+        let number = |field: &str| field.parse::<f64>().ok().filter(|&x| x > 0.0 && x.is_finite());
+        let (Some(epsilon), Some(sigma)) = (number(fields[2]), number(fields[3])) else {
+            return Err(format!("{}: epsilon and sigma must be positive", text));
+        };
+        let cutoff = match (kind, fields.get(4)) {
+            (WallKind::Harmonic, _) => sigma,
+            (_, Some(field)) => number(field).ok_or_else(|| format!("{}: the cutoff must be positive", text))?,
+            (_, None) => 2.5 * sigma,
+        };
+        Ok(Wall { axis, high, kind, epsilon, sigma, cutoff })
+    }
+}
+
+impl Wall {
+    pub fn face(&self) -> String {
+        format!("{}{}", AXES[self.axis], if self.high { "hi" } else { "lo" })
+    }
+
+    // Energy and force (along the outward distance from the face, so positive pushes away) at
+    // distance r, zero beyond the cutoff
+    pub fn interaction(&self, r: f64) -> (f64, f64) {
+        if r >= self.cutoff {
+            return (0.0, 0.0);
+        }
+        let (energy, force) = self.unshifted(r);
+        (energy - self.unshifted(self.cutoff).0, force)
+    }
+
+    fn unshifted(&self, r: f64) -> (f64, f64) {
+        let (eps, s) = (self.epsilon, self.sigma);
+        match self.kind {
+            WallKind::Lj93 => {
+                let s3 = (s / r).powi(3);
+                let s9 = s3 * s3 * s3;
+                (eps * (2.0 / 15.0 * s9 - s3), eps * (6.0 / 5.0 * s9 - 3.0 * s3) / r)
+            }
+            WallKind::Lj104 => {
+                let s4 = (s / r).powi(4);
+                let s10 = (s / r).powi(10);
+                (2.0 * PI * eps * (0.4 * s10 - s4), 2.0 * PI * eps * (4.0 * s10 - 4.0 * s4) / r)
+            }
+            WallKind::Harmonic => (eps * (r - s).powi(2), 2.0 * eps * (s - r)),
+        }
+    }
+}
+
+// Adds the forces of the walls to `forces`, and their energies to `atom_energies` unless that
+// is empty, and returns the total wall energy. Fails when an atom sits on or behind a wall,
+// where the potentials are undefined.
+pub fn add_wall_forces(
+    walls: &[Wall],
+    positions: &[[f64; 3]],
+    box_lengths: [f64; 3],
+    forces: &mut [[f64; 3]],
+    atom_energies: &mut [f64],
+) -> Result<f64, String> {
+    let mut total = 0.0;
+    for wall in walls {
+        let k = wall.axis;
+        for (i, pos) in positions.iter().enumerate() {
+            let r = if wall.high { box_lengths[k] - pos[k] } else { pos[k] };
+            if r <= 0.0 {
+                return Err(format!("atom {} is on or behind the {} wall", i, wall.face()));
+            }
+            let (energy, force) = wall.interaction(r);
+            forces[i][k] += if wall.high { -force } else { force };
+            if let Some(e) = atom_energies.get_mut(i) {
+                *e += energy;
+            }
+            total += energy;
+        }
+    }
+    Ok(total)
+}
@@ -0,0 +1,540 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::xyz::Frame;
+
+const MAGIC: i32 = 1995;
+
+// Largest absolute coordinate, in units of 1/precision, that still fits an int
+const MAX_ABS: f32 = (i32::MAX - 2) as f32;
+
+// Sizes of the small-difference classes: magic_ints[i]^3 fits in i bits
+const MAGIC_INTS: [i32; 73] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0,
+    8, 10, 12, 16, 20, 25, 32, 40, 50, 64,
+    80, 101, 128, 161, 203, 256, 322, 406, 512, 645,
+    812, 1024, 1290, 1625, 2048, 2580, 3250, 4096, 5060, 6501,
+    8192, 10321, 13003, 16384, 20642, 26007, 32768, 41285, 52015, 65536,
+    82570, 104031, 131072, 165140, 208063, 262144, 330280, 416127, 524287, 660561,
+    832255, 1048576, 1321122, 1664510, 2097152, 2642245, 3329021, 4194304, 5284491, 6658042,
+    8388607, 10568983, 13316085, 16777216,
+];
+const FIRST_IDX: usize = 9;
+const LAST_IDX: usize = MAGIC_INTS.len() - 1;
+
+// GROMACS XTC trajectory: positions rounded to 1/precision (nm) and packed with the xdrfile
+// coordinate compression, so any GROMACS-compatible reader (MDAnalysis, MDTraj, VMD) loads it
+pub struct XtcWriter {
+    writer: BufWriter<File>,
+    precision: f32,
+}
+
+impl XtcWriter {
+    pub fn create<P: AsRef<Path>>(path: P, precision: f32) -> io::Result<Self> {
+        Ok(XtcWriter { writer: BufWriter::new(File::create(path)?), precision })
+    }
+
+    // Continues a file at byte `offset`, as returned by `sync`, dropping anything after it
+    pub fn append<P: AsRef<Path>>(path: P, precision: f32, offset: u64) -> io::Result<Self> {
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        file.set_len(offset)?;
+        file.seek(SeekFrom::End(0))?;
+        Ok(XtcWriter { writer: BufWriter::new(file), precision })
+    }
+
+    pub fn write_frame(&mut self, step: usize, time: f64, positions: &[[f64; 3]], box_lengths: [f64; 3]) -> io::Result<()> {
+        let w = &mut self.writer;
+        write_int(w, MAGIC)?;
+        write_int(w, positions.len() as i32)?;
+        write_int(w, step as i32)?;
+        write_float(w, time as f32)?;
+        for (a, &length) in box_lengths.iter().enumerate() {
+            for b in 0..3 {
+                write_float(w, if a == b { length as f32 } else { 0.0 })?;
+            }
+        }
+        let coords: Vec<f32> = positions.iter().flatten().map(|&x| x as f32).collect();
+        compress_coordinates(w, &coords, self.precision)
+    }
+
+    // Writes out the buffered frames and returns the file length
+    pub fn sync(&mut self) -> io::Result<u64> {
+        self.writer.flush()?;
+        self.writer.stream_position()
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// Reads every complete frame of an XTC file, as GROMACS or `XtcWriter` wrote it. Positions
+// are exact to 1/precision; a truncated last frame is dropped.
+pub fn read_frames<P: AsRef<Path>>(path: P) -> io::Result<Vec<Frame>> {
+    let bytes = fs::read(path)?;
+    let mut reader = XdrReader { bytes: &bytes, offset: 0 };
+    let mut frames = Vec::new();
+    while reader.offset < bytes.len() {
+        match read_frame(&mut reader) {
+            Ok(frame) => frames.push(frame),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(frames)
+}
+
+fn read_frame(reader: &mut XdrReader) -> io::Result<Frame> {
+    if reader.int()? != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not an XTC frame"));
+    }
+    let natoms = reader.int()?;
+    if natoms < 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "negative XTC atom count"));
+    }
+    let natoms = natoms as usize;
+    let _step = reader.int()?;
+    let time = reader.float()? as f64;
+    let mut box_lengths = [0.0; 3];
+    for (a, length) in box_lengths.iter_mut().enumerate() {
+        for b in 0..3 {
+            let value = reader.float()? as f64;
+            if a == b {
+                *length = value;
+            }
+        }
+    }
+    let positions = decompress_coordinates(reader, natoms)?;
+    Ok(Frame { positions, velocities: None, box_lengths: Some(box_lengths), species: None, time: Some(time) })
+}
+
+// Port of xdrfile_decompress_coord_float, the inverse of `compress_coordinates`
+fn decompress_coordinates(reader: &mut XdrReader, natoms: usize) -> io::Result<Vec<[f64; 3]>> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    if reader.int()? as usize != natoms {
+        return Err(invalid("XTC atom counts do not match"));
+    }
+    if natoms <= 9 {
+        let mut positions = vec![[0.0; 3]; natoms];
+        for pos in &mut positions {
+            for x in pos.iter_mut() {
+                *x = reader.float()? as f64;
+            }
+        }
+        return Ok(positions);
+    }
+    let precision = reader.float()?;
+
+    let mut min_int = [0i32; 3];
+    let mut max_int = [0i32; 3];
+    for value in min_int.iter_mut().chain(max_int.iter_mut()) {
+        *value = reader.int()?;
+    }
+    let size_int: [u32; 3] = std::array::from_fn(|k| max_int[k].wrapping_sub(min_int[k]).wrapping_add(1) as u32);
+    let (bit_size, bit_size_int) = if (size_int[0] | size_int[1] | size_int[2]) > 0xffffff {
+        (0, size_int.map(size_of_int))
+    } else {
+        (size_of_ints(&size_int), [0; 3])
+    };
+
+    let mut small_idx = reader.int()? as usize;
+    if !(FIRST_IDX..=LAST_IDX).contains(&small_idx) {
+        return Err(invalid("XTC small-difference class out of range"));
+    }
+    let mut smaller = MAGIC_INTS[FIRST_IDX.max(small_idx - 1)] / 2;
+    let mut small_num = MAGIC_INTS[small_idx] / 2;
+    let mut size_small = [MAGIC_INTS[small_idx] as u32; 3];
+
+    let length = reader.int()? as u32 as usize;
+    let mut bits = BitReader { bytes: reader.bytes(length)?, position: 0 };
+    // Every atom takes at least one bit, so a count beyond that is corrupt rather than allocated
+    if natoms > 8 * length {
+        return Err(invalid("XTC atom count exceeds the frame size"));
+    }
+
+    let mut ints: Vec<[i32; 3]> = Vec::with_capacity(natoms);
+    let mut run = 0;
+    while ints.len() < natoms {
+        let offset = if bit_size == 0 {
+            [bits.receive(bit_size_int[0])?, bits.receive(bit_size_int[1])?, bits.receive(bit_size_int[2])?]
+        } else {
+            bits.receive_ints(bit_size, &size_int)?
+        };
+        let mut prev: [i32; 3] = std::array::from_fn(|k| (offset[k] as i32).wrapping_add(min_int[k]));
+
+        let mut is_smaller = 0;
+        if bits.receive(1)? == 1 {
+            run = bits.receive(5)? as i32;
+            is_smaller = run % 3;
+            run -= is_smaller;
+            is_smaller -= 1;
+        }
+        if run > 0 {
+            for k in (0..run).step_by(3) {
+                let delta = bits.receive_ints(small_idx as u32, &size_small)?;
+                let this: [i32; 3] = std::array::from_fn(|d| delta[d] as i32 + prev[d] - small_num);
+                if k == 0 {
+                    // The writer sent the second atom of a pair first
+                    ints.push(this);
+                    ints.push(prev);
+                } else {
+                    ints.push(this);
+                }
+                prev = this;
+            }
+        } else {
+            ints.push(prev);
+        }
+
+        if is_smaller != 0 {
+            small_idx = (small_idx as i32 + is_smaller) as usize;
+            if !(FIRST_IDX..=LAST_IDX).contains(&small_idx) {
+                return Err(invalid("XTC small-difference class out of range"));
+            }
+            if is_smaller < 0 {
+                small_num = smaller;
+                smaller = if small_idx > FIRST_IDX { MAGIC_INTS[small_idx - 1] / 2 } else { 0 };
+            } else {
+                smaller = small_num;
+                small_num = MAGIC_INTS[small_idx] / 2;
+            }
+            size_small = [MAGIC_INTS[small_idx] as u32; 3];
+        }
+    }
+    if ints.len() != natoms {
+        return Err(invalid("XTC frame decodes to the wrong atom count"));
+    }
+    Ok(ints.iter().map(|atom| atom.map(|x| x as f64 / precision as f64)).collect())
+}
+
+// Big-endian XDR words; running out of bytes is an unexpected end of file
+struct XdrReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> XdrReader<'a> {
+    // `length` bytes of opaque data, skipping the padding to a multiple of four
+    fn bytes(&mut self, length: usize) -> io::Result<&'a [u8]> {
+        // Compared with what is left, so a corrupt length cannot overflow
+        if length > self.bytes.len() - self.offset || length.div_ceil(4) * 4 > self.bytes.len() - self.offset {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let padded = length.div_ceil(4) * 4;
+        let data = &self.bytes[self.offset..self.offset + length];
+        self.offset += padded;
+        Ok(data)
+    }
+
+    fn int(&mut self) -> io::Result<i32> {
+        Ok(i32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn float(&mut self) -> io::Result<f32> {
+        Ok(f32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+}
+
+// Takes values most significant bit first, as `BitWriter` appends them
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl BitReader<'_> {
+    fn receive(&mut self, num_bits: u32) -> io::Result<u32> {
+        if self.position + num_bits as usize > 8 * self.bytes.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "XTC bit stream ends early"));
+        }
+        let mut value = 0;
+        for _ in 0..num_bits {
+            let bit = (self.bytes[self.position / 8] >> (7 - self.position % 8)) & 1;
+            value = (value << 1) | bit as u32;
+            self.position += 1;
+        }
+        Ok(value)
+    }
+
+    fn receive_ints(&mut self, num_bits: u32, sizes: &[u32; 3]) -> io::Result<[u32; 3]> {
+        let mut combined = 0u128;
+        let mut remaining = num_bits;
+        let mut byte = 0;
+        while remaining > 0 {
+            let chunk = remaining.min(8);
+            combined |= (self.receive(chunk)? as u128) << (8 * byte);
+            remaining -= chunk;
+            byte += 1;
+        }
+        let third = combined % sizes[2] as u128;
+        combined /= sizes[2] as u128;
+        let second = combined % sizes[1] as u128;
+        Ok([(combined / sizes[1] as u128) as u32, second as u32, third as u32])
+    }
+}
+
+// Port of xdrfile_compress_coord_float: coordinates become integers relative to the minimum,
+// and runs of atoms close to their predecessor (water-like clusters, neighbours along a chain)
+// are sent as small differences whose bit width adapts as the frame goes on
+fn compress_coordinates<W: Write>(w: &mut W, coords: &[f32], precision: f32) -> io::Result<()> {
+    let natoms = coords.len() / 3;
+    write_int(w, natoms as i32)?;
+    // Not worth compressing
+    if natoms <= 9 {
+        for &x in coords {
+            write_float(w, x)?;
+        }
+        return Ok(());
+    }
+    write_float(w, precision)?;
+
+    let mut ints = Vec::with_capacity(coords.len());
+    let mut min_int = [i32::MAX; 3];
+    let mut max_int = [i32::MIN; 3];
+    let mut min_diff = i32::MAX;
+    for (i, atom) in coords.chunks_exact(3).enumerate() {
+        for k in 0..3 {
+            let scaled = atom[k] * precision;
+            let rounded = if scaled >= 0.0 { scaled + 0.5 } else { scaled - 0.5 };
+            if rounded.abs() > MAX_ABS {
+                return Err(overflow());
+            }
+            let value = rounded as i32;
+            min_int[k] = min_int[k].min(value);
+            max_int[k] = max_int[k].max(value);
+            ints.push(value);
+        }
+        if i > 0 {
+            let diff = (0..3).map(|k| (ints[3 * i + k] - ints[3 * i + k - 3]).abs()).sum::<i32>();
+            min_diff = min_diff.min(diff);
+        }
+    }
+    for value in min_int.iter().chain(max_int.iter()) {
+        write_int(w, *value)?;
+    }
+    if (0..3).any(|k| max_int[k] as f32 - min_int[k] as f32 >= MAX_ABS) {
+        return Err(overflow());
+    }
+
+    let size_int: [u32; 3] = std::array::from_fn(|k| (max_int[k] - min_int[k] + 1) as u32);
+    // Ranges too large to multiply together are sent one by one
+    let (bit_size, bit_size_int) = if (size_int[0] | size_int[1] | size_int[2]) > 0xffffff {
+        (0, size_int.map(size_of_int))
+    } else {
+        (size_of_ints(&size_int), [0; 3])
+    };
+
+    let mut small_idx = FIRST_IDX;
+    while small_idx < LAST_IDX && MAGIC_INTS[small_idx] < min_diff {
+        small_idx += 1;
+    }
+    write_int(w, small_idx as i32)?;
+
+    let max_idx = LAST_IDX.min(small_idx + 8);
+    let min_idx = max_idx - 8;
+    let larger = MAGIC_INTS[max_idx] / 2;
+    let mut smaller = MAGIC_INTS[FIRST_IDX.max(small_idx - 1)] / 2;
+    let mut small_num = MAGIC_INTS[small_idx] / 2;
+    let mut size_small = [MAGIC_INTS[small_idx] as u32; 3];
+
+    let mut bits = BitWriter::default();
+    let mut prev = [0i32; 3];
+    let mut prev_run: i32 = -1;
+    let mut i = 0;
+    while i < natoms {
+        let mut is_small = false;
+        let this = 3 * i;
+        let mut is_smaller: i32 = if small_idx < max_idx && i >= 1 && (0..3).all(|k| (ints[this + k] - prev[k]).abs() < larger) {
+            1
+        } else if small_idx > min_idx {
+            -1
+        } else {
+            0
+        };
+        if i + 1 < natoms && (0..3).all(|k| (ints[this + k] - ints[this + 3 + k]).abs() < small_num) {
+            // Send the second atom first, which compresses water molecules better
+            for k in 0..3 {
+                ints.swap(this + k, this + 3 + k);
+            }
+            is_small = true;
+        }
+
+        let offset: [u32; 3] = std::array::from_fn(|k| (ints[this + k] - min_int[k]) as u32);
+        if bit_size == 0 {
+            for k in 0..3 {
+                bits.send(bit_size_int[k], offset[k]);
+            }
+        } else {
+            bits.send_ints(bit_size, &size_int, &offset);
+        }
+        prev.copy_from_slice(&ints[this..this + 3]);
+        i += 1;
+
+        let mut run = 0;
+        let mut deltas = [0u32; 24];
+        if !is_small && is_smaller == -1 {
+            is_smaller = 0;
+        }
+        while is_small && run < 24 {
+            let this = 3 * i;
+            let distance_sq: i64 = (0..3).map(|k| ((ints[this + k] - prev[k]) as i64).pow(2)).sum();
+            if is_smaller == -1 && distance_sq >= (smaller as i64).pow(2) {
+                is_smaller = 0;
+            }
+            for k in 0..3 {
+                deltas[run + k] = (ints[this + k] - prev[k] + small_num) as u32;
+            }
+            run += 3;
+            prev.copy_from_slice(&ints[this..this + 3]);
+            i += 1;
+            is_small = i < natoms && (0..3).all(|k| (ints[3 * i + k] - prev[k]).abs() < small_num);
+        }
+
+        if run as i32 != prev_run || is_smaller != 0 {
+            prev_run = run as i32;
+            bits.send(1, 1);
+            bits.send(5, (run as i32 + is_smaller + 1) as u32);
+        } else {
+            bits.send(1, 0);
+        }
+        for delta in deltas[..run].chunks_exact(3) {
+            bits.send_ints(small_idx as u32, &size_small, delta);
+        }
+
+        if is_smaller != 0 {
+            small_idx = (small_idx as i32 + is_smaller) as usize;
+            if is_smaller < 0 {
+                small_num = smaller;
+                smaller = if small_idx > FIRST_IDX { MAGIC_INTS[small_idx - 1] / 2 } else { 0 };
+            } else {
+                smaller = small_num;
+                small_num = MAGIC_INTS[small_idx] / 2;
+            }
+            size_small = [MAGIC_INTS[small_idx] as u32; 3];
+        }
+    }
+
+    let bytes = bits.into_bytes();
+    write_int(w, bytes.len() as i32)?;
+    w.write_all(&bytes)?;
+    // XDR opaque data is padded to a multiple of four bytes
+    w.write_all(&[0u8; 3][..(4 - bytes.len() % 4) % 4])
+}
+
+// Appends values most significant bit first
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    last: u32,
+    last_bits: u32,
+}
+
+impl BitWriter {
+    fn send(&mut self, num_bits: u32, value: u32) {
+        for bit in (0..num_bits).rev() {
+            self.last = (self.last << 1) | ((value >> bit) & 1);
+            self.last_bits += 1;
+            if self.last_bits == 8 {
+                self.bytes.push(self.last as u8);
+                self.last = 0;
+                self.last_bits = 0;
+            }
+        }
+    }
+
+    // Packs three bounded integers into one mixed-radix number of `num_bits` bits, sent as
+    // little-endian bytes
+    fn send_ints(&mut self, num_bits: u32, sizes: &[u32; 3], values: &[u32]) {
+        let combined = (values[0] as u128 * sizes[1] as u128 + values[1] as u128) * sizes[2] as u128 + values[2] as u128;
+        let mut remaining = num_bits;
+        let mut byte = 0;
+        while remaining > 0 {
+            let chunk = remaining.min(8);
+            self.send(chunk, ((combined >> (8 * byte)) & 0xff) as u32);
+            remaining -= chunk;
+            byte += 1;
+        }
+    }
+
+    fn into_bytes(mut self) -> Vec<u8> {
+        if self.last_bits > 0 {
+            self.bytes.push((self.last << (8 - self.last_bits)) as u8);
+        }
+        self.bytes
+    }
+}
+
+// Bits needed to store values below `size`
+fn size_of_int(size: u32) -> u32 {
+    32 - size.leading_zeros()
+}
+
+// Bits needed to store the mixed-radix combination of values below `sizes`
+fn size_of_ints(sizes: &[u32; 3]) -> u32 {
+    let product = sizes.iter().fold(1u128, |p, &s| p * s as u128);
+    128 - product.leading_zeros()
+}
+
+fn write_int<W: Write>(w: &mut W, value: i32) -> io::Result<()> {
+    w.write_all(&value.to_be_bytes())
+}
+
+fn write_float<W: Write>(w: &mut W, value: f32) -> io::Result<()> {
+    w.write_all(&value.to_be_bytes())
+}
+
+fn overflow() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "coordinates too large for XTC precision")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn positions(n: usize) -> Vec<[f64; 3]> {
+        (0..n).map(|i| {
+            let x = i as f64;
+            [(0.37 * x).rem_euclid(3.1), (0.61 * x * x).rem_euclid(3.1), 0.05 * x]
+        }).collect()
+    }
+
+    #[test]
+    fn frames_read_back_to_the_precision() {
+        let path = std::env::temp_dir().join(format!("sim-xtc-{}.xtc", std::process::id()));
+        let frames = [positions(40), positions(5)];
+        let mut writer = XtcWriter::create(&path, 1000.0).unwrap();
+        for (i, frame) in frames.iter().enumerate() {
+            writer.write_frame(10 * i, 0.5 * i as f64, frame, [3.1, 3.2, 3.3]).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let read = read_frames(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read.len(), 2);
+        for (i, (frame, written)) in read.iter().zip(&frames).enumerate() {
+            assert_eq!(frame.time, Some(0.5 * i as f64));
+            assert_eq!(frame.box_lengths, Some([3.1f32 as f64, 3.2f32 as f64, 3.3f32 as f64]));
+            assert_eq!(frame.positions.len(), written.len());
+            for (a, b) in frame.positions.iter().flatten().zip(written.iter().flatten()) {
+                assert!((a - b).abs() <= 0.5e-3 + 1e-6, "{} against {}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn corrupt_atom_counts_are_errors() {
+        let path = std::env::temp_dir().join(format!("sim-xtc-corrupt-{}.xtc", std::process::id()));
+        let mut writer = XtcWriter::create(&path, 1000.0).unwrap();
+        writer.write_frame(0, 0.0, &positions(40), [3.1; 3]).unwrap();
+        writer.finish().unwrap();
+        let mut bytes = std::fs::read(&path).unwrap();
+        // The count in the frame header and again before the compressed coordinates
+        for count in [i32::MAX, -7] {
+            bytes[4..8].copy_from_slice(&count.to_be_bytes());
+            bytes[52..56].copy_from_slice(&count.to_be_bytes());
+            std::fs::write(&path, &bytes).unwrap();
+            assert_eq!(read_frames(&path).err().unwrap().kind(), io::ErrorKind::InvalidData);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
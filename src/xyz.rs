@@ -0,0 +1,258 @@
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+use crate::compress;
+use crate::geometry::Cell;
+
+// Atoms reserved up front when reading a frame; the count in the header is only a hint, since a
+// corrupt file may claim any number
+const RESERVE_ATOMS: usize = 1 << 20;
+
+// Writes one extended XYZ frame. The box goes into the `Lattice` key of the comment line
+// and velocities, when given, become an extra `velo` column.
+pub fn write_frame<W: Write>(
+    writer: &mut W,
+    positions: &[[f64; 3]],
+    velocities: Option<&[[f64; 3]]>,
+    box_length: f64,
+    comment: &str,
+) -> io::Result<()> {
+    write_frame_with_columns(writer, positions, None, velocities, &[], [box_length; 3], comment)
+}
+
+// Like `write_frame` for any periodic cell, its edge vectors a, b and c making up the `Lattice`
+pub fn write_cell_frame<W: Write>(
+    writer: &mut W,
+    positions: &[[f64; 3]],
+    velocities: Option<&[[f64; 3]]>,
+    cell: &Cell,
+    comment: &str,
+) -> io::Result<()> {
+    write_lattice_frame(writer, positions, None, velocities, &[], cell.vectors(), comment)
+}
+
+// Like `write_frame` in a box of edges `box_lengths`, with the element of every atom (argon for all when None) and extra named
+// per-atom real columns after the velocities, which OVITO and ASE pick up as per-atom properties
+pub fn write_frame_with_columns<W: Write>(
+    writer: &mut W,
+    positions: &[[f64; 3]],
+    species: Option<&[String]>,
+    velocities: Option<&[[f64; 3]]>,
+    columns: &[(&str, &[f64])],
+    box_lengths: [f64; 3],
+    comment: &str,
+) -> io::Result<()> {
+    write_lattice_frame(writer, positions, species, velocities, columns, Cell::orthorhombic(box_lengths).vectors(), comment)
+}
+
+fn write_lattice_frame<W: Write>(
+    writer: &mut W,
+    positions: &[[f64; 3]],
+    species: Option<&[String]>,
+    velocities: Option<&[[f64; 3]]>,
+    columns: &[(&str, &[f64])],
+    lattice: [[f64; 3]; 3],
+    comment: &str,
+) -> io::Result<()> {
+    writeln!(writer, "{}", positions.len())?;
+    let mut properties = String::from("species:S:1:pos:R:3");
+    if velocities.is_some() {
+        properties.push_str(":velo:R:3");
+    }
+    for (name, _) in columns {
+        properties.push_str(&format!(":{}:R:1", name));
+    }
+    let lattice: Vec<String> = lattice.iter().flatten().map(|x| x.to_string()).collect();
+    writeln!(writer, "Lattice=\"{}\" Properties={} pbc=\"T T T\" {}", lattice.join(" "), properties, comment)?;
+    for (i, pos) in positions.iter().enumerate() {
+        let element = species.map_or("Ar", |species| species[i].as_str());
+        write!(writer, "{} {:.8} {:.8} {:.8}", element, pos[0], pos[1], pos[2])?;
+        if let Some(vel) = velocities {
+            write!(writer, " {:.8} {:.8} {:.8}", vel[i][0], vel[i][1], vel[i][2])?;
+        }
+        for (_, values) in columns {
+            write!(writer, " {:.8}", values[i])?;
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+// Writes one plain XYZ frame (`Ar x y z` lines), the lowest common denominator that every
+// viewer reads
+pub fn write_plain_frame<W: Write>(writer: &mut W, positions: &[[f64; 3]], comment: &str) -> io::Result<()> {
+    writeln!(writer, "{}", positions.len())?;
+    writeln!(writer, "{}", comment)?;
+    for pos in positions {
+        writeln!(writer, "Ar {:.8} {:.8} {:.8}", pos[0], pos[1], pos[2])?;
+    }
+    Ok(())
+}
+
+pub struct Frame {
+    pub positions: Vec<[f64; 3]>,
+    pub velocities: Option<Vec<[f64; 3]>>,
+    // Diagonal of the `Lattice` key, when present
+    pub box_lengths: Option<[f64; 3]>,
+    // Element or type label of every atom, when the file has them
+    pub species: Option<Vec<String>>,
+    // Simulated time from the `Time` key, when present
+    pub time: Option<f64>,
+}
+
+// Reads the first frame of a plain or extended XYZ file. Columns are located through the
+// `Properties` key when present, otherwise the file is taken to be `species x y z`.
+pub fn read_frame<P: AsRef<Path>>(path: P) -> io::Result<Frame> {
+    let mut lines = compress::open(path)?.lines();
+    next_frame(&mut lines)?.ok_or_else(|| invalid("file is empty"))
+}
+
+// Reads every frame of an XYZ trajectory
+pub fn read_frames<P: AsRef<Path>>(path: P) -> io::Result<Vec<Frame>> {
+    let mut lines = compress::open(path)?.lines();
+    let mut frames = Vec::new();
+    while let Some(frame) = next_frame(&mut lines)? {
+        frames.push(frame);
+    }
+    Ok(frames)
+}
+
+// The frame starting at the next line, or None at the end of the file
+fn next_frame<I: Iterator<Item = io::Result<String>>>(lines: &mut I) -> io::Result<Option<Frame>> {
+    let n: usize = loop {
+        match lines.next() {
+            Some(line) => {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                break line.trim().parse().map_err(|_| invalid("frame does not start with an atom count"))?;
+            }
+            None => return Ok(None),
+        }
+    };
+    let comment = lines.next().ok_or_else(|| invalid("missing comment line"))??;
+    let keys = parse_comment(&comment);
+
+    let mut pos_column = 1;
+    let mut velo_column = None;
+    let mut species_column = Some(0);
+    if let Some(properties) = keys.iter().find(|(key, _)| key.eq_ignore_ascii_case("Properties")) {
+        let fields: Vec<&str> = properties.1.split(':').collect();
+        if !fields.len().is_multiple_of(3) {
+            return Err(invalid("malformed Properties key"));
+        }
+        let mut column = 0;
+        let mut found_pos = false;
+        species_column = None;
+        for field in fields.chunks(3) {
+            let count: usize = field[2].parse().map_err(|_| invalid("malformed Properties key"))?;
+            match field[0] {
+                "pos" => {
+                    pos_column = column;
+                    found_pos = true;
+                }
+                "velo" | "vel" | "velocities" => velo_column = Some(column),
+                "species" => species_column = Some(column),
+                _ => {}
+            }
+            column += count;
+        }
+        if !found_pos {
+            return Err(invalid("Properties key has no pos column"));
+        }
+    }
+
+    let box_lengths = match keys.iter().find(|(key, _)| key.eq_ignore_ascii_case("Lattice")) {
+        Some((_, lattice)) => {
+            let values: Vec<f64> = lattice.split_whitespace()
+                .map(|v| v.parse().map_err(|_| invalid("malformed Lattice key")))
+                .collect::<io::Result<_>>()?;
+            if values.len() != 9 {
+                return Err(invalid("Lattice key must have 9 components"));
+            }
+            Some([values[0], values[4], values[8]])
+        }
+        None => None,
+    };
+
+    let reserve = n.min(RESERVE_ATOMS);
+    let mut positions = Vec::with_capacity(reserve);
+    let mut velocities = velo_column.map(|_| Vec::with_capacity(reserve));
+    let mut species = species_column.map(|_| Vec::with_capacity(reserve));
+    for i in 0..n {
+        let line = lines.next().ok_or_else(|| invalid(&format!("expected {} atoms, found {}", n, i)))??;
+        let columns: Vec<&str> = line.split_whitespace().collect();
+        positions.push(parse_vector(&columns, pos_column, i)?);
+        if let (Some(column), Some(velocities)) = (velo_column, velocities.as_mut()) {
+            velocities.push(parse_vector(&columns, column, i)?);
+        }
+        if let (Some(column), Some(species)) = (species_column, species.as_mut()) {
+            species.push(columns.get(column).ok_or_else(|| invalid(&format!("atom {} has too few columns", i)))?.to_string());
+        }
+    }
+
+    let time = keys.iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case("Time"))
+        .and_then(|(_, value)| value.parse().ok());
+    Ok(Some(Frame { positions, velocities, box_lengths, species, time }))
+}
+
+fn parse_vector(columns: &[&str], start: usize, atom: usize) -> io::Result<[f64; 3]> {
+    let mut vector = [0.0; 3];
+    for (k, value) in vector.iter_mut().enumerate() {
+        *value = columns.get(start + k)
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(|| invalid(&format!("bad coordinates for atom {}", atom + 1)))?;
+    }
+    Ok(vector)
+}
+
+// Splits an extended XYZ comment line into `key=value` pairs, honouring double quotes
+fn parse_comment(comment: &str) -> Vec<(String, String)> {
+    let mut pairs = Vec::new();
+    let mut chars = comment.chars().peekable();
+    loop {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        if chars.peek().is_none() {
+            break;
+        }
+        let mut key = String::new();
+        while let Some(&c) = chars.peek() {
+            if c == '=' || c.is_whitespace() {
+                break;
+            }
+            key.push(c);
+            chars.next();
+        }
+        let mut value = String::new();
+        if chars.peek() == Some(&'=') {
+            chars.next();
+            if chars.peek() == Some(&'"') {
+                chars.next();
+                for c in chars.by_ref() {
+                    if c == '"' {
+                        break;
+                    }
+                    value.push(c);
+                }
+            } else {
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() {
+                        break;
+                    }
+                    value.push(c);
+                    chars.next();
+                }
+            }
+        }
+        pairs.push((key, value));
+    }
+    pairs
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
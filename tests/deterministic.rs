@@ -0,0 +1,37 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+// Runs the main run on 1100 atoms of a cubic grid, enough for several chunks of the fixed-order
+// sums, and returns the trajectory it wrote
+fn trajectory(threads: usize) -> Vec<u8> {
+    let dir: PathBuf = std::env::temp_dir().join(format!("sim-deterministic-{}-{}", std::process::id(), threads));
+    fs::create_dir_all(&dir).unwrap();
+    let mut grid = String::from("1100\ncubic grid\n");
+    for i in 0..11 {
+        for j in 0..10 {
+            for k in 0..10 {
+                grid += &format!("Ar {} {} {}\n", 1.1 * i as f64 + 0.3, 1.1 * j as f64 + 0.3, 1.1 * k as f64 + 0.3);
+            }
+        }
+    }
+    fs::write(dir.join("grid.xyz"), grid).unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_sim"))
+        .current_dir(&dir)
+        .args(["12.1,11,11", "1100", "0.001", "30", "10", "--init-from", "grid.xyz", "--seed", "3", "--stress", "--deterministic"])
+        .args(["--threads", &threads.to_string()])
+        .output()
+        .unwrap();
+    assert!(status.status.success(), "{}", String::from_utf8_lossy(&status.stderr));
+    let trajectory = fs::read(dir.join("simulation_data.json")).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    trajectory
+}
+
+#[test]
+fn deterministic_runs_match_on_any_number_of_threads() {
+    let single = trajectory(1);
+    for threads in [2, 4] {
+        assert!(trajectory(threads) == single, "the trajectory on {} threads differs from the one on 1", threads);
+    }
+}
@@ -0,0 +1,68 @@
+// Browser demo: a few hundred Lennard-Jones atoms stepped live for teaching. Built for
+// wasm32-unknown-unknown and wrapped by wasm-bindgen; index.html draws the atoms and drives
+// the run, one `step` call per animation frame.
+use wasm_bindgen::prelude::*;
+
+use sim::simulation::{initial_state, BerendsenThermostat, Simulation};
+
+const TIMESTEP: f64 = 0.005;
+const CUTOFF: f64 = 2.5;
+// Berendsen coupling time, ps
+const TAU: f64 = 0.1;
+
+#[wasm_bindgen]
+pub struct Demo {
+    sim: Simulation,
+}
+
+#[wasm_bindgen]
+impl Demo {
+    // `n` atoms on a lattice at `density` with velocities at `temperature` (K), coupled to
+    // that temperature
+    #[wasm_bindgen(constructor)]
+    pub fn new(n: usize, density: f64, temperature: f64, seed: u32) -> Demo {
+        let mut sim = initial_state(n, density, temperature, TIMESTEP, CUTOFF, seed as u64);
+        sim.thermostat = Some(BerendsenThermostat { target_temperature: temperature, tau: TAU });
+        Demo { sim }
+    }
+
+    pub fn step(&mut self, steps: u32) {
+        for _ in 0..steps {
+            self.sim.step();
+        }
+    }
+
+    pub fn set_temperature(&mut self, temperature: f64) {
+        self.sim.set_target_temperature(temperature);
+    }
+
+    // x y z of every atom
+    pub fn positions(&self) -> Vec<f64> {
+        self.sim.positions().as_flattened().to_vec()
+    }
+
+    // Speed of every atom, for coloring
+    pub fn speeds(&self) -> Vec<f64> {
+        self.sim.velocities().iter().map(|vel| (vel[0] * vel[0] + vel[1] * vel[1] + vel[2] * vel[2]).sqrt()).collect()
+    }
+
+    pub fn box_length(&self) -> f64 {
+        self.sim.box_length()
+    }
+
+    pub fn time(&self) -> f64 {
+        self.sim.time()
+    }
+
+    pub fn temperature(&self) -> f64 {
+        self.sim.temperature()
+    }
+
+    pub fn potential_energy_per_atom(&self) -> f64 {
+        self.sim.potential_energy() / self.sim.num_atoms() as f64
+    }
+
+    pub fn pressure(&self) -> f64 {
+        self.sim.corrected_pressure()
+    }
+}